@@ -113,7 +113,7 @@ impl AuditSubcommand for AuditDepFilesCommand {
                     Cow::Owned(dep_files),
                     true,
                     ctx.global_data().get_digest_config(),
-                );
+                )?;
 
                 let dirs = match &*fingerprints {
                     StoredFingerprints::Digests(..) => {
@@ -352,11 +352,19 @@ async fn command_details(
         stderr = pair.stderr;
     };
 
+    let execution_stats = match command.status.execution_kind() {
+        Some(CommandExecutionKind::Local {
+            execution_stats, ..
+        }) => execution_stats.clone(),
+        _ => None,
+    };
+
     let command = command.status.execution_kind().map(|kind| match kind {
         CommandExecutionKind::Local {
             command,
             env,
             digest,
+            ..
         } => {
             if omit_details {
                 buck2_data::OmittedLocalCommand {
@@ -390,6 +398,10 @@ async fn command_details(
             queue_time: command.timing.re_queue_time.and_then(|d| d.try_into().ok()),
         }
         .into(),
+        CommandExecutionKind::LocalActionCache { digest } => buck2_data::LocalCacheHit {
+            action_digest: digest.to_string(),
+        }
+        .into(),
     });
 
     buck2_data::CommandExecutionDetails {
@@ -398,6 +410,7 @@ async fn command_details(
         stderr,
         command,
         signed_exit_code,
+        execution_stats,
     }
 }
 
@@ -457,6 +470,7 @@ mod tests {
     use buck2_execute::execute::result::CommandExecutionStatus;
     use buck2_execute::execute::testing_dry_run::DryRunEntry;
     use buck2_execute::execute::testing_dry_run::DryRunExecutor;
+    use buck2_execute::materialize::http::HasUrlMirrors;
     use buck2_execute::materialize::materializer::SetMaterializer;
     use buck2_execute::materialize::nodisk::NoDiskMaterializer;
     use buck2_execute::re::manager::ManagedRemoteExecutionClient;
@@ -595,6 +609,7 @@ mod tests {
         extra.set_re_client(ManagedRemoteExecutionClient::testing_new_dummy());
         extra.data.set(EventDispatcher::null());
         extra.data.set(RunActionKnobs::default());
+        extra.set_url_mirrors(Default::default());
         extra.spawner = Arc::new(BuckSpawner::default());
 
         let mut computations = dice_builder.build(extra)?;
@@ -902,6 +917,7 @@ mod tests {
                     digest: ActionDigest::empty(digest_config.cas_digest_config()),
                     command: vec![],
                     env: sorted_vector_map![],
+                    execution_stats: None,
                 },
             },
             timing: Default::default(),
@@ -927,6 +943,7 @@ mod tests {
                 digest: ActionDigest::empty(digest_config.cas_digest_config()),
                 command: vec![],
                 env: sorted_vector_map![],
+                execution_stats: None,
             },
         };
         let proto = command_details(&report, true).await;
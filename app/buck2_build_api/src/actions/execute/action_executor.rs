@@ -9,6 +9,7 @@
 
 use std::fmt::Debug;
 use std::sync::Arc;
+use std::time::Duration;
 
 use allocative::Allocative;
 use anyhow::Context;
@@ -33,14 +34,20 @@ use buck2_execute::execute::dice_data::GetReClient;
 use buck2_execute::execute::dice_data::HasCommandExecutor;
 use buck2_execute::execute::kind::CommandExecutionKind;
 use buck2_execute::execute::manager::CommandExecutionManager;
+use buck2_execute::execute::output::CommandStdStreams;
+use buck2_execute::execute::output::StdStreamPair;
 use buck2_execute::execute::request::CommandExecutionRequest;
 use buck2_execute::execute::request::OutputType;
+use buck2_execute::execute::request::StdRedirects;
 use buck2_execute::execute::result::CommandExecutionReport;
 use buck2_execute::execute::result::CommandExecutionResult;
 use buck2_execute::execute::result::CommandExecutionStatus;
 use buck2_execute::execute::target::CommandExecutionTarget;
+use buck2_execute::materialize::http::HasUrlMirrors;
+use buck2_execute::materialize::http::UrlMirrors;
 use buck2_execute::materialize::materializer::HasMaterializer;
 use buck2_execute::materialize::materializer::Materializer;
+use buck2_execute::materialize::materializer::WriteRequest;
 use buck2_execute::output_size::OutputCountAndBytes;
 use buck2_execute::output_size::OutputSize;
 use buck2_execute::path::buck_out_path::BuckOutPath;
@@ -56,6 +63,8 @@ use itertools::Itertools;
 use crate::actions::artifact::build_artifact::BuildArtifact;
 use crate::actions::execute::error::CommandExecutionErrorMarker;
 use crate::actions::execute::error::ExecuteError;
+use crate::actions::impls::run::determinism::DeterminismReport;
+use crate::actions::impls::run::determinism::HasDeterminismReport;
 use crate::actions::impls::run::knobs::HasRunActionKnobs;
 use crate::actions::impls::run::knobs::RunActionKnobs;
 use crate::actions::ActionExecutable;
@@ -221,6 +230,8 @@ impl HasActionExecutor for DiceComputations {
         let events = self.per_transaction_data().get_dispatcher().dupe();
         let re_client = self.per_transaction_data().get_re_client();
         let run_action_knobs = self.per_transaction_data().get_run_action_knobs();
+        let url_mirrors = self.per_transaction_data().get_url_mirrors();
+        let determinism_report = self.per_transaction_data().get_determinism_report();
 
         Ok(Arc::new(BuckActionExecutor::new(
             CommandExecutor::new(executor, artifact_fs, executor_config.options, platform),
@@ -230,6 +241,8 @@ impl HasActionExecutor for DiceComputations {
             re_client,
             digest_config,
             run_action_knobs,
+            url_mirrors,
+            determinism_report,
         )))
     }
 }
@@ -242,6 +255,8 @@ pub struct BuckActionExecutor {
     re_client: ManagedRemoteExecutionClient,
     digest_config: DigestConfig,
     run_action_knobs: RunActionKnobs,
+    url_mirrors: Arc<UrlMirrors>,
+    determinism_report: Option<Arc<DeterminismReport>>,
 }
 
 impl BuckActionExecutor {
@@ -253,6 +268,8 @@ impl BuckActionExecutor {
         re_client: ManagedRemoteExecutionClient,
         digest_config: DigestConfig,
         run_action_knobs: RunActionKnobs,
+        url_mirrors: Arc<UrlMirrors>,
+        determinism_report: Option<Arc<DeterminismReport>>,
     ) -> Self {
         Self {
             command_executor,
@@ -262,6 +279,8 @@ impl BuckActionExecutor {
             re_client,
             digest_config,
             run_action_knobs,
+            url_mirrors,
+            determinism_report,
         }
     }
 }
@@ -274,6 +293,54 @@ struct BuckActionExecutionContext<'a> {
     command_reports: &'a mut Vec<CommandExecutionReport>,
 }
 
+impl BuckActionExecutionContext<'_> {
+    /// Write the std streams of a command that succeeded to the outputs it redirects them to.
+    async fn write_std_redirects(
+        &self,
+        request: &CommandExecutionRequest,
+        std_streams: &CommandStdStreams,
+    ) -> anyhow::Result<Vec<(BuckOutPath, ArtifactValue)>> {
+        let std_streams = std_streams
+            .clone()
+            .into_bytes()
+            .await
+            .context("Error fetching the std streams of the command")?;
+        let (paths, requests) =
+            std_redirect_write_requests(self.fs(), request.std_redirects(), std_streams);
+
+        let values = self
+            .executor
+            .materializer
+            .declare_write(Box::new(move || Ok(requests)))
+            .await?;
+
+        Ok(paths.into_iter().zip(values).collect())
+    }
+}
+
+/// The writes needed to redirect std streams to `redirects`, along with the outputs they produce.
+fn std_redirect_write_requests(
+    fs: &ArtifactFs,
+    redirects: &StdRedirects,
+    std_streams: StdStreamPair<Vec<u8>>,
+) -> (Vec<BuckOutPath>, Vec<WriteRequest>) {
+    let StdRedirects { stdout, stderr } = redirects;
+
+    let mut paths = Vec::new();
+    let mut requests = Vec::new();
+    for (path, content) in [(stdout, std_streams.stdout), (stderr, std_streams.stderr)] {
+        if let Some(path) = path {
+            requests.push(WriteRequest {
+                path: fs.resolve_build(path),
+                content,
+                is_executable: false,
+            });
+            paths.push(path.dupe());
+        }
+    }
+    (paths, requests)
+}
+
 #[async_trait]
 impl ActionExecutionCtx for BuckActionExecutionContext<'_> {
     fn target(&self) -> CommandExecutionTarget<'_> {
@@ -321,6 +388,18 @@ impl ActionExecutionCtx for BuckActionExecutionContext<'_> {
         self.executor.run_action_knobs
     }
 
+    fn default_timeout(&self) -> Option<Duration> {
+        self.executor.command_executor.options().default_timeout
+    }
+
+    fn url_mirrors(&self) -> &UrlMirrors {
+        &self.executor.url_mirrors
+    }
+
+    fn determinism_report(&self) -> Option<Arc<DeterminismReport>> {
+        self.executor.determinism_report.dupe()
+    }
+
     async fn exec_cmd(
         &mut self,
         request: &CommandExecutionRequest,
@@ -347,24 +426,37 @@ impl ActionExecutionCtx for BuckActionExecutionContext<'_> {
             .await;
 
         // TODO (@torozco): The execution kind should be made to come via the command reports too.
-        let res = match &report.status {
-            CommandExecutionStatus::Success { execution_kind } => Ok((
-                outputs
-                    .into_iter()
-                    .filter_map(|(output, value)| Some((output.into_build_artifact()?.0, value)))
-                    .collect(),
-                ActionExecutionMetadata {
-                    execution_kind: ActionExecutionKind::Command {
-                        kind: execution_kind.clone(),
-                        prefers_local: request.executor_preference().prefers_local(),
-                        requires_local: request.executor_preference().requires_local(),
-                        allows_cache_upload: request.allow_cache_upload(),
-                        did_cache_upload,
-                        eligible_for_full_hybrid,
-                    },
-                    timing: report.timing.into(),
-                },
-            )),
+        let res: anyhow::Result<_> = match &report.status {
+            CommandExecutionStatus::Success { execution_kind } => {
+                try {
+                    let mut outputs: IndexMap<_, _> = outputs
+                        .into_iter()
+                        .filter_map(|(output, value)| {
+                            Some((output.into_build_artifact()?.0, value))
+                        })
+                        .collect();
+                    if !request.std_redirects().is_empty() {
+                        outputs.extend(
+                            self.write_std_redirects(request, &report.std_streams)
+                                .await?,
+                        );
+                    }
+                    (
+                        outputs,
+                        ActionExecutionMetadata {
+                            execution_kind: ActionExecutionKind::Command {
+                                kind: execution_kind.clone(),
+                                prefers_local: request.executor_preference().prefers_local(),
+                                requires_local: request.executor_preference().requires_local(),
+                                allows_cache_upload: request.allow_cache_upload(),
+                                did_cache_upload,
+                                eligible_for_full_hybrid,
+                            },
+                            timing: report.timing.into(),
+                        },
+                    )
+                }
+            }
 
             _ => Err(CommandExecutionErrorMarker.into()),
         };
@@ -520,6 +612,8 @@ mod tests {
     use buck2_common::executor_config::CommandExecutorConfig;
     use buck2_common::executor_config::CommandGenerationOptions;
     use buck2_common::executor_config::PathSeparatorKind;
+    use buck2_common::file_ops::FileMetadata;
+    use buck2_common::file_ops::TrackedFileDigest;
     use buck2_core::buck_path::path::BuckPath;
     use buck2_core::buck_path::resolver::BuckPathResolver;
     use buck2_core::category::Category;
@@ -547,9 +641,18 @@ mod tests {
     use buck2_execute::execute::clean_output_paths::cleanup_path;
     use buck2_execute::execute::command_executor::ActionExecutionTimingData;
     use buck2_execute::execute::command_executor::CommandExecutor;
+    use buck2_execute::execute::kind::CommandExecutionKind;
+    use buck2_execute::execute::manager::CommandExecutionManager;
+    use buck2_execute::execute::manager::CommandExecutionManagerExt;
+    use buck2_execute::execute::output::StdStreamPair;
+    use buck2_execute::execute::prepared::PreparedCommand;
+    use buck2_execute::execute::prepared::PreparedCommandExecutor;
     use buck2_execute::execute::request::CommandExecutionInput;
     use buck2_execute::execute::request::CommandExecutionRequest;
     use buck2_execute::execute::request::OutputType;
+    use buck2_execute::execute::request::StdRedirects;
+    use buck2_execute::execute::result::CommandExecutionResult;
+    use buck2_execute::execute::result::CommandExecutionTimingData;
     use buck2_execute::execute::testing_dry_run::DryRunExecutor;
     use buck2_execute::materialize::nodisk::NoDiskMaterializer;
     use buck2_execute::path::buck_out_path::BuckOutPathResolver;
@@ -564,11 +667,14 @@ mod tests {
     use crate::actions::artifact::testing::BuildArtifactTestingExt;
     use crate::actions::artifact::Artifact;
     use crate::actions::box_slice_set::BoxSliceSet;
+    use crate::actions::execute::action_executor::std_redirect_write_requests;
     use crate::actions::execute::action_executor::ActionExecutionKind;
     use crate::actions::execute::action_executor::ActionExecutionMetadata;
     use crate::actions::execute::action_executor::ActionExecutor;
     use crate::actions::execute::action_executor::ActionOutputs;
     use crate::actions::execute::action_executor::BuckActionExecutor;
+    use crate::actions::impls::run::determinism::rerun_and_compare;
+    use crate::actions::impls::run::determinism::DeterminismReport;
     use crate::actions::key::ActionKey;
     use crate::actions::Action;
     use crate::actions::ActionExecutable;
@@ -614,6 +720,7 @@ mod tests {
                 CommandGenerationOptions {
                     path_separator: PathSeparatorKind::Unix,
                     output_paths_behavior: Default::default(),
+                    default_timeout: None,
                 },
                 Default::default(),
             ),
@@ -623,6 +730,8 @@ mod tests {
             ManagedRemoteExecutionClient::testing_new_dummy(),
             DigestConfig::testing_default(),
             Default::default(),
+            Default::default(),
+            None,
         );
 
         #[derive(Debug, Allocative)]
@@ -770,6 +879,278 @@ mod tests {
         assert_eq!(res.0, ActionOutputs::new(outputs));
     }
 
+    #[tokio::test]
+    async fn test_check_determinism() -> anyhow::Result<()> {
+        /// Produces a different output every time it runs, and records which requests it got.
+        struct NonDeterministicExecutor {
+            requests: Mutex<Vec<(bool, bool)>>,
+        }
+
+        #[async_trait]
+        impl PreparedCommandExecutor for NonDeterministicExecutor {
+            async fn exec_cmd(
+                &self,
+                command: &PreparedCommand<'_, '_>,
+                manager: CommandExecutionManager,
+            ) -> CommandExecutionResult {
+                let manager = manager.claim().await;
+
+                let run = {
+                    let mut requests = self.requests.lock().unwrap();
+                    requests.push((
+                        command.request.skip_cache_lookup(),
+                        command.request.allow_cache_upload(),
+                    ));
+                    requests.len()
+                };
+
+                let value = ArtifactValue::file(FileMetadata {
+                    digest: TrackedFileDigest::from_content(
+                        run.to_string().as_bytes(),
+                        command.digest_config.cas_digest_config(),
+                    ),
+                    is_executable: false,
+                });
+
+                manager.success(
+                    CommandExecutionKind::Local {
+                        digest: command.prepared_action.action.dupe(),
+                        command: Default::default(),
+                        env: Default::default(),
+                        execution_stats: None,
+                    },
+                    command
+                        .request
+                        .outputs()
+                        .map(|o| (o.cloned(), value.dupe()))
+                        .collect(),
+                    Default::default(),
+                    CommandExecutionTimingData::default(),
+                )
+            }
+        }
+
+        #[derive(Debug, Allocative)]
+        struct TestingAction {
+            outputs: BoxSliceSet<BuildArtifact>,
+        }
+
+        #[async_trait]
+        impl Action for TestingAction {
+            fn kind(&self) -> buck2_data::ActionKind {
+                buck2_data::ActionKind::NotSet
+            }
+
+            fn inputs(&self) -> anyhow::Result<Cow<'_, [ArtifactGroup]>> {
+                Ok(Cow::Borrowed(&[]))
+            }
+
+            fn outputs(&self) -> anyhow::Result<Cow<'_, [BuildArtifact]>> {
+                Ok(Cow::Borrowed(self.outputs.as_slice()))
+            }
+
+            fn as_executable(&self) -> ActionExecutable<'_> {
+                ActionExecutable::Pristine(self)
+            }
+
+            fn category(&self) -> &Category {
+                static TEST_CATEGORY: Lazy<Category> =
+                    Lazy::new(|| Category::try_from("testing").unwrap());
+
+                &TEST_CATEGORY
+            }
+        }
+
+        #[async_trait]
+        impl PristineActionExecutable for TestingAction {
+            async fn execute(
+                &self,
+                ctx: &mut dyn ActionExecutionCtx,
+            ) -> anyhow::Result<(ActionOutputs, ActionExecutionMetadata)> {
+                let req = CommandExecutionRequest::new(
+                    vec!["cmd".to_owned()],
+                    Vec::new(),
+                    self.outputs
+                        .iter()
+                        .map(|b| (b.get_path().dupe(), OutputType::File))
+                        .collect(),
+                    SortedVectorMap::new(),
+                )
+                .with_allow_cache_upload(true);
+
+                let (outputs, _meta) = ctx.exec_cmd(&req).await?;
+                let report = ctx.determinism_report().unwrap();
+                let (outputs, meta) = rerun_and_compare(ctx, &report, req, &outputs).await?;
+                Ok((ActionOutputs::new(outputs), meta))
+            }
+        }
+
+        let temp_fs = ProjectRootTemp::new()?;
+        let project_fs = temp_fs.path().dupe();
+        let artifact_fs = ArtifactFs::new(
+            BuckPathResolver::new(CellResolver::of_names_and_paths(
+                CellName::testing_new("root"),
+                &[],
+            )),
+            BuckOutPathResolver::new(ProjectRelativePathBuf::unchecked_new("buck-out/v2".into())),
+            project_fs.dupe(),
+        );
+
+        let command_executor = Arc::new(NonDeterministicExecutor {
+            requests: Mutex::new(Vec::new()),
+        });
+        let determinism_report = Arc::new(DeterminismReport::default());
+
+        let executor = BuckActionExecutor::new(
+            CommandExecutor::new(
+                command_executor.dupe(),
+                artifact_fs,
+                CommandGenerationOptions {
+                    path_separator: PathSeparatorKind::Unix,
+                    output_paths_behavior: Default::default(),
+                    default_timeout: None,
+                },
+                Default::default(),
+            ),
+            Arc::new(DummyBlockingExecutor { fs: project_fs }),
+            Arc::new(NoDiskMaterializer),
+            EventDispatcher::null(),
+            ManagedRemoteExecutionClient::testing_new_dummy(),
+            DigestConfig::testing_default(),
+            Default::default(),
+            Default::default(),
+            Some(determinism_report.dupe()),
+        );
+
+        let label = ConfiguredTargetLabel::testing_new(
+            PackageLabel::new(
+                CellName::testing_new("root"),
+                CellRelativePath::unchecked_new("pkg"),
+            ),
+            TargetName::unchecked_new("foo"),
+            ConfigurationData::testing_new(),
+        );
+        let output = BuildArtifact::testing_new(
+            label.dupe(),
+            ForwardRelativePathBuf::unchecked_new("output".into()),
+            DeferredId::testing_new(0),
+        );
+        let action = RegisteredAction::new(
+            ActionKey::new(DeferredData::testing_new(DeferredKey::Base(
+                BaseDeferredKey::TargetLabel(label),
+                DeferredId::testing_new(0),
+            ))),
+            Box::new(TestingAction {
+                outputs: BoxSliceSet::from(indexset![output.dupe()]),
+            }),
+            CommandExecutorConfig::testing_local(),
+        );
+
+        with_dispatcher_async(
+            EventDispatcher::null(),
+            executor.execute(Default::default(), &action),
+        )
+        .await
+        .0?;
+
+        // The second execution bypasses caches, and is not cached.
+        assert_eq!(
+            *command_executor.requests.lock().unwrap(),
+            vec![(false, true), (true, false)]
+        );
+        assert_eq!(determinism_report.checked(), 1);
+
+        let mut json = Vec::new();
+        determinism_report.write_json(&mut json)?;
+        let json: serde_json::Value = serde_json::from_slice(&json)?;
+        let actions = json["non_deterministic_actions"].as_array().unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0]["category"], "testing");
+        assert_eq!(
+            actions[0]["outputs"][0]["path"],
+            executor
+                .command_executor
+                .fs()
+                .resolve_build(output.get_path())
+                .as_str()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_std_redirect_write_requests() {
+        let temp_fs = ProjectRootTemp::new().unwrap();
+        let artifact_fs = ArtifactFs::new(
+            BuckPathResolver::new(CellResolver::of_names_and_paths(
+                CellName::testing_new("root"),
+                &[],
+            )),
+            BuckOutPathResolver::new(ProjectRelativePathBuf::unchecked_new("buck-out/v2".into())),
+            temp_fs.path().dupe(),
+        );
+
+        let label = ConfiguredTargetLabel::testing_new(
+            PackageLabel::new(
+                CellName::testing_new("root"),
+                CellRelativePath::unchecked_new("pkg"),
+            ),
+            TargetName::unchecked_new("foo"),
+            ConfigurationData::testing_new(),
+        );
+        let output = |name: &str, id| {
+            BuildArtifact::testing_new(
+                label.dupe(),
+                ForwardRelativePathBuf::unchecked_new(name.to_owned()),
+                DeferredId::testing_new(id),
+            )
+            .get_path()
+            .dupe()
+        };
+        let stdout = output("stdout.txt", 0);
+        let stderr = output("stderr.txt", 1);
+
+        let std_streams = || StdStreamPair {
+            stdout: b"out".to_vec(),
+            stderr: b"err".to_vec(),
+        };
+
+        // Only the streams that are redirected are written.
+        let (paths, requests) = std_redirect_write_requests(
+            &artifact_fs,
+            &StdRedirects {
+                stdout: None,
+                stderr: Some(stderr.dupe()),
+            },
+            std_streams(),
+        );
+        assert_eq!(paths, vec![stderr.dupe()]);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, artifact_fs.resolve_build(&stderr));
+        assert_eq!(requests[0].content, b"err");
+        assert!(!requests[0].is_executable);
+
+        let (paths, requests) = std_redirect_write_requests(
+            &artifact_fs,
+            &StdRedirects {
+                stdout: Some(stdout.dupe()),
+                stderr: Some(stderr.dupe()),
+            },
+            std_streams(),
+        );
+        assert_eq!(paths, vec![stdout.dupe(), stderr.dupe()]);
+        assert_eq!(
+            requests
+                .iter()
+                .map(|r| (r.path.clone(), r.content.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (artifact_fs.resolve_build(&stdout), b"out".to_vec()),
+                (artifact_fs.resolve_build(&stderr), b"err".to_vec()),
+            ]
+        );
+    }
+
     #[test]
     fn test_cleanup_path_missing() -> anyhow::Result<()> {
         let fs = ProjectRootTemp::new()?;
@@ -0,0 +1,398 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Reading and writing the archive formats supported by `ctx.actions.create_archive` and
+//! `ctx.actions.extract_archive`.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use allocative::Allocative;
+use anyhow::Context as _;
+use derive_more::Display;
+use dupe::Dupe;
+use thiserror::Error;
+
+/// The modification time of every entry in the archives we create: 1980-01-01T00:00:00Z, which is
+/// the earliest time zip files can represent.
+const ARCHIVE_MTIME: u64 = 315532800;
+
+const FILE_MODE: u32 = 0o644;
+const EXECUTABLE_MODE: u32 = 0o755;
+const DIRECTORY_MODE: u32 = 0o755;
+
+/// The file type bits of a unix mode, and the value they have for symlinks.
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+#[derive(Debug, Error)]
+pub(crate) enum ArchiveError {
+    #[error("Unknown archive format `{0}`, expected one of `zip`, `tar`, `tar.gz` or `tar.zst`")]
+    UnknownFormat(String),
+    #[error("Cannot infer the archive format of `{0}`, pass `format` explicitly")]
+    CannotInferFormat(String),
+    #[error("Entry `{0}` has an unsupported type `{1}`")]
+    UnsupportedEntryType(String, String),
+    #[error("Entry `{0}` does not have a UTF-8 path")]
+    NonUtf8Path(String),
+}
+
+#[derive(Debug, Display, Clone, Copy, Dupe, PartialEq, Eq, Hash, Allocative)]
+pub(crate) enum ArchiveFormat {
+    #[display(fmt = "zip")]
+    Zip,
+    #[display(fmt = "tar")]
+    Tar,
+    #[display(fmt = "tar.gz")]
+    TarGz,
+    #[display(fmt = "tar.zst")]
+    TarZst,
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = ArchiveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zip" => Ok(Self::Zip),
+            "tar" => Ok(Self::Tar),
+            "tar.gz" | "tgz" => Ok(Self::TarGz),
+            "tar.zst" | "tzst" => Ok(Self::TarZst),
+            _ => Err(ArchiveError::UnknownFormat(s.to_owned())),
+        }
+    }
+}
+
+impl ArchiveFormat {
+    /// Use the format passed in explicitly if any, otherwise infer it from the archive's file
+    /// name.
+    pub(crate) fn new(format: Option<&str>, path: &str) -> anyhow::Result<Self> {
+        match format {
+            Some(format) => Ok(format.parse()?),
+            None => Ok(Self::from_file_name(path)
+                .ok_or_else(|| ArchiveError::CannotInferFormat(path.to_owned()))?),
+        }
+    }
+
+    fn from_file_name(path: &str) -> Option<Self> {
+        [
+            (".zip", Self::Zip),
+            (".tar", Self::Tar),
+            (".tar.gz", Self::TarGz),
+            (".tgz", Self::TarGz),
+            (".tar.zst", Self::TarZst),
+            (".tzst", Self::TarZst),
+        ]
+        .into_iter()
+        .find_map(|(ext, format)| path.ends_with(ext).then_some(format))
+    }
+}
+
+/// An entry to add to an archive we create.
+pub(crate) enum ArchiveEntry {
+    Directory,
+    File { src: PathBuf, is_executable: bool },
+}
+
+/// Write `entries`, keyed by their path in the archive, to `out`. The output only depends on the
+/// paths, contents and executable bits of the entries: they are written in order with fixed
+/// mtimes, owners and permissions.
+pub(crate) fn write_archive(
+    format: ArchiveFormat,
+    entries: &BTreeMap<String, ArchiveEntry>,
+    out: File,
+) -> anyhow::Result<()> {
+    match format {
+        ArchiveFormat::Zip => write_zip(entries, out),
+        ArchiveFormat::Tar => {
+            write_tar(entries, out)?;
+            Ok(())
+        }
+        ArchiveFormat::TarGz => {
+            // The gzip header has an mtime too, which this leaves unset.
+            let out = flate2::write::GzEncoder::new(out, flate2::Compression::default());
+            write_tar(entries, out)?.finish()?;
+            Ok(())
+        }
+        ArchiveFormat::TarZst => {
+            let out = zstd::stream::write::Encoder::new(out, 0)?;
+            write_tar(entries, out)?.finish()?;
+            Ok(())
+        }
+    }
+}
+
+fn write_zip<W: Write + Seek>(
+    entries: &BTreeMap<String, ArchiveEntry>,
+    out: W,
+) -> anyhow::Result<()> {
+    let mut zip = zip::ZipWriter::new(out);
+
+    let options = zip::write::FileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .last_modified_time(zip::DateTime::default());
+
+    for (path, entry) in entries {
+        match entry {
+            ArchiveEntry::Directory => {
+                zip.add_directory(path, options.unix_permissions(DIRECTORY_MODE))?;
+            }
+            ArchiveEntry::File { src, is_executable } => {
+                let mode = if *is_executable {
+                    EXECUTABLE_MODE
+                } else {
+                    FILE_MODE
+                };
+                zip.start_file(path, options.unix_permissions(mode))?;
+                io::copy(&mut open(src)?, &mut zip)?;
+            }
+        }
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+fn write_tar<W: Write>(entries: &BTreeMap<String, ArchiveEntry>, out: W) -> anyhow::Result<W> {
+    let mut tar = tar::Builder::new(out);
+
+    for (path, entry) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_mtime(ARCHIVE_MTIME);
+        header.set_uid(0);
+        header.set_gid(0);
+
+        match entry {
+            ArchiveEntry::Directory => {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(DIRECTORY_MODE);
+                header.set_size(0);
+                tar.append_data(&mut header, path, io::empty())?;
+            }
+            ArchiveEntry::File { src, is_executable } => {
+                let file = open(src)?;
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_mode(if *is_executable {
+                    EXECUTABLE_MODE
+                } else {
+                    FILE_MODE
+                });
+                header.set_size(file.metadata()?.len());
+                tar.append_data(&mut header, path, file)?;
+            }
+        }
+    }
+
+    Ok(tar.into_inner()?)
+}
+
+fn open(path: &PathBuf) -> anyhow::Result<File> {
+    File::open(path).with_context(|| format!("Error opening `{}`", path.display()))
+}
+
+/// An entry read from an archive.
+pub(crate) enum ArchiveMember<'a> {
+    Directory,
+    File {
+        is_executable: bool,
+        contents: &'a mut dyn Read,
+    },
+    Symlink {
+        target: String,
+    },
+}
+
+/// Call `f` with the path and contents of every entry of the archive in `file`, in the order they
+/// appear in the archive.
+pub(crate) fn read_archive(
+    format: ArchiveFormat,
+    file: File,
+    f: impl FnMut(&str, ArchiveMember<'_>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    match format {
+        ArchiveFormat::Zip => read_zip(file, f),
+        ArchiveFormat::Tar => read_tar(file, f),
+        ArchiveFormat::TarGz => read_tar(flate2::read::GzDecoder::new(file), f),
+        ArchiveFormat::TarZst => read_tar(zstd::stream::read::Decoder::new(file)?, f),
+    }
+}
+
+fn read_zip<R: Read + Seek>(
+    file: R,
+    mut f: impl FnMut(&str, ArchiveMember<'_>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut zip = zip::ZipArchive::new(file)?;
+
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        let path = entry.name().to_owned();
+        let mode = entry.unix_mode().unwrap_or(FILE_MODE);
+
+        if entry.is_dir() {
+            f(&path, ArchiveMember::Directory)?;
+        } else if mode & S_IFMT == S_IFLNK {
+            let mut target = String::new();
+            entry
+                .read_to_string(&mut target)
+                .with_context(|| format!("Error reading the target of symlink `{}`", path))?;
+            f(&path, ArchiveMember::Symlink { target })?;
+        } else {
+            f(
+                &path,
+                ArchiveMember::File {
+                    is_executable: mode & 0o111 != 0,
+                    contents: &mut entry,
+                },
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn read_tar<R: Read>(
+    file: R,
+    mut f: impl FnMut(&str, ArchiveMember<'_>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut tar = tar::Archive::new(file);
+
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let path = {
+            let path = entry.path()?;
+            path.to_str()
+                .ok_or_else(|| ArchiveError::NonUtf8Path(path.display().to_string()))?
+                .to_owned()
+        };
+
+        let entry_type = entry.header().entry_type();
+        match entry_type {
+            tar::EntryType::Directory => f(&path, ArchiveMember::Directory)?,
+            tar::EntryType::Regular | tar::EntryType::Continuous => {
+                let is_executable = entry.header().mode()? & 0o111 != 0;
+                f(
+                    &path,
+                    ArchiveMember::File {
+                        is_executable,
+                        contents: &mut entry,
+                    },
+                )?
+            }
+            tar::EntryType::Symlink => {
+                let target = entry
+                    .link_name()?
+                    .with_context(|| format!("Symlink `{}` has no target", path))?;
+                let target = target
+                    .to_str()
+                    .ok_or_else(|| ArchiveError::NonUtf8Path(target.display().to_string()))?
+                    .to_owned();
+                f(&path, ArchiveMember::Symlink { target })?
+            }
+            // Metadata about other entries, which carries nothing we need.
+            tar::EntryType::XGlobalHeader | tar::EntryType::XHeader => {}
+            other => {
+                return Err(
+                    ArchiveError::UnsupportedEntryType(path, format!("{:?}", other)).into(),
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() -> anyhow::Result<()> {
+        assert_eq!(ArchiveFormat::new(None, "foo/bar.zip")?, ArchiveFormat::Zip);
+        assert_eq!(ArchiveFormat::new(None, "bar.tar")?, ArchiveFormat::Tar);
+        assert_eq!(ArchiveFormat::new(None, "bar.tgz")?, ArchiveFormat::TarGz);
+        assert_eq!(
+            ArchiveFormat::new(None, "bar.tar.zst")?,
+            ArchiveFormat::TarZst
+        );
+        assert_eq!(
+            ArchiveFormat::new(Some("tar.gz"), "bar.zip")?,
+            ArchiveFormat::TarGz
+        );
+        assert!(ArchiveFormat::new(None, "bar.rar").is_err());
+        assert!(ArchiveFormat::new(Some("rar"), "bar.zip").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_round_trip() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let src = tempdir.path().join("src");
+        std::fs::write(&src, "contents")?;
+
+        let entries = BTreeMap::from([
+            ("dir".to_owned(), ArchiveEntry::Directory),
+            (
+                "dir/file".to_owned(),
+                ArchiveEntry::File {
+                    src: src.clone(),
+                    is_executable: true,
+                },
+            ),
+        ]);
+
+        for format in [
+            ArchiveFormat::Zip,
+            ArchiveFormat::Tar,
+            ArchiveFormat::TarGz,
+            ArchiveFormat::TarZst,
+        ] {
+            let first = tempdir.path().join(format!("first.{}", format));
+            let second = tempdir.path().join(format!("second.{}", format));
+            write_archive(format, &entries, File::create(&first)?)?;
+            write_archive(format, &entries, File::create(&second)?)?;
+            assert_eq!(std::fs::read(&first)?, std::fs::read(&second)?);
+
+            let mut read = Vec::new();
+            read_archive(format, File::open(&first)?, |path, member| {
+                let path = path.trim_end_matches('/').to_owned();
+                match member {
+                    ArchiveMember::Directory => read.push((path, None)),
+                    ArchiveMember::File {
+                        is_executable,
+                        contents,
+                    } => {
+                        let mut s = String::new();
+                        contents.read_to_string(&mut s)?;
+                        read.push((path, Some((s, is_executable))));
+                    }
+                    ArchiveMember::Symlink { .. } => panic!("No symlinks were archived"),
+                }
+                Ok(())
+            })?;
+
+            assert_eq!(
+                read,
+                vec![
+                    ("dir".to_owned(), None),
+                    ("dir/file".to_owned(), Some(("contents".to_owned(), true))),
+                ],
+                "{}",
+                format
+            );
+        }
+
+        Ok(())
+    }
+}
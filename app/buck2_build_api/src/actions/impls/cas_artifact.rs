@@ -21,7 +21,6 @@ use buck2_common::file_ops::TrackedFileDigest;
 use buck2_core::category::Category;
 use buck2_execute::artifact_value::ArtifactValue;
 use buck2_execute::digest::CasDigestToReExt;
-use buck2_execute::directory::re_directory_to_re_tree;
 use buck2_execute::directory::re_tree_to_directory;
 use buck2_execute::directory::ActionDirectoryEntry;
 use buck2_execute::directory::INTERNER;
@@ -208,24 +207,11 @@ impl IncrementalActionExecutable for CasArtifactAction {
                         .with_context(|| {
                             format!("Error downloading tree: {}", self.inner.digest)
                         })?,
-                    DirectoryKind::Directory => {
-                        let re_client = ctx.re_client();
-                        let root_directory = re_client
-                            .download_typed_blobs::<RE::Directory>(
-                                vec![self.inner.digest.to_re()],
-                                self.inner.re_use_case,
-                            )
-                            .await
-                            .map_err(anyhow::Error::from)
-                            .and_then(|dirs| {
-                                dirs.into_iter().next().context("RE response was empty")
-                            })
-                            .with_context(|| {
-                                format!("Error downloading dir: {}", self.inner.digest)
-                            })?;
-                        re_directory_to_re_tree(root_directory, &re_client, self.inner.re_use_case)
-                            .await?
-                    }
+                    DirectoryKind::Directory => ctx
+                        .re_client()
+                        .download_tree(self.inner.digest.to_re(), self.inner.re_use_case)
+                        .await
+                        .with_context(|| format!("Error downloading dir: {}", self.inner.digest))?,
                 };
 
                 // NOTE: We assign a zero timestamp here because we didn't check the nodes in the tree,
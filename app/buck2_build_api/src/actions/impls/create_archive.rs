@@ -0,0 +1,402 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::borrow::Cow;
+use std::collections::btree_map;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::time::Instant;
+
+use allocative::Allocative;
+use anyhow::Context as _;
+use async_trait::async_trait;
+use buck2_common::file_ops::FileDigest;
+use buck2_common::file_ops::FileMetadata;
+use buck2_common::file_ops::TrackedFileDigest;
+use buck2_core::category::Category;
+use buck2_core::fs::fs_util;
+use buck2_core::fs::paths::abs_norm_path::AbsNormPathBuf;
+use buck2_core::fs::paths::file_name::FileName;
+use buck2_core::fs::paths::forward_rel_path::ForwardRelativePath;
+use buck2_core::fs::paths::forward_rel_path::ForwardRelativePathBuf;
+use buck2_execute::artifact::fs::ExecutorFs;
+use buck2_execute::artifact_value::ArtifactValue;
+use buck2_execute::execute::command_executor::ActionExecutionTimingData;
+use dupe::Dupe;
+use faccess::PathExt;
+use gazebo::prelude::*;
+use indexmap::indexmap;
+use indexmap::IndexMap;
+use indexmap::IndexSet;
+use once_cell::sync::Lazy;
+use starlark::values::dict::DictRef;
+use starlark::values::OwnedFrozenValue;
+use starlark::values::Value;
+use starlark::values::ValueError;
+use thiserror::Error;
+
+use crate::actions::artifact::build_artifact::BuildArtifact;
+use crate::actions::box_slice_set::BoxSliceSet;
+use crate::actions::execute::action_executor::ActionExecutionKind;
+use crate::actions::execute::action_executor::ActionExecutionMetadata;
+use crate::actions::execute::action_executor::ActionOutputs;
+use crate::actions::impls::archive::write_archive;
+use crate::actions::impls::archive::ArchiveEntry;
+use crate::actions::impls::archive::ArchiveFormat;
+use crate::actions::Action;
+use crate::actions::ActionExecutable;
+use crate::actions::ActionExecutionCtx;
+use crate::actions::IncrementalActionExecutable;
+use crate::actions::UnregisteredAction;
+use crate::artifact_groups::ArtifactGroup;
+use crate::interpreter::rule_defs::artifact::ValueAsArtifactLike;
+
+#[derive(Debug, Error)]
+enum CreateArchiveError {
+    #[error("Exactly one output file must be specified for a create archive action, got {0}")]
+    WrongNumberOfOutputs(usize),
+    #[error("Only artifact inputs are supported in create archive actions, got {0}")]
+    UnsupportedInput(ArtifactGroup),
+    #[error("`{0}` is a file, so it must be given a non-empty path in the archive")]
+    EmptyPathForFile(String),
+    #[error("More than one source was given for `{0}` in the archive")]
+    ConflictingEntries(String),
+}
+
+#[derive(Allocative)]
+pub(crate) struct UnregisteredCreateArchiveAction {
+    format: ArchiveFormat,
+    srcs: Vec<(ArtifactGroup, ForwardRelativePathBuf)>,
+}
+
+impl UnregisteredCreateArchiveAction {
+    pub(crate) fn new(format: ArchiveFormat, srcs: Value) -> anyhow::Result<Self> {
+        let srcs = DictRef::from_value(srcs)
+            .ok_or_else(|| ValueError::IncorrectParameterTypeNamed("srcs".to_owned()))?;
+
+        let srcs = srcs
+            .iter()
+            .map(|(k, v)| {
+                let path = k
+                    .unpack_str()
+                    .ok_or_else(|| ValueError::IncorrectParameterTypeNamed("srcs".to_owned()))?;
+                let artifact = v
+                    .as_artifact()
+                    .ok_or_else(|| ValueError::IncorrectParameterTypeNamed("srcs".to_owned()))?
+                    .get_bound_artifact()?;
+                Ok((
+                    ArtifactGroup::Artifact(artifact),
+                    ForwardRelativePathBuf::try_from(path.to_owned())?,
+                ))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self { format, srcs })
+    }
+
+    pub(crate) fn inputs(&self) -> IndexSet<ArtifactGroup> {
+        self.srcs.iter().map(|(g, _)| g.dupe()).collect()
+    }
+}
+
+impl UnregisteredAction for UnregisteredCreateArchiveAction {
+    fn register(
+        self: Box<Self>,
+        inputs: IndexSet<ArtifactGroup>,
+        outputs: IndexSet<BuildArtifact>,
+        _starlark_data: Option<OwnedFrozenValue>,
+    ) -> anyhow::Result<Box<dyn Action>> {
+        for input in &inputs {
+            match input {
+                ArtifactGroup::Artifact(..) => {}
+                other => return Err(CreateArchiveError::UnsupportedInput(other.dupe()).into()),
+            }
+        }
+
+        if outputs.len() != 1 {
+            return Err(CreateArchiveError::WrongNumberOfOutputs(outputs.len()).into());
+        }
+
+        Ok(Box::new(CreateArchiveAction {
+            format: self.format,
+            srcs: self.srcs,
+            inputs: BoxSliceSet::from(inputs),
+            outputs: BoxSliceSet::from(outputs),
+        }))
+    }
+}
+
+#[derive(Debug, Allocative)]
+struct CreateArchiveAction {
+    format: ArchiveFormat,
+    srcs: Vec<(ArtifactGroup, ForwardRelativePathBuf)>,
+    inputs: BoxSliceSet<ArtifactGroup>,
+    outputs: BoxSliceSet<BuildArtifact>,
+}
+
+impl CreateArchiveAction {
+    fn output(&self) -> &BuildArtifact {
+        self.outputs
+            .iter()
+            .next()
+            .expect("a single artifact by construction")
+    }
+}
+
+#[async_trait]
+impl Action for CreateArchiveAction {
+    fn kind(&self) -> buck2_data::ActionKind {
+        buck2_data::ActionKind::CreateArchive
+    }
+
+    fn inputs(&self) -> anyhow::Result<Cow<'_, [ArtifactGroup]>> {
+        Ok(Cow::Borrowed(self.inputs.as_slice()))
+    }
+
+    fn outputs(&self) -> anyhow::Result<Cow<'_, [BuildArtifact]>> {
+        Ok(Cow::Borrowed(self.outputs.as_slice()))
+    }
+
+    fn as_executable(&self) -> ActionExecutable<'_> {
+        ActionExecutable::Incremental(self)
+    }
+
+    fn category(&self) -> &Category {
+        static CREATE_ARCHIVE_CATEGORY: Lazy<Category> =
+            Lazy::new(|| Category::try_from("create_archive").unwrap());
+
+        &CREATE_ARCHIVE_CATEGORY
+    }
+
+    fn identifier(&self) -> Option<&str> {
+        Some(self.output().get_path().path().as_str())
+    }
+
+    fn aquery_attributes(&self, _fs: &ExecutorFs) -> IndexMap<String, String> {
+        indexmap! {
+            "format".to_owned() => self.format.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl IncrementalActionExecutable for CreateArchiveAction {
+    async fn execute(
+        &self,
+        ctx: &mut dyn ActionExecutionCtx,
+    ) -> anyhow::Result<(ActionOutputs, ActionExecutionMetadata)> {
+        ctx.cleanup_outputs().await?;
+
+        let artifact_fs = ctx.fs();
+        let project_fs = artifact_fs.fs();
+
+        let mut srcs = Vec::with_capacity(self.srcs.len());
+        for (group, dest) in &self.srcs {
+            let (artifact, _) = ctx
+                .artifact_values(group)
+                .iter()
+                .into_singleton()
+                .context("Input did not dereference to exactly one artifact")?;
+            srcs.push((artifact_fs.resolve(artifact.get_path())?, dest));
+        }
+
+        ctx.materializer()
+            .ensure_materialized(srcs.map(|(src, _)| src.clone()))
+            .await?;
+
+        let output = artifact_fs.resolve_build(self.output().get_path());
+        let digest_config = ctx.digest_config();
+
+        let execution_start = Instant::now();
+
+        let digest = ctx
+            .blocking_executor()
+            .execute_io_inline(|| {
+                let mut entries = BTreeMap::new();
+                for (src, dest) in &srcs {
+                    collect_entries(dest, &mut project_fs.resolve(src), &mut entries)?;
+                }
+
+                let output = project_fs.resolve(&output);
+                if let Some(parent) = output.parent() {
+                    fs_util::create_dir_all(parent)?;
+                }
+                let file = File::create(&output)
+                    .with_context(|| format!("Error creating `{}`", output.display()))?;
+                write_archive(self.format, &entries, file)?;
+
+                Ok(FileDigest::from_file(
+                    &output,
+                    digest_config.cas_digest_config(),
+                )?)
+            })
+            .await?;
+
+        let value = ArtifactValue::file(FileMetadata {
+            digest: TrackedFileDigest::new(digest, digest_config.cas_digest_config()),
+            is_executable: false,
+        });
+
+        ctx.materializer()
+            .declare_existing(vec![(output, value.dupe())])
+            .await?;
+
+        Ok((
+            ActionOutputs::from_single(self.output().get_path().dupe(), value),
+            ActionExecutionMetadata {
+                execution_kind: ActionExecutionKind::Simple,
+                timing: ActionExecutionTimingData {
+                    wall_time: execution_start.elapsed(),
+                },
+            },
+        ))
+    }
+}
+
+/// Add the entries for `src` to `entries`, at `dest` in the archive, as well as its parent
+/// directories. Directories are added recursively, and symlinks are followed, so the archive
+/// contains what they point to.
+fn collect_entries(
+    dest: &ForwardRelativePath,
+    src: &mut AbsNormPathBuf,
+    entries: &mut BTreeMap<String, ArchiveEntry>,
+) -> anyhow::Result<()> {
+    let mut parent = dest.parent();
+    while let Some(dir) = parent {
+        if !dir.is_empty() {
+            insert_entry(entries, dir, ArchiveEntry::Directory)?;
+        }
+        parent = dir.parent();
+    }
+
+    collect_entries_recursive(dest, src, entries)
+}
+
+fn collect_entries_recursive(
+    dest: &ForwardRelativePath,
+    src: &mut AbsNormPathBuf,
+    entries: &mut BTreeMap<String, ArchiveEntry>,
+) -> anyhow::Result<()> {
+    let metadata = fs_util::metadata(&src)?;
+
+    if metadata.is_dir() {
+        if !dest.is_empty() {
+            insert_entry(entries, dest, ArchiveEntry::Directory)?;
+        }
+
+        for file in fs_util::read_dir(&src)? {
+            let file_name = file?.file_name();
+            let file_name = file_name
+                .to_str()
+                .with_context(|| format!("Filename is not UTF-8: {}", src.display()))?;
+            let file_name = FileName::new(file_name)?;
+
+            src.push(file_name);
+            collect_entries_recursive(&dest.join(file_name), src, entries)?;
+            src.pop();
+        }
+    } else {
+        if dest.is_empty() {
+            return Err(CreateArchiveError::EmptyPathForFile(src.display().to_string()).into());
+        }
+
+        insert_entry(
+            entries,
+            dest,
+            ArchiveEntry::File {
+                src: src.as_path().to_path_buf(),
+                is_executable: src.as_path().executable(),
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+fn insert_entry(
+    entries: &mut BTreeMap<String, ArchiveEntry>,
+    path: &ForwardRelativePath,
+    entry: ArchiveEntry,
+) -> anyhow::Result<()> {
+    match entries.entry(path.as_str().to_owned()) {
+        btree_map::Entry::Vacant(v) => {
+            v.insert(entry);
+        }
+        // Sources can share directories.
+        btree_map::Entry::Occupied(o)
+            if matches!(
+                (o.get(), &entry),
+                (ArchiveEntry::Directory, ArchiveEntry::Directory)
+            ) => {}
+        btree_map::Entry::Occupied(o) => {
+            return Err(CreateArchiveError::ConflictingEntries(o.key().clone()).into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_entries() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let root = AbsNormPathBuf::try_from(tempdir.path().to_path_buf())?;
+
+        fs_util::create_dir_all(root.join(ForwardRelativePath::new("dir/sub")?))?;
+        fs_util::write(root.join(ForwardRelativePath::new("dir/sub/b")?), "b")?;
+        fs_util::write(root.join(ForwardRelativePath::new("dir/a")?), "a")?;
+        fs_util::write(root.join(ForwardRelativePath::new("file")?), "file")?;
+
+        let mut entries = BTreeMap::new();
+        collect_entries(
+            ForwardRelativePath::new("out/dir")?,
+            &mut root.join(ForwardRelativePath::new("dir")?),
+            &mut entries,
+        )?;
+        collect_entries(
+            ForwardRelativePath::new("out/file")?,
+            &mut root.join(ForwardRelativePath::new("file")?),
+            &mut entries,
+        )?;
+
+        assert_eq!(
+            entries.keys().map(|k| k.as_str()).collect::<Vec<_>>(),
+            vec![
+                "out",
+                "out/dir",
+                "out/dir/a",
+                "out/dir/sub",
+                "out/dir/sub/b",
+                "out/file"
+            ]
+        );
+
+        assert!(
+            collect_entries(
+                ForwardRelativePath::new("out/file")?,
+                &mut root.join(ForwardRelativePath::new("dir/a")?),
+                &mut entries,
+            )
+            .is_err()
+        );
+
+        assert!(
+            collect_entries(
+                ForwardRelativePath::empty(),
+                &mut root.join(ForwardRelativePath::new("file")?),
+                &mut entries,
+            )
+            .is_err()
+        );
+
+        Ok(())
+    }
+}
@@ -25,6 +25,7 @@ use buck2_execute::materialize::http::http_client;
 use buck2_execute::materialize::http::http_download;
 use buck2_execute::materialize::http::http_head;
 use buck2_execute::materialize::http::Checksum;
+use buck2_execute::materialize::http::HttpClient;
 use buck2_execute::materialize::http::HttpError;
 use buck2_execute::materialize::materializer::HttpDownloadInfo;
 use dupe::Dupe;
@@ -57,7 +58,7 @@ enum DownloadFileActionError {
 #[derive(Debug, Allocative)]
 pub struct UnregisteredDownloadFileAction {
     checksum: Checksum,
-    url: Arc<str>,
+    urls: Box<[Arc<str>]>,
     is_executable: bool,
     is_deferrable: bool,
 }
@@ -65,13 +66,13 @@ pub struct UnregisteredDownloadFileAction {
 impl UnregisteredDownloadFileAction {
     pub fn new(
         checksum: Checksum,
-        url: Arc<str>,
+        urls: Box<[Arc<str>]>,
         is_executable: bool,
         is_deferrable: bool,
     ) -> Self {
         Self {
             checksum,
-            url,
+            urls,
             is_executable,
             is_deferrable,
         }
@@ -129,7 +130,8 @@ impl DownloadFileAction {
     /// Try to produce a FileMetadata without downloading the file.
     async fn declared_metadata(
         &self,
-        client: &reqwest::Client,
+        client: &HttpClient,
+        urls: &[Arc<str>],
         digest_config: DigestConfig,
     ) -> anyhow::Result<Option<FileMetadata>> {
         if !self.inner.is_deferrable {
@@ -150,7 +152,7 @@ impl DownloadFileAction {
             Err(_) => return Ok(None),
         };
 
-        let head = http_head(client, &self.inner.url).await?;
+        let head = http_head(client, urls).await?;
 
         // NOTE: Don't use reqwest's content_length() method here, that always returns zero!
         // https://github.com/seanmonstar/reqwest/issues/843
@@ -170,7 +172,7 @@ impl DownloadFileAction {
             .with_context(|| {
                 format!(
                     "Request to `{}` returned an invalid `{}` header",
-                    self.inner.url,
+                    head.url(),
                     http::header::CONTENT_LENGTH
                 )
             })?;
@@ -231,58 +233,61 @@ impl IncrementalActionExecutable for DownloadFileAction {
         ctx: &mut dyn ActionExecutionCtx,
     ) -> anyhow::Result<(ActionOutputs, ActionExecutionMetadata)> {
         let client = http_client()?;
-
-        let (metadata, execution_kind) =
-            match self.declared_metadata(&client, ctx.digest_config()).await? {
-                Some(metadata) => {
-                    let artifact_fs = ctx.fs();
-                    let rel_path = artifact_fs.resolve_build(self.output().get_path());
-
-                    // Fast path: download later via the materializer.
-                    ctx.materializer()
-                        .declare_http(
-                            rel_path,
-                            HttpDownloadInfo {
-                                url: self.inner.url.dupe(),
-                                checksum: self.inner.checksum.dupe(),
-                                metadata: metadata.dupe(),
-                                owner: ctx.target().owner.dupe(),
-                            },
-                        )
-                        .await?;
-
-                    (metadata, ActionExecutionKind::Deferred)
-                }
-                None => {
-                    ctx.cleanup_outputs().await?;
-
-                    let artifact_fs = ctx.fs();
-                    let project_fs = artifact_fs.fs();
-                    let rel_path = artifact_fs.resolve_build(self.output().get_path());
-
-                    // Slow path: download now.
-                    let digest = http_download(
-                        &client,
-                        project_fs,
-                        ctx.digest_config(),
-                        &rel_path,
-                        &self.inner.url,
-                        &self.inner.checksum,
-                        self.inner.is_executable,
+        let urls = ctx.url_mirrors().expand(&self.inner.urls);
+
+        let (metadata, execution_kind) = match self
+            .declared_metadata(&client, &urls, ctx.digest_config())
+            .await?
+        {
+            Some(metadata) => {
+                let artifact_fs = ctx.fs();
+                let rel_path = artifact_fs.resolve_build(self.output().get_path());
+
+                // Fast path: download later via the materializer.
+                ctx.materializer()
+                    .declare_http(
+                        rel_path,
+                        HttpDownloadInfo {
+                            urls,
+                            checksum: self.inner.checksum.dupe(),
+                            metadata: metadata.dupe(),
+                            owner: ctx.target().owner.dupe(),
+                        },
                     )
                     .await?;
 
-                    let metadata = FileMetadata {
-                        digest,
-                        is_executable: self.inner.is_executable,
-                    };
-                    ctx.materializer()
-                        .declare_existing(vec![(rel_path, ArtifactValue::file(metadata.dupe()))])
-                        .await?;
-
-                    (metadata, ActionExecutionKind::Simple)
-                }
-            };
+                (metadata, ActionExecutionKind::Deferred)
+            }
+            None => {
+                ctx.cleanup_outputs().await?;
+
+                let artifact_fs = ctx.fs();
+                let project_fs = artifact_fs.fs();
+                let rel_path = artifact_fs.resolve_build(self.output().get_path());
+
+                // Slow path: download now.
+                let digest = http_download(
+                    &client,
+                    project_fs,
+                    ctx.digest_config(),
+                    &rel_path,
+                    &urls,
+                    &self.inner.checksum,
+                    self.inner.is_executable,
+                )
+                .await?;
+
+                let metadata = FileMetadata {
+                    digest,
+                    is_executable: self.inner.is_executable,
+                };
+                ctx.materializer()
+                    .declare_existing(vec![(rel_path, ArtifactValue::file(metadata.dupe()))])
+                    .await?;
+
+                (metadata, ActionExecutionKind::Simple)
+            }
+        };
 
         let value = ArtifactValue::file(metadata);
 
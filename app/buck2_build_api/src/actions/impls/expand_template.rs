@@ -0,0 +1,317 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::borrow::Cow;
+use std::slice;
+use std::time::Instant;
+
+use allocative::Allocative;
+use anyhow::Context as _;
+use async_trait::async_trait;
+use buck2_core::category::Category;
+use buck2_core::fs::fs_util;
+use buck2_execute::artifact::fs::ExecutorFs;
+use buck2_execute::execute::command_executor::ActionExecutionTimingData;
+use buck2_execute::materialize::materializer::WriteRequest;
+use dupe::Dupe;
+use gazebo::prelude::*;
+use indexmap::indexmap;
+use indexmap::IndexMap;
+use indexmap::IndexSet;
+use once_cell::sync::Lazy;
+use starlark::values::dict::DictRef;
+use starlark::values::OwnedFrozenValue;
+use thiserror::Error;
+
+use crate::actions::artifact::build_artifact::BuildArtifact;
+use crate::actions::execute::action_executor::ActionExecutionKind;
+use crate::actions::execute::action_executor::ActionExecutionMetadata;
+use crate::actions::execute::action_executor::ActionOutputs;
+use crate::actions::Action;
+use crate::actions::ActionExecutable;
+use crate::actions::ActionExecutionCtx;
+use crate::actions::IncrementalActionExecutable;
+use crate::actions::UnregisteredAction;
+use crate::artifact_groups::ArtifactGroup;
+use crate::interpreter::rule_defs::cmd_args::CommandLineArgLike;
+use crate::interpreter::rule_defs::cmd_args::DefaultCommandLineContext;
+use crate::interpreter::rule_defs::cmd_args::ValueAsCommandLineLike;
+
+#[derive(Debug, Error)]
+enum ExpandTemplateActionValidationError {
+    #[error("ExpandTemplateAction received {0} inputs, expected exactly one")]
+    WrongNumberOfInputs(usize),
+    #[error("Only artifact inputs are supported in expand_template actions, got {0}")]
+    UnsupportedInput(ArtifactGroup),
+    #[error("ExpandTemplateAction received {0} outputs, expected exactly one")]
+    WrongNumberOfOutputs(usize),
+    #[error("Expected a dict of command line values for substitutions, got {0}")]
+    SubstitutionsNotCommandLineValues(String),
+}
+
+#[derive(Allocative)]
+pub struct UnregisteredExpandTemplateAction {
+    is_executable: bool,
+}
+
+impl UnregisteredExpandTemplateAction {
+    pub fn new(is_executable: bool) -> Self {
+        Self { is_executable }
+    }
+}
+
+impl UnregisteredAction for UnregisteredExpandTemplateAction {
+    fn register(
+        self: Box<Self>,
+        inputs: IndexSet<ArtifactGroup>,
+        outputs: IndexSet<BuildArtifact>,
+        starlark_data: Option<OwnedFrozenValue>,
+    ) -> anyhow::Result<Box<dyn Action>> {
+        let substitutions = starlark_data.expect("module data to be present");
+
+        let action = ExpandTemplateAction::new(substitutions, self.is_executable, inputs, outputs)?;
+        Ok(Box::new(action))
+    }
+}
+
+#[derive(Debug, Allocative)]
+struct ExpandTemplateAction {
+    substitutions: OwnedFrozenValue, // Dict<String, StarlarkCommandLine>
+    is_executable: bool,
+    template: ArtifactGroup,
+    output: BuildArtifact,
+}
+
+impl ExpandTemplateAction {
+    fn new(
+        substitutions: OwnedFrozenValue,
+        is_executable: bool,
+        inputs: IndexSet<ArtifactGroup>,
+        outputs: IndexSet<BuildArtifact>,
+    ) -> anyhow::Result<Self> {
+        let template = match inputs.iter().into_singleton() {
+            Some(ArtifactGroup::Artifact(a)) => ArtifactGroup::Artifact(a.dupe()),
+            Some(other) => {
+                return Err(
+                    ExpandTemplateActionValidationError::UnsupportedInput(other.dupe()).into(),
+                );
+            }
+            None => {
+                return Err(
+                    ExpandTemplateActionValidationError::WrongNumberOfInputs(inputs.len()).into(),
+                );
+            }
+        };
+
+        let output = match outputs.iter().into_singleton() {
+            Some(o) => o.dupe(),
+            None => {
+                return Err(ExpandTemplateActionValidationError::WrongNumberOfOutputs(
+                    outputs.len(),
+                )
+                .into());
+            }
+        };
+
+        if Self::unpack(&substitutions).is_none() {
+            return Err(
+                ExpandTemplateActionValidationError::SubstitutionsNotCommandLineValues(
+                    substitutions.value().to_repr(),
+                )
+                .into(),
+            );
+        }
+
+        Ok(ExpandTemplateAction {
+            substitutions,
+            is_executable,
+            template,
+            output,
+        })
+    }
+
+    fn unpack(substitutions: &OwnedFrozenValue) -> Option<Vec<(&str, &dyn CommandLineArgLike)>> {
+        let d = DictRef::from_value(substitutions.value())?;
+        let mut res = Vec::with_capacity(d.len());
+        for (k, v) in d.iter() {
+            res.push((k.unpack_str()?, v.as_command_line()?));
+        }
+        Some(res)
+    }
+
+    /// Render the substitutions, the same way `ctx.actions.run` renders `env`.
+    fn get_substitutions(&self, fs: &ExecutorFs) -> anyhow::Result<Vec<(&str, String)>> {
+        let mut ctx = DefaultCommandLineContext::new(fs);
+
+        Self::unpack(&self.substitutions)
+            .unwrap()
+            .into_try_map(|(k, v)| {
+                let mut cli = Vec::<String>::new();
+                v.add_to_command_line(&mut cli, &mut ctx)?;
+                anyhow::Ok((k, cli.join(" ")))
+            })
+    }
+}
+
+/// Replace every occurrence of the keys of `substitutions` in `template` by their value. This is
+/// done in a single pass, so substituted values are not themselves expanded. When more than one key
+/// matches at the same position, the longest one wins.
+fn expand_template(template: &str, substitutions: &[(&str, String)]) -> String {
+    let mut res = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(c) = rest.chars().next() {
+        let matched = substitutions
+            .iter()
+            .filter(|(k, _)| !k.is_empty() && rest.starts_with(k))
+            .max_by_key(|(k, _)| k.len());
+
+        match matched {
+            Some((k, v)) => {
+                res.push_str(v);
+                rest = &rest[k.len()..];
+            }
+            None => {
+                res.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+
+    res
+}
+
+#[async_trait]
+impl Action for ExpandTemplateAction {
+    fn kind(&self) -> buck2_data::ActionKind {
+        buck2_data::ActionKind::ExpandTemplate
+    }
+
+    fn inputs(&self) -> anyhow::Result<Cow<'_, [ArtifactGroup]>> {
+        Ok(Cow::Borrowed(slice::from_ref(&self.template)))
+    }
+
+    fn outputs(&self) -> anyhow::Result<Cow<'_, [BuildArtifact]>> {
+        Ok(Cow::Borrowed(slice::from_ref(&self.output)))
+    }
+
+    fn as_executable(&self) -> ActionExecutable<'_> {
+        ActionExecutable::Incremental(self)
+    }
+
+    fn category(&self) -> &Category {
+        static EXPAND_TEMPLATE_CATEGORY: Lazy<Category> =
+            Lazy::new(|| Category::try_from("expand_template").unwrap());
+
+        &EXPAND_TEMPLATE_CATEGORY
+    }
+
+    fn identifier(&self) -> Option<&str> {
+        Some(self.output.get_path().path().as_str())
+    }
+
+    fn aquery_attributes(&self, fs: &ExecutorFs) -> IndexMap<String, String> {
+        let mut attrs = indexmap! {
+            "is_executable".to_owned() => self.is_executable.to_string(),
+        };
+        match self.get_substitutions(fs) {
+            Ok(substitutions) => {
+                for (k, v) in substitutions {
+                    attrs.insert(format!("substitution:{}", k), v);
+                }
+            }
+            Err(e) => {
+                attrs.insert(
+                    "substitutions".to_owned(),
+                    format!("ERROR: constructing substitutions ({})", e),
+                );
+            }
+        }
+        attrs
+    }
+}
+
+#[async_trait]
+impl IncrementalActionExecutable for ExpandTemplateAction {
+    async fn execute(
+        &self,
+        ctx: &mut dyn ActionExecutionCtx,
+    ) -> anyhow::Result<(ActionOutputs, ActionExecutionMetadata)> {
+        let fs = ctx.fs();
+
+        let (template, _) = ctx
+            .artifact_values(&self.template)
+            .iter()
+            .into_singleton()
+            .context("Input did not dereference to exactly one artifact")?;
+        let template = fs.resolve(template.get_path())?;
+
+        ctx.materializer()
+            .ensure_materialized(vec![template.clone()])
+            .await?;
+
+        let execution_start = Instant::now();
+
+        let template = ctx
+            .blocking_executor()
+            .execute_io_inline(|| fs_util::read_to_string(fs.fs().resolve(&template)))
+            .await?;
+
+        let value = ctx
+            .materializer()
+            .declare_write(Box::new(|| {
+                let substitutions = self.get_substitutions(&ctx.executor_fs())?;
+                Ok(vec![WriteRequest {
+                    path: fs.resolve_build(self.output.get_path()),
+                    content: expand_template(&template, &substitutions).into_bytes(),
+                    is_executable: self.is_executable,
+                }])
+            }))
+            .await?
+            .into_iter()
+            .next()
+            .context("Write did not execute")?;
+
+        Ok((
+            ActionOutputs::new(indexmap![self.output.get_path().dupe() => value]),
+            ActionExecutionMetadata {
+                execution_kind: ActionExecutionKind::Simple,
+                timing: ActionExecutionTimingData {
+                    wall_time: execution_start.elapsed(),
+                },
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_template() {
+        let substitutions = [
+            ("{NAME}", "world".to_owned()),
+            ("{NAME_UPPER}", "WORLD".to_owned()),
+            ("{SELF}", "{NAME}".to_owned()),
+            ("", "ignored".to_owned()),
+        ];
+
+        assert_eq!(
+            expand_template("hello {NAME}, {NAME_UPPER}!", &substitutions),
+            "hello world, WORLD!"
+        );
+        assert_eq!(expand_template("{SELF}", &substitutions), "{NAME}");
+        assert_eq!(
+            expand_template("ünïcödé {NAME}", &substitutions),
+            "ünïcödé world"
+        );
+        assert_eq!(expand_template("", &substitutions), "");
+    }
+}
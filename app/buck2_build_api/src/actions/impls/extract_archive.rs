@@ -0,0 +1,504 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::fs::File;
+use std::time::Instant;
+
+use allocative::Allocative;
+use anyhow::Context as _;
+use async_trait::async_trait;
+use buck2_common::file_ops::FileDigest;
+use buck2_common::file_ops::FileMetadata;
+use buck2_common::file_ops::TrackedFileDigest;
+use buck2_core::category::Category;
+use buck2_core::directory::DirectoryEntry;
+use buck2_core::fs::fs_util;
+use buck2_core::fs::paths::abs_norm_path::AbsNormPath;
+use buck2_core::fs::paths::forward_rel_path::ForwardRelativePath;
+use buck2_core::fs::paths::forward_rel_path::ForwardRelativePathBuf;
+use buck2_core::fs::paths::RelativePath;
+use buck2_core::fs::project::ProjectRoot;
+use buck2_execute::artifact::fs::ExecutorFs;
+use buck2_execute::artifact_utils::ArtifactValueBuilder;
+use buck2_execute::digest_config::DigestConfig;
+use buck2_execute::directory::new_symlink;
+use buck2_execute::directory::ActionDirectoryBuilder;
+use buck2_execute::directory::ActionDirectoryEntry;
+use buck2_execute::directory::ActionDirectoryMember;
+use buck2_execute::execute::command_executor::ActionExecutionTimingData;
+use dupe::Dupe;
+use gazebo::prelude::*;
+use indexmap::indexmap;
+use indexmap::IndexMap;
+use indexmap::IndexSet;
+use once_cell::sync::Lazy;
+use starlark::values::OwnedFrozenValue;
+use thiserror::Error;
+
+use crate::actions::artifact::build_artifact::BuildArtifact;
+use crate::actions::box_slice_set::BoxSliceSet;
+use crate::actions::execute::action_executor::ActionExecutionKind;
+use crate::actions::execute::action_executor::ActionExecutionMetadata;
+use crate::actions::execute::action_executor::ActionOutputs;
+use crate::actions::impls::archive::read_archive;
+use crate::actions::impls::archive::ArchiveFormat;
+use crate::actions::impls::archive::ArchiveMember;
+use crate::actions::Action;
+use crate::actions::ActionExecutable;
+use crate::actions::ActionExecutionCtx;
+use crate::actions::IncrementalActionExecutable;
+use crate::actions::UnregisteredAction;
+use crate::artifact_groups::ArtifactGroup;
+
+#[derive(Debug, Error)]
+enum ExtractArchiveError {
+    #[error("Exactly one input file must be specified for an extract archive action, got {0}")]
+    WrongNumberOfInputs(usize),
+    #[error(
+        "Exactly one output directory must be specified for an extract archive action, got {0}"
+    )]
+    WrongNumberOfOutputs(usize),
+    #[error("Only artifact inputs are supported in extract archive actions, got {0}")]
+    UnsupportedInput(ArtifactGroup),
+    #[error("Archive contains an invalid path `{0}`")]
+    InvalidPath(String),
+    #[error("Archive entry `{0}` is not under the `strip_prefix` of `{1}`")]
+    NotUnderStripPrefix(String, ForwardRelativePathBuf),
+    #[error("Archive contains more than one entry for `{0}`")]
+    DuplicateEntry(ForwardRelativePathBuf),
+    #[error("Archive entry `{0}` is a symlink to `{1}`, which points outside of the archive")]
+    SymlinkOutsideArchive(ForwardRelativePathBuf, String),
+    #[error("Archive entry `{0}` is inside of `{1}`, which is a symlink")]
+    InsideSymlink(ForwardRelativePathBuf, ForwardRelativePathBuf),
+}
+
+#[derive(Allocative)]
+pub(crate) struct UnregisteredExtractArchiveAction {
+    format: ArchiveFormat,
+    strip_prefix: Option<ForwardRelativePathBuf>,
+}
+
+impl UnregisteredExtractArchiveAction {
+    pub(crate) fn new(format: ArchiveFormat, strip_prefix: Option<ForwardRelativePathBuf>) -> Self {
+        Self {
+            format,
+            strip_prefix,
+        }
+    }
+}
+
+impl UnregisteredAction for UnregisteredExtractArchiveAction {
+    fn register(
+        self: Box<Self>,
+        inputs: IndexSet<ArtifactGroup>,
+        outputs: IndexSet<BuildArtifact>,
+        _starlark_data: Option<OwnedFrozenValue>,
+    ) -> anyhow::Result<Box<dyn Action>> {
+        match inputs.iter().into_singleton() {
+            Some(ArtifactGroup::Artifact(..)) => {}
+            Some(other) => return Err(ExtractArchiveError::UnsupportedInput(other.dupe()).into()),
+            None => return Err(ExtractArchiveError::WrongNumberOfInputs(inputs.len()).into()),
+        }
+
+        if outputs.len() != 1 {
+            return Err(ExtractArchiveError::WrongNumberOfOutputs(outputs.len()).into());
+        }
+
+        Ok(Box::new(ExtractArchiveAction {
+            format: self.format,
+            strip_prefix: self.strip_prefix,
+            inputs: BoxSliceSet::from(inputs),
+            outputs: BoxSliceSet::from(outputs),
+        }))
+    }
+}
+
+#[derive(Debug, Allocative)]
+struct ExtractArchiveAction {
+    format: ArchiveFormat,
+    strip_prefix: Option<ForwardRelativePathBuf>,
+    inputs: BoxSliceSet<ArtifactGroup>,
+    outputs: BoxSliceSet<BuildArtifact>,
+}
+
+impl ExtractArchiveAction {
+    fn input(&self) -> &ArtifactGroup {
+        self.inputs
+            .iter()
+            .next()
+            .expect("a single input by construction")
+    }
+
+    fn output(&self) -> &BuildArtifact {
+        self.outputs
+            .iter()
+            .next()
+            .expect("a single artifact by construction")
+    }
+}
+
+#[async_trait]
+impl Action for ExtractArchiveAction {
+    fn kind(&self) -> buck2_data::ActionKind {
+        buck2_data::ActionKind::ExtractArchive
+    }
+
+    fn inputs(&self) -> anyhow::Result<Cow<'_, [ArtifactGroup]>> {
+        Ok(Cow::Borrowed(self.inputs.as_slice()))
+    }
+
+    fn outputs(&self) -> anyhow::Result<Cow<'_, [BuildArtifact]>> {
+        Ok(Cow::Borrowed(self.outputs.as_slice()))
+    }
+
+    fn as_executable(&self) -> ActionExecutable<'_> {
+        ActionExecutable::Incremental(self)
+    }
+
+    fn category(&self) -> &Category {
+        static EXTRACT_ARCHIVE_CATEGORY: Lazy<Category> =
+            Lazy::new(|| Category::try_from("extract_archive").unwrap());
+
+        &EXTRACT_ARCHIVE_CATEGORY
+    }
+
+    fn identifier(&self) -> Option<&str> {
+        Some(self.output().get_path().path().as_str())
+    }
+
+    fn aquery_attributes(&self, _fs: &ExecutorFs) -> IndexMap<String, String> {
+        indexmap! {
+            "format".to_owned() => self.format.to_string(),
+            "strip_prefix".to_owned() => self.strip_prefix.as_ref().map_or_else(String::new, |p| p.to_string()),
+        }
+    }
+}
+
+#[async_trait]
+impl IncrementalActionExecutable for ExtractArchiveAction {
+    async fn execute(
+        &self,
+        ctx: &mut dyn ActionExecutionCtx,
+    ) -> anyhow::Result<(ActionOutputs, ActionExecutionMetadata)> {
+        ctx.cleanup_outputs().await?;
+
+        let artifact_fs = ctx.fs();
+        let project_fs = artifact_fs.fs();
+
+        let (src, _) = ctx
+            .artifact_values(self.input())
+            .iter()
+            .into_singleton()
+            .context("Input did not dereference to exactly one artifact")?;
+        let src = artifact_fs.resolve(src.get_path())?;
+
+        ctx.materializer()
+            .ensure_materialized(vec![src.clone()])
+            .await?;
+
+        let output = artifact_fs.resolve_build(self.output().get_path());
+        let digest_config = ctx.digest_config();
+
+        let execution_start = Instant::now();
+
+        let value = ctx
+            .blocking_executor()
+            .execute_io_inline(|| {
+                let src = project_fs.resolve(&src);
+                let file = File::open(&src)
+                    .with_context(|| format!("Error opening `{}`", src.display()))?;
+
+                let entries = extract_archive(
+                    self.format,
+                    file,
+                    self.strip_prefix.as_deref(),
+                    &project_fs.resolve(&output),
+                    project_fs,
+                    digest_config,
+                )?;
+
+                let mut builder = ArtifactValueBuilder::new(project_fs, digest_config);
+                for (path, entry) in entries {
+                    builder.add_entry(&output.join(&path), entry)?;
+                }
+                builder.build(&output)
+            })
+            .await?;
+
+        ctx.materializer()
+            .declare_existing(vec![(output, value.dupe())])
+            .await?;
+
+        Ok((
+            ActionOutputs::from_single(self.output().get_path().dupe(), value),
+            ActionExecutionMetadata {
+                execution_kind: ActionExecutionKind::Simple,
+                timing: ActionExecutionTimingData {
+                    wall_time: execution_start.elapsed(),
+                },
+            },
+        ))
+    }
+}
+
+/// Extract the archive in `file` into `dest`, which must exist. Returns the entries to add to the
+/// output, relative to `dest`. Directories that contain other entries are omitted, since they are
+/// implied by their contents.
+fn extract_archive(
+    format: ArchiveFormat,
+    file: File,
+    strip_prefix: Option<&ForwardRelativePath>,
+    dest: &AbsNormPath,
+    project_fs: &ProjectRoot,
+    digest_config: DigestConfig,
+) -> anyhow::Result<BTreeMap<ForwardRelativePathBuf, ActionDirectoryEntry<ActionDirectoryBuilder>>>
+{
+    let mut entries = BTreeMap::new();
+    let mut symlinks: HashSet<ForwardRelativePathBuf> = HashSet::new();
+
+    fs_util::create_dir_all(dest)?;
+
+    read_archive(format, file, |path, member| {
+        // Archives commonly have entries like `./foo`, and an entry for `./` itself.
+        let raw_path = path;
+        let path = path.trim_start_matches("./");
+        if path.is_empty() || path == "." {
+            return Ok(());
+        }
+        let path = ForwardRelativePath::new_trim_trailing_slashes(path)
+            .map_err(|_| ExtractArchiveError::InvalidPath(raw_path.to_owned()))?;
+
+        let path = match strip_prefix {
+            Some(prefix) => match path.strip_prefix(prefix) {
+                Ok(path) => path,
+                // Directories above the prefix are expected, we just skip them.
+                Err(_) if matches!(member, ArchiveMember::Directory) => return Ok(()),
+                Err(_) => {
+                    return Err(ExtractArchiveError::NotUnderStripPrefix(
+                        raw_path.to_owned(),
+                        prefix.to_buf(),
+                    )
+                    .into());
+                }
+            },
+            None => path,
+        };
+
+        if path.is_empty() {
+            return match member {
+                ArchiveMember::Directory => Ok(()),
+                _ => Err(ExtractArchiveError::InvalidPath(raw_path.to_owned()).into()),
+            };
+        }
+
+        // Symlink targets are only checked against the path of the symlink itself, so writing
+        // through one (or through a chain of them) could still end up outside of `dest`.
+        if let Some(symlink) =
+            std::iter::successors(path.parent(), |p| p.parent()).find(|p| symlinks.contains(*p))
+        {
+            return Err(ExtractArchiveError::InsideSymlink(path.to_buf(), symlink.to_buf()).into());
+        }
+
+        let abs_path = dest.join(path);
+        if let Some(parent) = abs_path.parent() {
+            fs_util::create_dir_all(parent)?;
+        }
+
+        let entry = match member {
+            ArchiveMember::Directory => {
+                fs_util::create_dir_all(&abs_path)?;
+                DirectoryEntry::Dir(ActionDirectoryBuilder::empty())
+            }
+            ArchiveMember::File {
+                is_executable,
+                contents,
+            } => {
+                let mut out = File::create(&abs_path)
+                    .with_context(|| format!("Error creating `{}`", abs_path.display()))?;
+                std::io::copy(contents, &mut out)
+                    .with_context(|| format!("Error writing `{}`", abs_path.display()))?;
+                drop(out);
+                if is_executable {
+                    project_fs.set_executable(&*abs_path)?;
+                }
+
+                DirectoryEntry::Leaf(ActionDirectoryMember::File(FileMetadata {
+                    digest: TrackedFileDigest::new(
+                        FileDigest::from_file(&abs_path, digest_config.cas_digest_config())?,
+                        digest_config.cas_digest_config(),
+                    ),
+                    is_executable,
+                }))
+            }
+            ArchiveMember::Symlink { target } => {
+                // Only relative symlinks that stay inside the output are allowed, so that
+                // whatever we extract after them can't be written outside of it.
+                let parent = path.parent().unwrap_or_else(ForwardRelativePath::empty);
+                if target.starts_with('/')
+                    || parent.join_normalized(RelativePath::new(&target)).is_err()
+                {
+                    return Err(
+                        ExtractArchiveError::SymlinkOutsideArchive(path.to_buf(), target).into(),
+                    );
+                }
+
+                fs_util::symlink(&target, &abs_path)?;
+                symlinks.insert(path.to_buf());
+                DirectoryEntry::Leaf(new_symlink(&target)?)
+            }
+        };
+
+        match entries.entry(path.to_buf()) {
+            std::collections::btree_map::Entry::Vacant(v) => {
+                v.insert(entry);
+            }
+            std::collections::btree_map::Entry::Occupied(o)
+                if matches!(
+                    (o.get(), &entry),
+                    (DirectoryEntry::Dir(..), DirectoryEntry::Dir(..))
+                ) => {}
+            std::collections::btree_map::Entry::Occupied(o) => {
+                return Err(ExtractArchiveError::DuplicateEntry(o.key().clone()).into());
+            }
+        }
+
+        Ok(())
+    })?;
+
+    // Adding an empty directory would replace whatever is in it, so only keep the ones that are
+    // actually empty.
+    let ancestors = entries
+        .keys()
+        .flat_map(|path| std::iter::successors(path.parent(), |p| p.parent()))
+        .map(|p| p.to_buf())
+        .collect::<HashSet<_>>();
+    entries.retain(|path, entry| match entry {
+        DirectoryEntry::Dir(..) => !ancestors.contains(path),
+        DirectoryEntry::Leaf(..) => true,
+    });
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_core::fs::paths::abs_norm_path::AbsNormPathBuf;
+
+    use super::*;
+    use crate::actions::impls::archive::write_archive;
+    use crate::actions::impls::archive::ArchiveEntry;
+
+    fn extract(
+        root: &AbsNormPath,
+        entries: &BTreeMap<String, ArchiveEntry>,
+        strip_prefix: Option<&str>,
+    ) -> anyhow::Result<Vec<String>> {
+        let archive = root.join(ForwardRelativePath::new("archive.tar")?);
+        write_archive(ArchiveFormat::Tar, entries, File::create(&archive)?)?;
+
+        let project_fs = ProjectRoot::new_unchecked(root.to_buf());
+        let strip_prefix = strip_prefix.map(ForwardRelativePath::new).transpose()?;
+        let extracted = extract_archive(
+            ArchiveFormat::Tar,
+            File::open(&archive)?,
+            strip_prefix,
+            &root.join(ForwardRelativePath::new("out")?),
+            &project_fs,
+            DigestConfig::testing_default(),
+        )?;
+        Ok(extracted.keys().map(|p| p.to_string()).collect())
+    }
+
+    #[test]
+    fn test_extract_archive() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let root = AbsNormPathBuf::try_from(tempdir.path().to_path_buf())?;
+        let src = root.join(ForwardRelativePath::new("src")?);
+        fs_util::write(&src, "contents")?;
+
+        let file = || ArchiveEntry::File {
+            src: src.as_path().to_path_buf(),
+            is_executable: false,
+        };
+        let entries = BTreeMap::from([
+            ("top".to_owned(), ArchiveEntry::Directory),
+            ("top/a".to_owned(), file()),
+            ("top/empty".to_owned(), ArchiveEntry::Directory),
+            ("top/sub".to_owned(), ArchiveEntry::Directory),
+            ("top/sub/b".to_owned(), file()),
+        ]);
+
+        assert_eq!(
+            extract(&root, &entries, None)?,
+            vec!["top/a", "top/empty", "top/sub/b"]
+        );
+        assert_eq!(
+            fs_util::read_to_string(root.join(ForwardRelativePath::new("out/top/sub/b")?))?,
+            "contents"
+        );
+
+        fs_util::remove_all(root.join(ForwardRelativePath::new("out")?))?;
+        assert_eq!(
+            extract(&root, &entries, Some("top"))?,
+            vec!["a", "empty", "sub/b"]
+        );
+
+        fs_util::remove_all(root.join(ForwardRelativePath::new("out")?))?;
+        assert!(extract(&root, &entries, Some("top/sub")).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_archive_chained_symlinks() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let root = AbsNormPathBuf::try_from(tempdir.path().to_path_buf())?;
+        let dest = root.join(ForwardRelativePath::new("a/b/out")?);
+        let archive = root.join(ForwardRelativePath::new("archive.tar")?);
+
+        // Each symlink stays inside the output on its own, but following the chain ends up two
+        // levels above it.
+        let mut tar = tar::Builder::new(File::create(&archive)?);
+        for (path, target) in [("x/y", ".."), ("x/y/z", "..")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_size(0);
+            header.set_link_name(target)?;
+            tar.append_data(&mut header, path, std::io::empty())?;
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(4);
+        tar.append_data(&mut header, "x/y/z/evil", &b"evil"[..])?;
+        tar.into_inner()?;
+
+        let err = extract_archive(
+            ArchiveFormat::Tar,
+            File::open(&archive)?,
+            None,
+            &dest,
+            &ProjectRoot::new_unchecked(root.clone()),
+            DigestConfig::testing_default(),
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("which is a symlink"),
+            "unexpected error: {:#}",
+            err
+        );
+        assert!(!fs_util::try_exists(
+            root.join(ForwardRelativePath::new("a/evil")?)
+        )?);
+
+        Ok(())
+    }
+}
@@ -7,9 +7,13 @@
  * of this source tree.
  */
 
+pub(crate) mod archive;
 pub mod cas_artifact;
 pub mod copy;
+pub mod create_archive;
 pub mod download_file;
+pub mod expand_template;
+pub mod extract_archive;
 pub mod run;
 pub mod symlinked_dir;
 pub mod write;
@@ -0,0 +1,307 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::borrow::Cow;
+use std::path::Path;
+use std::str::FromStr;
+
+use allocative::Allocative;
+use anyhow::Context as _;
+use buck2_core::fs::paths::abs_norm_path::AbsNormPath;
+use buck2_core::fs::project_rel_path::ProjectRelativePath;
+use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
+use derive_more::Display;
+use dupe::Dupe;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+enum DepFileFormatError {
+    #[error("Unknown dep file format `{0}`, expected one of `paths`, `makefile` or `json`")]
+    UnknownFormat(String),
+    #[error("Expected a rule of the form `target: dependencies`, got `{0}`")]
+    InvalidMakefileRule(String),
+    #[error("Path `{0}` is outside the project root")]
+    OutsideProject(String),
+}
+
+/// The format of a dep file, i.e. how we find the list of paths it contains.
+#[derive(Copy, Clone, Dupe, Debug, Display, Hash, PartialEq, Eq, Allocative)]
+pub enum DepFileFormat {
+    /// One path relative to the project root per line.
+    #[display(fmt = "paths")]
+    Paths,
+    /// A Makefile, as produced by e.g. `gcc -MD` or `clang -MD`. Only the dependencies of its rules
+    /// are used.
+    #[display(fmt = "makefile")]
+    Makefile,
+    /// A JSON list of paths.
+    #[display(fmt = "json")]
+    Json,
+}
+
+impl Default for DepFileFormat {
+    fn default() -> Self {
+        Self::Paths
+    }
+}
+
+impl FromStr for DepFileFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "paths" => Ok(Self::Paths),
+            "makefile" => Ok(Self::Makefile),
+            "json" => Ok(Self::Json),
+            _ => Err(DepFileFormatError::UnknownFormat(s.to_owned()).into()),
+        }
+    }
+}
+
+impl DepFileFormat {
+    /// Whether the paths listed in dep files of this format must be inputs of the action. The
+    /// `paths` format predates this check, and is not subject to it.
+    pub fn checks_inputs(self) -> bool {
+        match self {
+            Self::Paths => false,
+            Self::Makefile | Self::Json => true,
+        }
+    }
+
+    /// Parse the contents of a dep file in this format. `project_root` is used to relativize
+    /// absolute paths. Absolute paths outside of the project are skipped, since Buck2 can't track
+    /// them anyway (this is typically the case for system headers).
+    pub fn parse(
+        self,
+        contents: &str,
+        project_root: &AbsNormPath,
+    ) -> anyhow::Result<Vec<ProjectRelativePathBuf>> {
+        match self {
+            Self::Paths => contents
+                .split('\n')
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(|line| Ok(ProjectRelativePath::new(line)?.to_buf()))
+                .collect(),
+            Self::Makefile => normalize_all(parse_makefile(contents)?, project_root),
+            Self::Json => normalize_all(
+                serde_json::from_str::<Vec<String>>(contents)
+                    .context("Expected a JSON list of paths")?,
+                project_root,
+            ),
+        }
+    }
+}
+
+/// Return the dependencies of all the rules in a Makefile. We support line continuations, as well
+/// as the escaping GCC and Clang do (`\ ` for spaces, `\#` for `#` and `$$` for `$`).
+fn parse_makefile(contents: &str) -> anyhow::Result<Vec<String>> {
+    let mut deps = Vec::new();
+    let mut rule = String::new();
+
+    for line in contents.lines() {
+        match line.strip_suffix('\\') {
+            Some(line) => {
+                rule.push_str(line);
+                rule.push(' ');
+            }
+            None => {
+                rule.push_str(line);
+                parse_makefile_rule(&rule, &mut deps)?;
+                rule.clear();
+            }
+        }
+    }
+    parse_makefile_rule(&rule, &mut deps)?;
+
+    Ok(deps)
+}
+
+fn parse_makefile_rule(rule: &str, deps: &mut Vec<String>) -> anyhow::Result<()> {
+    if rule.trim().is_empty() || rule.trim_start().starts_with('#') {
+        return Ok(());
+    }
+
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut seen_targets = false;
+    let mut chars = rule.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), Some(' ' | '#')) => {
+                token.push(chars.next().unwrap());
+            }
+            '$' if chars.peek() == Some(&'$') => {
+                token.push(chars.next().unwrap());
+            }
+            ':' if !seen_targets && chars.peek().map_or(true, |c| c.is_whitespace()) => {
+                // Everything so far was a target, which we don't care about.
+                seen_targets = true;
+                token.clear();
+                tokens.clear();
+            }
+            c if c.is_whitespace() => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+
+    if !seen_targets {
+        return Err(DepFileFormatError::InvalidMakefileRule(rule.trim().to_owned()).into());
+    }
+
+    deps.extend(tokens);
+    Ok(())
+}
+
+fn normalize_all(
+    paths: Vec<String>,
+    project_root: &AbsNormPath,
+) -> anyhow::Result<Vec<ProjectRelativePathBuf>> {
+    let mut res = Vec::with_capacity(paths.len());
+    for path in paths {
+        if let Some(path) = normalize(&path, project_root)? {
+            res.push(path);
+        }
+    }
+    Ok(res)
+}
+
+/// Turn a path as reported by a tool into a path relative to the project root, resolving `.` and
+/// `..` lexically. Returns None for absolute paths outside of the project.
+fn normalize(
+    path: &str,
+    project_root: &AbsNormPath,
+) -> anyhow::Result<Option<ProjectRelativePathBuf>> {
+    let path = if cfg!(windows) {
+        Cow::Owned(path.replace('\\', "/"))
+    } else {
+        Cow::Borrowed(path)
+    };
+
+    let relative = if Path::new(&*path).is_absolute() {
+        match Path::new(&*path).strip_prefix(project_root.as_path()) {
+            Ok(relative) => relative.to_str().context("Path is not UTF-8")?,
+            Err(_) => return Ok(None),
+        }
+    } else {
+        &*path
+    };
+
+    let separators: &[char] = if cfg!(windows) { &['/', '\\'] } else { &['/'] };
+
+    let mut components = Vec::new();
+    for component in relative.split(separators) {
+        match component {
+            "" | "." => {}
+            ".." => {
+                if components.pop().is_none() {
+                    return Err(DepFileFormatError::OutsideProject((*path).to_owned()).into());
+                }
+            }
+            component => components.push(component),
+        }
+    }
+
+    if components.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        ProjectRelativePath::new(&components.join("/"))
+            .with_context(|| format!("Invalid path `{}`", path))?
+            .to_buf(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_core::fs::paths::abs_norm_path::AbsNormPathBuf;
+
+    use super::*;
+
+    fn project_root() -> AbsNormPathBuf {
+        if cfg!(windows) {
+            AbsNormPathBuf::from("C:\\project".to_owned()).unwrap()
+        } else {
+            AbsNormPathBuf::from("/project".to_owned()).unwrap()
+        }
+    }
+
+    fn parse(format: DepFileFormat, contents: &str) -> anyhow::Result<Vec<String>> {
+        Ok(format
+            .parse(contents, &project_root())?
+            .into_iter()
+            .map(|p| p.to_string())
+            .collect())
+    }
+
+    #[test]
+    fn test_parse_paths() -> anyhow::Result<()> {
+        assert_eq!(
+            parse(DepFileFormat::Paths, "foo/bar.h\n\n  foo/baz.h  \n")?,
+            vec!["foo/bar.h", "foo/baz.h"]
+        );
+        assert!(parse(DepFileFormat::Paths, "foo/../bar.h").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_makefile() -> anyhow::Result<()> {
+        let root = if cfg!(windows) {
+            "C:/project"
+        } else {
+            "/project"
+        };
+        let contents = format!(
+            "buck-out/v2/gen/foo.o: foo/foo.c foo/with\\ space.h \\\n  ./foo/../bar/bar.h {root}/baz.h \\\n  /usr/include/stdio.h foo/cost$$.h foo/hash\\#.h\n\n# A comment\nfoo/foo.h:\n"
+        );
+
+        assert_eq!(
+            parse(DepFileFormat::Makefile, &contents)?,
+            vec![
+                "foo/foo.c",
+                "foo/with space.h",
+                "bar/bar.h",
+                "baz.h",
+                "foo/cost$.h",
+                "foo/hash#.h",
+            ]
+        );
+
+        assert!(parse(DepFileFormat::Makefile, "foo.o foo.c").is_err());
+        assert!(parse(DepFileFormat::Makefile, "foo.o: ../foo.c").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_json() -> anyhow::Result<()> {
+        assert_eq!(
+            parse(DepFileFormat::Json, r#"["foo/bar.h", "foo/./baz.h"]"#)?,
+            vec!["foo/bar.h", "foo/baz.h"]
+        );
+        assert!(parse(DepFileFormat::Json, r#"{"foo": "bar"}"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_checks_inputs() {
+        assert!(!DepFileFormat::Paths.checks_inputs());
+        assert!(DepFileFormat::Makefile.checks_inputs());
+        assert!(DepFileFormat::Json.checks_inputs());
+    }
+}
@@ -17,11 +17,13 @@ use allocative::Allocative;
 use anyhow::Context as _;
 use buck2_common::file_ops::TrackedFileDigest;
 use buck2_core::category::Category;
+use buck2_core::directory::find_prefix_fingerprinted;
 use buck2_core::directory::DirectorySelector;
 use buck2_core::directory::FingerprintedDirectory;
 use buck2_core::env_helper::EnvHelper;
 use buck2_core::fs::fs_util;
-use buck2_core::fs::project_rel_path::ProjectRelativePath;
+use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
+use buck2_core::quiet_soft_error;
 use buck2_core::soft_error;
 use buck2_execute::artifact::fs::ArtifactFs;
 use buck2_execute::base_deferred_key_dyn::BaseDeferredKeyDyn;
@@ -38,16 +40,23 @@ use dashmap::DashMap;
 use derive_more::Display;
 use dupe::Dupe;
 use futures::StreamExt;
+use indexmap::IndexMap;
 use once_cell::sync::Lazy;
 use parking_lot::MappedMutexGuard;
 use parking_lot::Mutex;
 use parking_lot::MutexGuard;
+use parking_lot::RwLock;
 use thiserror::Error;
 use tracing::instrument;
 
 use crate::actions::artifact::Artifact;
 use crate::actions::artifact::OutputArtifact;
 use crate::actions::execute::action_executor::ActionOutputs;
+use crate::actions::impls::run::dep_file_format::DepFileFormat;
+use crate::actions::impls::run::dep_files_sqlite::DepFilesSqliteDb;
+use crate::actions::impls::run::dep_files_sqlite::PersistedDepFileState;
+use crate::actions::impls::run::dep_files_sqlite::PersistedFingerprints;
+use crate::actions::impls::run::dep_files_sqlite::PersistedOutput;
 use crate::actions::impls::run::expanded_command_line::ExpandedCommandLineDigest;
 use crate::actions::ActionExecutionCtx;
 use crate::actions::BuildArtifact;
@@ -58,6 +67,10 @@ use crate::interpreter::rule_defs::cmd_args::CommandLineArtifactVisitor;
 #[allocative::root]
 static DEP_FILES: Lazy<DashMap<DepFilesKey, Arc<DepFileState>>> = Lazy::new(DashMap::new);
 
+/// Where dep file states are persisted so they survive daemon restarts, if enabled (via
+/// `buck2.sqlite_dep_files_state`). States that aren't in `DEP_FILES` are looked up here.
+static DEP_FILES_DB: Lazy<RwLock<Option<Arc<DepFilesSqliteDb>>>> = Lazy::new(|| RwLock::new(None));
+
 /// When this is set, we retain directories after fingerprintig, so that we can output them later
 /// for debugging via `buck2 audit dep-files`.
 static KEEP_DIRECTORIES: EnvHelper<bool> = EnvHelper::new("BUCK2_KEEP_DEP_FILE_DIRECTORIES");
@@ -68,12 +81,30 @@ pub fn flush_dep_files() {
     DEP_FILES.clear();
 }
 
+/// Forget about all the dep files persisted on disk. Unlike `flush_dep_files`, this isn't needed
+/// when files might have changed, since persisted dep files are always verified before use.
+pub fn flush_persisted_dep_files() -> anyhow::Result<()> {
+    match dep_files_db() {
+        Some(db) => db.clear(),
+        None => Ok(()),
+    }
+}
+
+/// Set the db dep file states are persisted to. This is called once on daemon startup.
+pub fn set_dep_files_db(db: Option<DepFilesSqliteDb>) {
+    *DEP_FILES_DB.write() = db.map(Arc::new);
+}
+
+fn dep_files_db() -> Option<Arc<DepFilesSqliteDb>> {
+    DEP_FILES_DB.read().as_ref().map(|db| db.dupe())
+}
+
 pub fn get_dep_files(key: &DepFilesKey) -> Option<Arc<DepFileState>> {
     DEP_FILES.get(key).map(|s| s.dupe())
 }
 
 /// A key used to associate a RunAction with a possible previous dep file.
-#[derive(Eq, PartialEq, Hash, Display, Allocative)]
+#[derive(Clone, Eq, PartialEq, Hash, Display, Allocative)]
 #[display(
     fmt = "{} {} {}",
     owner,
@@ -186,6 +217,19 @@ impl DepFileState {
         }
     }
 
+    /// Check that the dep files, as returned by read_dep_files, only list inputs of the action
+    /// that produced them. This must be called before the signatures are computed.
+    fn check_inputs(&self, dep_files: &ConcreteDepFiles) -> anyhow::Result<()> {
+        match &*self.input_signatures.lock() {
+            DepFileStateInputSignatures::Deferred(directories) => dep_files.check_inputs(
+                directories
+                    .as_ref()
+                    .expect("Poisoned DepFileStateInputSignatures"),
+            ),
+            DepFileStateInputSignatures::Computed(..) => Ok(()),
+        }
+    }
+
     /// Compute the signature for this DepFileState, having provided the dep files from
     /// read_dep_files.
     pub fn locked_compute_fingerprints<'a>(
@@ -221,13 +265,92 @@ impl DepFileState {
             DepFileStateInputSignatures::Deferred(..) => unreachable!(),
         })
     }
+
+    /// Produce the representation of this state that we persist. Returns None if this state
+    /// cannot be persisted, i.e. its signatures haven't been computed, or it has outputs that
+    /// aren't files or symlinks.
+    fn to_persisted(&self, fs: &ArtifactFs) -> anyhow::Result<Option<PersistedDepFileState>> {
+        let fingerprints = match &*self.input_signatures.lock() {
+            DepFileStateInputSignatures::Computed(StoredFingerprints::Digests(fingerprints)) => {
+                PersistedFingerprints::new(fingerprints)
+            }
+            DepFileStateInputSignatures::Computed(StoredFingerprints::Dirs(dirs)) => {
+                PersistedFingerprints::new(&dirs.as_fingerprints())
+            }
+            DepFileStateInputSignatures::Deferred(..) => return Ok(None),
+        };
+
+        let mut outputs = Vec::new();
+        for (path, value) in self.result.iter() {
+            let output = match PersistedOutput::new(value) {
+                Some(output) => output,
+                None => return Ok(None),
+            };
+            outputs.push((
+                fs.buck_out_path_resolver().resolve_gen(path).to_string(),
+                output,
+            ));
+        }
+
+        Ok(Some(PersistedDepFileState {
+            cli_digest: self.cli_digest.to_hex(),
+            dep_files: self.declared_dep_files.persisted(fs)?,
+            fingerprints,
+            outputs,
+        }))
+    }
+
+    /// Rebuild a state from its persisted representation, for an action that currently declares
+    /// `declared_outputs` and `declared_dep_files`. Returns None if the action changed in a way
+    /// that means the state cannot be reused.
+    fn from_persisted(
+        persisted: PersistedDepFileState,
+        cli_digest: &ExpandedCommandLineDigest,
+        declared_outputs: &[BuildArtifact],
+        declared_dep_files: &DeclaredDepFiles,
+        fs: &ArtifactFs,
+        digest_config: DigestConfig,
+    ) -> anyhow::Result<Option<Self>> {
+        if persisted.cli_digest != cli_digest.to_hex()
+            || persisted.dep_files != declared_dep_files.persisted(fs)?
+        {
+            return Ok(None);
+        }
+
+        let persisted_outputs = persisted
+            .outputs
+            .iter()
+            .map(|(path, output)| (path.as_str(), output))
+            .collect::<HashMap<_, _>>();
+
+        let mut outputs = IndexMap::with_capacity(declared_outputs.len());
+        for output in declared_outputs {
+            let path = fs.buck_out_path_resolver().resolve_gen(output.get_path());
+            match persisted_outputs.get(path.as_str()) {
+                Some(value) => {
+                    outputs.insert(output.get_path().dupe(), value.to_value(digest_config)?);
+                }
+                None => return Ok(None),
+            }
+        }
+
+        Ok(Some(Self {
+            cli_digest: cli_digest.clone(),
+            input_signatures: Mutex::new(DepFileStateInputSignatures::Computed(
+                StoredFingerprints::Digests(persisted.fingerprints.to_fingerprints(digest_config)?),
+            )),
+            declared_dep_files: declared_dep_files.clone(),
+            result: ActionOutputs::new(outputs),
+        }))
+    }
 }
 
-/// The set of dep files declared by a RunAction, matching tags to their labels. We enforce at
-/// creation time that tags and lables are both unique.
+/// The set of dep files declared by a RunAction, matching tags to their labels and formats. We
+/// enforce at creation time that tags and lables are both unique.
 #[derive(Debug, Allocative)]
 pub struct RunActionDepFiles {
     pub labels: HashMap<ArtifactTag, Arc<str>>,
+    pub formats: HashMap<ArtifactTag, DepFileFormat>,
 }
 
 impl Display for RunActionDepFiles {
@@ -245,6 +368,7 @@ impl RunActionDepFiles {
     pub fn new() -> Self {
         Self {
             labels: HashMap::new(),
+            formats: HashMap::new(),
         }
     }
 }
@@ -261,7 +385,14 @@ pub async fn match_or_clear_dep_file(
 ) -> anyhow::Result<Option<ActionOutputs>> {
     let previous_state = match get_dep_files(key) {
         Some(d) => d.dupe(),
-        None => return Ok(None),
+        None => {
+            match restore_dep_file_state(key, cli_digest, declared_outputs, declared_dep_files, ctx)
+                .await?
+            {
+                Some(d) => d,
+                None => return Ok(None),
+            }
+        }
     };
 
     // We first need to check if the same dep files existed before or not. If not, then we
@@ -342,10 +473,119 @@ pub async fn match_or_clear_dep_file(
     tracing::trace!("Dep files are a miss");
 
     DEP_FILES.remove(key);
+    if let Some(db) = dep_files_db() {
+        delete_persisted_dep_file_state(&db, &key.to_string(), ctx).await;
+    }
 
     Ok(None)
 }
 
+/// Look for a state for this key in the dep files persisted by a previous daemon. If there is one
+/// and it applies to this action, it's returned and added to `DEP_FILES`.
+async fn restore_dep_file_state(
+    key: &DepFilesKey,
+    cli_digest: &ExpandedCommandLineDigest,
+    declared_outputs: &[BuildArtifact],
+    declared_dep_files: &DeclaredDepFiles,
+    ctx: &dyn ActionExecutionCtx,
+) -> anyhow::Result<Option<Arc<DepFileState>>> {
+    let db = match dep_files_db() {
+        Some(db) => db,
+        None => return Ok(None),
+    };
+
+    let key_str = key.to_string();
+    let persisted = ctx
+        .blocking_executor()
+        .execute_io_inline(|| db.get(&key_str))
+        .await;
+
+    let persisted = match persisted {
+        Ok(Some(persisted)) => persisted,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            quiet_soft_error!("dep_files_sqlite_error", e).unwrap();
+            return Ok(None);
+        }
+    };
+
+    let state = match DepFileState::from_persisted(
+        persisted,
+        cli_digest,
+        declared_outputs,
+        declared_dep_files,
+        ctx.fs(),
+        ctx.digest_config(),
+    )? {
+        Some(state) => state,
+        None => {
+            delete_persisted_dep_file_state(&db, &key_str, ctx).await;
+            return Ok(None);
+        }
+    };
+
+    // Since the signatures are already computed, `read_dep_files` won't materialize the dep
+    // files, so we do it here.
+    match state
+        .declared_dep_files
+        .materialize(ctx.fs(), ctx.materializer())
+        .await
+    {
+        Ok(()) => {}
+        Err(MaterializeDepFilesError::NotFound) => {
+            delete_persisted_dep_file_state(&db, &key_str, ctx).await;
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    let state = Arc::new(state);
+    DEP_FILES.insert(key.clone(), state.dupe());
+    Ok(Some(state))
+}
+
+/// Write this state to the dep files db, or remove the previous state for this key if this one
+/// can't be persisted. Failing to persist isn't fatal: it just means the action will re-run after
+/// a restart.
+async fn persist_dep_file_state(
+    db: &DepFilesSqliteDb,
+    key: &DepFilesKey,
+    state: &DepFileState,
+    ctx: &dyn ActionExecutionCtx,
+) {
+    let res: anyhow::Result<()> = try {
+        let persisted = state.to_persisted(ctx.fs())?;
+        let key = key.to_string();
+        ctx.blocking_executor()
+            .execute_io_inline(|| match persisted {
+                Some(persisted) => db.insert(key, &persisted),
+                None => db.delete(&key),
+            })
+            .await?
+    };
+
+    if let Err(e) = res {
+        quiet_soft_error!("dep_files_sqlite_error", e).unwrap();
+    }
+}
+
+/// Remove a persisted state that can't be reused, so that it isn't restored and checked again
+/// after the next restart. Like persisting, this isn't fatal if it fails.
+async fn delete_persisted_dep_file_state(
+    db: &DepFilesSqliteDb,
+    key: &str,
+    ctx: &dyn ActionExecutionCtx,
+) {
+    let res = ctx
+        .blocking_executor()
+        .execute_io_inline(|| db.delete(key))
+        .await;
+
+    if let Err(e) = res {
+        quiet_soft_error!("dep_files_sqlite_error", e).unwrap();
+    }
+}
+
 /// If an action is unchanged but now requires a different set of outputs, that's not a cache hit
 /// because we need to rehash the outputs. Having to re-run the action isn't the best, but it's
 /// probably infrequent enough that we seem unlikely to care.
@@ -382,18 +622,37 @@ pub async fn populate_dep_files(
         result: result.dupe(),
     };
 
-    if has_no_dep_files || ctx.run_action_knobs().eager_dep_files {
+    let db = dep_files_db();
+
+    // Dep files in formats that are checked against the inputs are read now, so that invalid
+    // ones fail the action that produced them, rather than a later build that tries to reuse them.
+    // We also need the signatures to persist the state, so compute them eagerly if we are doing so.
+    let check_inputs = state.declared_dep_files.checks_inputs();
+    let compute_fingerprints =
+        has_no_dep_files || ctx.run_action_knobs().eager_dep_files || db.is_some();
+
+    if check_inputs || compute_fingerprints {
         let dep_files = state
             .read_dep_files(ctx.fs(), ctx.materializer())
             .await?
             .context("Dep file not found")?;
 
-        // Evaluate the fingerprints, but release the lock immediately.
-        std::mem::drop(state.locked_compute_fingerprints(
-            Cow::Owned(dep_files),
-            KEEP_DIRECTORIES.get_copied()?.unwrap_or_default(),
-            digest_config,
-        ));
+        state
+            .check_inputs(&dep_files)
+            .context("Action execution produced an invalid dep file")?;
+
+        if compute_fingerprints {
+            // Evaluate the fingerprints, but release the lock immediately.
+            std::mem::drop(state.locked_compute_fingerprints(
+                Cow::Owned(dep_files),
+                KEEP_DIRECTORIES.get_copied()?.unwrap_or_default(),
+                digest_config,
+            ));
+        }
+    }
+
+    if let Some(db) = db {
+        persist_dep_file_state(&db, &key, &state, ctx).await;
     }
 
     DEP_FILES.insert(key, Arc::new(state));
@@ -572,10 +831,11 @@ impl PartitionedInputs<ActionImmutableDirectory> {
 struct DeclaredDepFile {
     label: Arc<str>,
     output: Artifact,
+    format: DepFileFormat,
 }
 
 /// All the dep files declared by a command;
-#[derive(Default, Debug, Allocative)]
+#[derive(Default, Debug, Clone, Allocative)]
 pub struct DeclaredDepFiles {
     tagged: HashMap<ArtifactTag, DeclaredDepFile>,
 }
@@ -585,6 +845,11 @@ impl DeclaredDepFiles {
         self.tagged.is_empty()
     }
 
+    /// Whether any of these dep files is in a format whose paths are checked against the inputs.
+    fn checks_inputs(&self) -> bool {
+        self.tagged.values().any(|d| d.format.checks_inputs())
+    }
+
     /// Add dep file to this set.
     fn visit_output(
         &mut self,
@@ -607,6 +872,7 @@ impl DeclaredDepFiles {
                         DeclaredDepFile {
                             label: label.dupe(),
                             output,
+                            format: dep_files.formats.get(tag).copied().unwrap_or_default(),
                         },
                     );
                 }
@@ -665,13 +931,12 @@ impl DeclaredDepFiles {
     /// signatures for the input set that was used, and for future input sets.
     fn read(&self, fs: &ArtifactFs) -> anyhow::Result<Option<ConcreteDepFiles>> {
         let mut contents = HashMap::with_capacity(self.tagged.len());
+        let mut checked_paths = HashMap::new();
 
         for declared_dep_file in self.tagged.values() {
-            let mut selector = DirectorySelector::empty();
-
             let dep_file = fs.resolve(declared_dep_file.output.get_path())?;
 
-            let read_dep_file: anyhow::Result<()> = try {
+            let read_dep_file: anyhow::Result<Vec<ProjectRelativePathBuf>> = try {
                 let dep_file_path = fs.fs().resolve(&dep_file);
                 let dep_file = fs_util::read_to_string_opt(&dep_file_path)?;
 
@@ -686,29 +951,34 @@ impl DeclaredDepFiles {
                     }
                 };
 
-                for line in dep_file.split('\n') {
-                    let line = line.trim();
-                    if line.is_empty() {
-                        continue;
-                    }
-                    let path = ProjectRelativePath::new(line)
-                        .context("Invalid line encountered in dep file")?;
-
-                    selector.select(path);
-                }
+                declared_dep_file
+                    .format
+                    .parse(&dep_file, fs.fs().root())
+                    .with_context(|| format!("Invalid `{}` dep file", declared_dep_file.format))?
             };
 
-            read_dep_file.with_context(|| {
+            let dep_file_paths = read_dep_file.with_context(|| {
                 format!(
                     "Action execution produced an invalid `{}` dep file at `{}`",
                     declared_dep_file.label, dep_file,
                 )
             })?;
 
+            let mut selector = DirectorySelector::empty();
+            for path in &dep_file_paths {
+                selector.select(path);
+            }
+
             contents.insert(declared_dep_file.label.dupe(), selector);
+            if declared_dep_file.format.checks_inputs() {
+                checked_paths.insert(declared_dep_file.label.dupe(), dep_file_paths);
+            }
         }
 
-        Ok(Some(ConcreteDepFiles { contents }))
+        Ok(Some(ConcreteDepFiles {
+            contents,
+            checked_paths,
+        }))
     }
 
     /// Returns whether two DeclaredDepFile instances have the same dep files. This ignores the tag
@@ -719,6 +989,39 @@ impl DeclaredDepFiles {
         let other = other.tagged.values().collect::<HashSet<_>>();
         this == other
     }
+
+    /// The labels, formats and resolved paths of these dep files, sorted. This is what we persist
+    /// to be able to tell whether an action declares the same dep files as it did in a previous
+    /// daemon.
+    fn persisted(&self, fs: &ArtifactFs) -> anyhow::Result<Vec<(String, String, String)>> {
+        let mut dep_files = self
+            .tagged
+            .values()
+            .map(|dep_file| {
+                anyhow::Ok((
+                    (*dep_file.label).to_owned(),
+                    dep_file.format.to_string(),
+                    fs.resolve(dep_file.output.get_path())?.to_string(),
+                ))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        dep_files.sort();
+        Ok(dep_files)
+    }
+}
+
+#[derive(Error, Debug)]
+enum DepFilesError {
+    #[error(
+        "Dep file `{}` lists `{}`, which is not an input of this action. \
+        Dep files must list inputs of the action, relative to the project root",
+        .label,
+        .path
+    )]
+    NotAnInput {
+        label: Arc<str>,
+        path: ProjectRelativePathBuf,
+    },
 }
 
 #[derive(Error, Debug)]
@@ -738,6 +1041,47 @@ enum MaterializeDepFilesError {
 #[derive(Clone)]
 pub struct ConcreteDepFiles {
     contents: HashMap<Arc<str>, DirectorySelector>,
+    /// The paths listed in each dep file whose format checks them against the inputs of the
+    /// action (see `DepFileFormat::checks_inputs`).
+    checked_paths: HashMap<Arc<str>, Vec<ProjectRelativePathBuf>>,
+}
+
+impl ConcreteDepFiles {
+    /// Check that all the checked paths listed in those dep files are among `inputs`, which should be the
+    /// inputs of the action that produced the dep files. It's fine for a dep file to list inputs
+    /// that aren't tagged with its label (e.g. a compiler will list the file it is compiling),
+    /// but anything else means the command read something it didn't declare, or that the paths
+    /// in the dep file are not in the format we expect.
+    fn check_inputs(
+        &self,
+        inputs: &PartitionedInputs<ActionSharedDirectory>,
+    ) -> anyhow::Result<()> {
+        let dirs = std::iter::once(&inputs.untagged)
+            .chain(inputs.tagged.values())
+            .collect::<Vec<_>>();
+
+        for (label, paths) in &self.checked_paths {
+            for path in paths {
+                let mut is_input = false;
+                for dir in &dirs {
+                    if find_prefix_fingerprinted(*dir, path.iter())?.is_some() {
+                        is_input = true;
+                        break;
+                    }
+                }
+
+                if !is_input {
+                    return Err(DepFilesError::NotAnInput {
+                        label: label.dupe(),
+                        path: path.clone(),
+                    }
+                    .into());
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
 
 /// A command line visitor to collect inputs and outputs in a form relevant for dep files
@@ -806,16 +1150,25 @@ mod test {
         let depfile1 = DeclaredDepFile {
             label: Arc::from("foo"),
             output: artifact1,
+            format: DepFileFormat::Paths,
         };
 
         let depfile2 = DeclaredDepFile {
             label: Arc::from("foo"),
             output: artifact2.dupe(),
+            format: DepFileFormat::Paths,
         };
 
         let depfile3 = DeclaredDepFile {
+            label: Arc::from("bar"),
+            output: artifact2.dupe(),
+            format: DepFileFormat::Paths,
+        };
+
+        let depfile4 = DeclaredDepFile {
             label: Arc::from("bar"),
             output: artifact2,
+            format: DepFileFormat::Makefile,
         };
 
         let tag1 = ArtifactTag::new();
@@ -842,5 +1195,11 @@ mod test {
         assert!(decl1.declares_same_dep_files(&decl2));
         assert!(!decl2.declares_same_dep_files(&decl3));
         assert!(!decl3.declares_same_dep_files(&decl4));
+
+        let decl5 = DeclaredDepFiles {
+            tagged: hashmap! { tag2.dupe() => depfile4.dupe() },
+        };
+
+        assert!(!decl4.declares_same_dep_files(&decl5));
     }
 }
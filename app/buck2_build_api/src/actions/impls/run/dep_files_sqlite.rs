@@ -0,0 +1,387 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! On-disk storage for dep file state, so that actions that are unchanged (and whose outputs are
+//! still on disk) can be skipped after the daemon restarts.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Context as _;
+use buck2_common::file_ops::FileDigest;
+use buck2_common::file_ops::FileMetadata;
+use buck2_common::file_ops::TrackedFileDigest;
+use buck2_common::sqlite::KeyValueSqliteTable;
+use buck2_core::fs::fs_util;
+use buck2_core::fs::paths::abs_norm_path::AbsNormPath;
+use buck2_core::fs::paths::abs_norm_path::AbsNormPathBuf;
+use buck2_core::fs::paths::file_name::FileName;
+use buck2_execute::artifact_value::ArtifactValue;
+use buck2_execute::digest_config::DigestConfig;
+use buck2_execute::directory::ActionDirectoryEntry;
+use buck2_execute::directory::ActionDirectoryMember;
+use buck2_execute::directory::Symlink;
+use buck2_execute::execute::blocking::BlockingExecutor;
+use dupe::Dupe;
+use parking_lot::Mutex;
+use rusqlite::Connection;
+use serde::Deserialize;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::actions::impls::run::dep_files::PartitionedInputs;
+
+/// Hand-maintained schema version for the dep files state sqlite db. PLEASE bump this version if
+/// you are making a breaking change to the db schema or to the serialized representation of
+/// `PersistedDepFileState`. If you forget, you can fix forward by bumping the
+/// `buck2.sqlite_dep_files_state_version` buckconfig in the project root's .buckconfig.
+pub const DEP_FILES_DB_SCHEMA_VERSION: u64 = 2;
+
+const STATE_TABLE_NAME: &str = "dep_files_state";
+
+#[derive(Error, Debug)]
+enum DepFilesSqliteDbError {
+    #[error("Path {} does not exist", .0)]
+    PathDoesNotExist(AbsNormPathBuf),
+
+    #[error("Expected versions {:?}. Found versions {:?} in sqlite db at {}", .expected, .found, .path)]
+    VersionMismatch {
+        expected: HashMap<String, String>,
+        found: HashMap<String, String>,
+        path: AbsNormPathBuf,
+    },
+}
+
+/// DB that holds the dep file state of actions that ran in previous daemons, keyed by the
+/// `DepFilesKey` of the action. Values are JSON-serialized `PersistedDepFileState`.
+pub struct DepFilesSqliteDb {
+    state_table: KeyValueSqliteTable,
+    /// When loading from an existing db, we check if the versions from this table match the
+    /// versions this buck2 binary expects. If they don't, we throw away the db and start over.
+    versions_table: KeyValueSqliteTable,
+}
+
+impl DepFilesSqliteDb {
+    const DB_FILENAME: &'static str = "db.sqlite";
+
+    fn open(path: &AbsNormPath) -> anyhow::Result<Self> {
+        let connection = Connection::open(path)?;
+        // TODO: make this work on Windows too
+        if cfg!(unix) {
+            connection.pragma_update(None, "journal_mode", "WAL")?;
+        }
+        // Like for the materializer state, we'd rather lose this state (which we recover from by
+        // re-running actions) than `fsync` in the middle of a build.
+        connection.pragma_update(None, "synchronous", "OFF")?;
+
+        let connection = Arc::new(Mutex::new(connection));
+        Ok(Self {
+            state_table: KeyValueSqliteTable::new(STATE_TABLE_NAME.to_owned(), connection.dupe()),
+            versions_table: KeyValueSqliteTable::new("versions".to_owned(), connection),
+        })
+    }
+
+    /// Open the db in `dep_files_state_dir`. If it does not exist, can't be read, or was written
+    /// with different `versions`, it is deleted and a new, empty db is created instead.
+    pub async fn initialize(
+        dep_files_state_dir: AbsNormPathBuf,
+        versions: HashMap<String, String>,
+        io_executor: Arc<dyn BlockingExecutor>,
+    ) -> anyhow::Result<Self> {
+        io_executor
+            .execute_io_inline(|| Self::initialize_impl(dep_files_state_dir, versions))
+            .await
+    }
+
+    fn initialize_impl(
+        dep_files_state_dir: AbsNormPathBuf,
+        versions: HashMap<String, String>,
+    ) -> anyhow::Result<Self> {
+        let db_path = dep_files_state_dir.join(FileName::unchecked_new(Self::DB_FILENAME));
+
+        let existing: anyhow::Result<Self> = try {
+            if !db_path.exists() {
+                Err(DepFilesSqliteDbError::PathDoesNotExist(db_path.clone()))?
+            }
+
+            let db = Self::open(&db_path)?;
+            let read_versions = db.versions_table.read_all()?;
+            if read_versions != versions {
+                Err(DepFilesSqliteDbError::VersionMismatch {
+                    expected: versions.clone(),
+                    found: read_versions,
+                    path: db_path.clone(),
+                })?;
+            }
+            db
+        };
+
+        match existing {
+            Ok(db) => Ok(db),
+            Err(e) => {
+                tracing::debug!("Creating a new dep files state db: {:#}", e);
+
+                // We delete the entire directory and not just the db file because sqlite can
+                // leave behind other files.
+                if dep_files_state_dir.exists() {
+                    fs_util::remove_dir_all(&dep_files_state_dir)?;
+                }
+                fs_util::create_dir_all(&dep_files_state_dir)?;
+
+                let db = Self::open(&db_path)?;
+                db.state_table.create_table()?;
+                db.versions_table.create_table()?;
+                db.versions_table.insert_all(versions)?;
+                Ok(db)
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, key: &str) -> anyhow::Result<Option<PersistedDepFileState>> {
+        match self.state_table.get(key)? {
+            Some(value) => {
+                Ok(Some(serde_json::from_str(&value).with_context(|| {
+                    format!("Invalid dep file state for `{}`", key)
+                })?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn insert(&self, key: String, state: &PersistedDepFileState) -> anyhow::Result<()> {
+        let value = serde_json::to_string(state)?;
+        self.state_table.insert_all(HashMap::from([(key, value)]))
+    }
+
+    pub(crate) fn delete(&self, key: &str) -> anyhow::Result<()> {
+        self.state_table.delete(key)
+    }
+
+    pub(crate) fn clear(&self) -> anyhow::Result<()> {
+        self.state_table.delete_all()
+    }
+}
+
+/// The serialized form of a `DepFileState`. Paths are relative to the project root.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub(crate) struct PersistedDepFileState {
+    /// Hex-encoded digest of the expanded command line.
+    pub(crate) cli_digest: String,
+    /// Labels, formats and paths of the dep files, sorted.
+    pub(crate) dep_files: Vec<(String, String, String)>,
+    pub(crate) fingerprints: PersistedFingerprints,
+    pub(crate) outputs: Vec<(String, PersistedOutput)>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub(crate) struct PersistedFingerprints {
+    untagged: PersistedDigest,
+    tagged: Vec<(String, PersistedDigest)>,
+}
+
+impl PersistedFingerprints {
+    pub(crate) fn new(fingerprints: &PartitionedInputs<TrackedFileDigest>) -> Self {
+        let mut tagged = fingerprints
+            .tagged
+            .iter()
+            .map(|(label, digest)| ((**label).to_owned(), PersistedDigest::new(digest)))
+            .collect::<Vec<_>>();
+        tagged.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Self {
+            untagged: PersistedDigest::new(&fingerprints.untagged),
+            tagged,
+        }
+    }
+
+    pub(crate) fn to_fingerprints(
+        &self,
+        digest_config: DigestConfig,
+    ) -> anyhow::Result<PartitionedInputs<TrackedFileDigest>> {
+        Ok(PartitionedInputs {
+            untagged: self.untagged.to_digest(digest_config)?,
+            tagged: self
+                .tagged
+                .iter()
+                .map(|(label, digest)| {
+                    anyhow::Ok((Arc::from(label.as_str()), digest.to_digest(digest_config)?))
+                })
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub(crate) struct PersistedDigest {
+    algorithm: u8,
+    hash: String,
+    size: u64,
+}
+
+impl PersistedDigest {
+    fn new(digest: &TrackedFileDigest) -> Self {
+        Self {
+            algorithm: digest.digest().algorithm() as _,
+            hash: hex::encode(digest.digest().as_bytes()),
+            size: digest.size(),
+        }
+    }
+
+    fn to_digest(&self, digest_config: DigestConfig) -> anyhow::Result<TrackedFileDigest> {
+        let algorithm = self
+            .algorithm
+            .try_into()
+            .with_context(|| format!("Invalid digest algorithm: `{}`", self.algorithm))?;
+        let hash = hex::decode(&self.hash).context("Invalid digest")?;
+        let digest = FileDigest::from_digest_bytes(algorithm, &hash, self.size)?;
+        Ok(TrackedFileDigest::new(
+            digest,
+            digest_config.cas_digest_config(),
+        ))
+    }
+}
+
+/// An action output. We only persist files and symlinks that don't depend on other artifacts,
+/// since restoring directories would require persisting their whole tree.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub(crate) enum PersistedOutput {
+    File {
+        digest: PersistedDigest,
+        is_executable: bool,
+    },
+    Symlink {
+        target: String,
+    },
+}
+
+impl PersistedOutput {
+    pub(crate) fn new(value: &ArtifactValue) -> Option<Self> {
+        if value.deps().is_some() {
+            return None;
+        }
+
+        match value.entry() {
+            ActionDirectoryEntry::Leaf(ActionDirectoryMember::File(meta)) => Some(Self::File {
+                digest: PersistedDigest::new(&meta.digest),
+                is_executable: meta.is_executable,
+            }),
+            ActionDirectoryEntry::Leaf(ActionDirectoryMember::Symlink(symlink)) => {
+                Some(Self::Symlink {
+                    target: symlink.target().as_str().to_owned(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn to_value(&self, digest_config: DigestConfig) -> anyhow::Result<ArtifactValue> {
+        Ok(match self {
+            Self::File {
+                digest,
+                is_executable,
+            } => ArtifactValue::file(FileMetadata {
+                digest: digest.to_digest(digest_config)?,
+                is_executable: *is_executable,
+            }),
+            Self::Symlink { target } => ArtifactValue::from(ActionDirectoryEntry::Leaf(
+                ActionDirectoryMember::Symlink(Arc::new(Symlink::new(target.clone().into()))),
+            )),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_core::fs::project::ProjectRootTemp;
+    use buck2_core::fs::project_rel_path::ProjectRelativePath;
+
+    use super::*;
+
+    fn testing_state(digest_config: DigestConfig) -> PersistedDepFileState {
+        let digest = TrackedFileDigest::from_content(b"foo", digest_config.cas_digest_config());
+
+        PersistedDepFileState {
+            cli_digest: "abcd".to_owned(),
+            dep_files: vec![(
+                "dep".to_owned(),
+                "paths".to_owned(),
+                "buck-out/v2/gen/foo.d".to_owned(),
+            )],
+            fingerprints: PersistedFingerprints::new(&PartitionedInputs {
+                untagged: digest.dupe(),
+                tagged: HashMap::from([(Arc::from("dep"), digest.dupe())]),
+            }),
+            outputs: vec![(
+                "buck-out/v2/gen/foo.o".to_owned(),
+                PersistedOutput::new(&ArtifactValue::file(FileMetadata {
+                    digest,
+                    is_executable: false,
+                }))
+                .unwrap(),
+            )],
+        }
+    }
+
+    #[test]
+    fn test_persisted_output_roundtrip() -> anyhow::Result<()> {
+        let digest_config = DigestConfig::testing_default();
+
+        let file = ArtifactValue::file(FileMetadata {
+            digest: TrackedFileDigest::from_content(b"foo", digest_config.cas_digest_config()),
+            is_executable: true,
+        });
+        let symlink = ArtifactValue::from(ActionDirectoryEntry::Leaf(
+            ActionDirectoryMember::Symlink(Arc::new(Symlink::new("../foo".to_owned().into()))),
+        ));
+
+        for value in [file, symlink] {
+            let persisted = PersistedOutput::new(&value).context("Not persisted")?;
+            assert_eq!(persisted.to_value(digest_config)?, value);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dep_files_sqlite_db() -> anyhow::Result<()> {
+        let fs = ProjectRootTemp::new()?;
+        let dir = fs
+            .path()
+            .resolve(ProjectRelativePath::unchecked_new("dep_files_state"));
+        let digest_config = DigestConfig::testing_default();
+        let versions = HashMap::from([("version".to_owned(), "0".to_owned())]);
+
+        let db = DepFilesSqliteDb::initialize_impl(dir.clone(), versions.clone())?;
+        db.insert("key".to_owned(), &testing_state(digest_config))?;
+        assert_eq!(db.get("key")?, Some(testing_state(digest_config)));
+        assert_eq!(db.get("other")?, None);
+        drop(db);
+
+        // Reopening with the same versions retains the state.
+        let db = DepFilesSqliteDb::initialize_impl(dir.clone(), versions)?;
+        assert_eq!(db.get("key")?, Some(testing_state(digest_config)));
+        db.delete("key")?;
+        assert_eq!(db.get("key")?, None);
+        db.insert("key".to_owned(), &testing_state(digest_config))?;
+        db.clear()?;
+        assert_eq!(db.get("key")?, None);
+        db.insert("key".to_owned(), &testing_state(digest_config))?;
+        drop(db);
+
+        // Reopening with different versions drops it.
+        let db = DepFilesSqliteDb::initialize_impl(
+            dir,
+            HashMap::from([("version".to_owned(), "1".to_owned())]),
+        )?;
+        assert_eq!(db.get("key")?, None);
+
+        Ok(())
+    }
+}
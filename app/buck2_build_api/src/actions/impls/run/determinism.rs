@@ -0,0 +1,355 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Support for `--check-determinism`: commands are executed a second time, bypassing caches, and
+//! the ones whose outputs differ between the two executions are reported.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use buck2_core::directory::Directory;
+use buck2_core::directory::DirectoryEntry;
+use buck2_core::directory::DirectoryIterator;
+use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
+use buck2_events::dispatch::console_message;
+use buck2_events::dispatch::instant_event;
+use buck2_execute::artifact::fs::ArtifactFs;
+use buck2_execute::artifact_value::ArtifactValue;
+use buck2_execute::execute::request::CommandExecutionRequest;
+use buck2_execute::path::buck_out_path::BuckOutPath;
+use dice::UserComputationData;
+use dupe::Dupe;
+use indexmap::IndexMap;
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::actions::execute::action_executor::ActionExecutionMetadata;
+use crate::actions::ActionExecutionCtx;
+
+/// An output that differs between two executions of the same command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NonDeterministicOutput {
+    /// The path of the output. When an output is a directory, this is the path of a file within
+    /// it.
+    pub path: ProjectRelativePathBuf,
+    /// What the first execution produced at this path, if anything.
+    pub first: Option<String>,
+    /// What the second execution produced at this path, if anything.
+    pub second: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NonDeterministicAction {
+    pub owner: String,
+    pub category: String,
+    pub identifier: Option<String>,
+    pub outputs: Vec<NonDeterministicOutput>,
+}
+
+impl NonDeterministicAction {
+    fn to_proto(&self) -> buck2_data::NonDeterministicAction {
+        buck2_data::NonDeterministicAction {
+            owner: self.owner.clone(),
+            name: Some(buck2_data::ActionName {
+                category: self.category.clone(),
+                identifier: self.identifier.clone().unwrap_or_default(),
+            }),
+            outputs: self
+                .outputs
+                .iter()
+                .map(|o| buck2_data::NonDeterministicOutput {
+                    path: o.path.to_string(),
+                    first: o.first.clone().unwrap_or_default(),
+                    second: o.second.clone().unwrap_or_default(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Collects the non-deterministic actions found during a command. This is only set on commands
+/// that check determinism.
+#[derive(Default)]
+pub struct DeterminismReport {
+    actions: Mutex<Vec<NonDeterministicAction>>,
+    /// How many commands were executed twice.
+    checked: AtomicUsize,
+}
+
+impl DeterminismReport {
+    /// How many commands were executed twice. Commands whose results were already known (e.g.
+    /// from a previous build in the same daemon) are not executed, and therefore not checked.
+    pub fn checked(&self) -> usize {
+        self.checked.load(Ordering::Relaxed)
+    }
+
+    /// Record a non-deterministic action, and notify the client about it.
+    pub fn record(&self, action: NonDeterministicAction) {
+        instant_event(action.to_proto());
+        console_message(format!(
+            "Action `{} {}` for `{}` is not deterministic, {} output(s) differ between executions",
+            action.category,
+            action.identifier.as_deref().unwrap_or_default(),
+            action.owner,
+            action.outputs.len(),
+        ));
+        self.actions.lock().push(action);
+    }
+
+    /// Write this report as JSON.
+    pub fn write_json(&self, writer: impl Write) -> anyhow::Result<()> {
+        #[derive(Serialize)]
+        struct Report<'a> {
+            non_deterministic_actions: &'a [NonDeterministicAction],
+        }
+
+        let actions = self.actions.lock();
+        serde_json::to_writer_pretty(
+            writer,
+            &Report {
+                non_deterministic_actions: &actions,
+            },
+        )?;
+        Ok(())
+    }
+}
+
+pub trait HasDeterminismReport {
+    fn set_determinism_report(&mut self, report: Arc<DeterminismReport>);
+
+    /// The report to record non-deterministic actions in, if this command checks determinism.
+    fn get_determinism_report(&self) -> Option<Arc<DeterminismReport>>;
+}
+
+impl HasDeterminismReport for UserComputationData {
+    fn set_determinism_report(&mut self, report: Arc<DeterminismReport>) {
+        self.data.set(report);
+    }
+
+    fn get_determinism_report(&self) -> Option<Arc<DeterminismReport>> {
+        self.data.get::<Arc<DeterminismReport>>().ok().cloned()
+    }
+}
+
+/// Execute `request` a second time, bypassing caches, and record it in `report` if its outputs
+/// differ from `outputs`, which the first execution produced. Returns the result of the second
+/// execution, since its outputs are the ones now on disk.
+pub async fn rerun_and_compare(
+    ctx: &mut dyn ActionExecutionCtx,
+    report: &DeterminismReport,
+    request: CommandExecutionRequest,
+    outputs: &IndexMap<BuckOutPath, ArtifactValue>,
+) -> anyhow::Result<(
+    IndexMap<BuckOutPath, ArtifactValue>,
+    ActionExecutionMetadata,
+)> {
+    let request = request
+        .with_skip_cache_lookup(true)
+        .with_allow_cache_upload(false);
+    let (rerun_outputs, rerun_meta) = ctx.exec_cmd(&request).await?;
+    report.checked.fetch_add(1, Ordering::Relaxed);
+
+    let differences = diff_outputs(ctx.fs(), outputs, &rerun_outputs);
+    if !differences.is_empty() {
+        let target = ctx.target();
+        report.record(NonDeterministicAction {
+            owner: target.owner.to_string(),
+            category: target.category.to_string(),
+            identifier: target.identifier.map(str::to_owned),
+            outputs: differences,
+        });
+    }
+
+    Ok((rerun_outputs, rerun_meta))
+}
+
+/// Compare the outputs of two executions of the same command, and return the ones that differ.
+/// Directories are compared file by file, so that we can point at what actually differs.
+pub fn diff_outputs(
+    fs: &ArtifactFs,
+    first: &IndexMap<BuckOutPath, ArtifactValue>,
+    second: &IndexMap<BuckOutPath, ArtifactValue>,
+) -> Vec<NonDeterministicOutput> {
+    let resolve = |outputs: &IndexMap<BuckOutPath, ArtifactValue>| {
+        outputs
+            .iter()
+            .map(|(path, value)| (fs.resolve_build(path), value.dupe()))
+            .collect::<BTreeMap<_, _>>()
+    };
+
+    diff_values(&resolve(first), &resolve(second))
+}
+
+fn diff_values(
+    first: &BTreeMap<ProjectRelativePathBuf, ArtifactValue>,
+    second: &BTreeMap<ProjectRelativePathBuf, ArtifactValue>,
+) -> Vec<NonDeterministicOutput> {
+    let mut first_leaves = leaves(first, second);
+    let mut second_leaves = leaves(second, first);
+
+    let paths = first_leaves
+        .keys()
+        .chain(second_leaves.keys())
+        .cloned()
+        .collect::<BTreeSet<_>>();
+
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let first = first_leaves.remove(&path);
+            let second = second_leaves.remove(&path);
+            if first == second {
+                None
+            } else {
+                Some(NonDeterministicOutput {
+                    path,
+                    first,
+                    second,
+                })
+            }
+        })
+        .collect()
+}
+
+/// Describe every file in `outputs` that isn't identical in `others`.
+fn leaves(
+    outputs: &BTreeMap<ProjectRelativePathBuf, ArtifactValue>,
+    others: &BTreeMap<ProjectRelativePathBuf, ArtifactValue>,
+) -> BTreeMap<ProjectRelativePathBuf, String> {
+    let mut res = BTreeMap::new();
+    for (path, value) in outputs {
+        if others.get(path) == Some(value) {
+            continue;
+        }
+        match value.entry() {
+            DirectoryEntry::Dir(dir) => {
+                for (sub_path, entry) in dir.unordered_walk().with_paths() {
+                    if let DirectoryEntry::Leaf(leaf) = entry {
+                        res.insert(path.join(&sub_path), leaf.to_string());
+                    }
+                }
+            }
+            DirectoryEntry::Leaf(leaf) => {
+                res.insert(path.clone(), leaf.to_string());
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_common::file_ops::FileMetadata;
+    use buck2_common::file_ops::TrackedFileDigest;
+    use buck2_core::fs::paths::forward_rel_path::ForwardRelativePath;
+    use buck2_execute::digest_config::DigestConfig;
+    use buck2_execute::directory::ActionDirectoryBuilder;
+    use buck2_execute::directory::ActionDirectoryMember;
+    use buck2_execute::directory::INTERNER;
+
+    use super::*;
+
+    fn file(contents: &str) -> FileMetadata {
+        let digest_config = DigestConfig::testing_default();
+        FileMetadata {
+            digest: TrackedFileDigest::from_content(
+                contents.as_bytes(),
+                digest_config.cas_digest_config(),
+            ),
+            is_executable: false,
+        }
+    }
+
+    fn dir(files: &[(&str, &str)]) -> anyhow::Result<ArtifactValue> {
+        let mut builder = ActionDirectoryBuilder::empty();
+        for (path, contents) in files {
+            builder.insert(
+                ForwardRelativePath::new(path)?,
+                DirectoryEntry::Leaf(ActionDirectoryMember::File(file(contents))),
+            )?;
+        }
+        Ok(ArtifactValue::dir(
+            builder
+                .fingerprint(DigestConfig::testing_default().as_directory_serializer())
+                .shared(&*INTERNER),
+        ))
+    }
+
+    fn outputs(
+        values: Vec<(&str, ArtifactValue)>,
+    ) -> BTreeMap<ProjectRelativePathBuf, ArtifactValue> {
+        values
+            .into_iter()
+            .map(|(path, value)| {
+                (
+                    ProjectRelativePathBuf::unchecked_new(path.to_owned()),
+                    value,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_values() -> anyhow::Result<()> {
+        let first = outputs(vec![
+            ("out/same", ArtifactValue::file(file("same"))),
+            ("out/file", ArtifactValue::file(file("first"))),
+            ("out/dir", dir(&[("same", "same"), ("differs", "first")])?),
+            ("out/first_only", ArtifactValue::file(file("first"))),
+        ]);
+        let second = outputs(vec![
+            ("out/same", ArtifactValue::file(file("same"))),
+            ("out/file", ArtifactValue::file(file("second"))),
+            (
+                "out/dir",
+                dir(&[
+                    ("same", "same"),
+                    ("differs", "second"),
+                    ("second_only", "second"),
+                ])?,
+            ),
+        ]);
+
+        let describe = |contents| Some(ActionDirectoryMember::File(file(contents)).to_string());
+
+        assert_eq!(
+            diff_values(&first, &second),
+            vec![
+                NonDeterministicOutput {
+                    path: ProjectRelativePathBuf::unchecked_new("out/dir/differs".to_owned()),
+                    first: describe("first"),
+                    second: describe("second"),
+                },
+                NonDeterministicOutput {
+                    path: ProjectRelativePathBuf::unchecked_new("out/dir/second_only".to_owned()),
+                    first: None,
+                    second: describe("second"),
+                },
+                NonDeterministicOutput {
+                    path: ProjectRelativePathBuf::unchecked_new("out/file".to_owned()),
+                    first: describe("first"),
+                    second: describe("second"),
+                },
+                NonDeterministicOutput {
+                    path: ProjectRelativePathBuf::unchecked_new("out/first_only".to_owned()),
+                    first: describe("first"),
+                    second: None,
+                },
+            ]
+        );
+
+        assert_eq!(diff_values(&first, &first), Vec::new());
+
+        Ok(())
+    }
+}
@@ -17,12 +17,18 @@ pub(crate) struct ExpandedCommandLine {
 }
 
 /// The digest of an ExpandedCommandLine.
-#[derive(Eq, PartialEq, Debug, Allocative)]
+#[derive(Eq, PartialEq, Clone, Debug, Allocative)]
 pub struct ExpandedCommandLineDigest(
     // This is OK to skip because hash is stored inline.
     #[allocative(skip)] blake3::Hash,
 );
 
+impl ExpandedCommandLineDigest {
+    pub(crate) fn to_hex(&self) -> String {
+        self.0.to_hex().to_string()
+    }
+}
+
 impl ExpandedCommandLine {
     /// Obtain a hash of this command line. Conceptually this is as if we serialized the command
     /// line to a length-prefixed list then hashed it, except we never actually produce the
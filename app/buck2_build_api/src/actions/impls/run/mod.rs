@@ -9,6 +9,7 @@
 
 use std::borrow::Cow;
 use std::fmt::Display;
+use std::time::Duration;
 
 use allocative::Allocative;
 use async_trait::async_trait;
@@ -21,6 +22,9 @@ use buck2_execute::execute::request::ActionMetadataBlob;
 use buck2_execute::execute::request::CommandExecutionInput;
 use buck2_execute::execute::request::CommandExecutionRequest;
 use buck2_execute::execute::request::ExecutorPreference;
+use buck2_execute::execute::request::LocalResourceLimits;
+use buck2_execute::execute::request::StdRedirects;
+use buck2_execute::execute::request::WorkerSpec;
 use buck2_execute::path::buck_out_path::BuckOutPath;
 use dupe::Dupe;
 use gazebo::prelude::*;
@@ -37,6 +41,7 @@ use starlark::values::OwnedFrozenValue;
 use thiserror::Error;
 
 use crate::actions::artifact::build_artifact::BuildArtifact;
+use crate::actions::artifact::Artifact;
 use crate::actions::box_slice_set::BoxSliceSet;
 use crate::actions::execute::action_executor::ActionExecutionKind;
 use crate::actions::execute::action_executor::ActionExecutionMetadata;
@@ -46,6 +51,7 @@ use crate::actions::impls::run::dep_files::populate_dep_files;
 use crate::actions::impls::run::dep_files::DepFilesCommandLineVisitor;
 use crate::actions::impls::run::dep_files::DepFilesKey;
 use crate::actions::impls::run::dep_files::RunActionDepFiles;
+use crate::actions::impls::run::determinism::rerun_and_compare;
 use crate::actions::impls::run::expanded_command_line::ExpandedCommandLine;
 use crate::actions::impls::run::metadata::metadata_content;
 use crate::actions::Action;
@@ -60,8 +66,12 @@ use crate::interpreter::rule_defs::cmd_args::CommandLineArtifactVisitor;
 use crate::interpreter::rule_defs::cmd_args::DefaultCommandLineContext;
 use crate::interpreter::rule_defs::cmd_args::SimpleCommandLineArtifactVisitor;
 use crate::interpreter::rule_defs::cmd_args::ValueAsCommandLineLike;
+use crate::interpreter::rule_defs::provider::builtin::worker_info::WorkerInfo;
 
+pub mod dep_file_format;
 pub mod dep_files;
+pub mod dep_files_sqlite;
+pub mod determinism;
 mod expanded_command_line;
 pub mod knobs;
 mod metadata;
@@ -123,6 +133,14 @@ pub(crate) struct UnregisteredRunAction {
     pub no_outputs_cleanup: bool,
     pub allow_cache_upload: bool,
     pub force_full_hybrid_if_capable: bool,
+    pub local_resource_limits: LocalResourceLimits,
+    pub timeout: Option<Duration>,
+    /// An input to use as the command's stdin.
+    pub stdin: Option<Artifact>,
+    /// Outputs to write the command's stdout and stderr to. They are also among the action's
+    /// outputs.
+    pub stdout: Option<BuckOutPath>,
+    pub stderr: Option<BuckOutPath>,
 }
 
 impl UnregisteredAction for UnregisteredRunAction {
@@ -138,6 +156,13 @@ impl UnregisteredAction for UnregisteredRunAction {
     }
 }
 
+/// The contents of a `RunAction`'s Starlark value.
+struct UnpackedRunAction<'v> {
+    cli: &'v dyn CommandLineArgLike,
+    env: Vec<(&'v str, &'v dyn CommandLineArgLike)>,
+    worker: Option<&'v WorkerInfo<'v>>,
+}
+
 #[derive(Debug, Allocative)]
 pub(crate) struct RunAction {
     inner: UnregisteredRunAction,
@@ -146,15 +171,11 @@ pub(crate) struct RunAction {
 }
 
 impl RunAction {
-    fn unpack(
-        args: &OwnedFrozenValue,
-    ) -> Option<(
-        &dyn CommandLineArgLike,
-        Vec<(&str, &dyn CommandLineArgLike)>,
-    )> {
-        // We expect (CmdArgs, Option<Dict<String, CmdArgs>>) in the Starlark value
-        let (cli, env) = match TupleRef::from_value(args.value())?.content() {
-            [cli, env] => (*cli, *env),
+    fn unpack(args: &OwnedFrozenValue) -> Option<UnpackedRunAction<'_>> {
+        // We expect (CmdArgs, Option<Dict<String, CmdArgs>>, Option<WorkerInfo>) in the Starlark
+        // value
+        let (cli, env, worker) = match TupleRef::from_value(args.value())?.content() {
+            [cli, env, worker] => (*cli, *env, *worker),
             _ => return None,
         };
         let cli = cli.as_command_line()?;
@@ -168,7 +189,12 @@ impl RunAction {
             }
             res
         };
-        Some((cli, env))
+        let worker = if worker.is_none() {
+            None
+        } else {
+            Some(WorkerInfo::from_value(worker)?)
+        };
+        Some(UnpackedRunAction { cli, env, worker })
     }
 
     /// Get the command line expansion for this RunAction.
@@ -180,9 +206,20 @@ impl RunAction {
         let mut cli_rendered = Vec::<String>::new();
         let mut ctx = DefaultCommandLineContext::new(fs);
 
-        let (cli, env) = Self::unpack(&self.starlark_cli).unwrap();
+        let UnpackedRunAction { cli, env, worker } = Self::unpack(&self.starlark_cli).unwrap();
+        // A worker's arguments are appended to its exe, so that it can still run as a regular
+        // command (and the worker is part of what identifies the command).
+        if let Some(worker) = worker {
+            worker
+                .exe()
+                .add_to_command_line(&mut cli_rendered, &mut ctx)?;
+            worker.exe().visit_artifacts(artifact_visitor)?;
+        }
         cli.add_to_command_line(&mut cli_rendered, &mut ctx)?;
         cli.visit_artifacts(artifact_visitor)?;
+        if let Some(stdin) = &self.inner.stdin {
+            artifact_visitor.visit_input(ArtifactGroup::Artifact(stdin.dupe()), None);
+        }
 
         let cli_env: anyhow::Result<SortedVectorMap<_, _>> = env
             .into_iter()
@@ -202,6 +239,25 @@ impl RunAction {
         })
     }
 
+    /// The persistent worker this action can run in, if any.
+    fn worker_spec(&self, fs: &ExecutorFs) -> anyhow::Result<Option<WorkerSpec>> {
+        let worker = match Self::unpack(&self.starlark_cli).unwrap().worker {
+            Some(worker) => worker,
+            None => return Ok(None),
+        };
+
+        let mut exe = Vec::<String>::new();
+        let mut ctx = DefaultCommandLineContext::new(fs);
+        worker.exe().add_to_command_line(&mut exe, &mut ctx)?;
+
+        Ok(Some(WorkerSpec {
+            exe,
+            protocol: worker.protocol(),
+            max_instances: worker.max_instances(),
+            max_multiplex_requests: worker.max_multiplex_requests(),
+        }))
+    }
+
     pub(crate) fn new(
         inner: UnregisteredRunAction,
         starlark_cli: OwnedFrozenValue,
@@ -230,12 +286,18 @@ impl Action for RunAction {
     }
 
     fn inputs(&self) -> anyhow::Result<Cow<'_, [ArtifactGroup]>> {
-        let (cli, env) = Self::unpack(&self.starlark_cli).unwrap();
+        let UnpackedRunAction { cli, env, worker } = Self::unpack(&self.starlark_cli).unwrap();
         let mut artifact_visitor = SimpleCommandLineArtifactVisitor::new();
         cli.visit_artifacts(&mut artifact_visitor)?;
         for (_, v) in env.iter() {
             v.visit_artifacts(&mut artifact_visitor)?;
         }
+        if let Some(worker) = worker {
+            worker.exe().visit_artifacts(&mut artifact_visitor)?;
+        }
+        if let Some(stdin) = &self.inner.stdin {
+            artifact_visitor.visit_input(ArtifactGroup::Artifact(stdin.dupe()), None);
+        }
         Ok(Cow::Owned(artifact_visitor.inputs.into_iter().collect()))
     }
 
@@ -262,11 +324,11 @@ impl Action for RunAction {
     fn aquery_attributes(&self, fs: &ExecutorFs) -> indexmap::IndexMap<String, String> {
         let mut cli_rendered = Vec::<String>::new();
         let mut ctx = DefaultCommandLineContext::new(fs);
-        let (cli, _env) = Self::unpack(&self.starlark_cli).unwrap();
+        let UnpackedRunAction { cli, worker, .. } = Self::unpack(&self.starlark_cli).unwrap();
         cli.add_to_command_line(&mut cli_rendered, &mut ctx)
             .unwrap();
         let cmd = format!("[{}]", cli_rendered.iter().join(", "));
-        indexmap! {
+        let mut attributes = indexmap! {
             "cmd".to_owned() => cmd,
             "executor_preference".to_owned() => self.inner.executor_preference.to_string(),
             "always_print_stderr".to_owned() => self.inner.always_print_stderr.to_string(),
@@ -277,7 +339,32 @@ impl Action for RunAction {
                 Some(x) => x.to_string(),
             },
             "no_outputs_cleanup".to_owned() => self.inner.no_outputs_cleanup.to_string(),
+        };
+        if let Some(worker) = worker {
+            let mut exe_rendered = Vec::<String>::new();
+            let mut ctx = DefaultCommandLineContext::new(fs);
+            worker
+                .exe()
+                .add_to_command_line(&mut exe_rendered, &mut ctx)
+                .unwrap();
+            attributes.insert(
+                "worker".to_owned(),
+                format!("[{}]", exe_rendered.iter().join(", ")),
+            );
         }
+        if let Some(timeout) = self.inner.timeout {
+            attributes.insert("timeout".to_owned(), format!("{}s", timeout.as_secs()));
+        }
+        if let Some(stdin) = &self.inner.stdin {
+            attributes.insert("stdin".to_owned(), stdin.to_string());
+        }
+        if let Some(stdout) = &self.inner.stdout {
+            attributes.insert("stdout".to_owned(), stdout.to_string());
+        }
+        if let Some(stderr) = &self.inner.stderr {
+            attributes.insert("stderr".to_owned(), stderr.to_string());
+        }
+        attributes
     }
 }
 
@@ -386,25 +473,69 @@ impl IncrementalActionExecutable for RunAction {
         // Run actions are assumed to be shared
         let host_sharing_requirements = HostSharingRequirements::Shared(self.inner.weight);
 
-        let req = CommandExecutionRequest::new(
+        // The std streams are written by the action executor rather than the command.
+        let std_redirects = StdRedirects {
+            stdout: self.inner.stdout.dupe(),
+            stderr: self.inner.stderr.dupe(),
+        };
+        let is_std_redirect = |path: &BuckOutPath| {
+            Some(path) == std_redirects.stdout.as_ref()
+                || Some(path) == std_redirects.stderr.as_ref()
+        };
+
+        let stdin = self
+            .inner
+            .stdin
+            .as_ref()
+            .map(|stdin| fs.resolve(stdin.get_path()))
+            .transpose()?;
+
+        // Only local executors can feed a file to the command's stdin.
+        let executor_preference = if stdin.is_some() {
+            self.inner
+                .executor_preference
+                .and(&ExecutorPreference::LocalRequired)?
+        } else {
+            self.inner.executor_preference
+        };
+
+        let mut req = CommandExecutionRequest::new(
             cli,
             inputs,
             self.outputs
                 .iter()
+                .filter(|b| !is_std_redirect(b.get_path()))
                 .map(|b| (b.get_path().dupe(), b.output_type()))
                 .collect(),
             env,
         )
         .with_prefetch_lossy_stderr(true)
-        .with_executor_preference(self.inner.executor_preference)
+        .with_executor_preference(executor_preference)
         .with_host_sharing_requirements(host_sharing_requirements)
         .with_outputs_cleanup(!self.inner.no_outputs_cleanup)
         .with_allow_cache_upload(self.inner.allow_cache_upload)
         .with_local_environment_inheritance(EnvironmentInheritance::local_command_exclusions())
-        .with_force_full_hybrid_if_capable(self.inner.force_full_hybrid_if_capable);
+        .with_force_full_hybrid_if_capable(self.inner.force_full_hybrid_if_capable)
+        .with_local_resource_limits(self.inner.local_resource_limits)
+        .with_worker(self.worker_spec(&ctx.executor_fs())?)
+        .with_stdin(stdin)
+        .with_std_redirects(std_redirects);
+
+        if let Some(timeout) = self.inner.timeout.or_else(|| ctx.default_timeout()) {
+            req = req.with_timeout(timeout);
+        }
 
         let (outputs, meta) = ctx.exec_cmd(&req).await?;
 
+        // Commands that don't clean up their outputs depend on them, so they would not run the
+        // same way a second time.
+        let (outputs, meta) = match ctx.determinism_report() {
+            Some(report) if req.outputs_cleanup() => {
+                rerun_and_compare(ctx, &report, req, &outputs).await?
+            }
+            _ => (outputs, meta),
+        };
+
         let outputs = ActionOutputs::new(outputs);
 
         if let Some(dep_files) = dep_files {
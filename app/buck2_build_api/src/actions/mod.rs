@@ -35,6 +35,7 @@ pub(crate) mod registry;
 use std::borrow::Cow;
 use std::fmt::Debug;
 use std::sync::Arc;
+use std::time::Duration;
 
 use allocative::Allocative;
 use async_trait::async_trait;
@@ -49,11 +50,13 @@ use buck2_execute::digest_config::DigestConfig;
 use buck2_execute::execute::blocking::BlockingExecutor;
 use buck2_execute::execute::request::CommandExecutionRequest;
 use buck2_execute::execute::target::CommandExecutionTarget;
+use buck2_execute::materialize::http::UrlMirrors;
 use buck2_execute::materialize::materializer::Materializer;
 use buck2_execute::path::buck_out_path::BuckOutPath;
 use buck2_execute::re::manager::ManagedRemoteExecutionClient;
 use derivative::Derivative;
 use derive_more::Display;
+use impls::run::determinism::DeterminismReport;
 use impls::run::knobs::RunActionKnobs;
 use indexmap::indexmap;
 use indexmap::IndexMap;
@@ -212,6 +215,15 @@ pub trait ActionExecutionCtx: Send + Sync {
 
     /// Obtian per-command knobs for RunAction.
     fn run_action_knobs(&self) -> RunActionKnobs;
+
+    /// The timeout for commands that don't set one, from the executor config.
+    fn default_timeout(&self) -> Option<Duration>;
+
+    /// Mirrors to try for downloads, from buckconfig.
+    fn url_mirrors(&self) -> &UrlMirrors;
+
+    /// Where to record non-deterministic commands, if this command checks determinism.
+    fn determinism_report(&self) -> Option<Arc<DeterminismReport>>;
 }
 
 #[derive(Error, Debug)]
@@ -18,6 +18,7 @@ use buck2_execute::digest_config::DigestConfig;
 use buck2_execute::digest_config::SetDigestConfig;
 use dice::DetectCycles;
 use dice::Dice;
+use dice::DicePersistence;
 
 /// Utility to configure the dice globals.
 /// One place to not forget to initialize something in all places.
@@ -26,10 +27,21 @@ pub async fn configure_dice_for_buck(
     digest_config: DigestConfig,
     root_config: Option<&LegacyBuckConfig>,
     detect_cycles: Option<DetectCycles>,
+    persistence: Option<Arc<DicePersistence>>,
 ) -> anyhow::Result<Arc<Dice>> {
     let mut dice = Dice::builder();
     dice.set_io_provider(io);
     dice.set_digest_config(digest_config);
+    if let Some(persistence) = persistence {
+        dice.set_persistence(persistence);
+    }
+    if let Some(max_bytes) = root_config
+        .map(|c| c.parse::<usize>("buck2", "dice_memory_budget_bytes"))
+        .transpose()?
+        .flatten()
+    {
+        dice.set_memory_budget(max_bytes);
+    }
 
     let detect_cycles = detect_cycles.map_or_else(
         || {
@@ -8,6 +8,7 @@
  */
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use allocative::Allocative;
 use anyhow::Context as _;
@@ -42,6 +43,8 @@ enum CommandExecutorConfigErrors {
     MissingField(&'static str),
     #[error("invalid value in `{0}`")]
     InvalidField(&'static str),
+    #[error("`{0}` must be positive")]
+    NonPositiveField(&'static str),
     #[error(
         "executor config must specify at least `local_enabled = True` or `remote_enabled = True`"
     )]
@@ -103,6 +106,10 @@ pub fn register_command_executor_config(builder: &mut GlobalsBuilder) {
         #[starlark(default = false, require = named)] experimental_low_pass_filter: bool,
         // How to express output paths to RE.
         #[starlark(default = NoneOr::None, require = named)] remote_output_paths: NoneOr<&str>,
+        // Whether to run local actions in a sandbox that only exposes their declared inputs.
+        #[starlark(default = false, require = named)] use_local_sandbox: bool,
+        // The timeout in seconds for actions that don't set their own `timeout_seconds`.
+        #[starlark(default = NoneOr::None, require = named)] default_timeout_seconds: NoneOr<i32>,
         heap: &'v Heap,
     ) -> anyhow::Result<Value<'v>> {
         let command_executor_config = {
@@ -143,7 +150,9 @@ pub fn register_command_executor_config(builder: &mut GlobalsBuilder) {
             };
 
             let local_options = if local_enabled {
-                Some(LocalExecutorOptions {})
+                Some(LocalExecutorOptions {
+                    use_sandbox: use_local_sandbox,
+                })
             } else {
                 None
             };
@@ -263,6 +272,17 @@ pub fn register_command_executor_config(builder: &mut GlobalsBuilder) {
                 ))?
                 .unwrap_or_default();
 
+            let default_timeout = match default_timeout_seconds.into_option() {
+                None => None,
+                Some(seconds) if seconds > 0 => Some(Duration::from_secs(seconds as u64)),
+                Some(..) => {
+                    return Err(CommandExecutorConfigErrors::NonPositiveField(
+                        "default_timeout_seconds",
+                    )
+                    .into());
+                }
+            };
+
             CommandExecutorConfig {
                 executor,
                 options: CommandGenerationOptions {
@@ -272,6 +292,7 @@ pub fn register_command_executor_config(builder: &mut GlobalsBuilder) {
                         PathSeparatorKind::Unix
                     },
                     output_paths_behavior,
+                    default_timeout,
                 },
             }
         };
@@ -281,3 +302,53 @@ pub fn register_command_executor_config(builder: &mut GlobalsBuilder) {
         ))))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use buck2_interpreter_for_build::interpreter::testing::Tester;
+    use indoc::indoc;
+
+    use super::register_command_executor_config;
+
+    fn tester() -> Tester {
+        let mut tester = Tester::new().unwrap();
+        tester.set_additional_globals(register_command_executor_config);
+        tester
+    }
+
+    #[test]
+    fn test_default_timeout() -> anyhow::Result<()> {
+        let mut tester = tester();
+        tester.run_starlark_bzl_test(indoc!(
+            r#"
+            def test():
+                config = CommandExecutorConfig(True, False, default_timeout_seconds = 30)
+                assert_eq(True, "default_timeout: Some(30s)" in repr(config))
+                config = CommandExecutorConfig(True, False)
+                assert_eq(True, "default_timeout: None" in repr(config))
+            "#
+        ))?;
+
+        tester.run_starlark_bzl_test_expecting_error(
+            indoc!(
+                r#"
+            def test():
+                CommandExecutorConfig(True, False, default_timeout_seconds = 0)
+            "#
+            ),
+            "`default_timeout_seconds` must be positive",
+        );
+
+        tester.run_starlark_bzl_test_expecting_error(
+            indoc!(
+                r#"
+            def test():
+                CommandExecutorConfig(True, False, default_timeout_seconds = -1)
+            "#
+            ),
+            "`default_timeout_seconds` must be positive",
+        );
+
+        Ok(())
+    }
+}
@@ -12,6 +12,7 @@ use std::cell::RefMut;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use allocative::Allocative;
 use anyhow::Context as _;
@@ -19,11 +20,14 @@ use buck2_common::cas_digest::CasDigest;
 use buck2_common::executor_config::RemoteExecutorUseCase;
 use buck2_core::category::Category;
 use buck2_core::collections::ordered_set::OrderedSet;
+use buck2_core::fs::paths::forward_rel_path::ForwardRelativePath;
 use buck2_core::fs::paths::forward_rel_path::ForwardRelativePathBuf;
 use buck2_core::fs::paths::RelativePathBuf;
 use buck2_execute::digest_config::DigestConfig;
+use buck2_execute::execute::request::LocalResourceLimits;
 use buck2_execute::execute::request::OutputType;
 use buck2_execute::materialize::http::Checksum;
+use buck2_execute::path::artifact_path::ArtifactPath;
 use buck2_interpreter::starlark_promise::StarlarkPromise;
 use buck2_interpreter::types::label::Label;
 use buck2_interpreter_for_build::rule::FrozenRuleCallable;
@@ -32,6 +36,7 @@ use chrono::Utc;
 use derive_more::Display;
 use dice::DiceComputations;
 use dupe::Dupe;
+use either::Either;
 use host_sharing::WeightClass;
 use host_sharing::WeightPercentage;
 use indexmap::indexset;
@@ -51,6 +56,7 @@ use starlark::values::function::FUNCTION_TYPE;
 use starlark::values::none::NoneOr;
 use starlark::values::none::NoneType;
 use starlark::values::structs::StructRef;
+use starlark::values::tuple::TupleRef;
 use starlark::values::type_repr::StarlarkTypeRepr;
 use starlark::values::AllocValue;
 use starlark::values::Heap;
@@ -67,12 +73,17 @@ use starlark_map::small_set::SmallSet;
 use thiserror::Error;
 
 use crate::actions::artifact::OutputArtifact;
+use crate::actions::impls::archive::ArchiveFormat;
 use crate::actions::impls::cas_artifact::ArtifactKind;
 use crate::actions::impls::cas_artifact::DirectoryKind;
 use crate::actions::impls::cas_artifact::UnregisteredCasArtifactAction;
 use crate::actions::impls::copy::CopyMode;
 use crate::actions::impls::copy::UnregisteredCopyAction;
+use crate::actions::impls::create_archive::UnregisteredCreateArchiveAction;
 use crate::actions::impls::download_file::UnregisteredDownloadFileAction;
+use crate::actions::impls::expand_template::UnregisteredExpandTemplateAction;
+use crate::actions::impls::extract_archive::UnregisteredExtractArchiveAction;
+use crate::actions::impls::run::dep_file_format::DepFileFormat;
 use crate::actions::impls::run::dep_files::RunActionDepFiles;
 use crate::actions::impls::run::new_executor_preference;
 use crate::actions::impls::run::MetadataParameter;
@@ -96,11 +107,14 @@ use crate::interpreter::rule_defs::cmd_args::SimpleCommandLineArtifactVisitor;
 use crate::interpreter::rule_defs::cmd_args::StarlarkCommandLine;
 use crate::interpreter::rule_defs::cmd_args::ValueAsCommandLineLike;
 use crate::interpreter::rule_defs::cmd_args::WriteToFileMacroVisitor;
+use crate::interpreter::rule_defs::provider::builtin::worker_info::WorkerInfo;
 
 #[derive(Error, Debug)]
 enum DownloadFileError {
     #[error("Must pass in at least one checksum (e.g. `sha1 = ...`)")]
     MissingChecksum,
+    #[error("Must pass in at least one URL")]
+    MissingUrl,
 }
 
 #[derive(Error, Debug)]
@@ -344,8 +358,16 @@ enum RunActionError {
     InvalidWeight(i32),
     #[error("`weight` and `weight_percentage` cannot both be passed")]
     DuplicateWeightsSpecified,
-    #[error("`dep_files` values must be artifact tags, got `{}` for key `{}`", .value, .key)]
+    #[error("`{0}` must be a positive integer")]
+    InvalidLocalResourceLimit(&'static str),
+    #[error("`timeout_seconds` must be a positive integer")]
+    InvalidTimeout,
+    #[error("`worker` must be a `WorkerInfo`, got `{0}`")]
+    InvalidWorker(String),
+    #[error("`dep_files` values must be artifact tags or `(tag, format)` tuples, got `{}` for key `{}`", .value, .key)]
     InvalidDepFileTag { key: String, value: String },
+    #[error("`dep_files` formats must be strings, got `{}` for key `{}`", .value, .key)]
+    InvalidDepFileFormat { key: String, value: String },
     #[error("`dep_files` value with key `{}` has an invalid count of associated outputs. Expected 1, got {}.", .key, .count)]
     InvalidDepFileOutputs { key: String, count: usize },
     #[error("`dep_files` with keys `{}` and {} are using the same tag", .first, .second)]
@@ -358,6 +380,10 @@ enum RunActionError {
         "missing `metadata_env_var` parameter which is required when `metadata_path` parameter is present"
     )]
     MetadataEnvVarMissing,
+    #[error("`{0}` must be an output artifact without a projection")]
+    ProjectedStdRedirect(String),
+    #[error("`stdout` and `stderr` cannot be redirected to the same output")]
+    SameStdRedirect,
 }
 
 #[derive(Debug, Error)]
@@ -722,6 +748,96 @@ fn register_context_actions(builder: &mut MethodsBuilder) {
         create_dir_tree(eval, this, output, srcs, true)
     }
 
+    fn expand_template<'v>(
+        this: &AnalysisActions<'v>,
+        #[starlark(require = pos)] output: Value<'v>,
+        #[starlark(require = pos)] template: Value<'v>,
+        #[starlark(require = pos)] substitutions: ValueOf<'v, SmallMap<&'v str, Value<'v>>>,
+        #[starlark(require = named, default = false)] is_executable: bool,
+        eval: &mut Evaluator<'v, '_>,
+    ) -> anyhow::Result<Value<'v>> {
+        let template = template
+            .as_artifact()
+            .ok_or_else(|| ValueError::IncorrectParameterTypeNamed("template".to_owned()))?
+            .get_bound_artifact()?;
+
+        for v in substitutions.typed.values() {
+            v.as_command_line_err()?;
+        }
+
+        let mut this = this.state();
+        let (declaration, output_artifact) =
+            this.get_or_declare_output(eval, output, "output", OutputType::File)?;
+
+        this.register_action(
+            indexset![ArtifactGroup::Artifact(template)],
+            indexset![output_artifact],
+            UnregisteredExpandTemplateAction::new(is_executable),
+            Some(substitutions.value),
+        )?;
+
+        let value = declaration.into_declared_artifact(Default::default());
+        Ok(value)
+    }
+
+    fn create_archive<'v>(
+        this: &AnalysisActions<'v>,
+        #[starlark(require = pos)] output: Value<'v>,
+        #[starlark(require = pos)] srcs: Value<'v>,
+        #[starlark(require = named, default = NoneOr::None)] format: NoneOr<&str>,
+        eval: &mut Evaluator<'v, '_>,
+    ) -> anyhow::Result<Value<'v>> {
+        let mut this = this.state();
+        let (declaration, output_artifact) =
+            this.get_or_declare_output(eval, output, "output", OutputType::File)?;
+
+        let format = output_artifact
+            .get_path()
+            .with_filename(|name| ArchiveFormat::new(format.into_option(), name?.as_str()))?;
+        let action = UnregisteredCreateArchiveAction::new(format, srcs)?;
+
+        this.register_action(action.inputs(), indexset![output_artifact], action, None)?;
+
+        let value = declaration.into_declared_artifact(Default::default());
+        Ok(value)
+    }
+
+    fn extract_archive<'v>(
+        this: &AnalysisActions<'v>,
+        #[starlark(require = pos)] src: Value<'v>,
+        #[starlark(require = pos)] output_dir: Value<'v>,
+        #[starlark(require = named, default = NoneOr::None)] strip_prefix: NoneOr<&str>,
+        #[starlark(require = named, default = NoneOr::None)] format: NoneOr<&str>,
+        eval: &mut Evaluator<'v, '_>,
+    ) -> anyhow::Result<Value<'v>> {
+        let src = src
+            .as_artifact()
+            .ok_or_else(|| ValueError::IncorrectParameterTypeNamed("src".to_owned()))?
+            .get_bound_artifact()?;
+
+        let format = src
+            .get_path()
+            .with_filename(|name| ArchiveFormat::new(format.into_option(), name?.as_str()))?;
+        let strip_prefix = strip_prefix
+            .into_option()
+            .map(|p| ForwardRelativePath::new_trim_trailing_slashes(p).map(|p| p.to_buf()))
+            .transpose()?;
+
+        let mut this = this.state();
+        let (declaration, output_artifact) =
+            this.get_or_declare_output(eval, output_dir, "output_dir", OutputType::Directory)?;
+
+        this.register_action(
+            indexset![ArtifactGroup::Artifact(src)],
+            indexset![output_artifact],
+            UnregisteredExtractArchiveAction::new(format, strip_prefix),
+            None,
+        )?;
+
+        let value = declaration.into_declared_artifact(Default::default());
+        Ok(value)
+    }
+
     fn run<'v>(
         this: &AnalysisActions<'v>,
         #[starlark(require = pos)] arguments: Value<'v>,
@@ -740,6 +856,13 @@ fn register_context_actions(builder: &mut MethodsBuilder) {
         #[starlark(require = named, default = false)] no_outputs_cleanup: bool,
         #[starlark(require = named, default = false)] allow_cache_upload: bool,
         #[starlark(require = named, default = false)] force_full_hybrid_if_capable: bool,
+        #[starlark(require = named)] local_memory_max_bytes: Option<u64>,
+        #[starlark(require = named)] local_cpu_max_percent: Option<u32>,
+        #[starlark(require = named)] worker: Option<Value<'v>>,
+        #[starlark(require = named)] timeout_seconds: Option<u32>,
+        #[starlark(require = named)] stdin: Option<Value<'v>>,
+        #[starlark(require = named)] stdout: Option<Value<'v>>,
+        #[starlark(require = named)] stderr: Option<Value<'v>>,
         eval: &mut Evaluator<'v, '_>,
     ) -> anyhow::Result<NoneType> {
         struct RunCommandArtifactVisitor {
@@ -783,6 +906,17 @@ fn register_context_actions(builder: &mut MethodsBuilder) {
         let starlark_cli = StarlarkCommandLine::try_from_value(arguments)?;
         starlark_cli.visit_artifacts(&mut artifact_visitor)?;
 
+        let starlark_worker = match worker {
+            None => Value::new_none(),
+            Some(worker) => {
+                WorkerInfo::from_value(worker)
+                    .ok_or_else(|| RunActionError::InvalidWorker(worker.to_repr()))?
+                    .exe()
+                    .visit_artifacts(&mut artifact_visitor)?;
+                worker
+            }
+        };
+
         let weight = match (weight, weight_percentage) {
             (None, None) => WeightClass::Permits(1),
             (Some(v), None) => {
@@ -800,6 +934,22 @@ fn register_context_actions(builder: &mut MethodsBuilder) {
             }
         };
 
+        if local_memory_max_bytes == Some(0) {
+            return Err(RunActionError::InvalidLocalResourceLimit("local_memory_max_bytes").into());
+        }
+        if local_cpu_max_percent == Some(0) {
+            return Err(RunActionError::InvalidLocalResourceLimit("local_cpu_max_percent").into());
+        }
+        let local_resource_limits = LocalResourceLimits {
+            memory_max_bytes: local_memory_max_bytes,
+            cpu_max_percent: local_cpu_max_percent,
+        };
+
+        if timeout_seconds == Some(0) {
+            return Err(RunActionError::InvalidTimeout.into());
+        }
+        let timeout = timeout_seconds.map(|t| Duration::from_secs(t.into()));
+
         let starlark_env = match env {
             None => Value::new_none(),
             Some(env) => {
@@ -811,6 +961,47 @@ fn register_context_actions(builder: &mut MethodsBuilder) {
             }
         };
 
+        let stdin = stdin
+            .map(|stdin| {
+                let stdin = stdin
+                    .as_artifact()
+                    .ok_or_else(|| ValueError::IncorrectParameterTypeNamed("stdin".to_owned()))?
+                    .get_bound_artifact()?;
+                artifact_visitor.visit_input(ArtifactGroup::Artifact(stdin.dupe()), None);
+                anyhow::Ok(stdin)
+            })
+            .transpose()?;
+
+        let mut std_redirect = |name: &str, value: Option<Value<'v>>| {
+            value
+                .map(|value| {
+                    let output = StarlarkOutputArtifact::unpack_value(value)
+                        .ok_or_else(|| ValueError::IncorrectParameterTypeNamed(name.to_owned()))?
+                        .artifact();
+                    output.ensure_output_type(OutputType::File)?;
+                    let path = match output.get_path() {
+                        ArtifactPath {
+                            base_path: Either::Left(path),
+                            projected_path: None,
+                            ..
+                        } => (*path).dupe(),
+                        _ => {
+                            return Err(
+                                RunActionError::ProjectedStdRedirect(name.to_owned()).into()
+                            );
+                        }
+                    };
+                    artifact_visitor.visit_output(output, None);
+                    anyhow::Ok(path)
+                })
+                .transpose()
+        };
+        let stdout = std_redirect("stdout", stdout)?;
+        let stderr = std_redirect("stderr", stderr)?;
+        if stdout.is_some() && stdout == stderr {
+            return Err(RunActionError::SameStdRedirect.into());
+        }
+
         let RunCommandArtifactVisitor {
             inner: artifacts,
             tagged_outputs,
@@ -820,12 +1011,32 @@ fn register_context_actions(builder: &mut MethodsBuilder) {
 
         if let Some(dep_files) = dep_files {
             for (key, value) in dep_files.typed.iter() {
-                let tag = value.downcast_ref::<ArtifactTag>().ok_or_else(|| {
-                    RunActionError::InvalidDepFileTag {
-                        key: (*key).to_owned(),
-                        value: value.to_string(),
-                    }
-                })?;
+                let invalid_tag = || RunActionError::InvalidDepFileTag {
+                    key: (*key).to_owned(),
+                    value: value.to_string(),
+                };
+
+                let (tag, format) = match TupleRef::from_value(*value) {
+                    Some(tuple) => match tuple.content() {
+                        [tag, format] => (*tag, Some(*format)),
+                        _ => return Err(invalid_tag().into()),
+                    },
+                    None => (*value, None),
+                };
+
+                let tag = tag.downcast_ref::<ArtifactTag>().ok_or_else(invalid_tag)?;
+
+                let format = match format {
+                    Some(format) => format
+                        .unpack_str()
+                        .ok_or_else(|| RunActionError::InvalidDepFileFormat {
+                            key: (*key).to_owned(),
+                            value: format.to_string(),
+                        })?
+                        .parse::<DepFileFormat>()
+                        .with_context(|| format!("Invalid format for dep file `{}`", key))?,
+                    None => DepFileFormat::default(),
+                };
 
                 let tagged = tagged_outputs.get(tag);
                 let count = tagged.map_or(0, |t| t.len());
@@ -841,6 +1052,7 @@ fn register_context_actions(builder: &mut MethodsBuilder) {
                 match dep_files_configuration.labels.entry(tag.dupe()) {
                     Entry::Vacant(v) => {
                         v.insert(Arc::from(*key));
+                        dep_files_configuration.formats.insert(tag.dupe(), format);
                     }
                     Entry::Occupied(o) => {
                         return Err(RunActionError::ConflictingDepFiles {
@@ -870,7 +1082,9 @@ fn register_context_actions(builder: &mut MethodsBuilder) {
         if artifacts.outputs.is_empty() {
             return Err(RunActionError::NoOutputsSpecified.into());
         }
-        let starlark = eval.heap().alloc((starlark_cli, starlark_env));
+        let starlark = eval
+            .heap()
+            .alloc((starlark_cli, starlark_env, starlark_worker));
 
         let action = UnregisteredRunAction {
             category,
@@ -883,6 +1097,11 @@ fn register_context_actions(builder: &mut MethodsBuilder) {
             no_outputs_cleanup,
             allow_cache_upload,
             force_full_hybrid_if_capable,
+            local_resource_limits,
+            timeout,
+            stdin,
+            stdout,
+            stderr,
         };
         this.state().register_action(
             artifacts.inputs,
@@ -896,9 +1115,11 @@ fn register_context_actions(builder: &mut MethodsBuilder) {
     fn download_file<'v>(
         this: &AnalysisActions<'v>,
         #[starlark(require = pos)] output: Value<'v>,
-        #[starlark(require = pos)] url: &str,
+        #[starlark(require = pos)] url: Either<&'v str, Vec<&'v str>>,
         #[starlark(require = named, default = NoneOr::None)] sha1: NoneOr<&str>,
         #[starlark(require = named, default = NoneOr::None)] sha256: NoneOr<&str>,
+        #[starlark(require = named, default = NoneOr::None)] sha512: NoneOr<&str>,
+        #[starlark(require = named, default = NoneOr::None)] blake3: NoneOr<&str>,
         #[starlark(require = named, default = false)] is_executable: bool,
         #[starlark(require = named, default = false)] is_deferrable: bool,
         eval: &mut Evaluator<'v, '_>,
@@ -907,25 +1128,26 @@ fn register_context_actions(builder: &mut MethodsBuilder) {
         let (declaration, output_artifact) =
             this.get_or_declare_output(eval, output, "output", OutputType::File)?;
 
-        let checksum = match (
+        let checksum = Checksum::new(
             sha1.into_option().map(Arc::from),
             sha256.into_option().map(Arc::from),
-        ) {
-            (Some(sha1), None) => Checksum::Sha1(sha1),
-            (None, Some(sha256)) => Checksum::Sha256(sha256),
-            (Some(sha1), Some(sha256)) => Checksum::Both { sha1, sha256 },
-            (None, None) => return Err(DownloadFileError::MissingChecksum.into()),
+            sha512.into_option().map(Arc::from),
+            blake3.into_option().map(Arc::from),
+        )
+        .ok_or(DownloadFileError::MissingChecksum)?;
+
+        let urls: Box<[Arc<str>]> = match url {
+            Either::Left(url) => Box::new([Arc::from(url)]),
+            Either::Right(urls) => urls.into_iter().map(Arc::from).collect(),
         };
+        if urls.is_empty() {
+            return Err(DownloadFileError::MissingUrl.into());
+        }
 
         this.register_action(
             IndexSet::new(),
             indexset![output_artifact],
-            UnregisteredDownloadFileAction::new(
-                checksum,
-                Arc::from(url),
-                is_executable,
-                is_deferrable,
-            ),
+            UnregisteredDownloadFileAction::new(checksum, urls, is_executable, is_deferrable),
             None,
         )?;
 
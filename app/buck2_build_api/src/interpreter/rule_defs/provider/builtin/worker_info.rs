@@ -0,0 +1,224 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::fmt::Debug;
+
+use allocative::Allocative;
+use buck2_build_api_derive::internal_provider;
+use buck2_execute::execute::request::WorkerProtocol;
+use starlark::any::ProvidesStaticType;
+use starlark::coerce::Coerce;
+use starlark::environment::GlobalsBuilder;
+use starlark::eval::Evaluator;
+use starlark::values::list::AllocList;
+use starlark::values::none::NoneOr;
+use starlark::values::Freeze;
+use starlark::values::Trace;
+use starlark::values::UnpackValue;
+use starlark::values::Value;
+use starlark::values::ValueLike;
+use thiserror::Error;
+
+use crate::interpreter::rule_defs::cmd_args::CommandLineArgLike;
+use crate::interpreter::rule_defs::cmd_args::StarlarkCommandLine;
+use crate::interpreter::rule_defs::cmd_args::ValueAsCommandLineLike;
+
+#[derive(Debug, Error)]
+enum WorkerInfoError {
+    #[error("`protocol` must be `proto` or `json`, got `{0}`")]
+    InvalidProtocol(String),
+    #[error("`{0}` must be a positive integer, got `{1}`")]
+    InvalidLimit(&'static str, i32),
+}
+
+/// Provider that signals that a rule's executable can run as a persistent worker. Pass it as the
+/// `worker` of `ctx.actions.run` to send that action's arguments to a long-lived instance of the
+/// worker when the action runs locally, instead of spawning `exe` with them.
+#[internal_provider(worker_info_creator)]
+#[derive(Clone, Debug, Trace, Coerce, Freeze, ProvidesStaticType, Allocative)]
+#[repr(C)]
+pub struct WorkerInfoGen<V> {
+    /// The command that starts the worker. Buck appends `--persistent_worker` when it starts it as
+    /// a worker. Actions that don't run in a worker run this command, followed by their arguments.
+    #[provider(field_type = "StarlarkCommandLine")]
+    exe: V,
+
+    /// How requests are sent to the worker: `"proto"` (the default) for length-delimited
+    /// `WorkRequest` protobuf messages, or `"json"` for newline-delimited JSON.
+    #[provider(field_type = "String")]
+    protocol: V,
+
+    /// How many instances of this worker may run at once. Defaults to the
+    /// `buck2.worker_max_instances` buckconfig.
+    /// This is of type int.type | None
+    #[provider(field_type = "Option<i32>")]
+    max_instances: V,
+
+    /// If set, the worker supports multiplexing: each instance is sent up to this many requests at
+    /// once, which it tells apart by their `request_id`.
+    /// This is of type int.type | None
+    #[provider(field_type = "Option<i32>")]
+    max_multiplex_requests: V,
+}
+
+impl<'v, V: ValueLike<'v>> WorkerInfoGen<V> {
+    pub fn exe(&self) -> &'v dyn CommandLineArgLike {
+        self.exe
+            .to_value()
+            .as_command_line()
+            .expect("a command line from construction")
+    }
+
+    pub fn protocol(&self) -> WorkerProtocol {
+        parse_protocol(self.protocol.to_value().unpack_str().unwrap()).unwrap()
+    }
+
+    pub fn max_instances(&self) -> Option<usize> {
+        unpack_limit(self.max_instances.to_value())
+    }
+
+    pub fn max_multiplex_requests(&self) -> Option<usize> {
+        unpack_limit(self.max_multiplex_requests.to_value())
+    }
+}
+
+fn parse_protocol(protocol: &str) -> anyhow::Result<WorkerProtocol> {
+    match protocol {
+        "proto" => Ok(WorkerProtocol::Proto),
+        "json" => Ok(WorkerProtocol::Json),
+        _ => Err(WorkerInfoError::InvalidProtocol(protocol.to_owned()).into()),
+    }
+}
+
+// NOTE: This unwraps because we validate at construction time.
+fn unpack_limit(value: Value) -> Option<usize> {
+    NoneOr::<i32>::unpack_value(value)
+        .unwrap()
+        .into_option()
+        .map(|v| v as usize)
+}
+
+fn validate_limit(name: &'static str, value: NoneOr<i32>) -> anyhow::Result<()> {
+    match value {
+        NoneOr::Other(v) if v < 1 => Err(WorkerInfoError::InvalidLimit(name, v).into()),
+        _ => Ok(()),
+    }
+}
+
+#[starlark_module]
+fn worker_info_creator(globals: &mut GlobalsBuilder) {
+    #[starlark(type = "WorkerInfo")]
+    fn WorkerInfo<'v>(
+        #[starlark(default = AllocList::EMPTY)] exe: Value<'v>,
+        #[starlark(default = "proto")] protocol: &str,
+        #[starlark(default = NoneOr::None)] max_instances: NoneOr<i32>,
+        #[starlark(default = NoneOr::None)] max_multiplex_requests: NoneOr<i32>,
+        eval: &mut Evaluator<'v, '_>,
+    ) -> anyhow::Result<WorkerInfo<'v>> {
+        let heap = eval.heap();
+        let exe = StarlarkCommandLine::try_from_value(exe)?;
+        parse_protocol(protocol)?;
+        validate_limit("max_instances", max_instances)?;
+        validate_limit("max_multiplex_requests", max_multiplex_requests)?;
+
+        let alloc_limit = |v: NoneOr<i32>| match v {
+            NoneOr::None => Value::new_none(),
+            NoneOr::Other(v) => heap.alloc(v),
+        };
+
+        Ok(WorkerInfo {
+            exe: heap.alloc(exe),
+            protocol: heap.alloc(protocol),
+            max_instances: alloc_limit(max_instances),
+            max_multiplex_requests: alloc_limit(max_multiplex_requests),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_interpreter_for_build::interpreter::testing::Tester;
+    use indoc::indoc;
+
+    use crate::interpreter::rule_defs::artifact::testing::artifactory;
+    use crate::interpreter::rule_defs::register_rule_defs;
+
+    fn tester() -> Tester {
+        let mut tester = Tester::new().unwrap();
+        tester.set_additional_globals(|globals| {
+            artifactory(globals);
+            register_rule_defs(globals);
+        });
+        tester
+    }
+
+    #[test]
+    fn test_construction() -> anyhow::Result<()> {
+        let test = indoc!(
+            r#"
+            def test():
+                a = source_artifact("foo/bar", "worker.py")
+                WorkerInfo()
+                WorkerInfo(exe = ["python3", a])
+                WorkerInfo(exe = cmd_args(a), protocol = "json")
+                info = WorkerInfo(exe = "worker", max_instances = 2, max_multiplex_requests = 8)
+                assert_eq(2, info.max_instances)
+                assert_eq(8, info.max_multiplex_requests)
+                assert_eq("proto", info.protocol)
+            "#
+        );
+        let mut tester = tester();
+        tester.run_starlark_bzl_test(test)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut tester = tester();
+        tester.run_starlark_bzl_test_expecting_error(
+            indoc!(
+                r#"
+            def test():
+                WorkerInfo(exe = "worker", protocol = "xml")
+            "#
+            ),
+            "`protocol`",
+        );
+
+        tester.run_starlark_bzl_test_expecting_error(
+            indoc!(
+                r#"
+            def test():
+                WorkerInfo(exe = "worker", max_instances = 0)
+            "#
+            ),
+            "`max_instances`",
+        );
+
+        tester.run_starlark_bzl_test_expecting_error(
+            indoc!(
+                r#"
+            def test():
+                WorkerInfo(exe = "worker", max_multiplex_requests = -1)
+            "#
+            ),
+            "`max_multiplex_requests`",
+        );
+
+        tester.run_starlark_bzl_test_expecting_error(
+            indoc!(
+                r#"
+            def test():
+                WorkerInfo(exe = {})
+            "#
+            ),
+            "expected command line item",
+        );
+    }
+}
@@ -66,6 +66,12 @@ impl WhatMaterializedCommand {
                                             "http"
                                         }
                                         Some(buck2_data::MaterializationMethod::Write) => "write",
+                                        Some(buck2_data::MaterializationMethod::LocalReflink) => {
+                                            "reflink"
+                                        }
+                                        Some(buck2_data::MaterializationMethod::LocalHardlink) => {
+                                            "hardlink"
+                                        }
                                         _ => "<unknown>",
                                     };
 
@@ -9,6 +9,7 @@
 
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt;
 
 use async_trait::async_trait;
 use buck2_client_ctx::client_ctx::ClientCommandContext;
@@ -17,6 +18,7 @@ use buck2_client_ctx::stream_value::StreamValue;
 use buck2_client_ctx::subscribers::event_log::options::EventLogOptions;
 use buck2_event_observer::what_ran;
 use buck2_event_observer::what_ran::CommandReproducer;
+use buck2_event_observer::what_ran::WhatFailedKind;
 use buck2_event_observer::what_ran::WhatRanOptions;
 use buck2_event_observer::what_ran::WhatRanOutputCommand;
 use buck2_event_observer::what_ran::WhatRanOutputCommandExtra;
@@ -57,6 +59,12 @@ pub enum WhatRanSubcommandOutput {
 ///
 /// Details to reproduce it. For RE, that's the action digest. For local, the command.
 ///
+/// For local commands that ran in their own cgroup (see `buck2.local_action_cgroup_parent`), a
+/// fifth field reports the resources they used, e.g. `memory_peak_bytes=1024 cpu_usage_us=2048`.
+/// Local commands are listed once they finish, so that this is known. When only showing commands
+/// that failed, this field also reports how their action failed: `failure=failed`, or
+/// `failure=timed_out` if its command exceeded its timeout.
+///
 ///
 /// To reproduce an action that ran on RE, use the following command then follow the instructions.
 /// The DIGEST is of the form `hash:size`.
@@ -139,6 +147,15 @@ trait WhatRanComandImplementation: Default {
         options: &WhatRanOptions,
     ) -> anyhow::Result<()>;
 
+    /// Called once all events were received.
+    fn finish(
+        &mut self,
+        _output: &mut impl WhatRanOutputWriter,
+        _options: &WhatRanOptions,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
     async fn execute(
         mut events: impl Stream<Item = anyhow::Result<StreamValue>> + Unpin + Send,
         output: &mut (impl WhatRanOutputWriter + Send),
@@ -153,16 +170,38 @@ trait WhatRanComandImplementation: Default {
             }
         }
 
-        Ok(())
+        cmd.finish(output, options)
     }
 }
 
 /// The state for a WhatRan command. This is all the events we have seen that are
-/// WhatRanRelevantActions. This emits the actions immediately.
+/// WhatRanRelevantActions. This emits the actions immediately, except for local executions, which
+/// are emitted when they finish.
 #[derive(Default)]
 pub struct WhatRanImpl {
     /// Maps action spans to their details.
     known_actions: HashMap<u64, Box<buck2_data::BuckEvent>>,
+    /// Local executions that have not finished yet, by span. Known to be CommandReproducers.
+    pending_local_executions: IndexMap<u64, Box<buck2_data::BuckEvent>>,
+}
+
+impl WhatRanImpl {
+    fn emit_local_execution(
+        &self,
+        event: &buck2_data::BuckEvent,
+        execution_stats: Option<&buck2_data::CommandExecutionStats>,
+        output: &mut impl WhatRanOutputWriter,
+        options: &WhatRanOptions,
+    ) -> anyhow::Result<()> {
+        what_ran::emit_reproducer(
+            self.get(event.parent_id),
+            CommandReproducer::from_buck_data(event.data.as_ref().expect("Checked above"), options)
+                .expect("Checked above"),
+            execution_stats,
+            None,
+            output,
+        )
+    }
 }
 
 impl WhatRanState<u64> for WhatRanImpl {
@@ -186,6 +225,22 @@ impl WhatRanComandImplementation for WhatRanImpl {
         options: &WhatRanOptions,
     ) -> anyhow::Result<()> {
         if let Some(data) = &event.data {
+            if let Some(
+                CommandReproducer::LocalExecute(..) | CommandReproducer::WorkerExecute(..),
+            ) = CommandReproducer::from_buck_data(data, options)
+            {
+                self.pending_local_executions.insert(event.span_id, event);
+                return Ok(());
+            }
+
+            if let buck2_data::buck_event::Data::SpanEnd(..) = data {
+                if let Some(start) = self.pending_local_executions.remove(&event.span_id) {
+                    let execution_stats = what_ran::execution_stats_from_buck_data(data);
+                    self.emit_local_execution(&start, execution_stats, output, options)?;
+                    return Ok(());
+                }
+            }
+
             what_ran::emit_event_if_relevant(event.parent_id, data, &*self, output, options)?;
 
             if WhatRanRelevantAction::from_buck_data(data).is_some() {
@@ -195,6 +250,18 @@ impl WhatRanComandImplementation for WhatRanImpl {
 
         Ok(())
     }
+
+    /// Emit the local executions that never finished (e.g. because the build was interrupted).
+    fn finish(
+        &mut self,
+        output: &mut impl WhatRanOutputWriter,
+        options: &WhatRanOptions,
+    ) -> anyhow::Result<()> {
+        for (_, start) in std::mem::take(&mut self.pending_local_executions) {
+            self.emit_local_execution(&start, None, output, options)?;
+        }
+        Ok(())
+    }
 }
 
 /// The state for a WhatRan command when only showing actions that failed. This stores all the events
@@ -210,8 +277,11 @@ struct WhatFailedEntry {
     /// Knwon to be a WhatRanRelevantAction.
     event: Box<buck2_data::BuckEvent>,
 
-    /// Known to be a CommandReproducer.
-    reproducers: Vec<Box<buck2_data::BuckEvent>>,
+    /// Known to be CommandReproducers, along with the resources they used, if known.
+    reproducers: Vec<(
+        Box<buck2_data::BuckEvent>,
+        Option<buck2_data::CommandExecutionStats>,
+    )>,
 }
 
 impl WhatRanState<u64> for WhatFailedImpl {
@@ -248,22 +318,37 @@ impl WhatRanComandImplementation for WhatFailedImpl {
 
             if CommandReproducer::from_buck_data(data, options).is_some() {
                 if let Some(entry) = self.known_actions.get_mut(&event.parent_id) {
-                    entry.reproducers.push(event);
+                    entry.reproducers.push((event, None));
+                }
+                return Ok(());
+            }
+
+            if let Some(execution_stats) = what_ran::execution_stats_from_buck_data(data) {
+                if let Some(entry) = self.known_actions.get_mut(&event.parent_id) {
+                    if let Some((_, stats)) = entry
+                        .reproducers
+                        .iter_mut()
+                        .find(|(repro, _)| repro.span_id == event.span_id)
+                    {
+                        *stats = Some(execution_stats.clone());
+                    }
                 }
                 return Ok(());
             }
 
             match data {
                 buck2_data::buck_event::Data::SpanEnd(span) => match &span.data {
-                    Some(buck2_data::span_end_event::Data::ActionExecution(action))
-                        if action.failed =>
+                    Some(buck2_data::span_end_event::Data::ActionExecution(action_end))
+                        if action_end.failed =>
                     {
                         if let Some(entry) = self.known_actions.remove(&event.span_id) {
                             let action = WhatRanRelevantAction::from_buck_data(
                                 entry.event.data.as_ref().expect("Checked above"),
                             );
 
-                            for repro in entry.reproducers.iter() {
+                            let failure = WhatFailedKind::from_action_end(action_end);
+
+                            for (repro, execution_stats) in entry.reproducers.iter() {
                                 what_ran::emit_reproducer(
                                     action,
                                     CommandReproducer::from_buck_data(
@@ -271,6 +356,8 @@ impl WhatRanComandImplementation for WhatFailedImpl {
                                         options,
                                     )
                                     .expect("Checked above"),
+                                    execution_stats.as_ref(),
+                                    Some(failure),
                                     output,
                                 )?;
                             }
@@ -291,13 +378,33 @@ impl WhatRanOutputWriter for WhatRanSubcommandOutput {
     fn emit_command(&mut self, command: WhatRanOutputCommand<'_>) -> anyhow::Result<()> {
         match self {
             Self::Tabulated => {
-                buck2_client_ctx::println!(
-                    "{}\t{}\t{}\t{}",
-                    command.reason(),
-                    command.identity(),
-                    command.repro().executor(),
-                    command.repro().as_human_readable()
-                )?;
+                // Optional details go in a last column of `key=value` pairs.
+                let mut details = Vec::new();
+                if let Some(failure) = command.failure() {
+                    details.push(format!("failure={}", failure.as_str()));
+                }
+                if let Some(execution_stats) = command.execution_stats() {
+                    details.push(ExecutionStatsOutput::from(execution_stats).to_string());
+                }
+
+                if details.is_empty() {
+                    buck2_client_ctx::println!(
+                        "{}\t{}\t{}\t{}",
+                        command.reason(),
+                        command.identity(),
+                        command.repro().executor(),
+                        command.repro().as_human_readable()
+                    )?;
+                } else {
+                    buck2_client_ctx::println!(
+                        "{}\t{}\t{}\t{}\t{}",
+                        command.reason(),
+                        command.identity(),
+                        command.repro().executor(),
+                        command.repro().as_human_readable(),
+                        details.join(" "),
+                    )?;
+                }
             }
             Self::Json => {
                 let reproducer = match command.repro() {
@@ -307,6 +414,9 @@ impl WhatRanOutputWriter for WhatRanSubcommandOutput {
                     CommandReproducer::CacheHit(cache_hit) => JsonReproducer::Cache {
                         digest: &cache_hit.action_digest,
                     },
+                    CommandReproducer::LocalCacheHit(cache_hit) => JsonReproducer::LocalCache {
+                        digest: &cache_hit.action_digest,
+                    },
                     CommandReproducer::ReExecute(re_execute) => JsonReproducer::Re {
                         digest: &re_execute.action_digest,
                     },
@@ -323,6 +433,20 @@ impl WhatRanOutputWriter for WhatRanSubcommandOutput {
                             .map(|entry| (entry.key.as_ref(), entry.value.as_ref()))
                             .collect(),
                     },
+                    CommandReproducer::WorkerExecute(worker_execute) => JsonReproducer::Worker {
+                        command: worker_execute.command.as_ref().map_or_else(
+                            || Cow::Owned(Vec::new()),
+                            |command| Cow::Borrowed(command.argv.as_ref()),
+                        ),
+                        env: worker_execute
+                            .command
+                            .as_ref()
+                            .into_iter()
+                            .flat_map(|command| command.env.iter())
+                            .map(|entry| (entry.key.as_ref(), entry.value.as_ref()))
+                            .collect(),
+                        worker: Cow::Borrowed(worker_execute.worker_argv.as_ref()),
+                    },
                 };
 
                 let command = JsonCommand {
@@ -330,6 +454,8 @@ impl WhatRanOutputWriter for WhatRanSubcommandOutput {
                     identity: command.identity(),
                     reproducer,
                     extra: command.extra().map(Into::into),
+                    execution_stats: command.execution_stats().map(Into::into),
+                    failure: command.failure().map(WhatFailedKind::as_str),
                 };
 
                 let serialized_command = serde_json::to_string(&command)?;
@@ -348,6 +474,10 @@ struct JsonCommand<'a> {
     reproducer: JsonReproducer<'a>,
     #[serde(skip_serializing_if = "Option::is_none")]
     extra: Option<JsonExtra<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    execution_stats: Option<ExecutionStatsOutput>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failure: Option<&'a str>,
 }
 
 #[derive(serde::Serialize)]
@@ -359,6 +489,9 @@ enum JsonReproducer<'a> {
     Cache {
         digest: &'a str,
     },
+    LocalCache {
+        digest: &'a str,
+    },
     Re {
         digest: &'a str,
     },
@@ -366,6 +499,11 @@ enum JsonReproducer<'a> {
         command: Cow<'a, [String]>,
         env: IndexMap<&'a str, &'a str>,
     },
+    Worker {
+        command: Cow<'a, [String]>,
+        env: IndexMap<&'a str, &'a str>,
+        worker: Cow<'a, [String]>,
+    },
 }
 
 #[derive(serde::Serialize)]
@@ -382,6 +520,38 @@ impl<'a> From<WhatRanOutputCommandExtra<'a>> for JsonExtra<'a> {
     }
 }
 
+#[derive(serde::Serialize)]
+struct ExecutionStatsOutput {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory_peak_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cpu_usage_us: Option<u64>,
+}
+
+impl From<&buck2_data::CommandExecutionStats> for ExecutionStatsOutput {
+    fn from(stats: &buck2_data::CommandExecutionStats) -> Self {
+        Self {
+            memory_peak_bytes: stats.memory_peak_bytes,
+            cpu_usage_us: stats.cpu_usage_us,
+        }
+    }
+}
+
+/// Formats as `key=value` pairs, for the tabulated output.
+impl fmt::Display for ExecutionStatsOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut sep = "";
+        if let Some(v) = self.memory_peak_bytes {
+            write!(f, "memory_peak_bytes={}", v)?;
+            sep = " ";
+        }
+        if let Some(v) = self.cpu_usage_us {
+            write!(f, "{}cpu_usage_us={}", sep, v)?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -396,6 +566,8 @@ mod tests {
             identity: "some/target",
             reproducer: JsonReproducer::Local { command, env },
             extra: None,
+            execution_stats: None,
+            failure: None,
         }
     }
 
@@ -449,6 +621,67 @@ mod tests {
       "case"
     ]
   }
+}"#;
+        assert_eq!(expected, serde_json::to_string_pretty(&command)?);
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_what_ran_command_with_execution_stats() -> anyhow::Result<()> {
+        let mut command = make_base_command();
+        command.execution_stats = Some(ExecutionStatsOutput {
+            memory_peak_bytes: Some(1024),
+            cpu_usage_us: None,
+        });
+
+        let expected = r#"{
+  "reason": "test.run",
+  "identity": "some/target",
+  "reproducer": {
+    "executor": "Local",
+    "details": {
+      "command": [
+        "some",
+        "command"
+      ],
+      "env": {
+        "KEY": "val"
+      }
+    }
+  },
+  "execution_stats": {
+    "memory_peak_bytes": 1024
+  }
+}"#;
+        assert_eq!(expected, serde_json::to_string_pretty(&command)?);
+        assert_eq!(
+            "memory_peak_bytes=1024",
+            command.execution_stats.unwrap().to_string()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_what_ran_command_with_failure() -> anyhow::Result<()> {
+        let mut command = make_base_command();
+        command.failure = Some(WhatFailedKind::TimedOut.as_str());
+
+        let expected = r#"{
+  "reason": "test.run",
+  "identity": "some/target",
+  "reproducer": {
+    "executor": "Local",
+    "details": {
+      "command": [
+        "some",
+        "command"
+      ],
+      "env": {
+        "KEY": "val"
+      }
+    }
+  },
+  "failure": "timed_out"
 }"#;
         assert_eq!(expected, serde_json::to_string_pretty(&command)?);
         Ok(())
@@ -346,6 +346,16 @@ pub struct CommonBuildOptions {
 
     #[clap(long)]
     upload_all_actions: bool,
+
+    /// Execute every command a second time, bypassing caches, and report the ones whose outputs
+    /// differ between the two executions. Useful to find actions that would poison the cache.
+    #[clap(long)]
+    check_determinism: bool,
+
+    /// Write the report of non-deterministic actions found by --check-determinism to this file.
+    /// Implies --check-determinism.
+    #[clap(long = "determinism-report", value_name = "PATH")]
+    determinism_report: Option<String>,
 }
 
 impl CommonBuildOptions {
@@ -383,6 +393,8 @@ impl CommonBuildOptions {
             eager_dep_files: self.eager_dep_files,
             upload_all_actions: self.upload_all_actions,
             no_remote_cache: self.no_remote_cache,
+            check_determinism: self.check_determinism || self.determinism_report.is_some(),
+            determinism_report_filename: self.determinism_report.clone().unwrap_or_default(),
         }
     }
 }
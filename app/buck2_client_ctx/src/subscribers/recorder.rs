@@ -460,7 +460,10 @@ mod imp {
                 }
                 Some(buck2_data::executor_stage_start::Stage::Local(local_stage)) => {
                     match &local_stage.stage {
-                        Some(buck2_data::local_stage::Stage::Execute(_)) => {
+                        Some(
+                            buck2_data::local_stage::Stage::Execute(_)
+                            | buck2_data::local_stage::Stage::WorkerExecute(_),
+                        ) => {
                             self.time_to_first_command_execution_start
                                 .get_or_insert_with(|| self.start_time.elapsed());
                         }
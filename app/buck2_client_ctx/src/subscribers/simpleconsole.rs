@@ -122,7 +122,7 @@ fn eprint_command_details(
                 remote_command.action_digest
             )?;
         }
-        Some(Command::OmittedLocalCommand(..)) | None => {
+        Some(Command::OmittedLocalCommand(..)) | Some(Command::LocalCacheHit(..)) | None => {
             // Nothing to show in this case.
         }
     };
@@ -676,7 +676,7 @@ fn lines_for_command_details(
                 .with(Color::DarkRed),
             )]));
         }
-        Some(Command::OmittedLocalCommand(..)) | None => {
+        Some(Command::OmittedLocalCommand(..)) | Some(Command::LocalCacheHit(..)) | None => {
             // Nothing to show in this case.
         }
     };
@@ -11,6 +11,7 @@ use std::collections::HashSet;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use allocative::Allocative;
 use anyhow::Context;
@@ -20,6 +21,8 @@ use buck2_core::cells::cell_path::CellPathRef;
 use buck2_core::cells::cell_root_path::CellRootPathBuf;
 use buck2_core::cells::name::CellName;
 use buck2_core::cells::CellResolver;
+use buck2_core::fs::fs_util;
+use buck2_core::fs::paths::abs_norm_path::AbsNormPath;
 use buck2_core::fs::paths::file_name::FileNameBuf;
 use buck2_core::fs::project_rel_path::ProjectRelativePath;
 use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
@@ -28,6 +31,8 @@ use derive_more::Display;
 use dice::DiceComputations;
 use dice::DiceTransactionUpdater;
 use dice::Key;
+use dice::KeyPersistence;
+use dice::PersistentKey;
 use dupe::Dupe;
 use gazebo::cmp::PartialEqAny;
 
@@ -35,11 +40,14 @@ use crate::dice::cells::HasCellResolver;
 use crate::dice::data::HasIoProvider;
 use crate::dice::file_ops::keys::FileOpsKey;
 use crate::dice::file_ops::keys::FileOpsValue;
+use crate::file_ops::FileDigest;
+use crate::file_ops::FileMetadata;
 use crate::file_ops::FileOps;
 use crate::file_ops::RawDirEntry;
 use crate::file_ops::RawPathMetadata;
 use crate::file_ops::ReadDirOutput;
 use crate::file_ops::SimpleDirEntry;
+use crate::file_ops::TrackedFileDigest;
 use crate::ignores::AllCellIgnores;
 use crate::ignores::HasAllCellIgnores;
 use crate::ignores::MaybeIgnoredCellRelativePath;
@@ -441,6 +449,132 @@ impl Key for PathMetadataKey {
     fn validity(x: &Self::Value) -> bool {
         x.is_ok()
     }
+
+    fn persistence() -> Option<KeyPersistence<Self>> {
+        Some(KeyPersistence::new())
+    }
+}
+
+/// Persisting file digests means we don't need to hash every source file again after the daemon
+/// restarts. A digest is reused as long as the file's metadata shows it wasn't modified.
+///
+/// This is the only key that is persisted so far. Interpreter and analysis results hold frozen
+/// Starlark values, which can't be serialized. Package listings could be, but the file watcher
+/// invalidates them through the `ReadDirKey`s of their directories: reusing a persisted listing
+/// would still need to read all of those directories, which is most of the cost of computing it.
+#[async_trait]
+impl PersistentKey for PathMetadataKey {
+    fn serialize_key(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(self.0.to_string().into_bytes())
+    }
+
+    fn serialize_value(&self, value: &Self::Value) -> anyhow::Result<Option<Vec<u8>>> {
+        // Only files are expensive to compute.
+        Ok(match value {
+            Ok(Some(RawPathMetadata::File(meta))) => {
+                Some(format!("{} {}", meta.digest, meta.is_executable as u8).into_bytes())
+            }
+            _ => None,
+        })
+    }
+
+    fn deserialize_value(
+        &self,
+        ctx: &DiceComputations,
+        value: &[u8],
+    ) -> anyhow::Result<Self::Value> {
+        let value = std::str::from_utf8(value)?;
+        let (digest, is_executable) = value
+            .split_once(' ')
+            .with_context(|| format!("Invalid persisted file metadata: `{}`", value))?;
+
+        let cas_digest_config = ctx.global_data().get_io_provider().cas_digest_config();
+        let digest = FileDigest::parse_digest(digest, cas_digest_config)?;
+
+        Ok(Ok(Some(RawPathMetadata::File(FileMetadata {
+            digest: TrackedFileDigest::new(digest, cas_digest_config),
+            is_executable: is_executable == "1",
+        }))))
+    }
+
+    async fn inputs_fingerprint(
+        &self,
+        ctx: &DiceComputations,
+        computed_since: Option<SystemTime>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let cells = ctx.get_cell_resolver().await?;
+        let io = ctx.global_data().get_io_provider();
+        let root = io.project_root().root().to_buf();
+        let path = cells.resolve_path(self.0.as_ref())?;
+
+        tokio::task::spawn_blocking(move || path_fingerprint(&root, &path, computed_since)).await?
+    }
+}
+
+/// Identifies the state of a path on disk without reading it: if the file was modified, its
+/// fingerprint changes. We only fingerprint paths without symlinks in them, since resolving
+/// symlinks is not expensive anyway.
+///
+/// If `changed_since` is set, this fails if the path may have been modified since then.
+#[cfg(unix)]
+fn path_fingerprint(
+    root: &AbsNormPath,
+    path: &ProjectRelativePath,
+    changed_since: Option<SystemTime>,
+) -> anyhow::Result<Vec<u8>> {
+    use std::os::unix::fs::MetadataExt;
+    use std::time::Duration;
+
+    // Timestamps on disk can be coarser than the system clock, or come from the clock of another
+    // machine on network filesystems.
+    const TIMESTAMP_SLACK: Duration = Duration::from_secs(1);
+
+    let mut meta = None;
+    let mut current = root.to_buf();
+    for component in path.iter() {
+        current.push(component);
+        let m = fs_util::symlink_metadata(&current)?;
+        if m.file_type().is_symlink() {
+            return Err(anyhow::anyhow!("`{}` is a symlink", current));
+        }
+        meta = Some(m);
+    }
+    let meta = meta.context("Cannot fingerprint the project root")?;
+
+    if let Some(changed_since) = changed_since {
+        let changed = SystemTime::UNIX_EPOCH
+            + Duration::new(meta.ctime().try_into()?, meta.ctime_nsec().try_into()?);
+        if changed + TIMESTAMP_SLACK >= changed_since {
+            return Err(anyhow::anyhow!(
+                "`{}` was modified too recently to be fingerprinted",
+                path
+            ));
+        }
+    }
+
+    Ok(format!(
+        "{}:{}:{}:{}:{}.{}:{}.{}",
+        meta.dev(),
+        meta.ino(),
+        meta.mode(),
+        meta.size(),
+        meta.mtime(),
+        meta.mtime_nsec(),
+        meta.ctime(),
+        meta.ctime_nsec()
+    )
+    .into_bytes())
+}
+
+#[cfg(not(unix))]
+fn path_fingerprint(
+    _root: &AbsNormPath,
+    _path: &ProjectRelativePath,
+    _changed_since: Option<SystemTime>,
+) -> anyhow::Result<Vec<u8>> {
+    Err(anyhow::anyhow!(
+        "Fingerprinting paths is not supported on this platform"
+    ))
 }
 
 #[async_trait]
@@ -11,6 +11,7 @@ use std::hash::Hash;
 use std::hash::Hasher;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use allocative::Allocative;
 use buck2_core::collections::sorted_map::SortedMap;
@@ -20,8 +21,12 @@ use internment_tweaks::Intern;
 use internment_tweaks::StaticInterner;
 use once_cell::sync::Lazy;
 
-#[derive(Debug, Eq, Hash, PartialEq, Clone, Dupe, Allocative)]
-pub struct LocalExecutorOptions {}
+#[derive(Debug, Default, Eq, Hash, PartialEq, Clone, Dupe, Allocative)]
+pub struct LocalExecutorOptions {
+    /// Run actions in a sandbox where only their declared inputs are visible. This is only
+    /// supported on Linux, and requires the forkserver.
+    pub use_sandbox: bool,
+}
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone, Dupe, Display, Allocative)]
 pub struct RemoteExecutorUseCase(Intern<String>);
@@ -157,6 +162,8 @@ impl Default for CacheUploadBehavior {
 pub struct CommandGenerationOptions {
     pub path_separator: PathSeparatorKind,
     pub output_paths_behavior: OutputPathsBehavior,
+    /// Timeout for commands that don't set their own.
+    pub default_timeout: Option<Duration>,
 }
 
 #[derive(Debug, Eq, PartialEq, Hash, Allocative)]
@@ -184,10 +191,11 @@ pub enum HybridExecutionLevel {
 impl CommandExecutorConfig {
     pub fn testing_local() -> Arc<CommandExecutorConfig> {
         Arc::new(CommandExecutorConfig {
-            executor: Executor::Local(LocalExecutorOptions {}),
+            executor: Executor::Local(LocalExecutorOptions::default()),
             options: CommandGenerationOptions {
                 path_separator: PathSeparatorKind::system_default(),
                 output_paths_behavior: Default::default(),
+                default_timeout: None,
             },
         })
     }
@@ -145,6 +145,11 @@ impl InvocationPaths {
             .join(ForwardRelativePath::unchecked_new("re_logs"))
     }
 
+    pub fn worker_logs_dir(&self) -> AbsNormPathBuf {
+        self.buck_out_path()
+            .join(ForwardRelativePath::unchecked_new("worker_logs"))
+    }
+
     pub fn build_count_dir(&self) -> AbsNormPathBuf {
         self.buck_out_path()
             .join(ForwardRelativePath::unchecked_new("build_count"))
@@ -187,8 +192,29 @@ impl InvocationPaths {
         FileName::unchecked_new("materializer_state")
     }
 
+    pub fn dep_files_state_path(&self) -> AbsNormPathBuf {
+        self.cache_dir_path().join(self.dep_files_state_dir_name())
+    }
+
+    pub fn dep_files_state_dir_name(&self) -> &FileName {
+        FileName::unchecked_new("dep_files_state")
+    }
+
+    /// Subdirectory of `cache_dir` responsible for storing the DICE snapshot
+    pub fn dice_state_path(&self) -> AbsNormPathBuf {
+        self.cache_dir_path().join(self.dice_state_dir_name())
+    }
+
+    pub fn dice_state_dir_name(&self) -> &FileName {
+        FileName::unchecked_new("dice_state")
+    }
+
     pub fn valid_cache_dirs(&self) -> Vec<&FileName> {
-        vec![self.materializer_state_dir_name()]
+        vec![
+            self.materializer_state_dir_name(),
+            self.dep_files_state_dir_name(),
+            self.dice_state_dir_name(),
+        ]
     }
 }
 
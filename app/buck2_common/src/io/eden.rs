@@ -309,6 +309,10 @@ impl IoProvider for EdenIoProvider {
     fn project_root(&self) -> &ProjectRoot {
         self.fs.project_root()
     }
+
+    fn cas_digest_config(&self) -> CasDigestConfig {
+        self.fs.cas_digest_config()
+    }
 }
 
 /// We don't request sync on individual calls because we make a dedicated call for this before we
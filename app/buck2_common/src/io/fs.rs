@@ -52,10 +52,6 @@ impl FsIoProvider {
             cas_digest_config,
         }
     }
-
-    pub fn cas_digest_config(&self) -> CasDigestConfig {
-        self.cas_digest_config
-    }
 }
 
 #[derive(Debug, Error)]
@@ -149,6 +145,10 @@ impl IoProvider for FsIoProvider {
     fn project_root(&self) -> &ProjectRoot {
         &self.fs
     }
+
+    fn cas_digest_config(&self) -> CasDigestConfig {
+        self.cas_digest_config
+    }
 }
 
 fn read_path_metadata<P: AsRef<AbsNormPath>>(
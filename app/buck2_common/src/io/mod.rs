@@ -48,6 +48,9 @@ pub trait IoProvider: Allocative + Send + Sync {
     fn eq_token(&self) -> PartialEqAny<'_>;
 
     fn project_root(&self) -> &ProjectRoot;
+
+    /// The digest config used for the digests of the files this provider reads.
+    fn cas_digest_config(&self) -> CasDigestConfig;
 }
 
 impl PartialEq for dyn IoProvider {
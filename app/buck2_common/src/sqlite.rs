@@ -14,6 +14,7 @@ use anyhow::Context;
 use itertools::Itertools;
 use parking_lot::Mutex;
 use rusqlite::Connection;
+use rusqlite::OptionalExtension;
 
 /// A generic sqlite table for storing string key-value pairs.
 pub struct KeyValueSqliteTable {
@@ -76,6 +77,36 @@ impl KeyValueSqliteTable {
             .with_context(|| format!("reading from sqlite table {}", self.table_name))?;
         Ok(map)
     }
+
+    pub fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        let sql = format!("SELECT value FROM {} WHERE key = ?", self.table_name);
+        tracing::trace!(sql = %sql, key = %key, "reading from table");
+        self.connection
+            .lock()
+            .query_row(&sql, [key], |row| row.get(0))
+            .optional()
+            .with_context(|| format!("reading from sqlite table {}", self.table_name))
+    }
+
+    pub fn delete(&self, key: &str) -> anyhow::Result<()> {
+        let sql = format!("DELETE FROM {} WHERE key = ?", self.table_name);
+        tracing::trace!(sql = %sql, key = %key, "deleting from table");
+        self.connection
+            .lock()
+            .execute(&sql, [key])
+            .with_context(|| format!("deleting from sqlite table {}", self.table_name))?;
+        Ok(())
+    }
+
+    pub fn delete_all(&self) -> anyhow::Result<()> {
+        let sql = format!("DELETE FROM {}", self.table_name);
+        tracing::trace!(sql = %sql, "deleting all from table");
+        self.connection
+            .lock()
+            .execute(&sql, [])
+            .with_context(|| format!("deleting from sqlite table {}", self.table_name))?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -108,5 +139,15 @@ mod tests {
 
         let actual = table.read_all().unwrap();
         assert_eq!(expected, actual);
+
+        assert_eq!(table.get("foo").unwrap().as_deref(), Some("foo"));
+        assert_eq!(table.get("baz").unwrap(), None);
+
+        table.delete("foo").unwrap();
+        assert_eq!(table.get("foo").unwrap(), None);
+        assert_eq!(table.read_all().unwrap().len(), 1);
+
+        table.delete_all().unwrap();
+        assert!(table.read_all().unwrap().is_empty());
     }
 }
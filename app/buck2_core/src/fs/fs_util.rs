@@ -259,6 +259,58 @@ pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> anyhow::Result<u6
     })
 }
 
+/// Clone `from` to `to` so that they share their data until either of them is modified. This is
+/// only supported on Linux, on filesystems that support it (e.g. btrfs or XFS), and fails
+/// otherwise, in which case `to` is not left behind.
+pub fn reflink<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> anyhow::Result<()> {
+    let _guard = IoCounterKey::Copy.guard();
+    reflink_impl(from.as_ref(), to.as_ref()).with_context(|| {
+        format!(
+            "reflink(from={}, to={})",
+            P::as_ref(&from).display(),
+            Q::as_ref(&to).display()
+        )
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn reflink_impl(from: &Path, to: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // `_IOW(0x94, 9, int)`, from `linux/fs.h`.
+    const FICLONE: libc::c_ulong = 0x40049409;
+
+    let src = File::open(from)?;
+    let permissions = src.metadata()?.permissions();
+    let dest = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(to)?;
+
+    // SAFETY: both file descriptors are valid for the duration of the call.
+    let res = unsafe { libc::ioctl(dest.as_raw_fd(), FICLONE as _, src.as_raw_fd()) };
+    let res = if res == 0 {
+        dest.set_permissions(permissions)
+    } else {
+        Err(io::Error::last_os_error())
+    };
+
+    if res.is_err() {
+        drop(dest);
+        let _ignored = fs::remove_file(to);
+    }
+    res
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink_impl(_from: &Path, _to: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflinks are not supported on this platform",
+    ))
+}
+
 pub fn read_link<P: AsRef<Path>>(path: P) -> anyhow::Result<PathBuf> {
     let _guard = IoCounterKey::ReadLink.guard();
     fs::read_link(&path).with_context(|| format!("read_link({})", P::as_ref(&path).display()))
@@ -840,6 +892,23 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_reflink() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let src = tempdir.path().join("src");
+        let dest = tempdir.path().join("dest");
+
+        fs_util::write(&src, b"data")?;
+        // Whether this works depends on the filesystem the tests run on, but either way we should
+        // not leave a partial copy behind.
+        match fs_util::reflink(&src, &dest) {
+            Ok(()) => assert_eq!(fs_util::read_to_string(&dest)?, "data"),
+            Err(_) => assert!(!fs_util::try_exists(&dest)?),
+        }
+
+        Ok(())
+    }
+
     #[cfg(windows)]
     #[test]
     fn test_windows_relative_path() -> anyhow::Result<()> {
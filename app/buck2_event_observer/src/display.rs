@@ -332,6 +332,8 @@ pub fn display_executor_stage(
         Stage::Prepare(..) => "prepare",
         Stage::CacheQuery(..) => "re_action_cache",
         Stage::CacheHit(..) => "re_download",
+        Stage::LocalCacheQuery(..) => "local_action_cache",
+        Stage::LocalCacheHit(..) => "local_cache_restore",
         Stage::Re(re) => {
             use buck2_data::re_stage::Stage;
 
@@ -353,6 +355,8 @@ pub fn display_executor_stage(
                 Stage::Execute(..) => "local_execute",
                 Stage::MaterializeInputs(..) => "local_materialize_inputs",
                 Stage::PrepareOutputs(_) => "local_prepare_outputs",
+                Stage::WorkerInit(..) => "local_worker_init",
+                Stage::WorkerExecute(..) => "local_worker_execute",
             }
         }
     };
@@ -517,6 +521,7 @@ fn failure_reason_for_command_execution(
     let locality = match command.command {
         Some(Command::RemoteCommand(..)) => "Remote ",
         Some(Command::LocalCommand(..)) | Some(Command::OmittedLocalCommand(..)) => "Local ",
+        Some(Command::LocalCacheHit(..)) => "Local cache ",
         None => "",
     };
 
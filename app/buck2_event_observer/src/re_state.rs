@@ -158,6 +158,12 @@ impl ReState {
                 last.re_get_digest_expirations_finished_successfully,
                 last.re_get_digest_expirations_finished_with_error,
             )?);
+            if last.re_retries > 0 {
+                r.push(Line::unstyled(&format!(
+                    "{:<20}: {:>5}",
+                    "retries", last.re_retries
+                ))?);
+            }
         }
         Ok(r)
     }
@@ -64,6 +64,8 @@ pub struct WhatRanOutputCommand<'a> {
     identity: &'a str,
     repro: CommandReproducer<'a>,
     extra: Option<WhatRanOutputCommandExtra<'a>>,
+    execution_stats: Option<&'a buck2_data::CommandExecutionStats>,
+    failure: Option<WhatFailedKind>,
 }
 
 impl WhatRanOutputCommand<'_> {
@@ -79,6 +81,39 @@ impl WhatRanOutputCommand<'_> {
     pub fn extra(&self) -> Option<WhatRanOutputCommandExtra<'_>> {
         self.extra
     }
+    /// Resources used by the command. This is only known once the command has finished.
+    pub fn execution_stats(&self) -> Option<&buck2_data::CommandExecutionStats> {
+        self.execution_stats
+    }
+    /// How the action this command belongs to failed. Only set when showing failed actions.
+    pub fn failure(&self) -> Option<WhatFailedKind> {
+        self.failure
+    }
+}
+
+/// How an action failed.
+#[derive(Clone, Copy, Dupe, Debug, PartialEq, Eq)]
+pub enum WhatFailedKind {
+    Failed,
+    TimedOut,
+}
+
+impl WhatFailedKind {
+    /// Determine how an action failed from the status of the last command it ran, which is the one
+    /// whose failure is reported to the user.
+    pub fn from_action_end(action: &buck2_data::ActionExecutionEnd) -> Self {
+        match action.commands.last().and_then(|c| c.status.as_ref()) {
+            Some(buck2_data::command_execution::Status::Timeout(..)) => Self::TimedOut,
+            _ => Self::Failed,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Failed => "failed",
+            Self::TimedOut => "timed_out",
+        }
+    }
 }
 
 #[derive(Clone, Copy, Dupe)]
@@ -121,12 +156,14 @@ fn emit<T: fmt::Display + Copy>(
     state: &impl WhatRanState<T>,
     output: &mut impl WhatRanOutputWriter,
 ) -> anyhow::Result<()> {
-    emit_reproducer(state.get(parent_span_id), repro, output)
+    emit_reproducer(state.get(parent_span_id), repro, None, None, output)
 }
 
 pub fn emit_reproducer(
     action: Option<WhatRanRelevantAction<'_>>,
     repro: CommandReproducer<'_>,
+    execution_stats: Option<&buck2_data::CommandExecutionStats>,
+    failure: Option<WhatFailedKind>,
     output: &mut impl WhatRanOutputWriter,
 ) -> anyhow::Result<()> {
     let (reason, identity, extra) = match action {
@@ -160,18 +197,38 @@ pub fn emit_reproducer(
         identity: &identity,
         repro,
         extra,
+        execution_stats,
+        failure,
     })?;
 
     Ok(())
 }
 
+/// Extract the resources used by a command from the end of its executor stage, if it reported
+/// them.
+pub fn execution_stats_from_buck_data(
+    data: &buck2_data::buck_event::Data,
+) -> Option<&buck2_data::CommandExecutionStats> {
+    match data {
+        buck2_data::buck_event::Data::SpanEnd(span) => match &span.data {
+            Some(buck2_data::span_end_event::Data::ExecutorStage(stage)) => {
+                stage.execution_stats.as_ref()
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 /// The reproduction details for this command.
 #[derive(Clone, Copy, Dupe)]
 pub enum CommandReproducer<'a> {
     CacheQuery(&'a buck2_data::CacheQuery),
     CacheHit(&'a buck2_data::CacheHit),
+    LocalCacheHit(&'a buck2_data::LocalCacheHit),
     ReExecute(&'a buck2_data::ReExecute),
     LocalExecute(&'a buck2_data::LocalExecute),
+    WorkerExecute(&'a buck2_data::LocalWorkerExecute),
 }
 
 impl<'a> CommandReproducer<'a> {
@@ -179,8 +236,10 @@ impl<'a> CommandReproducer<'a> {
         match self {
             Self::CacheQuery(..) => "cache_query",
             Self::CacheHit(..) => "cache",
+            Self::LocalCacheHit(..) => "local_cache",
             Self::ReExecute(..) => "re",
             Self::LocalExecute(..) => "local",
+            Self::WorkerExecute(..) => "worker",
         }
     }
 
@@ -207,6 +266,11 @@ impl<'a> CommandReproducer<'a> {
                         {
                             return Some(CommandReproducer::CacheHit(cache_hit));
                         }
+                        Some(buck2_data::executor_stage_start::Stage::LocalCacheHit(cache_hit))
+                            if !options.skip_cache_hits =>
+                        {
+                            return Some(CommandReproducer::LocalCacheHit(cache_hit));
+                        }
                         Some(buck2_data::executor_stage_start::Stage::Re(re_stage))
                             if !options.skip_remote_executions =>
                         {
@@ -227,6 +291,13 @@ impl<'a> CommandReproducer<'a> {
                                             local_execute,
                                         ));
                                     }
+                                    Some(buck2_data::local_stage::Stage::WorkerExecute(
+                                        worker_execute,
+                                    )) => {
+                                        return Some(CommandReproducer::WorkerExecute(
+                                            worker_execute,
+                                        ));
+                                    }
                                     _ => {}
                                 }
                             }
@@ -257,6 +328,9 @@ impl<'a> fmt::Display for HumanReadableCommandReproducer<'a> {
             CommandReproducer::CacheHit(re_action_cache) => {
                 write!(formatter, "{}", re_action_cache.action_digest)
             }
+            CommandReproducer::LocalCacheHit(local_cache_hit) => {
+                write!(formatter, "{}", local_cache_hit.action_digest)
+            }
             CommandReproducer::ReExecute(re_action_cache) => {
                 write!(formatter, "{}", re_action_cache.action_digest)
             }
@@ -267,6 +341,13 @@ impl<'a> fmt::Display for HumanReadableCommandReproducer<'a> {
                     Ok(())
                 }
             }
+            CommandReproducer::WorkerExecute(worker_execute) => {
+                if let Some(command) = &worker_execute.command {
+                    write!(formatter, "{}", local_command_to_string(command))
+                } else {
+                    Ok(())
+                }
+            }
         }
     }
 }
@@ -101,6 +101,10 @@ impl CommandExecutor {
         ExecutorFs::new(&self.0.artifact_fs, self.0.options.path_separator)
     }
 
+    pub fn options(&self) -> &CommandGenerationOptions {
+        &self.0.options
+    }
+
     /// Execute a command.
     ///
     /// This intentionally does not return a Result since we want to capture information about the
@@ -156,7 +160,8 @@ impl CommandExecutor {
                     request.env(),
                     input_digest,
                     action_metadata_blobs,
-                    None,
+                    request.timeout().as_ref(),
+                    request.stdin(),
                     self.0.re_platform.clone(),
                     false,
                     digest_config,
@@ -232,6 +237,7 @@ fn re_create_action(
     input_digest: &TrackedFileDigest,
     blobs: impl Iterator<Item = (Vec<u8>, TrackedFileDigest)>,
     timeout: Option<&Duration>,
+    stdin: Option<&ProjectRelativePath>,
     platform: RE::Platform,
     do_not_cache: bool,
     digest_config: DigestConfig,
@@ -314,6 +320,12 @@ fn re_create_action(
         ),
         timeout,
         do_not_cache,
+        // The file the command reads its stdin from must be part of the action digest, but it
+        // isn't something the executor should schedule on, so it goes in the salt rather than in
+        // a platform property.
+        salt: stdin
+            .map(|stdin| format!("buck2.stdin:{}", stdin).into_bytes())
+            .unwrap_or_default(),
         ..Default::default()
     };
 
@@ -326,3 +338,124 @@ fn re_create_action(
             .expect("We did put a platform a few lines up"),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use buck2_common::executor_config::OutputPathsBehavior;
+    use buck2_common::file_ops::TrackedFileDigest;
+    use buck2_core::fs::project_rel_path::ProjectRelativePath;
+    use dupe::Dupe;
+    use prost::Message;
+    use remote_execution as RE;
+    use sorted_vector_map::SortedVectorMap;
+
+    use super::re_create_action;
+    use crate::digest::CasDigestToReExt;
+    use crate::digest_config::DigestConfig;
+    use crate::execute::action_digest::ActionDigest;
+    use crate::execute::prepared::PreparedAction;
+
+    fn create_action(
+        timeout: Option<Duration>,
+        stdin: Option<&ProjectRelativePath>,
+    ) -> anyhow::Result<PreparedAction> {
+        let digest_config = DigestConfig::testing_default();
+        re_create_action(
+            vec!["true".to_owned()],
+            &[],
+            None,
+            &SortedVectorMap::new(),
+            &TrackedFileDigest::empty(digest_config.cas_digest_config()),
+            std::iter::empty(),
+            timeout.as_ref(),
+            stdin,
+            RE::Platform {
+                properties: vec![
+                    RE::Property {
+                        name: "a".to_owned(),
+                        value: "1".to_owned(),
+                    },
+                    RE::Property {
+                        name: "c".to_owned(),
+                        value: "2".to_owned(),
+                    },
+                ],
+            },
+            false,
+            digest_config,
+            OutputPathsBehavior::Strict,
+        )
+    }
+
+    fn find_blob<'a>(
+        prepared: &'a PreparedAction,
+        matches: impl Fn(&TrackedFileDigest) -> bool,
+    ) -> &'a [u8] {
+        let digest = prepared
+            .blobs
+            .keys()
+            .find(|d| matches(d))
+            .expect("Digest is one of the blobs");
+        prepared.blobs.get(digest).unwrap().as_slice()
+    }
+
+    fn decode_action(prepared: &PreparedAction) -> anyhow::Result<(RE::Action, RE::Command)> {
+        let action = RE::Action::decode(find_blob(prepared, |d| {
+            let digest: ActionDigest = d.data().dupe().coerce();
+            digest == prepared.action
+        }))?;
+        let command = RE::Command::decode(find_blob(prepared, |d| {
+            Some(d.to_grpc()) == action.command_digest
+        }))?;
+        Ok((action, command))
+    }
+
+    #[test]
+    fn test_timeout() -> anyhow::Result<()> {
+        let (action, _) = decode_action(&create_action(None, None)?)?;
+        assert_eq!(action.timeout, None);
+
+        let (action, _) = decode_action(&create_action(Some(Duration::from_millis(1500)), None)?)?;
+        assert_eq!(
+            action.timeout,
+            Some(prost_types::Duration {
+                seconds: 1,
+                nanos: 500_000_000,
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_stdin() -> anyhow::Result<()> {
+        let no_stdin = create_action(None, None)?;
+        let stdin = create_action(None, Some(ProjectRelativePath::new("foo/stdin")?))?;
+        let other_stdin = create_action(None, Some(ProjectRelativePath::new("bar/stdin")?))?;
+
+        // Where the command reads its stdin from is part of the action digest.
+        assert_ne!(no_stdin.action, stdin.action);
+        assert_ne!(stdin.action, other_stdin.action);
+
+        let (action, command) = decode_action(&stdin)?;
+        assert_eq!(action.salt, b"buck2.stdin:foo/stdin");
+
+        // The platform is left alone, so it doesn't affect scheduling.
+        let properties = command
+            .platform
+            .unwrap()
+            .properties
+            .into_iter()
+            .map(|p| (p.name, p.value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            properties,
+            vec![
+                ("a".to_owned(), "1".to_owned()),
+                ("c".to_owned(), "2".to_owned()),
+            ]
+        );
+        Ok(())
+    }
+}
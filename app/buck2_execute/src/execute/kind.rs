@@ -22,6 +22,8 @@ pub enum CommandExecutionKind {
         digest: ActionDigest,
         command: Vec<String>,
         env: SortedVectorMap<String, String>,
+        /// Only available when the command ran in its own cgroup.
+        execution_stats: Option<buck2_data::CommandExecutionStats>,
     },
     /// This action was executed via a remote executor.
     #[display(fmt = "remote")]
@@ -29,6 +31,9 @@ pub enum CommandExecutionKind {
     /// This action was served by the action cache and not executed.
     #[display(fmt = "action_cache")]
     ActionCache { digest: ActionDigest },
+    /// This action was served by the local on-disk action cache and not executed.
+    #[display(fmt = "local_action_cache")]
+    LocalActionCache { digest: ActionDigest },
 }
 
 impl CommandExecutionKind {
@@ -37,6 +42,7 @@ impl CommandExecutionKind {
             Self::Local { .. } => buck2_data::ActionExecutionKind::Local,
             Self::Remote { .. } => buck2_data::ActionExecutionKind::Remote,
             Self::ActionCache { .. } => buck2_data::ActionExecutionKind::ActionCache,
+            Self::LocalActionCache { .. } => buck2_data::ActionExecutionKind::LocalActionCache,
         }
     }
 }
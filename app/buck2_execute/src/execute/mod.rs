@@ -39,5 +39,19 @@ pub fn executor_stage_async<F: Future>(
     let event = buck2_data::ExecutorStageStart {
         stage: Some(stage.into()),
     };
-    span_async(event, f.map(|v| (v, buck2_data::ExecutorStageEnd {})))
+    span_async(
+        event,
+        f.map(|v| (v, buck2_data::ExecutorStageEnd::default())),
+    )
+}
+
+/// Like `executor_stage_async`, but the future also produces the end event for the stage.
+pub fn executor_stage_with_end_async<F: Future<Output = (R, buck2_data::ExecutorStageEnd)>, R>(
+    stage: impl Into<buck2_data::executor_stage_start::Stage>,
+    f: F,
+) -> impl Future<Output = R> {
+    let event = buck2_data::ExecutorStageStart {
+        stage: Some(stage.into()),
+    };
+    span_async(event, f)
 }
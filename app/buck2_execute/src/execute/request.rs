@@ -141,9 +141,85 @@ pub struct CommandExecutionRequest {
     local_environment_inheritance: Option<EnvironmentInheritance>,
     /// Whether this command should be uploaded to cache when successful.
     allow_cache_upload: bool,
+    /// Whether to ignore cached results for this command, and always execute it.
+    skip_cache_lookup: bool,
     /// Whether this command should override the fallback-only behavior on an hybrid executor and
     /// thus always run as if the executor was full-hybrid, assuming it is capable.
     force_full_hybrid_if_capable: bool,
+    /// Resource limits to apply if this command runs locally.
+    local_resource_limits: LocalResourceLimits,
+    /// A persistent worker this command can be sent to if it runs locally.
+    worker: Option<WorkerSpec>,
+    /// A file to use as the command's stdin, relative to the project root. Only supported by the
+    /// local executor.
+    stdin: Option<ProjectRelativePathBuf>,
+    /// Where to write the command's stdout and stderr. These are not among `artifact_outputs`:
+    /// executors don't produce them, they are written from the command's std streams once it
+    /// succeeded, wherever it ran.
+    std_redirects: StdRedirects,
+}
+
+/// Outputs to write the std streams of a command to, see `CommandExecutionRequest::std_redirects`.
+#[derive(Debug, Default, Clone)]
+pub struct StdRedirects {
+    pub stdout: Option<BuckOutPath>,
+    pub stderr: Option<BuckOutPath>,
+}
+
+impl StdRedirects {
+    pub fn is_empty(&self) -> bool {
+        self.stdout.is_none() && self.stderr.is_none()
+    }
+}
+
+/// Resource limits for a command that runs locally. These are only enforced when local actions run
+/// in cgroups, see `ExecutorGlobalKnobs::local_action_cgroup_parent`.
+#[derive(Debug, Default, Clone, Copy, Dupe, PartialEq, Eq, Hash, Allocative)]
+pub struct LocalResourceLimits {
+    pub memory_max_bytes: Option<u64>,
+    /// In percent of one CPU.
+    pub cpu_max_percent: Option<u32>,
+}
+
+impl LocalResourceLimits {
+    /// Use the limits set here, and the ones from `defaults` for those that aren't.
+    pub fn or(self, defaults: LocalResourceLimits) -> Self {
+        Self {
+            memory_max_bytes: self.memory_max_bytes.or(defaults.memory_max_bytes),
+            cpu_max_percent: self.cpu_max_percent.or(defaults.cpu_max_percent),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.memory_max_bytes.is_none() && self.cpu_max_percent.is_none()
+    }
+}
+
+/// The protocol a persistent worker speaks on its stdin and stdout.
+#[derive(Debug, Display, Clone, Copy, Dupe, PartialEq, Eq, Hash, Allocative)]
+pub enum WorkerProtocol {
+    /// Varint length-delimited `WorkRequest` and `WorkResponse` protobuf messages.
+    #[display(fmt = "proto")]
+    Proto,
+    /// Newline-delimited `WorkRequest` and `WorkResponse` JSON objects.
+    #[display(fmt = "json")]
+    Json,
+}
+
+/// A persistent worker that can run a command instead of spawning it. The command's arguments
+/// always start with `exe`, so that executors that don't support workers can just run it as a
+/// regular command. Executors that do send the remaining arguments to the worker.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Allocative)]
+pub struct WorkerSpec {
+    /// The command that starts the worker.
+    pub exe: Vec<String>,
+    pub protocol: WorkerProtocol,
+    /// How many instances of this worker to run at most. Defaults to the
+    /// `buck2.worker_max_instances` buckconfig.
+    pub max_instances: Option<usize>,
+    /// If set, the worker supports multiplexing, and each instance can be sent this many
+    /// requests at once.
+    pub max_multiplex_requests: Option<usize>,
 }
 
 impl CommandExecutionRequest {
@@ -168,7 +244,12 @@ impl CommandExecutionRequest {
             outputs_cleanup: true,
             local_environment_inheritance: None,
             allow_cache_upload: false,
+            skip_cache_lookup: false,
             force_full_hybrid_if_capable: false,
+            local_resource_limits: LocalResourceLimits::default(),
+            worker: None,
+            stdin: None,
+            std_redirects: StdRedirects::default(),
         }
     }
 
@@ -295,6 +376,15 @@ impl CommandExecutionRequest {
         self.allow_cache_upload
     }
 
+    pub fn with_skip_cache_lookup(mut self, skip_cache_lookup: bool) -> Self {
+        self.skip_cache_lookup = skip_cache_lookup;
+        self
+    }
+
+    pub fn skip_cache_lookup(&self) -> bool {
+        self.skip_cache_lookup
+    }
+
     pub fn with_force_full_hybrid_if_capable(mut self, force_full_hybrid_if_capable: bool) -> Self {
         self.force_full_hybrid_if_capable = force_full_hybrid_if_capable;
         self
@@ -303,6 +393,45 @@ impl CommandExecutionRequest {
     pub fn force_full_hybrid_if_capable(&self) -> bool {
         self.force_full_hybrid_if_capable
     }
+
+    pub fn with_local_resource_limits(
+        mut self,
+        local_resource_limits: LocalResourceLimits,
+    ) -> Self {
+        self.local_resource_limits = local_resource_limits;
+        self
+    }
+
+    pub fn local_resource_limits(&self) -> LocalResourceLimits {
+        self.local_resource_limits
+    }
+
+    pub fn with_worker(mut self, worker: Option<WorkerSpec>) -> Self {
+        self.worker = worker;
+        self
+    }
+
+    pub fn worker(&self) -> Option<&WorkerSpec> {
+        self.worker.as_ref()
+    }
+
+    pub fn with_stdin(mut self, stdin: Option<ProjectRelativePathBuf>) -> Self {
+        self.stdin = stdin;
+        self
+    }
+
+    pub fn stdin(&self) -> Option<&ProjectRelativePath> {
+        self.stdin.as_deref()
+    }
+
+    pub fn with_std_redirects(mut self, std_redirects: StdRedirects) -> Self {
+        self.std_redirects = std_redirects;
+        self
+    }
+
+    pub fn std_redirects(&self) -> &StdRedirects {
+        &self.std_redirects
+    }
 }
 
 /// Is an output a file or a directory
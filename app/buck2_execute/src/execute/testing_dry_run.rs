@@ -77,6 +77,7 @@ impl PreparedCommandExecutor for DryRunExecutor {
             digest: ActionDigest::empty(digest_config.cas_digest_config()),
             command: Default::default(),
             env: Default::default(),
+            execution_stats: None,
         };
 
         match request
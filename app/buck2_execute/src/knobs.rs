@@ -7,8 +7,19 @@
  * of this source tree.
  */
 
+use std::sync::Arc;
+
+use buck2_core::fs::paths::abs_path::AbsPathBuf;
 use dupe::Dupe;
 
+use crate::execute::request::LocalResourceLimits;
+
 /// Daemon-level config that can tweak how the executors work.
 #[derive(Clone, Dupe, Default)]
-pub struct ExecutorGlobalKnobs {}
+pub struct ExecutorGlobalKnobs {
+    /// If set, local actions that run via the forkserver each get their own cgroup (v2) under this
+    /// one, so that their resource usage can be limited and reported.
+    pub local_action_cgroup_parent: Option<Arc<AbsPathBuf>>,
+    /// Limits for local actions that don't set their own.
+    pub local_action_limits: LocalResourceLimits,
+}
@@ -8,6 +8,7 @@
  */
 
 use std::io::Write;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -22,45 +23,151 @@ use buck2_core::fs::project::ProjectRoot;
 use buck2_core::fs::project_rel_path::ProjectRelativePath;
 use buck2_core::is_open_source;
 use bytes::Bytes;
+use dice::UserComputationData;
 use digest::DynDigest;
 use dupe::Dupe;
 use futures::future::Future;
 use futures::stream::Stream;
 use futures::StreamExt;
+use once_cell::sync::Lazy;
 use reqwest::Client;
 use reqwest::RequestBuilder;
 use reqwest::Response;
 use reqwest::StatusCode;
+use reqwest::Url;
 use sha1::Digest;
 use sha1::Sha1;
 use sha2::Sha256;
+use sha2::Sha512;
 use smallvec::SmallVec;
 use thiserror::Error;
 
 use crate::digest_config::DigestConfig;
+use crate::materialize::netrc::Netrc;
 
+/// The checksums a download is validated against. At least one of them is set.
 #[derive(Debug, Clone, Dupe, Allocative)]
-pub enum Checksum {
-    Sha1(Arc<str>),
-    Sha256(Arc<str>),
-    Both { sha1: Arc<str>, sha256: Arc<str> },
+pub struct Checksum {
+    sha1: Option<Arc<str>>,
+    sha256: Option<Arc<str>>,
+    sha512: Option<Arc<str>>,
+    blake3: Option<Arc<str>>,
 }
 
 impl Checksum {
-    pub fn sha1(&self) -> Option<&str> {
-        match self {
-            Self::Sha1(sha1) => Some(sha1),
-            Self::Sha256(..) => None,
-            Self::Both { sha1, .. } => Some(sha1),
+    /// Returns `None` if no checksum is provided.
+    pub fn new(
+        sha1: Option<Arc<str>>,
+        sha256: Option<Arc<str>>,
+        sha512: Option<Arc<str>>,
+        blake3: Option<Arc<str>>,
+    ) -> Option<Self> {
+        if sha1.is_none() && sha256.is_none() && sha512.is_none() && blake3.is_none() {
+            return None;
         }
+
+        Some(Self {
+            sha1,
+            sha256,
+            sha512,
+            blake3,
+        })
+    }
+
+    pub fn sha1(&self) -> Option<&str> {
+        self.sha1.as_deref()
     }
 
     pub fn sha256(&self) -> Option<&str> {
-        match self {
-            Self::Sha1(..) => None,
-            Self::Sha256(sha256) => Some(sha256),
-            Self::Both { sha256, .. } => Some(sha256),
+        self.sha256.as_deref()
+    }
+
+    pub fn sha512(&self) -> Option<&str> {
+        self.sha512.as_deref()
+    }
+
+    pub fn blake3(&self) -> Option<&str> {
+        self.blake3.as_deref()
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Invalid download mirror `{0}`, expected `<url prefix>=<mirror prefix>`")]
+struct InvalidUrlMirror(String);
+
+/// A mirror for downloads, written `<url prefix>=<mirror prefix>`. URLs that start with the URL
+/// prefix are first tried with it replaced by the mirror prefix.
+#[derive(Debug, Clone, PartialEq, Eq, Allocative)]
+pub struct UrlMirror {
+    prefix: String,
+    replacement: String,
+}
+
+impl FromStr for UrlMirror {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.split_once('=') {
+            Some((prefix, replacement)) if !prefix.is_empty() && !replacement.is_empty() => {
+                Ok(Self {
+                    prefix: prefix.to_owned(),
+                    replacement: replacement.to_owned(),
+                })
+            }
+            _ => Err(InvalidUrlMirror(s.to_owned()).into()),
+        }
+    }
+}
+
+/// The mirrors configured via `download.mirrors` in buckconfig.
+#[derive(Debug, Clone, Default, Allocative)]
+pub struct UrlMirrors(Vec<UrlMirror>);
+
+impl UrlMirrors {
+    pub fn new(mirrors: Vec<UrlMirror>) -> Self {
+        Self(mirrors)
+    }
+
+    /// The URLs to try, in order, to download something available at `urls`. Each URL is
+    /// preceded by its mirrors, in the order they are configured.
+    pub fn expand(&self, urls: &[Arc<str>]) -> Vec<Arc<str>> {
+        let mut res: Vec<Arc<str>> = Vec::with_capacity(urls.len());
+
+        let mut push = |url: Arc<str>| {
+            if !res.contains(&url) {
+                res.push(url);
+            }
+        };
+
+        for url in urls {
+            for mirror in &self.0 {
+                if let Some(rest) = url.strip_prefix(&mirror.prefix) {
+                    push(Arc::from(format!("{}{}", mirror.replacement, rest)));
+                }
+            }
+            push(url.dupe());
         }
+
+        res
+    }
+}
+
+pub trait HasUrlMirrors {
+    fn set_url_mirrors(&mut self, mirrors: Arc<UrlMirrors>);
+
+    fn get_url_mirrors(&self) -> Arc<UrlMirrors>;
+}
+
+impl HasUrlMirrors for UserComputationData {
+    fn set_url_mirrors(&mut self, mirrors: Arc<UrlMirrors>) {
+        self.data.set(mirrors);
+    }
+
+    fn get_url_mirrors(&self) -> Arc<UrlMirrors> {
+        // Without mirrors configured, we just download from the original URLs.
+        self.data
+            .get::<Arc<UrlMirrors>>()
+            .map_or_else(|_| Default::default(), |mirrors| mirrors.dupe())
     }
 }
 
@@ -134,6 +241,15 @@ fn http_error_label(status: StatusCode) -> &'static str {
     "Unknown"
 }
 
+#[derive(Debug, Error)]
+enum HttpUrlsError {
+    #[error("No URL to download from")]
+    NoUrls,
+
+    #[error("Failed to download from any of the URLs:\n{}", .0.join("\n"))]
+    AllUrlsFailed(Vec<String>),
+}
+
 #[derive(Debug, Error)]
 enum HttpHeadError {
     #[error("Error performing a http_head request")]
@@ -173,7 +289,57 @@ impl AsHttpError for HttpDownloadError {
     }
 }
 
-pub fn http_client() -> anyhow::Result<Client> {
+/// A HTTP client, along with the `.netrc` credentials to authenticate with.
+#[derive(Clone)]
+pub struct HttpClient {
+    client: Client,
+    netrc: Arc<Netrc>,
+}
+
+impl HttpClient {
+    pub fn new(client: Client, netrc: Netrc) -> Self {
+        Self {
+            client,
+            netrc: Arc::new(netrc),
+        }
+    }
+
+    fn get(&self, url: &str) -> RequestBuilder {
+        self.authenticate(self.client.get(url), url)
+    }
+
+    fn head(&self, url: &str) -> RequestBuilder {
+        self.authenticate(self.client.head(url), url)
+    }
+
+    /// Add the credentials for the URL's host, unless the URL carries its own.
+    fn authenticate(&self, req: RequestBuilder, url: &str) -> RequestBuilder {
+        let url = match Url::parse(url) {
+            Ok(url) => url,
+            Err(_) => return req,
+        };
+
+        if !url.username().is_empty() {
+            return req;
+        }
+
+        match url.host_str().and_then(|host| self.netrc.credentials(host)) {
+            Some(credentials) => req.basic_auth(&credentials.login, credentials.password.as_ref()),
+            None => req,
+        }
+    }
+}
+
+/// The `.netrc` credentials, read once rather than for every download. If the file can't be read,
+/// we download without credentials rather than failing every download.
+static NETRC: Lazy<Arc<Netrc>> = Lazy::new(|| {
+    Arc::new(Netrc::load().unwrap_or_else(|e| {
+        tracing::warn!("Not using credentials from `.netrc`: {:#}", e);
+        Netrc::default()
+    }))
+});
+
+pub fn http_client() -> anyhow::Result<HttpClient> {
     let mut builder = Client::builder();
 
     if !is_open_source() {
@@ -185,7 +351,10 @@ pub fn http_client() -> anyhow::Result<Client> {
         builder = builder.no_proxy();
     }
 
-    builder.build().context("Error creating http client")
+    Ok(HttpClient {
+        client: builder.build().context("Error creating http client")?,
+        netrc: NETRC.dupe(),
+    })
 }
 
 async fn http_dispatch(req: RequestBuilder, url: &str) -> Result<Response, HttpError> {
@@ -215,63 +384,96 @@ async fn http_dispatch(req: RequestBuilder, url: &str) -> Result<Response, HttpE
     Ok(response)
 }
 
-pub async fn http_head(client: &Client, url: &str) -> anyhow::Result<Response> {
-    Ok(http_retry(|| async {
-        let response = http_dispatch(client.head(url), url).await?;
-        Result::<_, HttpHeadError>::Ok(response)
+pub async fn http_head(client: &HttpClient, urls: &[Arc<str>]) -> anyhow::Result<Response> {
+    http_try_urls(urls, |url| async move {
+        Ok(http_retry(|| async {
+            let response = http_dispatch(client.head(url), url).await?;
+            Result::<_, HttpHeadError>::Ok(response)
+        })
+        .await?)
     })
-    .await?)
+    .await
 }
 
 pub async fn http_download(
-    client: &Client,
+    client: &HttpClient,
     fs: &ProjectRoot,
     digest_config: DigestConfig,
     path: &ProjectRelativePath,
-    url: &str,
+    urls: &[Arc<str>],
     checksum: &Checksum,
     executable: bool,
 ) -> anyhow::Result<TrackedFileDigest> {
-    let abs_path = fs.resolve(path);
+    let abs_path = &fs.resolve(path);
     if let Some(dir) = abs_path.parent() {
         fs_util::create_dir_all(fs.resolve(dir))?;
     }
 
-    Ok(http_retry(|| async {
-        let file = std::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(path.to_string())
-            .with_context(|| format!("open({})", abs_path))
-            .map_err(HttpDownloadError::IoError)?;
+    http_try_urls(urls, |url| async move {
+        Ok(http_retry(|| async {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(abs_path)
+                .with_context(|| format!("open({})", abs_path))
+                .map_err(HttpDownloadError::IoError)?;
 
-        let response = http_dispatch(client.get(url), url).await?;
+            let response = http_dispatch(client.get(url), url).await?;
 
-        let stream = response.bytes_stream();
-        let buf_writer = std::io::BufWriter::new(file);
+            let stream = response.bytes_stream();
+            let buf_writer = std::io::BufWriter::new(file);
 
-        let digest = copy_and_hash(
-            url,
-            &abs_path,
-            stream,
-            buf_writer,
-            digest_config.cas_digest_config(),
-            checksum,
-        )
-        .await?;
+            let digest = copy_and_hash(
+                url,
+                abs_path,
+                stream,
+                buf_writer,
+                digest_config.cas_digest_config(),
+                checksum,
+            )
+            .await?;
 
-        if executable {
-            fs.set_executable(path)
-                .map_err(HttpDownloadError::IoError)?;
-        }
+            if executable {
+                fs.set_executable(path)
+                    .map_err(HttpDownloadError::IoError)?;
+            }
 
-        Result::<_, HttpDownloadError>::Ok(TrackedFileDigest::new(
-            digest,
-            digest_config.cas_digest_config(),
-        ))
+            Result::<_, HttpDownloadError>::Ok(TrackedFileDigest::new(
+                digest,
+                digest_config.cas_digest_config(),
+            ))
+        })
+        .await?)
     })
-    .await?)
+    .await
+}
+
+/// Try `exec` with each of the URLs in order, until one of them succeeds.
+async fn http_try_urls<'a, Exec, F, T>(urls: &'a [Arc<str>], exec: Exec) -> anyhow::Result<T>
+where
+    Exec: Fn(&'a str) -> F,
+    F: Future<Output = anyhow::Result<T>>,
+{
+    let mut errors = Vec::new();
+
+    for (i, url) in urls.iter().enumerate() {
+        match exec(&**url).await {
+            Ok(res) => return Ok(res),
+            Err(e) => {
+                if i + 1 < urls.len() {
+                    tracing::warn!("Trying the next URL after an error: {:#}", e);
+                }
+                errors.push(e);
+            }
+        }
+    }
+
+    if errors.len() <= 1 {
+        return Err(errors.pop().unwrap_or_else(|| HttpUrlsError::NoUrls.into()));
+    }
+
+    Err(HttpUrlsError::AllUrlsFailed(errors.iter().map(|e| format!("{:#}", e)).collect()).into())
 }
 
 /// Copy a stream into a writer while producing its digest and checksumming it.
@@ -293,7 +495,7 @@ async fn copy_and_hash(
         ExtraDigest(Box<dyn DynDigest + Send>),
     }
 
-    let mut validators = SmallVec::<[_; 2]>::new();
+    let mut validators = SmallVec::<[_; 4]>::new();
 
     if let Some(sha1) = checksum.sha1() {
         let validator = if digester.algorithm() == DigestAlgorithm::Sha1 {
@@ -315,6 +517,24 @@ async fn copy_and_hash(
         validators.push((validator, sha256, "sha256"));
     }
 
+    if let Some(sha512) = checksum.sha512() {
+        validators.push((
+            Validator::ExtraDigest(Box::new(Sha512::new()) as _),
+            sha512,
+            "sha512",
+        ));
+    }
+
+    if let Some(blake3) = checksum.blake3() {
+        let validator = if digester.algorithm() == DigestAlgorithm::Blake3 {
+            Validator::PrimaryDigest
+        } else {
+            Validator::ExtraDigest(Box::new(blake3::Hasher::new()) as _)
+        };
+
+        validators.push((validator, blake3, "blake3"));
+    }
+
     while let Some(chunk) = stream.next().await {
         let chunk = chunk.map_err(|source| HttpError::HttpTransferError {
             received: digester.bytes_read(),
@@ -398,10 +618,34 @@ where
 mod test {
     use assert_matches::assert_matches;
     use buck2_common::cas_digest::testing;
+    use buck2_core::fs::paths::abs_norm_path::AbsNormPathBuf;
     use futures::stream;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
 
     use super::*;
 
+    const FOOBAR_SHA1: &str = "8843d7f92416211de9ebb963ff4ce28125932878";
+    const FOOBAR_SHA256: &str = "c3ab8ff13720e8ad9047dd39466b3c8974e592c2fa383d4a3960714caef0c4f2";
+    const FOOBAR_SHA512: &str = "0a50261ebd1a390fed2bf326f2673c145582a6342d523204973d0219337f81616a8069b012587cf5635f6925f1b56c360230c19b273500ee013e030601bf2425";
+    const FOOBAR_BLAKE3: &str = "aa51dcd43d5c6c5203ee16906fd6b35db298b9b2e1de3fce81811d4806b76b7d";
+
+    fn checksum(
+        sha1: Option<&str>,
+        sha256: Option<&str>,
+        sha512: Option<&str>,
+        blake3: Option<&str>,
+    ) -> Checksum {
+        Checksum::new(
+            sha1.map(Arc::from),
+            sha256.map(Arc::from),
+            sha512.map(Arc::from),
+            blake3.map(Arc::from),
+        )
+        .unwrap()
+    }
+
     async fn do_test(
         digest_config: CasDigestConfig,
         checksum: &Checksum,
@@ -425,34 +669,44 @@ mod test {
     async fn test_copy_and_hash_ok() -> anyhow::Result<()> {
         let (digest, bytes) = do_test(
             testing::blake3(),
-            &Checksum::Both {
-                sha1: Arc::from("8843d7f92416211de9ebb963ff4ce28125932878"),
-                sha256: Arc::from(
-                    "c3ab8ff13720e8ad9047dd39466b3c8974e592c2fa383d4a3960714caef0c4f2",
-                ),
-            },
+            &checksum(Some(FOOBAR_SHA1), Some(FOOBAR_SHA256), None, None),
         )
         .await?;
 
-        assert_eq!(
-            digest.to_string(),
-            "aa51dcd43d5c6c5203ee16906fd6b35db298b9b2e1de3fce81811d4806b76b7d:6"
-        );
+        assert_eq!(digest.to_string(), format!("{}:6", FOOBAR_BLAKE3));
 
         assert_eq!(std::str::from_utf8(&bytes).unwrap(), "foobar");
 
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_copy_and_hash_sha512_and_blake3() -> anyhow::Result<()> {
+        for digest_config in [testing::sha1(), testing::blake3()] {
+            do_test(
+                digest_config,
+                &checksum(None, None, Some(FOOBAR_SHA512), Some(FOOBAR_BLAKE3)),
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_copy_and_hash_invalid_primary_hash() -> anyhow::Result<()> {
         assert_matches!(
-            do_test(testing::sha1(), &Checksum::Sha1(Arc::from("oops")),).await,
+            do_test(testing::sha1(), &checksum(Some("oops"), None, None, None)).await,
             Err(HttpDownloadError::InvalidChecksum(..))
         );
 
         assert_matches!(
-            do_test(testing::sha256(), &Checksum::Sha256(Arc::from("oops")),).await,
+            do_test(testing::sha256(), &checksum(None, Some("oops"), None, None)).await,
+            Err(HttpDownloadError::InvalidChecksum(..))
+        );
+
+        assert_matches!(
+            do_test(testing::blake3(), &checksum(None, None, None, Some("oops"))).await,
             Err(HttpDownloadError::InvalidChecksum(..))
         );
 
@@ -462,15 +716,228 @@ mod test {
     #[tokio::test]
     async fn test_copy_and_hash_invalid_secondary_hash() -> anyhow::Result<()> {
         assert_matches!(
-            do_test(testing::blake3(), &Checksum::Sha1(Arc::from("oops")),).await,
+            do_test(testing::blake3(), &checksum(Some("oops"), None, None, None)).await,
             Err(HttpDownloadError::InvalidChecksum(..))
         );
 
         assert_matches!(
-            do_test(testing::blake3(), &Checksum::Sha256(Arc::from("oops")),).await,
+            do_test(testing::blake3(), &checksum(None, Some("oops"), None, None)).await,
             Err(HttpDownloadError::InvalidChecksum(..))
         );
 
+        assert_matches!(
+            do_test(testing::blake3(), &checksum(None, None, Some("oops"), None)).await,
+            Err(HttpDownloadError::InvalidChecksum(..))
+        );
+
+        assert_matches!(
+            do_test(testing::sha1(), &checksum(None, None, None, Some("oops"))).await,
+            Err(HttpDownloadError::InvalidChecksum(..))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_url_mirrors() -> anyhow::Result<()> {
+        let mirrors = UrlMirrors::new(vec![
+            "https://github.com/=https://mirror.example.com/github/".parse()?,
+            "https://github.com/=https://backup.example.com/".parse()?,
+            "https://example.org/=https://mirror.example.com/github/".parse()?,
+        ]);
+
+        let urls = [
+            Arc::from("https://github.com/foo/bar.tar.gz"),
+            Arc::from("https://example.org/foo/bar.tar.gz"),
+        ];
+
+        assert_eq!(
+            mirrors
+                .expand(&urls)
+                .iter()
+                .map(|u| &**u)
+                .collect::<Vec<_>>(),
+            vec![
+                "https://mirror.example.com/github/foo/bar.tar.gz",
+                "https://backup.example.com/foo/bar.tar.gz",
+                "https://github.com/foo/bar.tar.gz",
+                "https://example.org/foo/bar.tar.gz",
+            ]
+        );
+
+        assert!("https://github.com/".parse::<UrlMirror>().is_err());
+        assert!("=https://github.com/".parse::<UrlMirror>().is_err());
+
+        Ok(())
+    }
+
+    /// A stand-in HTTP server that serves `foobar` at `/file` and 404s everywhere else. If
+    /// `authorization` is set, requests that don't send it get a 401.
+    async fn serve(authorization: Option<&'static str>) -> anyhow::Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    let request = String::from_utf8_lossy(&request).into_owned();
+
+                    let authorized = authorization.map_or(true, |expected| {
+                        request.lines().any(|line| match line.split_once(':') {
+                            Some((name, value)) => {
+                                name.eq_ignore_ascii_case("authorization")
+                                    && value.trim() == expected
+                            }
+                            None => false,
+                        })
+                    });
+                    let is_head = request.starts_with("HEAD ");
+                    let path = request.split(' ').nth(1).unwrap_or_default();
+
+                    let (status, body) = if !authorized {
+                        ("401 Unauthorized", "")
+                    } else if path == "/file" {
+                        ("200 OK", "foobar")
+                    } else {
+                        ("404 Not Found", "")
+                    };
+
+                    let mut response = format!(
+                        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        status,
+                        body.len()
+                    );
+                    if !is_head {
+                        response.push_str(body);
+                    }
+                    let _ignored = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+
+        Ok(format!("http://{}", addr))
+    }
+
+    fn test_client(netrc: Netrc) -> anyhow::Result<HttpClient> {
+        Ok(HttpClient::new(
+            Client::builder().no_proxy().build()?,
+            netrc,
+        ))
+    }
+
+    async fn download(
+        client: &HttpClient,
+        urls: &[Arc<str>],
+        checksum: &Checksum,
+    ) -> anyhow::Result<String> {
+        let tempdir = tempfile::tempdir()?;
+        let fs =
+            ProjectRoot::new_unchecked(AbsNormPathBuf::try_from(tempdir.path().to_path_buf())?);
+        let path = ProjectRelativePath::new("out/file")?;
+
+        let digest = http_download(
+            client,
+            &fs,
+            DigestConfig::testing_default(),
+            path,
+            urls,
+            checksum,
+            false,
+        )
+        .await?;
+        assert_eq!(digest.size(), 6);
+
+        Ok(fs_util::read_to_string(fs.resolve(path))?)
+    }
+
+    #[tokio::test]
+    async fn test_http_download_tries_urls_in_order() -> anyhow::Result<()> {
+        let server = serve(None).await?;
+        let client = test_client(Netrc::default())?;
+
+        let urls = [
+            // Nothing listens on port 1.
+            Arc::from("http://127.0.0.1:1/file"),
+            Arc::from(format!("{}/missing", server)),
+            Arc::from(format!("{}/file", server)),
+        ];
+
+        assert_eq!(
+            download(
+                &client,
+                &urls,
+                &checksum(None, Some(FOOBAR_SHA256), None, None)
+            )
+            .await?,
+            "foobar"
+        );
+        assert_eq!(
+            http_head(&client, &urls)
+                .await?
+                .headers()
+                .get(reqwest::header::CONTENT_LENGTH)
+                .map(|v| v.as_bytes()),
+            Some(b"6".as_slice())
+        );
+
+        // When every URL fails, all the errors are reported.
+        let err = download(
+            &client,
+            &urls[..2],
+            &checksum(None, Some(FOOBAR_SHA256), None, None),
+        )
+        .await
+        .unwrap_err();
+        assert_matches!(
+            err.downcast_ref::<HttpUrlsError>(),
+            Some(HttpUrlsError::AllUrlsFailed(errors)) if errors.len() == 2
+        );
+
+        // A bad checksum fails the download.
+        assert!(
+            download(
+                &client,
+                &urls[2..],
+                &checksum(None, None, Some("oops"), None)
+            )
+            .await
+            .is_err()
+        );
+
+        assert_matches!(
+            download(&client, &[], &checksum(Some(FOOBAR_SHA1), None, None, None))
+                .await
+                .unwrap_err()
+                .downcast_ref::<HttpUrlsError>(),
+            Some(HttpUrlsError::NoUrls)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_http_download_netrc() -> anyhow::Result<()> {
+        // alice:secret
+        let server = serve(Some("Basic YWxpY2U6c2VjcmV0")).await?;
+        let urls = [Arc::from(format!("{}/file", server))];
+        let checksum = checksum(Some(FOOBAR_SHA1), None, None, None);
+
+        let client = test_client(Netrc::parse(
+            "machine 127.0.0.1 login alice password secret",
+        )?)?;
+        assert_eq!(download(&client, &urls, &checksum).await?, "foobar");
+
+        let client = test_client(Netrc::parse("machine example.com login alice")?)?;
+        assert!(download(&client, &urls, &checksum).await.is_err());
+
         Ok(())
     }
 }
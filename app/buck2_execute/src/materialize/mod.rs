@@ -12,4 +12,5 @@ pub mod eden_api;
 pub mod http;
 
 pub mod materializer;
+pub mod netrc;
 pub mod nodisk;
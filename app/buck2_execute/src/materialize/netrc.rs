@@ -0,0 +1,206 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Parsing of `.netrc` files, which hold credentials for the hosts we download from.
+
+use std::path::PathBuf;
+
+use anyhow::Context as _;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+enum NetrcError {
+    #[error("Expected a value after `{0}`")]
+    MissingValue(String),
+    #[error("Unexpected token `{0}`")]
+    UnexpectedToken(String),
+    #[error("`{0}` must follow a `machine` or `default` entry")]
+    NoEntry(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetrcCredentials {
+    pub login: String,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Netrc {
+    machines: Vec<(String, NetrcCredentials)>,
+    default: Option<NetrcCredentials>,
+}
+
+impl Netrc {
+    /// Load the `.netrc` file pointed to by `$NETRC`, or `~/.netrc` otherwise. A missing file
+    /// means no credentials.
+    pub fn load() -> anyhow::Result<Self> {
+        let path = match std::env::var_os("NETRC") {
+            Some(path) => PathBuf::from(path),
+            None => match dirs::home_dir() {
+                Some(home) => home.join(".netrc"),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(
+                    anyhow::Error::from(e).context(format!("Error reading `{}`", path.display()))
+                );
+            }
+        };
+
+        Self::parse(&contents).with_context(|| format!("Error parsing `{}`", path.display()))
+    }
+
+    pub fn parse(contents: &str) -> anyhow::Result<Self> {
+        enum Entry {
+            Machine(String),
+            Default,
+        }
+
+        let mut netrc = Self::default();
+        let mut current: Option<(Entry, Option<String>, Option<String>)> = None;
+
+        fn finish(netrc: &mut Netrc, entry: Option<(Entry, Option<String>, Option<String>)>) {
+            // Entries without a login can't be used for authentication.
+            if let Some((entry, Some(login), password)) = entry {
+                let credentials = NetrcCredentials { login, password };
+                match entry {
+                    Entry::Machine(host) => netrc.machines.push((host, credentials)),
+                    Entry::Default => netrc.default = Some(credentials),
+                }
+            }
+        }
+
+        let mut lines = contents.lines();
+        while let Some(line) = lines.next() {
+            let mut tokens = line.split_whitespace();
+            while let Some(token) = tokens.next() {
+                if token.starts_with('#') {
+                    break;
+                }
+
+                let mut value = || {
+                    tokens
+                        .next()
+                        .map(str::to_owned)
+                        .ok_or_else(|| NetrcError::MissingValue(token.to_owned()))
+                };
+
+                match token {
+                    "machine" => {
+                        let host = value()?;
+                        finish(&mut netrc, current.take());
+                        current = Some((Entry::Machine(host), None, None));
+                    }
+                    "default" => {
+                        finish(&mut netrc, current.take());
+                        current = Some((Entry::Default, None, None));
+                    }
+                    "login" | "password" | "account" => {
+                        let v = value()?;
+                        let (_, login, password) = current
+                            .as_mut()
+                            .ok_or_else(|| NetrcError::NoEntry(token.to_owned()))?;
+                        match token {
+                            "login" => *login = Some(v),
+                            "password" => *password = Some(v),
+                            _ => {}
+                        }
+                    }
+                    "macdef" => {
+                        // Macro definitions run until the next blank line, we don't use them.
+                        finish(&mut netrc, current.take());
+                        for line in lines.by_ref() {
+                            if line.trim().is_empty() {
+                                break;
+                            }
+                        }
+                        break;
+                    }
+                    _ => return Err(NetrcError::UnexpectedToken(token.to_owned()).into()),
+                }
+            }
+        }
+
+        finish(&mut netrc, current);
+
+        Ok(netrc)
+    }
+
+    /// The credentials to use for `host`, if any. The first matching `machine` entry wins, and
+    /// `default` applies to every other host.
+    pub fn credentials(&self, host: &str) -> Option<&NetrcCredentials> {
+        self.machines
+            .iter()
+            .find(|(machine, _)| machine == host)
+            .map(|(_, credentials)| credentials)
+            .or(self.default.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let netrc = Netrc::parse(
+            r#"
+# Comments are ignored.
+machine example.com login alice password secret
+machine example.com login bob password other
+
+machine mirror.example.com
+    login carol
+    account ignored
+macdef init
+cd /pub
+binary
+
+default login anonymous
+"#,
+        )?;
+
+        assert_eq!(
+            netrc.credentials("example.com"),
+            Some(&NetrcCredentials {
+                login: "alice".to_owned(),
+                password: Some("secret".to_owned()),
+            })
+        );
+        assert_eq!(
+            netrc.credentials("mirror.example.com"),
+            Some(&NetrcCredentials {
+                login: "carol".to_owned(),
+                password: None,
+            })
+        );
+        assert_eq!(
+            netrc.credentials("other.example.com"),
+            Some(&NetrcCredentials {
+                login: "anonymous".to_owned(),
+                password: None,
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Netrc::parse("machine").is_err());
+        assert!(Netrc::parse("login alice").is_err());
+        assert!(Netrc::parse("machine example.com user alice").is_err());
+        assert_eq!(Netrc::parse("").unwrap(), Netrc::default());
+    }
+}
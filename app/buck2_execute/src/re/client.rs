@@ -94,6 +94,8 @@ pub struct RemoteExecutionClientStats {
     pub uploaded: u64,
     /// In bytes.
     pub downloaded: u64,
+    /// Requests the client sent again after transient errors.
+    pub retries: u64,
     pub uploads: RemoteExecutionClientOpStats,
     pub downloads: RemoteExecutionClientOpStats,
     pub action_cache: RemoteExecutionClientOpStats,
@@ -348,6 +350,21 @@ impl RemoteExecutionClient {
             .await
     }
 
+    pub async fn download_tree(
+        &self,
+        digest: TDigest,
+        use_case: RemoteExecutorUseCase,
+    ) -> anyhow::Result<RE::Tree> {
+        self.data
+            .downloads
+            .op(self
+                .data
+                .client
+                .download_tree(digest, use_case)
+                .map_err(|e| self.decorate_error(e)))
+            .await
+    }
+
     pub async fn download_blob(
         &self,
         digest: &TDigest,
@@ -435,9 +452,19 @@ impl RemoteExecutionClient {
             .checked_sub(self.data.initial_network_stats.downloaded)
             .and_then(|d| u64::try_from(d).ok())
             .context("Overflow calculating downloaded bytes")?;
+        // The internal client retries on its own and doesn't report it.
+        #[cfg(fbcode_build)]
+        let retries = 0;
+        #[cfg(not(fbcode_build))]
+        let retries = updated
+            .retries
+            .checked_sub(self.data.initial_network_stats.retries)
+            .and_then(|d| u64::try_from(d).ok())
+            .context("Overflow calculating retries")?;
         Ok(RemoteExecutionClientStats {
             uploaded,
             downloaded,
+            retries,
             uploads: RemoteExecutionClientOpStats::from(&self.data.uploads),
             downloads: RemoteExecutionClientOpStats::from(&self.data.downloads),
             executes: RemoteExecutionClientOpStats::from(&self.data.executes),
@@ -919,6 +946,78 @@ impl RemoteExecutionClientImpl {
         Ok(blobs)
     }
 
+    /// Fetches a directory and all its descendants from the CAS, as a `Tree`.
+    async fn download_tree(
+        &self,
+        digest: TDigest,
+        use_case: RemoteExecutorUseCase,
+    ) -> anyhow::Result<RE::Tree> {
+        #[cfg(not(fbcode_build))]
+        {
+            use remote_execution::GetTreeRequest;
+
+            // GetTree gives us the whole tree in a handful of (paginated) calls, instead of one
+            // round trip per level.
+            let mut directories = self
+                .client()
+                .get_cas_client()
+                .get_tree(
+                    use_case.metadata(),
+                    GetTreeRequest {
+                        digest,
+                        ..Default::default()
+                    },
+                )
+                .await?
+                .directories
+                .into_iter();
+
+            let root = directories.next().context("GetTree response was empty")?;
+
+            Ok(RE::Tree {
+                root: Some(root),
+                children: directories.collect(),
+            })
+        }
+
+        #[cfg(fbcode_build)]
+        {
+            let root = self
+                .download_typed_blobs::<RE::Directory>(vec![digest], use_case)
+                .await?
+                .into_iter()
+                .next()
+                .context("RE response was empty")?;
+
+            let mut children: Vec<RE::Directory> = vec![];
+            let mut frontier = root.directories.clone();
+            while !frontier.is_empty() {
+                let digests: Vec<TDigest> = frontier
+                    .into_iter()
+                    .filter_map(|d| d.digest)
+                    .map(|digest| TDigest {
+                        hash: digest.hash,
+                        size_in_bytes: digest.size_bytes,
+                        ..Default::default()
+                    })
+                    .collect();
+                let mut retrieved = self
+                    .download_typed_blobs::<RE::Directory>(digests, use_case)
+                    .await?;
+                frontier = retrieved
+                    .iter()
+                    .flat_map(|d| d.directories.clone())
+                    .collect();
+                children.append(&mut retrieved);
+            }
+
+            Ok(RE::Tree {
+                root: Some(root),
+                children,
+            })
+        }
+    }
+
     pub async fn download_blob(
         &self,
         digest: &TDigest,
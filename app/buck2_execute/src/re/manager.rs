@@ -406,6 +406,18 @@ impl ManagedRemoteExecutionClient {
             .await
     }
 
+    pub async fn download_tree(
+        &self,
+        digest: TDigest,
+        use_case: RemoteExecutorUseCase,
+    ) -> anyhow::Result<RE::Tree> {
+        self.lock()?
+            .get()
+            .await?
+            .download_tree(digest, use_case)
+            .await
+    }
+
     pub async fn download_blob(
         &self,
         digest: &TDigest,
@@ -419,8 +419,10 @@ impl PreparedCommandExecutor for CachingExecutor {
             Err(e) => return manager.error("cache_upload", e),
         };
 
-        let manager = self
-            .try_action_cache_fetch(
+        let manager = if command.request.skip_cache_lookup() {
+            manager
+        } else {
+            self.try_action_cache_fetch(
                 manager,
                 command.request,
                 &command.action_paths,
@@ -428,7 +430,8 @@ impl PreparedCommandExecutor for CachingExecutor {
                 &command.prepared_action.blobs,
                 command.digest_config,
             )
-            .await?;
+            .await?
+        };
 
         let mut res = self.inner.exec_cmd(command, manager).await;
 
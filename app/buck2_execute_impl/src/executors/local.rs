@@ -9,25 +9,36 @@
 
 use std::borrow::Cow;
 use std::ffi::OsStr;
+use std::fs::File;
 use std::path::Path;
 use std::process::Command;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::time::Duration;
 use std::time::Instant;
 use std::time::SystemTime;
 
 use anyhow::Context as _;
 use async_trait::async_trait;
+use buck2_common::executor_config::LocalExecutorOptions;
 use buck2_common::file_ops::FileDigest;
 use buck2_common::file_ops::FileMetadata;
 use buck2_common::file_ops::TrackedFileDigest;
 use buck2_common::liveliness_observer::LivelinessObserver;
 use buck2_common::liveliness_observer::LivelinessObserverExt;
+use buck2_core::directory::Directory;
 use buck2_core::directory::DirectoryEntry;
+use buck2_core::directory::DirectoryIterator;
 use buck2_core::fs::fs_util;
 use buck2_core::fs::paths::abs_norm_path::AbsNormPathBuf;
+use buck2_core::fs::paths::abs_path::AbsPathBuf;
 use buck2_core::fs::paths::file_name::FileNameBuf;
+use buck2_core::fs::paths::forward_rel_path::ForwardRelativePath;
+use buck2_core::fs::paths::forward_rel_path::ForwardRelativePathBuf;
 use buck2_core::fs::project_rel_path::ProjectRelativePath;
+use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
+use buck2_events::dispatch::get_dispatcher;
+use buck2_events::trace::TraceId;
 use buck2_execute::artifact::fs::ArtifactFs;
 use buck2_execute::artifact_value::ArtifactValue;
 use buck2_execute::digest_config::DigestConfig;
@@ -42,6 +53,7 @@ use buck2_execute::execute::blocking::BlockingExecutor;
 use buck2_execute::execute::clean_output_paths::CleanOutputPaths;
 use buck2_execute::execute::environment_inheritance::EnvironmentInheritance;
 use buck2_execute::execute::executor_stage_async;
+use buck2_execute::execute::executor_stage_with_end_async;
 use buck2_execute::execute::inputs_directory::inputs_directory;
 use buck2_execute::execute::kind::CommandExecutionKind;
 use buck2_execute::execute::manager::CommandExecutionManager;
@@ -53,13 +65,14 @@ use buck2_execute::execute::request::CommandExecutionInput;
 use buck2_execute::execute::request::CommandExecutionOutput;
 use buck2_execute::execute::request::CommandExecutionOutputRef;
 use buck2_execute::execute::request::CommandExecutionRequest;
+use buck2_execute::execute::request::LocalResourceLimits;
 use buck2_execute::execute::result::CommandExecutionResult;
 use buck2_execute::execute::result::CommandExecutionTimingData;
 use buck2_execute::execute::target::CommandExecutionTarget;
 use buck2_execute::knobs::ExecutorGlobalKnobs;
 use buck2_execute::materialize::materializer::Materializer;
 use buck2_forkserver::client::ForkserverClient;
-use buck2_forkserver::run::gather_output;
+use buck2_forkserver::run::gather_output_with_stdin;
 use buck2_forkserver::run::timeout_into_cancellation;
 use buck2_forkserver::run::GatherOutputStatus;
 use buck2_util::process::background_command;
@@ -77,6 +90,8 @@ use more_futures::cancellable_future::CancellationObserver;
 use thiserror::Error;
 use tracing::info;
 
+use crate::executors::worker::WorkerPool;
+
 #[derive(Debug, Error)]
 enum LocalExecutionError {
     #[error("Args list was empty")]
@@ -84,6 +99,12 @@ enum LocalExecutionError {
 
     #[error("Trying to execute a remote-only action on a local executor")]
     RemoteOnlyAction,
+
+    #[error("Sandboxing local actions requires the forkserver")]
+    SandboxRequiresForkserver,
+
+    #[error("Running local actions in cgroups requires the forkserver")]
+    CgroupRequiresForkserver,
 }
 
 #[derive(Clone)]
@@ -95,8 +116,40 @@ pub struct LocalExecutor {
     root: AbsNormPathBuf,
     #[cfg_attr(not(unix), allow(unused))]
     forkserver: Option<ForkserverClient>,
-    #[allow(unused)]
     knobs: ExecutorGlobalKnobs,
+    options: LocalExecutorOptions,
+    workers: Arc<WorkerPool>,
+}
+
+/// Paths to expose to an action running in a sandbox, see `LocalExecutorOptions::use_sandbox`.
+struct SandboxPaths {
+    /// Where to build the sandbox.
+    dir: ProjectRelativePathBuf,
+    inputs: Vec<ProjectRelativePathBuf>,
+    writable: Vec<ProjectRelativePathBuf>,
+}
+
+/// The cgroup to run an action in, see `ExecutorGlobalKnobs::local_action_cgroup_parent`.
+struct CgroupRequest {
+    parent: Arc<AbsPathBuf>,
+    limits: LocalResourceLimits,
+}
+
+/// Tell the user that the resource limits some actions set are ignored, since local actions don't
+/// run in cgroups. We only do this once per command, since it applies to every such action.
+fn warn_resource_limits_not_enforced() {
+    static LAST_WARNED: Mutex<Option<TraceId>> = Mutex::new(None);
+
+    let dispatcher = get_dispatcher();
+    let mut last_warned = LAST_WARNED.lock().unwrap_or_else(|e| e.into_inner());
+    if last_warned.as_ref() != Some(dispatcher.trace_id()) {
+        *last_warned = Some(dispatcher.trace_id().clone());
+        dispatcher.console_message(
+            "Some actions set `local_memory_max_bytes` or `local_cpu_max_percent`, but those \
+            limits are not enforced because `buck2.local_action_cgroup_parent` is not set"
+                .to_owned(),
+        );
+    }
 }
 
 impl LocalExecutor {
@@ -108,6 +161,8 @@ impl LocalExecutor {
         root: AbsNormPathBuf,
         forkserver: Option<ForkserverClient>,
         knobs: ExecutorGlobalKnobs,
+        options: LocalExecutorOptions,
+        workers: Arc<WorkerPool>,
     ) -> Self {
         Self {
             artifact_fs,
@@ -117,6 +172,8 @@ impl LocalExecutor {
             root,
             forkserver,
             knobs,
+            options,
+            workers,
         }
     }
 
@@ -131,6 +188,9 @@ impl LocalExecutor {
         timeout: Option<Duration>,
         env_inheritance: Option<&'a EnvironmentInheritance>,
         liveliness_observer: impl LivelinessObserver + 'static,
+        sandbox: Option<&'a SandboxPaths>,
+        cgroup: Option<&'a CgroupRequest>,
+        stdin: Option<&'a ProjectRelativePath>,
     ) -> impl futures::future::Future<
         Output = anyhow::Result<(GatherOutputStatus, Vec<u8>, Vec<u8>)>,
     > + Send
@@ -156,17 +216,28 @@ impl LocalExecutor {
                             timeout,
                             env_inheritance,
                             liveliness_observer,
+                            sandbox.map(|s| unix::sandbox_request(&self.root, s)),
+                            cgroup.map(unix::cgroup_request),
+                            stdin.map(|p| self.root.join(p)),
                         )
                         .await
                     }
 
                     #[cfg(not(unix))]
                     {
-                        let _unused = forkserver;
+                        let _unused = (forkserver, sandbox, cgroup, stdin);
                         Err(anyhow::anyhow!("Forkserver is not supported off-UNIX"))
                     }
                 }
 
+                None if sandbox.is_some() => {
+                    Err(LocalExecutionError::SandboxRequiresForkserver.into())
+                }
+
+                None if cgroup.is_some() => {
+                    Err(LocalExecutionError::CgroupRequiresForkserver.into())
+                }
+
                 None => {
                     let mut cmd = background_command(exe);
                     cmd.current_dir(working_directory);
@@ -186,7 +257,17 @@ impl LocalExecutor {
                     let cancellation =
                         select(timeout.boxed(), alive.boxed()).map(|r| r.factor_first().0);
 
-                    gather_output(cmd, cancellation).await
+                    let stdin = match stdin {
+                        Some(stdin) => {
+                            let stdin = self.root.join(stdin);
+                            Some(File::open(&stdin).with_context(|| {
+                                format!("Error opening stdin `{}`", stdin.display())
+                            })?)
+                        }
+                        None => None,
+                    };
+
+                    gather_output_with_stdin(cmd, stdin, cancellation).await
                 }
                 .with_context(|| format!("Failed to gather output from command: {}", exe)),
             }
@@ -297,6 +378,30 @@ impl LocalExecutor {
             vec![]
         };
 
+        let sandbox = if self.options.use_sandbox {
+            match sandbox_paths(&self.artifact_fs, request, &scratch_dir, action_digest) {
+                Ok(sandbox) => Some(sandbox),
+                Err(e) => return manager.error("sandbox_failed", e),
+            }
+        } else {
+            None
+        };
+
+        let cgroup = match &self.knobs.local_action_cgroup_parent {
+            Some(parent) => Some(CgroupRequest {
+                parent: parent.dupe(),
+                limits: request
+                    .local_resource_limits()
+                    .or(self.knobs.local_action_limits),
+            }),
+            None => {
+                if !request.local_resource_limits().is_empty() {
+                    warn_resource_limits_not_enforced();
+                }
+                None
+            }
+        };
+
         let daemon_uuid: &str = &buck2_events::metadata::DAEMON_UUID.to_string();
 
         let iter_env = || {
@@ -317,7 +422,13 @@ impl LocalExecutor {
 
         let liveliness_observer = manager.liveliness_observer.dupe().and(cancellation);
 
-        let (timing, res) = executor_stage_async(
+        // Workers run outside of the sandbox and of the action's cgroup, and don't get a stdin, so
+        // actions that need any of those run as regular commands instead.
+        let worker = request
+            .worker()
+            .filter(|_| sandbox.is_none() && cgroup.is_none() && request.stdin().is_none());
+
+        let (timing, res) = executor_stage_with_end_async(
             {
                 let env = iter_env()
                     .map(|(k, v)| buck2_data::local_command::EnvironmentEntry {
@@ -325,36 +436,85 @@ impl LocalExecutor {
                         value: v.into_string_lossy(),
                     })
                     .collect();
-                let stage = buck2_data::LocalExecute {
-                    command: Some(buck2_data::LocalCommand {
-                        action_digest: action_digest.to_string(),
-                        argv: args.to_vec(),
-                        env,
-                    }),
+                let command = buck2_data::LocalCommand {
+                    action_digest: action_digest.to_string(),
+                    argv: args.to_vec(),
+                    env,
                 };
-                buck2_data::LocalStage {
-                    stage: Some(stage.into()),
-                }
+                let stage: buck2_data::local_stage::Stage = match worker {
+                    Some(worker) => buck2_data::LocalWorkerExecute {
+                        command: Some(command),
+                        worker_argv: worker.exe.clone(),
+                    }
+                    .into(),
+                    None => buck2_data::LocalExecute {
+                        command: Some(command),
+                    }
+                    .into(),
+                };
+                buck2_data::LocalStage { stage: Some(stage) }
             },
             async move {
                 let execution_start = Instant::now();
                 let start_time = SystemTime::now();
 
-                let env = iter_env().map(|(k, v)| (k, v.into_os_str()));
-                let r = self
-                    .exec(
-                        &args[0],
-                        &args[1..],
-                        env,
-                        request.working_directory(),
-                        request.timeout(),
-                        request.local_environment_inheritance(),
-                        liveliness_observer,
-                    )
-                    .await;
+                let r = match worker {
+                    Some(worker) => {
+                        // The worker outlives this action, so it doesn't get its scratch
+                        // directory as $TMPDIR.
+                        let env = request
+                            .env()
+                            .iter()
+                            .map(|(k, v)| (k.clone(), v.clone()))
+                            .chain(std::iter::once((
+                                "BUCK2_DAEMON_UUID".to_owned(),
+                                daemon_uuid.to_owned(),
+                            )))
+                            .collect();
+                        let working_directory = match request.working_directory() {
+                            Some(d) => self.root.join(d),
+                            None => self.root.clone(),
+                        };
+                        self.workers
+                            .execute(
+                                worker,
+                                &args[worker.exe.len()..],
+                                env,
+                                working_directory.as_path(),
+                                request.local_environment_inheritance(),
+                                request.timeout(),
+                                liveliness_observer,
+                            )
+                            .await
+                    }
+                    None => {
+                        let env = iter_env().map(|(k, v)| (k, v.into_os_str()));
+                        self.exec(
+                            &args[0],
+                            &args[1..],
+                            env,
+                            request.working_directory(),
+                            request.timeout(),
+                            request.local_environment_inheritance(),
+                            liveliness_observer,
+                            sandbox.as_ref(),
+                            cgroup.as_ref(),
+                            request.stdin(),
+                        )
+                        .await
+                    }
+                };
 
                 let execution_time = execution_start.elapsed();
 
+                if let Some(sandbox) = &sandbox {
+                    // This only contains mount points, the mounts themselves were private to the
+                    // action.
+                    if let Err(e) = fs_util::remove_all(self.root.join(&sandbox.dir)) {
+                        tracing::warn!("Error removing sandbox: {:#}", e);
+                    }
+                }
+
                 let timing = CommandExecutionTimingData {
                     wall_time: execution_time,
                     re_queue_time: None,
@@ -362,7 +522,11 @@ impl LocalExecutor {
                     start_time,
                 };
 
-                (timing, r)
+                let end = buck2_data::ExecutorStageEnd {
+                    execution_stats: execution_stats(&r),
+                };
+
+                ((timing, r), end)
             },
         )
         .await;
@@ -371,6 +535,7 @@ impl LocalExecutor {
             digest: action_digest.dupe(),
             command: args.to_vec(),
             env: request.env().clone(),
+            execution_stats: execution_stats(&res),
         };
 
         let (status, stdout, stderr) = match res {
@@ -381,10 +546,16 @@ impl LocalExecutor {
         let std_streams = CommandStdStreams::Local { stdout, stderr };
 
         match status {
-            GatherOutputStatus::Finished(status) => {
-                let outputs = match self
-                    .calculate_and_declare_output_values(request, digest_config)
-                    .await
+            GatherOutputStatus::Finished {
+                exit_code: status, ..
+            } => {
+                let outputs = match calculate_and_declare_output_values(
+                    &self.artifact_fs,
+                    &self.materializer,
+                    request,
+                    digest_config,
+                )
+                .await
                 {
                     Ok(output_values) => output_values,
                     Err(e) => return manager.error("calculate_output_values_failed", e),
@@ -417,130 +588,131 @@ impl LocalExecutor {
             GatherOutputStatus::Cancelled => manager.cancel_claim(),
         }
     }
+}
 
-    async fn calculate_and_declare_output_values(
-        &self,
-        request: &CommandExecutionRequest,
-        digest_config: DigestConfig,
-    ) -> anyhow::Result<IndexMap<CommandExecutionOutput, ArtifactValue>> {
-        let mut builder = inputs_directory(request.inputs(), &self.artifact_fs)?;
-
-        // Read outputs from disk and add them to the builder
-        let mut entries = Vec::new();
-        for output in request.outputs() {
-            let path = output.resolve(&self.artifact_fs).into_path();
-            let abspath = self.root.join(&path);
-            let entry = self
-                .build_entry_from_disk(abspath, digest_config)
-                .with_context(|| format!("collecting output {:?}", path))?;
-            if let Some(entry) = entry {
-                insert_entry(&mut builder, &path, entry)?;
-                entries.push((output.cloned(), path));
-            }
+/// Hash the outputs of a command that ran (or was restored) locally, and declare them to the
+/// materializer.
+pub(crate) async fn calculate_and_declare_output_values(
+    artifact_fs: &ArtifactFs,
+    materializer: &Arc<dyn Materializer>,
+    request: &CommandExecutionRequest,
+    digest_config: DigestConfig,
+) -> anyhow::Result<IndexMap<CommandExecutionOutput, ArtifactValue>> {
+    let mut builder = inputs_directory(request.inputs(), artifact_fs)?;
+
+    // Read outputs from disk and add them to the builder
+    let mut entries = Vec::new();
+    for output in request.outputs() {
+        let path = output.resolve(artifact_fs).into_path();
+        let abspath = artifact_fs.fs().resolve(&path);
+        let entry = build_entry_from_disk(abspath, digest_config)
+            .with_context(|| format!("collecting output {:?}", path))?;
+        if let Some(entry) = entry {
+            insert_entry(&mut builder, &path, entry)?;
+            entries.push((output.cloned(), path));
         }
+    }
 
-        let mut to_declare = vec![];
-        let mut mapped_outputs = IndexMap::with_capacity(entries.len());
+    let mut to_declare = vec![];
+    let mut mapped_outputs = IndexMap::with_capacity(entries.len());
 
-        for (output, path) in entries {
-            let value = extract_artifact_value(&builder, &path, digest_config)?;
-            if let Some(value) = value {
-                match output {
-                    CommandExecutionOutput::BuildArtifact { .. } => {
-                        to_declare.push((path, value.dupe()));
-                    }
-                    CommandExecutionOutput::TestPath { .. } => {
-                        // Don't declare those as we don't currently have any form of GC so this
-                        // would take up space for nothing, and most importantly, we will never
-                        // need them to be in materializer state for e.g. matching as nothign
-                        // should depend on them.
-                    }
+    for (output, path) in entries {
+        let value = extract_artifact_value(&builder, &path, digest_config)?;
+        if let Some(value) = value {
+            match output {
+                CommandExecutionOutput::BuildArtifact { .. } => {
+                    to_declare.push((path, value.dupe()));
+                }
+                CommandExecutionOutput::TestPath { .. } => {
+                    // Don't declare those as we don't currently have any form of GC so this
+                    // would take up space for nothing, and most importantly, we will never
+                    // need them to be in materializer state for e.g. matching as nothign
+                    // should depend on them.
                 }
-
-                mapped_outputs.insert(output, value);
             }
+
+            mapped_outputs.insert(output, value);
         }
+    }
 
-        self.materializer.declare_existing(to_declare).await?;
+    materializer.declare_existing(to_declare).await?;
 
-        Ok(mapped_outputs)
-    }
+    Ok(mapped_outputs)
+}
 
-    fn build_entry_from_disk(
-        &self,
-        mut path: AbsNormPathBuf,
+fn build_entry_from_disk(
+    mut path: AbsNormPathBuf,
+    digest_config: DigestConfig,
+) -> anyhow::Result<Option<ActionDirectoryEntry<ActionDirectoryBuilder>>> {
+    fn build_dir_from_disk(
+        disk_path: &mut AbsNormPathBuf,
         digest_config: DigestConfig,
-    ) -> anyhow::Result<Option<ActionDirectoryEntry<ActionDirectoryBuilder>>> {
-        fn build_dir_from_disk(
-            disk_path: &mut AbsNormPathBuf,
-            digest_config: DigestConfig,
-        ) -> anyhow::Result<ActionDirectoryBuilder> {
-            let mut builder = ActionDirectoryBuilder::empty();
-
-            for file in fs_util::read_dir(&disk_path)? {
-                let file = file?;
-                let filetype = file.file_type()?;
-                let filename = file.file_name();
-
-                let filename = filename
-                    .to_str()
-                    .context("Filename is not UTF-8")
-                    .and_then(|f| FileNameBuf::try_from(f.to_owned()))
-                    .with_context(|| format!("Invalid filename: {}", disk_path.display()))?;
-
-                disk_path.push(&filename);
-
-                if filetype.is_dir() {
-                    let dir = build_dir_from_disk(disk_path, digest_config)?;
-                    builder.insert(filename, DirectoryEntry::Dir(dir))?;
-                } else if filetype.is_symlink() {
-                    builder.insert(
-                        filename,
-                        DirectoryEntry::Leaf(new_symlink(fs_util::read_link(&disk_path)?)?),
-                    )?;
-                } else if filetype.is_file() {
-                    let metadata = FileMetadata {
-                        digest: TrackedFileDigest::new(
-                            FileDigest::from_file(&disk_path, digest_config.cas_digest_config())?,
-                            digest_config.cas_digest_config(),
-                        ),
-                        is_executable: file.path().executable(),
-                    };
-                    builder.insert(
-                        filename,
-                        DirectoryEntry::Leaf(ActionDirectoryMember::File(metadata)),
-                    )?;
-                }
-                disk_path.pop();
+    ) -> anyhow::Result<ActionDirectoryBuilder> {
+        let mut builder = ActionDirectoryBuilder::empty();
+
+        for file in fs_util::read_dir(&disk_path)? {
+            let file = file?;
+            let filetype = file.file_type()?;
+            let filename = file.file_name();
+
+            let filename = filename
+                .to_str()
+                .context("Filename is not UTF-8")
+                .and_then(|f| FileNameBuf::try_from(f.to_owned()))
+                .with_context(|| format!("Invalid filename: {}", disk_path.display()))?;
+
+            disk_path.push(&filename);
+
+            if filetype.is_dir() {
+                let dir = build_dir_from_disk(disk_path, digest_config)?;
+                builder.insert(filename, DirectoryEntry::Dir(dir))?;
+            } else if filetype.is_symlink() {
+                builder.insert(
+                    filename,
+                    DirectoryEntry::Leaf(new_symlink(fs_util::read_link(&disk_path)?)?),
+                )?;
+            } else if filetype.is_file() {
+                let metadata = FileMetadata {
+                    digest: TrackedFileDigest::new(
+                        FileDigest::from_file(&disk_path, digest_config.cas_digest_config())?,
+                        digest_config.cas_digest_config(),
+                    ),
+                    is_executable: file.path().executable(),
+                };
+                builder.insert(
+                    filename,
+                    DirectoryEntry::Leaf(ActionDirectoryMember::File(metadata)),
+                )?;
             }
-
-            Ok(builder)
+            disk_path.pop();
         }
 
-        // Get file metadata. If the file is missing, ignore it.
-        let m = match std::fs::symlink_metadata(&path) {
-            Ok(m) => m,
-            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
-            Err(err) => return Err(err.into()),
-        };
-
-        let value = if m.file_type().is_symlink() {
-            DirectoryEntry::Leaf(new_symlink(fs_util::read_link(&path)?)?)
-        } else if m.is_file() {
-            DirectoryEntry::Leaf(ActionDirectoryMember::File(FileMetadata {
-                digest: TrackedFileDigest::new(
-                    FileDigest::from_file(&path, digest_config.cas_digest_config())?,
-                    digest_config.cas_digest_config(),
-                ),
-                is_executable: path.executable(),
-            }))
-        } else if m.is_dir() {
-            DirectoryEntry::Dir(build_dir_from_disk(&mut path, digest_config)?)
-        } else {
-            unimplemented!("Path {:?} is of an unknown file type.", path)
-        };
-        Ok(Some(value))
+        Ok(builder)
     }
+
+    // Get file metadata. If the file is missing, ignore it.
+    let m = match std::fs::symlink_metadata(&path) {
+        Ok(m) => m,
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    let value = if m.file_type().is_symlink() {
+        DirectoryEntry::Leaf(new_symlink(fs_util::read_link(&path)?)?)
+    } else if m.is_file() {
+        DirectoryEntry::Leaf(ActionDirectoryMember::File(FileMetadata {
+            digest: TrackedFileDigest::new(
+                FileDigest::from_file(&path, digest_config.cas_digest_config())?,
+                digest_config.cas_digest_config(),
+            ),
+            is_executable: path.executable(),
+        }))
+    } else if m.is_dir() {
+        DirectoryEntry::Dir(build_dir_from_disk(&mut path, digest_config)?)
+    } else {
+        unimplemented!("Path {:?} is of an unknown file type.", path)
+    };
+    Ok(Some(value))
 }
 
 #[async_trait]
@@ -642,6 +814,84 @@ pub async fn materialize_inputs(
     materializer.ensure_materialized(paths).await
 }
 
+fn execution_stats(
+    res: &anyhow::Result<(GatherOutputStatus, Vec<u8>, Vec<u8>)>,
+) -> Option<buck2_data::CommandExecutionStats> {
+    match res {
+        Ok((
+            GatherOutputStatus::Finished {
+                execution_stats: Some(stats),
+                ..
+            },
+            ..,
+        )) => Some(buck2_data::CommandExecutionStats {
+            memory_peak_bytes: stats.memory_peak_bytes,
+            cpu_usage_us: stats.cpu_usage_us,
+        }),
+        _ => None,
+    }
+}
+
+/// Collect the paths an action may access when it runs in a sandbox: its inputs (including the
+/// targets of symlinks in them) can be read, and the directories containing its outputs and its
+/// scratch directory can be written to.
+fn sandbox_paths(
+    artifact_fs: &ArtifactFs,
+    request: &CommandExecutionRequest,
+    scratch_dir: &ProjectRelativePath,
+    action_digest: &ActionDigest,
+) -> anyhow::Result<SandboxPaths> {
+    let mut inputs = Vec::new();
+
+    for input in request.inputs() {
+        match input {
+            CommandExecutionInput::Artifact(group) => {
+                for (artifact, value) in group.iter() {
+                    inputs.push(artifact_fs.resolve(artifact.get_path())?);
+
+                    if let Some(deps) = value.deps() {
+                        for (path, entry) in deps.unordered_walk().with_paths() {
+                            if let DirectoryEntry::Leaf(..) = entry {
+                                inputs.push(ProjectRelativePathBuf::from(path));
+                            }
+                        }
+                    }
+                }
+            }
+            CommandExecutionInput::ActionMetadata(metadata) => {
+                inputs.push(
+                    artifact_fs
+                        .buck_out_path_resolver()
+                        .resolve_gen(&metadata.path),
+                );
+            }
+        }
+    }
+
+    let mut writable = Vec::new();
+    for output in request.outputs() {
+        let output = output.resolve(artifact_fs);
+        if let Some(parent) = output.path.parent() {
+            writable.push(parent.to_buf());
+        }
+    }
+    if request.custom_tmpdir {
+        writable.push(scratch_dir.to_buf());
+    }
+
+    let dir = artifact_fs
+        .buck_out_path_resolver()
+        .root()
+        .join(ForwardRelativePath::new("sandbox")?)
+        .join(ForwardRelativePathBuf::new(action_digest.to_string())?);
+
+    Ok(SandboxPaths {
+        dir,
+        inputs,
+        writable,
+    })
+}
+
 /// Materialize build outputs from the previous run of the same command.
 /// Useful when executing incremental actions first remotely and then locally.
 /// In that case output from remote execution which is incremental state should be materialized prior local execution.
@@ -794,6 +1044,9 @@ mod unix {
         comand_timeout: Option<Duration>,
         env_inheritance: Option<&EnvironmentInheritance>,
         liveliness_observer: impl LivelinessObserver + 'static,
+        sandbox: Option<buck2_forkserver_proto::Sandbox>,
+        cgroup: Option<buck2_forkserver_proto::Cgroup>,
+        stdin: Option<AbsNormPathBuf>,
     ) -> anyhow::Result<(GatherOutputStatus, Vec<u8>, Vec<u8>)> {
         let exe = exe.as_ref();
 
@@ -808,6 +1061,9 @@ mod unix {
             }),
             env: vec![],
             timeout: comand_timeout.try_map(|d| d.try_into())?,
+            sandbox,
+            cgroup,
+            stdin: stdin.map(|p| p.as_os_str().as_bytes().to_vec()),
         };
         apply_local_execution_environment(&mut req, working_directory, env, env_inheritance);
         forkserver
@@ -815,6 +1071,33 @@ mod unix {
             .await
     }
 
+    pub(super) fn sandbox_request(
+        root: &AbsNormPathBuf,
+        sandbox: &SandboxPaths,
+    ) -> buck2_forkserver_proto::Sandbox {
+        let relative = |paths: &[ProjectRelativePathBuf]| {
+            paths
+                .iter()
+                .map(|p| p.as_str().as_bytes().to_vec())
+                .collect()
+        };
+
+        buck2_forkserver_proto::Sandbox {
+            root: root.as_os_str().as_bytes().to_vec(),
+            dir: root.join(&sandbox.dir).as_os_str().as_bytes().to_vec(),
+            inputs: relative(&sandbox.inputs),
+            writable: relative(&sandbox.writable),
+        }
+    }
+
+    pub(super) fn cgroup_request(cgroup: &CgroupRequest) -> buck2_forkserver_proto::Cgroup {
+        buck2_forkserver_proto::Cgroup {
+            parent: cgroup.parent.as_os_str().as_bytes().to_vec(),
+            memory_max_bytes: cgroup.limits.memory_max_bytes,
+            cpu_max_percent: cgroup.limits.cpu_max_percent,
+        }
+    }
+
     trait CommandRequestExt {
         fn push_env_directive<D>(&mut self, directive: D)
         where
@@ -874,14 +1157,16 @@ mod tests {
     use buck2_core::cells::CellResolver;
     use buck2_core::fs::project::ProjectRoot;
     use buck2_core::fs::project::ProjectRootTemp;
-    use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
     use buck2_execute::artifact::fs::ArtifactFs;
     use buck2_execute::execute::blocking::testing::DummyBlockingExecutor;
     use buck2_execute::materialize::nodisk::NoDiskMaterializer;
     use buck2_execute::path::buck_out_path::BuckOutPathResolver;
+    use buck2_forkserver::run::gather_output;
     use host_sharing::HostSharingStrategy;
 
     use super::*;
+    use crate::executors::worker::DEFAULT_IDLE_TIMEOUT;
+    use crate::executors::worker::DEFAULT_MAX_INSTANCES;
 
     #[tokio::test]
     async fn test_gather_output() -> anyhow::Result<()> {
@@ -893,7 +1178,10 @@ mod tests {
         cmd.args(["-c", "echo hello"]);
 
         let (status, stdout, stderr) = gather_output(cmd, futures::future::pending()).await?;
-        assert!(matches!(status, GatherOutputStatus::Finished(s) if s == 0));
+        assert!(matches!(
+            status,
+            GatherOutputStatus::Finished { exit_code: 0, .. }
+        ));
         assert_eq!(str::from_utf8(&stdout)?.trim(), "hello");
         assert_eq!(stderr, b"");
 
@@ -924,7 +1212,7 @@ mod tests {
         )
         .await?;
         assert!(
-            matches!(status, GatherOutputStatus::Finished(s) if s == 0),
+            matches!(status, GatherOutputStatus::Finished { exit_code: 0, .. }),
             "status: {:?}",
             status
         );
@@ -996,6 +1284,14 @@ mod tests {
             temp.path().root().to_buf(),
             None,
             ExecutorGlobalKnobs::default(),
+            LocalExecutorOptions::default(),
+            Arc::new(WorkerPool::new(
+                temp.path()
+                    .root()
+                    .join(ForwardRelativePath::unchecked_new("worker_logs")),
+                DEFAULT_MAX_INSTANCES,
+                DEFAULT_IDLE_TIMEOUT,
+            )),
         );
 
         Ok((executor, temp.path().root().to_buf(), temp))
@@ -1015,9 +1311,15 @@ mod tests {
                 None,
                 None,
                 NoopLivelinessObserver::create(),
+                None,
+                None,
+                None,
             )
             .await?;
-        assert!(matches!(status, GatherOutputStatus::Finished(s) if s == 0));
+        assert!(matches!(
+            status,
+            GatherOutputStatus::Finished { exit_code: 0, .. }
+        ));
 
         let stdout = std::str::from_utf8(&stdout).context("Invalid stdout")?;
 
@@ -1050,9 +1352,15 @@ mod tests {
                 None,
                 Some(&EnvironmentInheritance::empty()),
                 NoopLivelinessObserver::create(),
+                None,
+                None,
+                None,
             )
             .await?;
-        assert!(matches!(status, GatherOutputStatus::Finished(s) if s == 0));
+        assert!(matches!(
+            status,
+            GatherOutputStatus::Finished { exit_code: 0, .. }
+        ));
         assert_eq!(stdout, b"\n");
 
         Ok(())
@@ -0,0 +1,367 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::collections::BTreeMap;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use buck2_common::file_ops::FileDigest;
+use buck2_core::directory::DirectoryEntry;
+use buck2_core::fs::fs_util;
+use buck2_core::fs::paths::abs_norm_path::AbsNormPathBuf;
+use buck2_core::fs::paths::file_name::FileName;
+use buck2_core::fs::project::ProjectRoot;
+use buck2_execute::artifact::fs::ArtifactFs;
+use buck2_execute::digest_config::DigestConfig;
+use buck2_execute::directory::ActionDirectoryEntry;
+use buck2_execute::directory::ActionDirectoryMember;
+use buck2_execute::directory::ActionSharedDirectory;
+use buck2_execute::execute::blocking::BlockingExecutor;
+use buck2_execute::execute::executor_stage_async;
+use buck2_execute::execute::kind::CommandExecutionKind;
+use buck2_execute::execute::manager::CommandExecutionManager;
+use buck2_execute::execute::manager::CommandExecutionManagerExt;
+use buck2_execute::execute::output::CommandStdStreams;
+use buck2_execute::execute::prepared::PreparedCommand;
+use buck2_execute::execute::prepared::PreparedCommandExecutor;
+use buck2_execute::execute::request::CommandExecutionOutputRef;
+use buck2_execute::execute::request::CommandExecutionRequest;
+use buck2_execute::execute::result::CommandExecutionResult;
+use buck2_execute::execute::result::CommandExecutionStatus;
+use buck2_execute::execute::result::CommandExecutionTimingData;
+use buck2_execute::materialize::materializer::Materializer;
+use dupe::Dupe;
+
+use crate::executors::local::calculate_and_declare_output_values;
+use crate::executors::local::create_output_dirs;
+use crate::local_cache::blob_key;
+use crate::local_cache::CachedActionResult;
+use crate::local_cache::CachedEntry;
+use crate::local_cache::LocalActionCache;
+use crate::local_cache::PinnedBlobs;
+
+/// A PreparedCommandExecutor that will check the local action cache before executing any actions
+/// using the underlying executor, and store the results of actions that ran locally in it. Like
+/// for the remote cache, only actions that allow cache uploads are stored.
+pub struct LocalCachingExecutor {
+    pub inner: Arc<dyn PreparedCommandExecutor>,
+    pub cache: Arc<LocalActionCache>,
+    pub artifact_fs: ArtifactFs,
+    pub materializer: Arc<dyn Materializer>,
+    pub blocking_executor: Arc<dyn BlockingExecutor>,
+}
+
+impl LocalCachingExecutor {
+    async fn try_local_cache_fetch(
+        &self,
+        command: &PreparedCommand<'_, '_>,
+        manager: CommandExecutionManager,
+    ) -> ControlFlow<CommandExecutionResult, CommandExecutionManager> {
+        let request = command.request;
+        let action_digest = &command.prepared_action.action;
+
+        let cached = executor_stage_async(
+            buck2_data::LocalCacheQuery {
+                action_digest: action_digest.to_string(),
+            },
+            self.blocking_executor
+                .execute_io_inline(|| self.cache.get(action_digest)),
+        )
+        .await;
+
+        let cached = match cached {
+            Ok(Some(cached)) => cached,
+            Ok(None) => return ControlFlow::Continue(manager),
+            Err(e) => {
+                tracing::warn!(
+                    "Error querying the local cache for `{}`: {:#}",
+                    action_digest,
+                    e
+                );
+                return ControlFlow::Continue(manager);
+            }
+        };
+
+        // Once we claimed the manager we can no longer fall back to running the action, so make
+        // sure the blobs we need can't go away first.
+        let pinned = match self
+            .blocking_executor
+            .execute_io_inline(|| self.cache.pin(&cached))
+            .await
+        {
+            Ok(pinned) => pinned,
+            Err(e) => {
+                tracing::warn!(
+                    "Error pinning local cache blobs for `{}`: {:#}",
+                    action_digest,
+                    e
+                );
+                return ControlFlow::Continue(manager);
+            }
+        };
+
+        let manager = manager.claim().await;
+
+        let start_time = SystemTime::now();
+        let start = Instant::now();
+
+        let res = executor_stage_async(
+            buck2_data::LocalCacheHit {
+                action_digest: action_digest.to_string(),
+            },
+            async {
+                create_output_dirs(
+                    &self.artifact_fs,
+                    request,
+                    self.materializer.dupe(),
+                    self.blocking_executor.dupe(),
+                )
+                .await?;
+
+                let std_streams = self
+                    .blocking_executor
+                    .execute_io_inline(|| self.restore(request, &cached, &pinned))
+                    .await?;
+
+                let outputs = calculate_and_declare_output_values(
+                    &self.artifact_fs,
+                    &self.materializer,
+                    request,
+                    command.digest_config,
+                )
+                .await?;
+
+                anyhow::Ok((outputs, std_streams))
+            },
+        )
+        .await;
+
+        let (outputs, std_streams) = match res {
+            Ok(res) => res,
+            Err(e) => return ControlFlow::Break(manager.error("local_action_cache", e)),
+        };
+
+        let wall_time = start.elapsed();
+        let timing = CommandExecutionTimingData {
+            wall_time,
+            re_queue_time: None,
+            execution_time: wall_time,
+            start_time,
+        };
+
+        ControlFlow::Break(manager.success(
+            CommandExecutionKind::LocalActionCache {
+                digest: action_digest.dupe(),
+            },
+            outputs,
+            std_streams,
+            timing,
+        ))
+    }
+
+    /// Copy the outputs of a cached action into place. Returns its std streams.
+    fn restore(
+        &self,
+        request: &CommandExecutionRequest,
+        cached: &CachedActionResult,
+        pinned: &PinnedBlobs,
+    ) -> anyhow::Result<CommandStdStreams> {
+        let fs = self.artifact_fs.fs();
+
+        for output in request.outputs() {
+            let path = output.resolve(&self.artifact_fs).into_path();
+            if let Some(entry) = cached.outputs.get(path.as_str()) {
+                self.restore_entry(fs, entry, pinned, &mut fs.resolve(&path))?;
+            }
+        }
+
+        Ok(CommandStdStreams::Local {
+            stdout: pinned.read(&cached.stdout)?,
+            stderr: pinned.read(&cached.stderr)?,
+        })
+    }
+
+    fn restore_entry(
+        &self,
+        fs: &ProjectRoot,
+        entry: &CachedEntry,
+        pinned: &PinnedBlobs,
+        path: &mut AbsNormPathBuf,
+    ) -> anyhow::Result<()> {
+        match entry {
+            CachedEntry::File { blob, executable } => {
+                self.cache.restore_blob(pinned, blob, path)?;
+                if *executable {
+                    fs.set_executable(&**path)?;
+                }
+            }
+            CachedEntry::Symlink { target } => {
+                fs_util::symlink(target, &path)?;
+            }
+            CachedEntry::Directory { entries } => {
+                fs_util::create_dir_all(&path)?;
+                for (name, entry) in entries {
+                    path.push(FileName::new(name)?);
+                    self.restore_entry(fs, entry, pinned, path)?;
+                    path.pop();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Store the result of an action in the local cache, if it ran locally, succeeded, and allows
+    /// cache uploads. Returns whether we did.
+    fn maybe_store(
+        &self,
+        command: &PreparedCommand<'_, '_>,
+        result: &CommandExecutionResult,
+    ) -> anyhow::Result<bool> {
+        if !command.request.allow_cache_upload() {
+            return Ok(false);
+        }
+
+        match &result.report.status {
+            CommandExecutionStatus::Success {
+                execution_kind: CommandExecutionKind::Local { .. },
+            } => {}
+            _ => return Ok(false),
+        }
+
+        let (stdout, stderr) = match &result.report.std_streams {
+            CommandStdStreams::Local { stdout, stderr } => (stdout.as_slice(), stderr.as_slice()),
+            CommandStdStreams::Empty => (&[][..], &[][..]),
+            CommandStdStreams::Remote(..) => return Ok(false),
+        };
+
+        let mut outputs = BTreeMap::new();
+        for (output, value) in result.resolve_outputs(&self.artifact_fs) {
+            let mut path = self.artifact_fs.fs().resolve(output.path());
+            match self.store_entry(value.entry(), &mut path)? {
+                Some(entry) => {
+                    outputs.insert(output.path().as_str().to_owned(), entry);
+                }
+                None => return Ok(false),
+            }
+        }
+
+        let cached = CachedActionResult {
+            outputs,
+            stdout: self.store_blob(stdout, command.digest_config)?,
+            stderr: self.store_blob(stderr, command.digest_config)?,
+        };
+
+        self.cache.put(&command.prepared_action.action, &cached)?;
+
+        Ok(true)
+    }
+
+    /// Store the blobs for an output. Returns `None` if it contains something we can't cache.
+    fn store_entry(
+        &self,
+        entry: &ActionDirectoryEntry<ActionSharedDirectory>,
+        path: &mut AbsNormPathBuf,
+    ) -> anyhow::Result<Option<CachedEntry>> {
+        Ok(Some(match entry {
+            DirectoryEntry::Dir(d) => {
+                let mut entries = BTreeMap::new();
+                for (name, entry) in d.entries() {
+                    path.push(name);
+                    let entry = self.store_entry(entry, path)?;
+                    path.pop();
+                    match entry {
+                        Some(entry) => entries.insert(name.as_str().to_owned(), entry),
+                        None => return Ok(None),
+                    };
+                }
+                CachedEntry::Directory { entries }
+            }
+            DirectoryEntry::Leaf(ActionDirectoryMember::File(f)) => {
+                let blob = blob_key(f.digest.data());
+                self.cache.put_blob_from_file(&blob, path)?;
+                CachedEntry::File {
+                    blob,
+                    executable: f.is_executable,
+                }
+            }
+            DirectoryEntry::Leaf(ActionDirectoryMember::Symlink(s)) => CachedEntry::Symlink {
+                target: s.target().as_str().to_owned(),
+            },
+            // These point outside of the project, so they are unlikely to be hermetic.
+            DirectoryEntry::Leaf(ActionDirectoryMember::ExternalSymlink(..)) => return Ok(None),
+        }))
+    }
+
+    fn store_blob(&self, data: &[u8], digest_config: DigestConfig) -> anyhow::Result<String> {
+        let blob = blob_key(&FileDigest::from_content(
+            data,
+            digest_config.cas_digest_config(),
+        ));
+        self.cache.put_blob(&blob, data)?;
+        Ok(blob)
+    }
+}
+
+/// Whether the result of this request can be served from (and stored in) the local cache.
+/// Incremental actions depend on their previous outputs, which are not part of the action digest,
+/// and tests are not cached.
+fn is_cacheable(request: &CommandExecutionRequest) -> bool {
+    request.outputs_cleanup
+        && request
+            .outputs()
+            .all(|o| matches!(o, CommandExecutionOutputRef::BuildArtifact { .. }))
+}
+
+#[async_trait]
+impl PreparedCommandExecutor for LocalCachingExecutor {
+    async fn exec_cmd(
+        &self,
+        command: &PreparedCommand<'_, '_>,
+        manager: CommandExecutionManager,
+    ) -> CommandExecutionResult {
+        if !is_cacheable(command.request) {
+            return self.inner.exec_cmd(command, manager).await;
+        }
+
+        let manager = if command.request.skip_cache_lookup() {
+            manager
+        } else {
+            self.try_local_cache_fetch(command, manager).await?
+        };
+
+        let res = self.inner.exec_cmd(command, manager).await;
+
+        match self
+            .blocking_executor
+            .execute_io_inline(|| self.maybe_store(command, &res))
+            .await
+        {
+            Ok(true) => {
+                tracing::debug!(
+                    "Stored `{}` in the local cache",
+                    command.prepared_action.action
+                );
+            }
+            Ok(false) => {}
+            Err(e) => {
+                tracing::warn!(
+                    "Error storing `{}` in the local cache: {:#}",
+                    command.prepared_action.action,
+                    e
+                );
+            }
+        }
+
+        res
+    }
+}
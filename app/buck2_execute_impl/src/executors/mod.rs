@@ -10,4 +10,6 @@
 pub mod caching;
 pub mod hybrid;
 pub mod local;
+pub mod local_cache;
 pub mod re;
+pub mod worker;
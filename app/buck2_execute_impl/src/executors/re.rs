@@ -130,7 +130,7 @@ impl ReExecutor {
                 self.re_use_case,
                 &identity,
                 &mut manager,
-                self.skip_cache_lookup,
+                self.skip_cache_lookup || request.skip_cache_lookup(),
                 self.re_max_queue_time_ms.map(Duration::from_millis),
             )
             .await;
@@ -142,6 +142,22 @@ impl ReExecutor {
 
         let action_result = &response.action_result;
 
+        if response.error.code == TCode::DEADLINE_EXCEEDED {
+            if let Some(timeout) = request.timeout() {
+                return ControlFlow::Break(manager.timeout(
+                    CommandExecutionKind::Remote {
+                        digest: action_digest.dupe(),
+                    },
+                    timeout,
+                    CommandStdStreams::Remote(response.std_streams(
+                        &self.re_client,
+                        self.re_use_case,
+                        digest_config,
+                    )),
+                    response.timing(),
+                ));
+            }
+        }
         if response.error.code != TCode::OK {
             return ControlFlow::Break(manager.error(
                 "remote_exec_error",
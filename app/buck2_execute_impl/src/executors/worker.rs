@@ -0,0 +1,628 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Persistent workers: long-lived processes that local commands are sent to, instead of spawning
+//! a new process for each of them. This saves paying for the tool's startup on every action, and
+//! lets it keep warm caches between them.
+//!
+//! Workers speak the same protocol as Bazel's: they're started with `--persistent_worker`, and
+//! read `WorkRequest`s on stdin and write `WorkResponse`s on stdout, either as varint
+//! length-delimited protobuf messages or as newline-delimited JSON. Whatever they write on stderr
+//! goes to a log file.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::atomic::AtomicI32;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context as _;
+use buck2_common::liveliness_observer::LivelinessObserver;
+use buck2_core::fs::fs_util;
+use buck2_core::fs::paths::abs_norm_path::AbsNormPathBuf;
+use buck2_execute::execute::environment_inheritance::EnvironmentInheritance;
+use buck2_execute::execute::executor_stage_async;
+use buck2_execute::execute::request::WorkerProtocol;
+use buck2_execute::execute::request::WorkerSpec;
+use buck2_forkserver::run::timeout_into_cancellation;
+use buck2_forkserver::run::GatherOutputStatus;
+use buck2_util::process::background_command;
+use buck2_worker_proto::WorkRequest;
+use buck2_worker_proto::WorkResponse;
+use dupe::Dupe;
+use futures::future::select;
+use futures::future::Either;
+use futures::future::FutureExt;
+use parking_lot::Mutex;
+use prost::Message;
+use thiserror::Error;
+use tokio::io::AsyncBufRead;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::io::BufReader;
+use tokio::process::Child;
+use tokio::process::ChildStdin;
+use tokio::process::ChildStdout;
+use tokio::sync::oneshot;
+use tokio::sync::Semaphore;
+
+use crate::executors::local::apply_local_execution_environment;
+
+/// How many instances of a worker we run at most, unless it says otherwise.
+pub const DEFAULT_MAX_INSTANCES: usize = 4;
+
+/// How long a worker can sit idle before we shut it down.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+static NEXT_WORKER_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Error)]
+enum WorkerError {
+    #[error("Worker exe is empty")]
+    NoExe,
+
+    #[error("Error spawning worker")]
+    SpawnFailed(#[source] io::Error),
+
+    #[error("Worker exited, see its log in `{}`", .0.display())]
+    Exited(PathBuf),
+
+    #[error("Worker response is too large")]
+    ResponseTooLarge,
+}
+
+/// All the persistent workers started by this daemon. Workers are shared between commands, and
+/// outlive them.
+pub struct WorkerPool {
+    /// Where worker stderr goes.
+    log_dir: AbsNormPathBuf,
+    default_max_instances: usize,
+    idle_timeout: Duration,
+    groups: Mutex<HashMap<WorkerKey, Arc<WorkerGroup>>>,
+}
+
+/// Requests can only go to a worker that was started the same way they would have been.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct WorkerKey {
+    exe: Vec<String>,
+    env: Vec<(String, String)>,
+    working_directory: PathBuf,
+    protocol: WorkerProtocol,
+    max_multiplex_requests: Option<usize>,
+    /// Part of the key so that a worker's limit doesn't depend on who happened to start it first.
+    max_instances: Option<usize>,
+}
+
+/// The instances of a given worker.
+struct WorkerGroup {
+    /// How many requests each instance runs at once.
+    capacity: usize,
+    /// How many requests all the instances run at once, between them.
+    permits: Semaphore,
+    instances: tokio::sync::Mutex<Vec<Arc<WorkerProcess>>>,
+}
+
+impl WorkerPool {
+    pub fn new(
+        log_dir: AbsNormPathBuf,
+        default_max_instances: usize,
+        idle_timeout: Duration,
+    ) -> Self {
+        Self {
+            log_dir,
+            default_max_instances,
+            idle_timeout,
+            groups: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Send a command to a worker, starting one if none of those that are running has capacity
+    /// for it. This returns the same thing as running the command as a process would: the
+    /// worker's output is reported as the command's stderr.
+    pub async fn execute(
+        &self,
+        worker: &WorkerSpec,
+        args: &[String],
+        env: Vec<(String, String)>,
+        working_directory: &Path,
+        env_inheritance: Option<&EnvironmentInheritance>,
+        timeout: Option<Duration>,
+        liveliness_observer: impl LivelinessObserver + 'static,
+    ) -> anyhow::Result<(GatherOutputStatus, Vec<u8>, Vec<u8>)> {
+        if worker.exe.is_empty() {
+            return Err(WorkerError::NoExe.into());
+        }
+
+        self.shutdown_idle();
+
+        let key = WorkerKey {
+            exe: worker.exe.clone(),
+            env,
+            working_directory: working_directory.to_owned(),
+            protocol: worker.protocol,
+            max_multiplex_requests: worker.max_multiplex_requests,
+            max_instances: worker.max_instances,
+        };
+
+        let group = self
+            .groups
+            .lock()
+            .entry(key.clone())
+            .or_insert_with(|| {
+                let capacity = worker.max_multiplex_requests.unwrap_or(1);
+                let max_instances = worker.max_instances.unwrap_or(self.default_max_instances);
+                Arc::new(WorkerGroup {
+                    capacity,
+                    permits: Semaphore::new(capacity * max_instances),
+                    instances: tokio::sync::Mutex::new(Vec::new()),
+                })
+            })
+            .dupe();
+
+        let _permit = group.permits.acquire().await?;
+
+        let lease = {
+            let mut instances = group.instances.lock().await;
+            instances.retain(|p| !p.has_exited());
+
+            let process = match instances
+                .iter()
+                .find(|p| p.in_flight.load(Ordering::Acquire) < group.capacity)
+            {
+                Some(process) => process.dupe(),
+                None => {
+                    let process = executor_stage_async(
+                        buck2_data::LocalStage {
+                            stage: Some(
+                                buck2_data::LocalWorkerInit {
+                                    argv: key.exe.clone(),
+                                }
+                                .into(),
+                            ),
+                        },
+                        async { WorkerProcess::spawn(&key, &self.log_dir, env_inheritance) },
+                    )
+                    .await;
+
+                    let process = match process {
+                        Ok(process) => Arc::new(process),
+                        Err(e) => match e.downcast_ref::<WorkerError>() {
+                            Some(WorkerError::SpawnFailed(e)) => {
+                                return Ok((
+                                    GatherOutputStatus::SpawnFailed(e.to_string()),
+                                    Vec::new(),
+                                    Vec::new(),
+                                ));
+                            }
+                            _ => return Err(e),
+                        },
+                    };
+
+                    instances.push(process.dupe());
+                    process
+                }
+            };
+
+            WorkerLease::new(process)
+        };
+
+        lease
+            .process
+            .execute(args, timeout, liveliness_observer)
+            .await
+            .with_context(|| format!("Failed to run command in worker: {}", key.exe.join(" ")))
+    }
+
+    /// Stop the workers that haven't been used in a while. Workers that are busy starting another
+    /// instance are skipped, they'll be checked next time.
+    fn shutdown_idle(&self) {
+        let groups = self.groups.lock();
+        for group in groups.values() {
+            if let Ok(mut instances) = group.instances.try_lock() {
+                instances.retain(|p| !p.has_exited() && !p.idle_for(self.idle_timeout));
+            }
+        }
+    }
+}
+
+/// A running worker. Dropping this kills it.
+struct WorkerProcess {
+    child: Mutex<Child>,
+    stdin: tokio::sync::Mutex<ChildStdin>,
+    protocol: WorkerProtocol,
+    multiplex: bool,
+    log_path: PathBuf,
+    /// The requests that are waiting for a response, by ID. This is `None` once the worker has
+    /// exited.
+    pending: Arc<Mutex<Option<HashMap<i32, oneshot::Sender<WorkResponse>>>>>,
+    next_request_id: AtomicI32,
+    in_flight: AtomicUsize,
+    last_used: Mutex<Instant>,
+}
+
+impl WorkerProcess {
+    fn spawn(
+        key: &WorkerKey,
+        log_dir: &AbsNormPathBuf,
+        env_inheritance: Option<&EnvironmentInheritance>,
+    ) -> anyhow::Result<Self> {
+        fs_util::create_dir_all(log_dir)?;
+        let log_path = log_dir.as_path().join(format!(
+            "worker-{}-{}.log",
+            std::process::id(),
+            NEXT_WORKER_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let log = std::fs::File::create(&log_path)
+            .with_context(|| format!("Error creating `{}`", log_path.display()))?;
+
+        let mut cmd = background_command(&key.exe[0]);
+        cmd.args(&key.exe[1..]);
+        cmd.arg("--persistent_worker");
+        cmd.current_dir(&key.working_directory);
+        apply_local_execution_environment(
+            &mut cmd,
+            &key.working_directory,
+            key.env.iter().map(|(k, v)| (k, v)),
+            env_inheritance,
+        );
+        cmd.stdin(Stdio::piped());
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(log);
+
+        let mut cmd = tokio::process::Command::from(cmd);
+        cmd.kill_on_drop(true);
+        let mut child = cmd.spawn().map_err(WorkerError::SpawnFailed)?;
+
+        let stdin = child.stdin.take().context("Worker has no stdin")?;
+        let stdout = child.stdout.take().context("Worker has no stdout")?;
+
+        let pending = Arc::new(Mutex::new(Some(HashMap::new())));
+        tokio::spawn(read_responses(stdout, key.protocol, pending.dupe()));
+
+        Ok(Self {
+            child: Mutex::new(child),
+            stdin: tokio::sync::Mutex::new(stdin),
+            protocol: key.protocol,
+            multiplex: key.max_multiplex_requests.is_some(),
+            log_path,
+            pending,
+            next_request_id: AtomicI32::new(0),
+            in_flight: AtomicUsize::new(0),
+            last_used: Mutex::new(Instant::now()),
+        })
+    }
+
+    async fn execute(
+        &self,
+        args: &[String],
+        timeout: Option<Duration>,
+        liveliness_observer: impl LivelinessObserver + 'static,
+    ) -> anyhow::Result<(GatherOutputStatus, Vec<u8>, Vec<u8>)> {
+        // Singleplex workers only ever get one request at a time, and expect its ID to be 0.
+        let request_id = if self.multiplex {
+            self.next_request_id.fetch_add(1, Ordering::Relaxed)
+        } else {
+            0
+        };
+
+        let (tx, rx) = oneshot::channel();
+        match &mut *self.pending.lock() {
+            Some(pending) => {
+                pending.insert(request_id, tx);
+            }
+            None => return Err(WorkerError::Exited(self.log_path.clone()).into()),
+        }
+
+        self.send(&WorkRequest {
+            arguments: args.to_vec(),
+            request_id,
+            ..Default::default()
+        })
+        .await?;
+
+        let timeout = timeout_into_cancellation(timeout);
+        let alive = liveliness_observer
+            .while_alive()
+            .map(|()| anyhow::Ok(GatherOutputStatus::Cancelled));
+        let cancellation = select(timeout.boxed(), alive.boxed()).map(|r| r.factor_first().0);
+
+        match select(rx, cancellation).await {
+            Either::Left((Ok(response), _)) => {
+                let status = if response.was_cancelled {
+                    GatherOutputStatus::Cancelled
+                } else {
+                    GatherOutputStatus::Finished {
+                        exit_code: response.exit_code,
+                        execution_stats: None,
+                    }
+                };
+                Ok((status, Vec::new(), response.output.into_bytes()))
+            }
+            Either::Left((Err(..), _)) => Err(WorkerError::Exited(self.log_path.clone()).into()),
+            Either::Right((status, _)) => {
+                self.cancel(request_id).await;
+                Ok((status?, Vec::new(), Vec::new()))
+            }
+        }
+    }
+
+    /// Stop working on a request. Multiplex workers are asked to cancel it, but singleplex
+    /// workers can't do that, so we kill them.
+    async fn cancel(&self, request_id: i32) {
+        if let Some(pending) = &mut *self.pending.lock() {
+            pending.remove(&request_id);
+        }
+
+        if self.multiplex {
+            let request = WorkRequest {
+                request_id,
+                cancel: true,
+                ..Default::default()
+            };
+            if let Err(e) = self.send(&request).await {
+                tracing::debug!("Error cancelling worker request: {:#}", e);
+            }
+        } else if let Err(e) = self.child.lock().start_kill() {
+            tracing::debug!("Error killing worker: {:#}", e);
+        }
+    }
+
+    async fn send(&self, request: &WorkRequest) -> anyhow::Result<()> {
+        let bytes = encode_request(request, self.protocol)?;
+        let mut stdin = self.stdin.lock().await;
+        let res: io::Result<()> = try {
+            stdin.write_all(&bytes).await?;
+            stdin.flush().await?;
+        };
+        res.map_err(|e| anyhow::Error::new(e).context(WorkerError::Exited(self.log_path.clone())))
+    }
+
+    fn has_exited(&self) -> bool {
+        self.pending.lock().is_none()
+    }
+
+    fn idle_for(&self, duration: Duration) -> bool {
+        self.in_flight.load(Ordering::Acquire) == 0 && self.last_used.lock().elapsed() >= duration
+    }
+}
+
+/// A request slot on a worker.
+struct WorkerLease {
+    process: Arc<WorkerProcess>,
+}
+
+impl WorkerLease {
+    fn new(process: Arc<WorkerProcess>) -> Self {
+        process.in_flight.fetch_add(1, Ordering::AcqRel);
+        Self { process }
+    }
+}
+
+impl Drop for WorkerLease {
+    fn drop(&mut self) {
+        *self.process.last_used.lock() = Instant::now();
+        self.process.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Hand the worker's responses to the requests waiting for them, until it exits.
+async fn read_responses(
+    stdout: ChildStdout,
+    protocol: WorkerProtocol,
+    pending: Arc<Mutex<Option<HashMap<i32, oneshot::Sender<WorkResponse>>>>>,
+) {
+    let mut stdout = BufReader::new(stdout);
+
+    loop {
+        match read_response(&mut stdout, protocol).await {
+            Ok(Some(response)) => {
+                let tx = pending
+                    .lock()
+                    .as_mut()
+                    .and_then(|p| p.remove(&response.request_id));
+                // The request might have been cancelled.
+                if let Some(tx) = tx {
+                    let _ignored = tx.send(response);
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                tracing::warn!("Error reading worker response: {:#}", e);
+                break;
+            }
+        }
+    }
+
+    // This fails all the requests that are still waiting.
+    *pending.lock() = None;
+}
+
+fn encode_request(request: &WorkRequest, protocol: WorkerProtocol) -> anyhow::Result<Vec<u8>> {
+    match protocol {
+        WorkerProtocol::Proto => Ok(request.encode_length_delimited_to_vec()),
+        WorkerProtocol::Json => {
+            let mut bytes = serde_json::to_vec(request)?;
+            bytes.push(b'\n');
+            Ok(bytes)
+        }
+    }
+}
+
+/// Read the next response, or `None` if the worker closed its stdout.
+async fn read_response<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    protocol: WorkerProtocol,
+) -> anyhow::Result<Option<WorkResponse>> {
+    match protocol {
+        WorkerProtocol::Proto => {
+            let len = match read_varint(reader).await? {
+                Some(len) => usize::try_from(len).map_err(|_| WorkerError::ResponseTooLarge)?,
+                None => return Ok(None),
+            };
+            let mut buf = vec![0; len];
+            reader.read_exact(&mut buf).await?;
+            Ok(Some(WorkResponse::decode(buf.as_slice())?))
+        }
+        WorkerProtocol::Json => {
+            let mut line = String::new();
+            loop {
+                line.clear();
+                if reader.read_line(&mut line).await? == 0 {
+                    return Ok(None);
+                }
+                if !line.trim().is_empty() {
+                    break;
+                }
+            }
+            Ok(Some(serde_json::from_str(&line)?))
+        }
+    }
+}
+
+async fn read_varint<R: AsyncBufRead + Unpin>(reader: &mut R) -> anyhow::Result<Option<u64>> {
+    let mut value = 0u64;
+
+    // A varint is at most 10 bytes long.
+    for i in 0..10 {
+        let byte = match reader.read_u8().await {
+            Ok(byte) => byte,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && i == 0 => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+
+    Err(WorkerError::ResponseTooLarge.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(request_id: i32, exit_code: i32, output: &str) -> WorkResponse {
+        WorkResponse {
+            exit_code,
+            output: output.to_owned(),
+            request_id,
+            was_cancelled: false,
+        }
+    }
+
+    #[test]
+    fn test_encode_request_json() -> anyhow::Result<()> {
+        let request = WorkRequest {
+            arguments: vec!["--foo".to_owned(), "bar".to_owned()],
+            request_id: 3,
+            ..Default::default()
+        };
+
+        let encoded = String::from_utf8(encode_request(&request, WorkerProtocol::Json)?)?;
+        assert!(encoded.ends_with('\n'));
+
+        let decoded: serde_json::Value = serde_json::from_str(&encoded)?;
+        assert_eq!(decoded["arguments"], serde_json::json!(["--foo", "bar"]));
+        assert_eq!(decoded["requestId"], 3);
+        assert_eq!(decoded["cancel"], false);
+        assert!(decoded.get("inputs").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_request_proto() -> anyhow::Result<()> {
+        let request = WorkRequest {
+            arguments: vec!["--foo".to_owned()],
+            request_id: 7,
+            ..Default::default()
+        };
+
+        let encoded = encode_request(&request, WorkerProtocol::Proto)?;
+        assert_eq!(
+            WorkRequest::decode_length_delimited(encoded.as_slice())?,
+            request
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_response_proto() -> anyhow::Result<()> {
+        let big = "x".repeat(1000);
+
+        let mut bytes = Vec::new();
+        response(0, 0, "ok").encode_length_delimited(&mut bytes)?;
+        response(1, 2, &big).encode_length_delimited(&mut bytes)?;
+
+        let mut reader = bytes.as_slice();
+        assert_eq!(
+            read_response(&mut reader, WorkerProtocol::Proto).await?,
+            Some(response(0, 0, "ok"))
+        );
+        assert_eq!(
+            read_response(&mut reader, WorkerProtocol::Proto).await?,
+            Some(response(1, 2, &big))
+        );
+        assert_eq!(
+            read_response(&mut reader, WorkerProtocol::Proto).await?,
+            None
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_response_json() -> anyhow::Result<()> {
+        let bytes =
+            b"{\"exitCode\": 1, \"output\": \"error\", \"requestId\": 4}\n\n{\"requestId\": 5}\n";
+
+        let mut reader = &bytes[..];
+        assert_eq!(
+            read_response(&mut reader, WorkerProtocol::Json).await?,
+            Some(response(4, 1, "error"))
+        );
+        assert_eq!(
+            read_response(&mut reader, WorkerProtocol::Json).await?,
+            Some(response(5, 0, ""))
+        );
+        assert_eq!(
+            read_response(&mut reader, WorkerProtocol::Json).await?,
+            None
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_response_truncated() {
+        let mut bytes = Vec::new();
+        response(0, 0, "ok")
+            .encode_length_delimited(&mut bytes)
+            .unwrap();
+        bytes.pop();
+
+        let mut reader = bytes.as_slice();
+        assert!(
+            read_response(&mut reader, WorkerProtocol::Proto)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -10,6 +10,7 @@
 #![feature(try_blocks)]
 
 pub mod executors;
+pub mod local_cache;
 pub mod low_pass_filter;
 pub mod materializers;
 pub mod re;
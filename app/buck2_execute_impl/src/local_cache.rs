@@ -0,0 +1,555 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! An action cache and CAS that live on local disk, for builds that don't use RE.
+//!
+//! The cache is a directory containing action results (`ac/`), content-addressed blobs (`cas/`)
+//! and a sqlite index that tracks the size and last access time of each of those, which we use to
+//! keep the cache within its size budget. Several daemons may share the same cache directory:
+//! files are written to a temporary path and atomically renamed into place, and sqlite takes care
+//! of concurrent access to the index. Evicting something another daemon is about to use just
+//! results in a cache miss: blobs are pinned before we commit to restoring them.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use anyhow::Context as _;
+use buck2_common::cas_digest::CasDigest;
+use buck2_core::fs::fs_util;
+use buck2_core::fs::paths::abs_norm_path::AbsNormPath;
+use buck2_core::fs::paths::abs_norm_path::AbsNormPathBuf;
+use buck2_core::fs::paths::forward_rel_path::ForwardRelativePath;
+use buck2_execute::execute::action_digest::ActionDigest;
+use chrono::Utc;
+use parking_lot::Mutex;
+use rusqlite::Connection;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::materializers::io::CopyMethod;
+
+/// How large the cache may grow by default, 10 GiB.
+pub const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+
+const INDEX_FILENAME: &str = "index.sqlite";
+
+/// Once the cache exceeds its budget, we evict down to this percentage of it, so that we don't
+/// have to evict again on every subsequent write.
+const EVICTION_LOW_WATERMARK_PERCENT: u64 = 90;
+
+static NEXT_TMP_ID: AtomicU64 = AtomicU64::new(0);
+
+/// The result of an action, as stored in the cache.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct CachedActionResult {
+    /// The outputs of the action, keyed by their path relative to the project root.
+    pub outputs: BTreeMap<String, CachedEntry>,
+    /// The blob holding the action's stdout.
+    pub stdout: String,
+    /// The blob holding the action's stderr.
+    pub stderr: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CachedEntry {
+    File {
+        blob: String,
+        executable: bool,
+    },
+    Symlink {
+        target: String,
+    },
+    Directory {
+        entries: BTreeMap<String, CachedEntry>,
+    },
+}
+
+impl CachedActionResult {
+    /// All the blobs this result references.
+    fn blobs(&self) -> Vec<&str> {
+        fn visit<'a>(entry: &'a CachedEntry, blobs: &mut Vec<&'a str>) {
+            match entry {
+                CachedEntry::File { blob, .. } => blobs.push(blob),
+                CachedEntry::Symlink { .. } => {}
+                CachedEntry::Directory { entries } => {
+                    for entry in entries.values() {
+                        visit(entry, blobs);
+                    }
+                }
+            }
+        }
+
+        let mut blobs = vec![self.stdout.as_str(), self.stderr.as_str()];
+        for entry in self.outputs.values() {
+            visit(entry, &mut blobs);
+        }
+        blobs
+    }
+}
+
+/// The name of the blob for some content, or of the result of some action.
+pub fn blob_key<Kind>(digest: &CasDigest<Kind>) -> String {
+    format!(
+        "{}_{}",
+        hex::encode(digest.digest().as_bytes()),
+        digest.size()
+    )
+}
+
+/// Blobs of a cached result, see `LocalActionCache::pin`. They are unpinned when this is dropped.
+pub struct PinnedBlobs {
+    dir: AbsNormPathBuf,
+}
+
+impl PinnedBlobs {
+    fn path(&self, blob: &str) -> AbsNormPathBuf {
+        self.dir.join(ForwardRelativePath::unchecked_new(blob))
+    }
+
+    pub fn read(&self, blob: &str) -> anyhow::Result<Vec<u8>> {
+        fs_util::read(self.path(blob))
+    }
+}
+
+impl Drop for PinnedBlobs {
+    fn drop(&mut self) {
+        if let Err(e) = fs_util::remove_dir_all(&self.dir) {
+            tracing::warn!("Error unpinning local cache blobs: {:#}", e);
+        }
+    }
+}
+
+pub struct LocalActionCache {
+    root: AbsNormPathBuf,
+    max_bytes: u64,
+    /// How blobs are copied in and out of the cache. We never hardlink them, since outputs might
+    /// have their permissions changed after being restored.
+    copy_method: CopyMethod,
+    index: Mutex<Connection>,
+}
+
+impl LocalActionCache {
+    pub fn open(
+        root: AbsNormPathBuf,
+        max_bytes: u64,
+        copy_method: CopyMethod,
+    ) -> anyhow::Result<Self> {
+        for dir in ["ac", "cas", "tmp"] {
+            fs_util::create_dir_all(root.join(ForwardRelativePath::unchecked_new(dir)))?;
+        }
+
+        let connection =
+            Connection::open(root.join(ForwardRelativePath::unchecked_new(INDEX_FILENAME)))?;
+        // TODO: make this work on Windows too
+        if cfg!(unix) {
+            connection.pragma_update(None, "journal_mode", "WAL")?;
+        }
+        // Other daemons sharing this cache might be holding the lock.
+        connection.busy_timeout(Duration::from_secs(60))?;
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS entries (
+                    key                 TEXT NOT NULL PRIMARY KEY,
+                    size                INTEGER NOT NULL,
+                    last_access_time    INTEGER NOT NULL
+                )",
+                [],
+            )
+            .context("creating sqlite table entries")?;
+        connection
+            .execute(
+                "CREATE INDEX IF NOT EXISTS entries_by_last_access_time ON entries (last_access_time)",
+                [],
+            )
+            .context("creating sqlite index entries_by_last_access_time")?;
+
+        Ok(Self {
+            root,
+            max_bytes,
+            copy_method,
+            index: Mutex::new(connection),
+        })
+    }
+
+    /// Find the result of an action. This only returns results whose blobs are all present, and
+    /// marks them as recently used.
+    pub fn get(&self, action: &ActionDigest) -> anyhow::Result<Option<CachedActionResult>> {
+        let key = format!("ac/{}", blob_key(action));
+        let path = self.path(&key);
+
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Error reading `{}`", path.display()));
+            }
+        };
+
+        let result: CachedActionResult = match serde_json::from_slice(&data) {
+            Ok(result) => result,
+            Err(e) => {
+                tracing::warn!("Ignoring invalid local cache entry `{}`: {}", key, e);
+                return Ok(None);
+            }
+        };
+
+        let mut keys = vec![key];
+        for blob in result.blobs() {
+            let key = format!("cas/{}", blob);
+            if !fs_util::try_exists(self.path(&key))? {
+                return Ok(None);
+            }
+            keys.push(key);
+        }
+
+        self.touch(&keys)?;
+
+        Ok(Some(result))
+    }
+
+    fn blob_path(&self, blob: &str) -> AbsNormPathBuf {
+        self.path(&format!("cas/{}", blob))
+    }
+
+    /// Hardlink all the blobs of a result returned by `get` into a temporary directory, so that
+    /// they remain available even if they are evicted before we are done restoring them.
+    pub fn pin(&self, result: &CachedActionResult) -> anyhow::Result<PinnedBlobs> {
+        let pinned = PinnedBlobs {
+            dir: self.tmp_path(),
+        };
+        fs_util::create_dir_all(&pinned.dir)?;
+
+        for blob in result.blobs() {
+            let dest = pinned.path(blob);
+            // The same blob can be referenced more than once.
+            if !fs_util::try_exists(&dest)? {
+                fs_util::hard_link(self.blob_path(blob), dest)?;
+            }
+        }
+
+        Ok(pinned)
+    }
+
+    /// Copy a pinned blob to `dest`.
+    pub fn restore_blob(
+        &self,
+        pinned: &PinnedBlobs,
+        blob: &str,
+        dest: &AbsNormPath,
+    ) -> anyhow::Result<()> {
+        self.copy_method
+            .copy_file(&pinned.path(blob), dest, false)?;
+        Ok(())
+    }
+
+    /// Store a blob by copying the file at `src`, unless we have it already.
+    pub fn put_blob_from_file(&self, blob: &str, src: &AbsNormPath) -> anyhow::Result<()> {
+        self.put_file(&format!("cas/{}", blob), |tmp| {
+            self.copy_method.copy_file(src, tmp, false)?;
+            // Whether outputs are executable is part of the action result, not the blob, since
+            // identical outputs might differ in that respect.
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs_util::set_permissions(tmp, std::fs::Permissions::from_mode(0o644))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Store a blob with the given contents, unless we have it already.
+    pub fn put_blob(&self, blob: &str, data: &[u8]) -> anyhow::Result<()> {
+        self.put_file(&format!("cas/{}", blob), |tmp| fs_util::write(tmp, data))
+    }
+
+    /// Store the result of an action. All the blobs it references must have been stored first.
+    pub fn put(&self, action: &ActionDigest, result: &CachedActionResult) -> anyhow::Result<()> {
+        let data = serde_json::to_vec(result)?;
+        let key = format!("ac/{}", blob_key(action));
+        // Unlike blobs, results for the same action might differ, so we always replace those.
+        self.write_file(&key, |tmp| fs_util::write(tmp, &data))?;
+        self.evict_if_needed()
+    }
+
+    fn path(&self, key: &str) -> AbsNormPathBuf {
+        // Shard the blobs by their first byte so we don't end up with huge directories.
+        let (kind, name) = key.split_once('/').unwrap_or(("", key));
+        let shard = name.get(..2).unwrap_or(name);
+        self.root.join(ForwardRelativePath::unchecked_new(&format!(
+            "{}/{}/{}",
+            kind, shard, name
+        )))
+    }
+
+    fn put_file(
+        &self,
+        key: &str,
+        write: impl FnOnce(&AbsNormPath) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        if fs_util::try_exists(self.path(key))? {
+            return self.touch(&[key.to_owned()]);
+        }
+        self.write_file(key, write)
+    }
+
+    fn write_file(
+        &self,
+        key: &str,
+        write: impl FnOnce(&AbsNormPath) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let path = self.path(key);
+        let tmp = self.tmp_path();
+
+        let res: anyhow::Result<u64> = try {
+            write(&tmp)?;
+            let size = fs_util::symlink_metadata(&tmp)?.len();
+            if let Some(parent) = path.parent() {
+                fs_util::create_dir_all(parent)?;
+            }
+            fs_util::rename(&tmp, &path)?;
+            size
+        };
+
+        let size = match res {
+            Ok(size) => size,
+            Err(e) => {
+                let _ignored = fs_util::remove_file(&tmp);
+                return Err(e.context(format!("Error writing `{}` to the local cache", key)));
+            }
+        };
+
+        self.index
+            .lock()
+            .execute(
+                "INSERT OR REPLACE INTO entries (key, size, last_access_time) VALUES (?1, ?2, ?3)",
+                rusqlite::params![key, size, Utc::now().timestamp()],
+            )
+            .context("inserting into sqlite table entries")?;
+
+        Ok(())
+    }
+
+    /// A path in our temporary directory that nobody else uses.
+    fn tmp_path(&self) -> AbsNormPathBuf {
+        self.root.join(ForwardRelativePath::unchecked_new(&format!(
+            "tmp/{}-{}",
+            std::process::id(),
+            NEXT_TMP_ID.fetch_add(1, Ordering::Relaxed)
+        )))
+    }
+
+    fn touch(&self, keys: &[String]) -> anyhow::Result<()> {
+        let mut index = self.index.lock();
+        let transaction = index.transaction()?;
+        {
+            let mut stmt = transaction
+                .prepare("UPDATE entries SET last_access_time = (?1) WHERE key = (?2)")?;
+            let now = Utc::now().timestamp();
+            for key in keys {
+                stmt.execute(rusqlite::params![now, key])
+                    .context("updating sqlite table entries")?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// If the cache is over budget, delete the least recently used entries until it is back under
+    /// the low watermark.
+    fn evict_if_needed(&self) -> anyhow::Result<()> {
+        let index = self.index.lock();
+
+        let total: u64 = index
+            .query_row("SELECT COALESCE(SUM(size), 0) FROM entries", [], |row| {
+                row.get(0)
+            })
+            .context("reading from sqlite table entries")?;
+
+        if total <= self.max_bytes {
+            return Ok(());
+        }
+
+        let target = self.max_bytes / 100 * EVICTION_LOW_WATERMARK_PERCENT;
+        let mut to_free = total - target;
+        let mut evicted = Vec::new();
+
+        {
+            let mut stmt =
+                index.prepare("SELECT key, size FROM entries ORDER BY last_access_time ASC")?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                if to_free == 0 {
+                    break;
+                }
+                let key: String = row.get(0)?;
+                let size: u64 = row.get(1)?;
+                to_free = to_free.saturating_sub(size);
+                evicted.push(key);
+            }
+        }
+
+        tracing::debug!(
+            "Local cache is over budget ({} > {} bytes), evicting {} entries",
+            total,
+            self.max_bytes,
+            evicted.len()
+        );
+
+        for key in &evicted {
+            match std::fs::remove_file(self.path(key)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => tracing::warn!("Error evicting `{}` from the local cache: {}", key, e),
+            }
+        }
+
+        let mut stmt = index.prepare("DELETE FROM entries WHERE key = (?1)")?;
+        for key in &evicted {
+            stmt.execute([key])
+                .context("deleting from sqlite table entries")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_common::cas_digest::CasDigestConfig;
+
+    use super::*;
+
+    fn result(stdout: &str, file: &str) -> CachedActionResult {
+        CachedActionResult {
+            outputs: BTreeMap::from([
+                (
+                    "buck-out/v2/out".to_owned(),
+                    CachedEntry::File {
+                        blob: file.to_owned(),
+                        executable: true,
+                    },
+                ),
+                (
+                    "buck-out/v2/dir".to_owned(),
+                    CachedEntry::Directory {
+                        entries: BTreeMap::from([(
+                            "link".to_owned(),
+                            CachedEntry::Symlink {
+                                target: "../out".to_owned(),
+                            },
+                        )]),
+                    },
+                ),
+            ]),
+            stdout: stdout.to_owned(),
+            stderr: stdout.to_owned(),
+        }
+    }
+
+    fn action(content: &str) -> ActionDigest {
+        ActionDigest::from_content(content.as_bytes(), CasDigestConfig::testing_default())
+    }
+
+    #[test]
+    fn test_put_get() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let cache = LocalActionCache::open(
+            AbsNormPathBuf::new(tempdir.path().to_owned())?,
+            1024 * 1024,
+            CopyMethod::Copy,
+        )?;
+
+        let digest = action("action");
+        assert_eq!(cache.get(&digest)?, None);
+
+        let result = result("stdout", "file");
+
+        // Results whose blobs are missing are ignored.
+        cache.put(&digest, &result)?;
+        assert_eq!(cache.get(&digest)?, None);
+
+        cache.put_blob("stdout", b"hello")?;
+        cache.put_blob("file", b"world")?;
+        assert_eq!(cache.get(&digest)?, Some(result));
+        assert_eq!(fs_util::read_to_string(cache.blob_path("file"))?, "world");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pinned_blobs_survive_eviction() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let cache = LocalActionCache::open(
+            AbsNormPathBuf::new(tempdir.path().to_owned())?,
+            1024 * 1024,
+            CopyMethod::Copy,
+        )?;
+
+        let digest = action("action");
+        cache.put_blob("stdout", b"hello")?;
+        cache.put_blob("file", b"world")?;
+        cache.put(&digest, &result("stdout", "file"))?;
+
+        let result = cache.get(&digest)?.unwrap();
+        let pinned = cache.pin(&result)?;
+
+        fs_util::remove_file(cache.blob_path("file"))?;
+        assert_eq!(cache.get(&digest)?, None);
+
+        let dest = AbsNormPathBuf::new(tempdir.path().join("restored"))?;
+        cache.restore_blob(&pinned, "file", &dest)?;
+        assert_eq!(fs_util::read_to_string(&dest)?, "world");
+        assert_eq!(pinned.read("stdout")?, b"hello");
+
+        let dir = pinned.dir.clone();
+        drop(pinned);
+        assert!(!fs_util::try_exists(&dir)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_eviction() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        // Enough for two of the results below, but not three.
+        let cache = LocalActionCache::open(
+            AbsNormPathBuf::new(tempdir.path().to_owned())?,
+            700,
+            CopyMethod::Copy,
+        )?;
+
+        let data = [0; 100];
+
+        cache.put_blob("a", &data)?;
+        cache.put_blob("b", &data)?;
+        cache.put(&action("a"), &result("a", "a"))?;
+        cache.put(&action("b"), &result("b", "b"))?;
+        assert!(cache.get(&action("a"))?.is_some());
+        assert!(cache.get(&action("b"))?.is_some());
+
+        // Make `a` the least recently used.
+        cache.index.lock().execute(
+            "UPDATE entries SET last_access_time = 0 WHERE key IN (?1, ?2)",
+            ["cas/a".to_owned(), format!("ac/{}", blob_key(&action("a")))],
+        )?;
+
+        cache.put_blob("c", &data)?;
+        cache.put(&action("c"), &result("c", "c"))?;
+
+        assert!(cache.get(&action("a"))?.is_none());
+        assert!(cache.get(&action("b"))?.is_some());
+        assert!(cache.get(&action("c"))?.is_some());
+
+        Ok(())
+    }
+}
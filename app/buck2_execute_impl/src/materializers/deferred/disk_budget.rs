@@ -0,0 +1,409 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Keeps materialized artifacts within a disk budget, by periodically evicting the least recently
+//! used ones from buck-out.
+
+use std::time::Instant;
+
+use anyhow::Context as _;
+use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
+use buck2_events::dispatch::EventDispatcher;
+use buck2_execute::execute::clean_output_paths::CleanOutputPaths;
+use chrono::DateTime;
+use chrono::Utc;
+use derivative::Derivative;
+use dupe::Dupe;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tokio::sync::oneshot;
+use tokio::sync::oneshot::Sender;
+use tokio::time::MissedTickBehavior;
+
+use crate::materializers::deferred::clean_path;
+use crate::materializers::deferred::extension::ExtensionCommand;
+use crate::materializers::deferred::join_all_existing_futs;
+use crate::materializers::deferred::ArtifactMaterializationData;
+use crate::materializers::deferred::ArtifactMaterializationStage;
+use crate::materializers::deferred::ArtifactTree;
+use crate::materializers::deferred::CleaningFuture;
+use crate::materializers::deferred::DefaultIoHandler;
+use crate::materializers::deferred::DeferredMaterializerCommandProcessor;
+use crate::materializers::deferred::DiskBudgetConfiguration;
+use crate::materializers::deferred::IoHandler;
+use crate::materializers::deferred::MaterializerCommand;
+use crate::materializers::deferred::MaterializerSender;
+use crate::materializers::deferred::Processing;
+use crate::materializers::deferred::ProcessingFuture;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct MaterializedArtifact {
+    pub(super) path: ProjectRelativePathBuf,
+    pub(super) last_access_time: DateTime<Utc>,
+    /// The size recorded when the artifact was materialized.
+    pub(super) size: u64,
+    /// See `is_evictable`.
+    pub(super) evictable: bool,
+}
+
+/// Lists all the artifacts that are currently materialized.
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct ListMaterializedArtifacts {
+    #[derivative(Debug = "ignore")]
+    sender: Sender<Vec<MaterializedArtifact>>,
+}
+
+impl ExtensionCommand<DefaultIoHandler> for ListMaterializedArtifacts {
+    fn execute(
+        self: Box<Self>,
+        processor: &mut DeferredMaterializerCommandProcessor<DefaultIoHandler>,
+    ) {
+        let _ignored = self
+            .sender
+            .send(list_materialized_artifacts(&processor.tree));
+    }
+}
+
+pub(super) fn list_materialized_artifacts(tree: &ArtifactTree) -> Vec<MaterializedArtifact> {
+    tree.iter_with_paths()
+        .filter_map(|(path, data)| match &data.stage {
+            ArtifactMaterializationStage::Materialized {
+                metadata,
+                last_access_time,
+                ..
+            } => Some(MaterializedArtifact {
+                path: ProjectRelativePathBuf::from(path),
+                last_access_time: *last_access_time,
+                size: metadata.total_size,
+                evictable: is_evictable(data),
+            }),
+            ArtifactMaterializationStage::Declared { .. } => None,
+        })
+        .collect()
+}
+
+/// Whether a materialized artifact can be evicted. Artifacts that are being materialized or
+/// deleted are in use, so they are left alone. Artifacts declared by the running daemon can only
+/// be evicted if we know how to materialize them again, since DICE assumes they are available.
+fn is_evictable(data: &ArtifactMaterializationData) -> bool {
+    match (&data.stage, &data.processing) {
+        (_, Processing::Active { .. }) => false,
+        (
+            ArtifactMaterializationStage::Materialized {
+                active,
+                declaration,
+                ..
+            },
+            Processing::Done(..),
+        ) => !active || declaration.is_some(),
+        (ArtifactMaterializationStage::Declared { .. }, Processing::Done(..)) => false,
+    }
+}
+
+/// Evicts artifacts that were selected for eviction, unless they were accessed (or replaced) since
+/// they were listed. Returns the artifacts that were actually evicted, along with a future that
+/// resolves once they are deleted.
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct EvictArtifacts {
+    artifacts: Vec<MaterializedArtifact>,
+    #[derivative(Debug = "ignore")]
+    sender: Sender<(
+        Vec<MaterializedArtifact>,
+        BoxFuture<'static, anyhow::Result<()>>,
+    )>,
+}
+
+impl ExtensionCommand<DefaultIoHandler> for EvictArtifacts {
+    fn execute(
+        self: Box<Self>,
+        processor: &mut DeferredMaterializerCommandProcessor<DefaultIoHandler>,
+    ) {
+        let mut evicted = Vec::new();
+        let mut cleaning_futs = Vec::new();
+
+        for (artifact, eviction) in remove_unchanged_artifacts(processor, self.artifacts) {
+            match eviction {
+                Eviction::Removed(existing_futs) => {
+                    let io = processor.io.dupe();
+                    let path = artifact.path.clone();
+                    cleaning_futs.push(
+                        async move {
+                            join_all_existing_futs(existing_futs).await?;
+                            io.io_executor
+                                .execute_io(Box::new(CleanOutputPaths { paths: vec![path] }))
+                                .await?;
+                            anyhow::Ok(())
+                        }
+                        .boxed(),
+                    );
+                }
+                Eviction::Redeclared(cleaning_fut) => {
+                    cleaning_futs.push(
+                        async move {
+                            cleaning_fut.await?;
+                            anyhow::Ok(())
+                        }
+                        .boxed(),
+                    );
+                }
+            }
+
+            evicted.push(artifact);
+        }
+
+        let fut = async move {
+            futures::future::try_join_all(cleaning_futs).await?;
+            Ok(())
+        }
+        .boxed();
+        let _ignored = self.sender.send((evicted, fut));
+    }
+}
+
+/// What happened to an evicted artifact.
+pub(super) enum Eviction {
+    /// The artifact was removed from the state, and must be deleted once these futures finish.
+    Removed(Vec<(ProjectRelativePathBuf, ProcessingFuture)>),
+    /// The artifact was declared again, and is being deleted by this future. It will be
+    /// materialized again when it is next needed.
+    Redeclared(CleaningFuture),
+}
+
+/// Evicts the artifacts that weren't accessed (or replaced) since they were listed, and that
+/// still can be evicted. Artifacts the running daemon declared are declared again, the others are
+/// removed from the tree and the sqlite state.
+pub(super) fn remove_unchanged_artifacts<T: IoHandler>(
+    processor: &mut DeferredMaterializerCommandProcessor<T>,
+    artifacts: Vec<MaterializedArtifact>,
+) -> Vec<(MaterializedArtifact, Eviction)> {
+    let mut removed = Vec::new();
+
+    for artifact in artifacts {
+        let unchanged = match processor.tree.prefix_get(&mut artifact.path.iter()) {
+            Some(data) if is_evictable(data) => match &data.stage {
+                ArtifactMaterializationStage::Materialized {
+                    last_access_time,
+                    declaration,
+                    ..
+                } if *last_access_time == artifact.last_access_time => {
+                    Some((data.deps.dupe(), declaration.clone()))
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        let (deps, declaration) = match unchanged {
+            Some(unchanged) => unchanged,
+            None => {
+                tracing::trace!(path = %artifact.path, "artifact changed, not evicting");
+                continue;
+            }
+        };
+
+        tracing::trace!(path = %artifact.path, "evicting artifact");
+
+        let existing_futs = processor.tree.invalidate_paths_and_collect_futures(
+            vec![artifact.path.clone()],
+            processor.sqlite_db.as_mut(),
+        );
+
+        let eviction = match declaration {
+            None => Eviction::Removed(existing_futs),
+            Some((entry, method)) => {
+                let version = processor.version_tracker.next();
+                let future = clean_path(
+                    &processor.io,
+                    artifact.path.clone(),
+                    version,
+                    processor.command_sender.dupe(),
+                    existing_futs,
+                    &processor.rt,
+                );
+                processor.tree.insert(
+                    artifact.path.iter().map(|f| f.to_owned()),
+                    Box::new(ArtifactMaterializationData {
+                        deps,
+                        stage: ArtifactMaterializationStage::Declared { entry, method },
+                        processing: Processing::Active {
+                            future: ProcessingFuture::Cleaning(future.clone()),
+                            version,
+                        },
+                    }),
+                );
+                Eviction::Redeclared(future)
+            }
+        };
+        removed.push((artifact, eviction));
+    }
+
+    removed
+}
+
+/// Periodically evicts artifacts until the materialized artifacts fit in the budget. This runs for
+/// as long as the materializer is alive.
+pub(super) async fn enforce_disk_budget_periodically(
+    command_sender: MaterializerSender<DefaultIoHandler>,
+    config: DiskBudgetConfiguration,
+) {
+    let mut ticker = tokio::time::interval_at(
+        tokio::time::Instant::now() + config.frequency,
+        config.frequency,
+    );
+    // If a run takes longer than a tick, just wait for the next one.
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+
+        match enforce_disk_budget(&command_sender, &config.event_dispatcher, config.max_bytes).await
+        {
+            Ok(()) => {}
+            Err(e) => {
+                tracing::warn!("Enforcing materializer disk budget failed: {:#}", e);
+            }
+        }
+    }
+}
+
+async fn enforce_disk_budget(
+    command_sender: &MaterializerSender<DefaultIoHandler>,
+    event_dispatcher: &EventDispatcher,
+    max_bytes: u64,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+
+    let (sender, receiver) = oneshot::channel();
+    command_sender.send(MaterializerCommand::Extension(Box::new(
+        ListMaterializedArtifacts { sender },
+    )))?;
+    let artifacts = receiver.await.context("No response from materializer")?;
+
+    let used_bytes = artifacts.iter().map(|artifact| artifact.size).sum::<u64>();
+    let to_evict = select_for_eviction(artifacts, max_bytes);
+
+    let (evicted, reclaimed_bytes) = if to_evict.is_empty() {
+        (Vec::new(), 0)
+    } else {
+        let (sender, receiver) = oneshot::channel();
+        command_sender.send(MaterializerCommand::Extension(Box::new(EvictArtifacts {
+            artifacts: to_evict,
+            sender,
+        })))?;
+        let (evicted, cleaning_fut) = receiver.await.context("No response from materializer")?;
+        cleaning_fut.await?;
+
+        let reclaimed_bytes = evicted.iter().map(|artifact| artifact.size).sum::<u64>();
+        (evicted, reclaimed_bytes)
+    };
+
+    tracing::info!(
+        "Materializer disk budget: {} used out of {}, evicted {} artifacts ({})",
+        bytesize::to_string(used_bytes, true),
+        bytesize::to_string(max_bytes, true),
+        evicted.len(),
+        bytesize::to_string(reclaimed_bytes, true),
+    );
+
+    event_dispatcher.instant_event(buck2_data::MaterializerDiskBudgetGc {
+        budget_bytes: max_bytes,
+        used_bytes,
+        evicted_artifact_count: evicted.len() as u64,
+        reclaimed_bytes,
+        duration: start.elapsed().try_into().ok(),
+    });
+
+    Ok(())
+}
+
+/// Select the least recently used artifacts to evict so that the remaining ones fit in
+/// `max_bytes`. Artifacts that can't be evicted are skipped, so this might not be enough to get
+/// under budget.
+fn select_for_eviction(
+    mut artifacts: Vec<MaterializedArtifact>,
+    max_bytes: u64,
+) -> Vec<MaterializedArtifact> {
+    let mut used_bytes = artifacts.iter().map(|artifact| artifact.size).sum::<u64>();
+    if used_bytes <= max_bytes {
+        return Vec::new();
+    }
+
+    artifacts.sort_by_key(|artifact| artifact.last_access_time);
+
+    let mut to_evict = Vec::new();
+    for artifact in artifacts {
+        if used_bytes <= max_bytes {
+            break;
+        }
+        if !artifact.evictable {
+            continue;
+        }
+        used_bytes -= artifact.size;
+        to_evict.push(artifact);
+    }
+    to_evict
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn artifact(
+        path: &str,
+        last_access_time: i64,
+        size: u64,
+        evictable: bool,
+    ) -> MaterializedArtifact {
+        MaterializedArtifact {
+            path: ProjectRelativePathBuf::unchecked_new(path.to_owned()),
+            last_access_time: Utc.timestamp_opt(last_access_time, 0).unwrap(),
+            size,
+            evictable,
+        }
+    }
+
+    fn paths(artifacts: Vec<MaterializedArtifact>) -> Vec<String> {
+        artifacts
+            .into_iter()
+            .map(|artifact| artifact.path.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_select_for_eviction() {
+        let artifacts = vec![
+            artifact("a", 3, 10, true),
+            artifact("b", 1, 20, true),
+            artifact("c", 0, 40, false),
+            artifact("d", 2, 30, true),
+        ];
+
+        // Within budget, nothing to do.
+        assert_eq!(
+            paths(select_for_eviction(artifacts.clone(), 100)),
+            Vec::<String>::new()
+        );
+
+        // Least recently used first, skipping artifacts that can't be evicted.
+        assert_eq!(paths(select_for_eviction(artifacts.clone(), 80)), vec!["b"]);
+        assert_eq!(
+            paths(select_for_eviction(artifacts.clone(), 60)),
+            vec!["b", "d"]
+        );
+
+        // Those are kept even if that means staying over budget.
+        assert_eq!(
+            paths(select_for_eviction(artifacts, 0)),
+            vec!["b", "d", "a"]
+        );
+    }
+}
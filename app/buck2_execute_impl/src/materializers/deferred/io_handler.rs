@@ -7,6 +7,7 @@
  * of this source tree.
  */
 
+use std::cmp::min;
 use std::collections::HashSet;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -61,6 +62,8 @@ use crate::materializers::deferred::SharedMaterializingError;
 use crate::materializers::deferred::Version;
 use crate::materializers::deferred::WriteFile;
 use crate::materializers::io::materialize_files;
+use crate::materializers::io::CopiedWith;
+use crate::materializers::io::CopyMethod;
 use crate::materializers::io::MaterializeTreeStructure;
 
 pub(super) struct DefaultIoHandler {
@@ -70,11 +73,14 @@ pub(super) struct DefaultIoHandler {
     pub(super) re_client_manager: Arc<ReConnectionManager>,
     /// Executor for blocking IO operations
     pub(super) io_executor: Arc<dyn BlockingExecutor>,
+    pub(super) copy_method: CopyMethod,
 }
 
 struct MaterializationStat {
     file_count: u64,
     total_bytes: u64,
+    /// For local copies, how the files were actually copied.
+    copied_with: Option<CopiedWith>,
 }
 
 #[async_trait]
@@ -186,7 +192,7 @@ impl DefaultIoHandler {
                         &self.fs,
                         self.digest_config,
                         &path,
-                        &info.url,
+                        &info.urls,
                         &info.checksum,
                         info.metadata.is_executable,
                     )
@@ -224,11 +230,21 @@ impl DefaultIoHandler {
                             stat.file_count += count_and_bytes.count;
                             stat.total_bytes += count_and_bytes.bytes;
 
-                            materialize_files(
+                            // Buck2 deletes outputs rather than modifying them, so it's safe to
+                            // hardlink them, but sources might be edited in place.
+                            let copied_with = materialize_files(
                                 a.dest_entry.as_ref(),
                                 &self.fs.root().join(&a.src),
                                 &self.fs.root().join(&a.dest),
+                                self.copy_method,
+                                a.src.starts_with(&self.buck_out_path),
                             )?;
+                            if let Some(copied_with) = copied_with {
+                                stat.copied_with = Some(
+                                    stat.copied_with
+                                        .map_or(copied_with, |c| min(c, copied_with)),
+                                );
+                            }
                         }
                         Ok(())
                     })
@@ -312,6 +328,7 @@ impl IoHandler for DefaultIoHandler {
                 let mut stat = MaterializationStat {
                     file_count: 0,
                     total_bytes: 0,
+                    copied_with: None,
                 };
                 let res = self
                     .materialize_entry_span(path, method.dupe(), entry, &mut stat)
@@ -327,7 +344,11 @@ impl IoHandler for DefaultIoHandler {
                         path: path_string,
                         success: error.is_none(),
                         error,
-                        method: Some(method.to_proto() as i32),
+                        method: Some(
+                            stat.copied_with
+                                .map_or_else(|| method.to_proto(), CopiedWith::to_proto)
+                                as i32,
+                        ),
                     },
                 )
             })
@@ -8,6 +8,7 @@
  */
 
 mod clean_stale;
+mod disk_budget;
 mod extension;
 mod file_tree;
 mod io_handler;
@@ -64,6 +65,7 @@ use buck2_execute::materialize::materializer::HttpDownloadInfo;
 use buck2_execute::materialize::materializer::MaterializationError;
 use buck2_execute::materialize::materializer::Materializer;
 use buck2_execute::materialize::materializer::WriteRequest;
+use buck2_execute::output_size::OutputSize;
 use buck2_execute::re::manager::ReConnectionManager;
 use chrono::DateTime;
 use chrono::Duration;
@@ -99,6 +101,7 @@ use crate::materializers::deferred::io_handler::IoHandler;
 use crate::materializers::deferred::subscriptions::MaterializerSubscriptionOperation;
 use crate::materializers::deferred::subscriptions::MaterializerSubscriptions;
 use crate::materializers::immediate;
+use crate::materializers::io::CopyMethod;
 use crate::materializers::sqlite::MaterializerState;
 use crate::materializers::sqlite::MaterializerStateSqliteDb;
 
@@ -158,6 +161,8 @@ pub struct DeferredMaterializerConfigs {
     pub materialize_final_artifacts: bool,
     pub defer_write_actions: bool,
     pub ttl_refresh: TtlRefreshConfiguration,
+    pub disk_budget: Option<DiskBudgetConfiguration>,
+    pub copy_method: CopyMethod,
 }
 
 pub struct TtlRefreshConfiguration {
@@ -166,6 +171,14 @@ pub struct TtlRefreshConfiguration {
     pub enabled: bool,
 }
 
+pub struct DiskBudgetConfiguration {
+    /// Materialized artifacts are evicted, least recently used first, to stay under this size.
+    pub max_bytes: u64,
+    pub frequency: std::time::Duration,
+    /// Eviction isn't part of any command, so it reports its events here.
+    pub event_dispatcher: EventDispatcher,
+}
+
 #[derive(Copy, Dupe, Clone)]
 struct MaterializerCounters {
     sent: &'static AtomicUsize,
@@ -486,15 +499,24 @@ pub type ActionDirectoryFingerprint = TrackedFileDigest;
 /// For everything else (files, symlinks, and external symlinks), we use `ActionDirectoryMember`
 /// as is because it already holds the metadata we need.
 #[derive(Clone, Dupe, Debug, PartialEq, Eq)]
-pub struct ArtifactMetadata(pub ActionDirectoryEntry<ActionDirectoryFingerprint>);
+pub struct ArtifactMetadata {
+    pub entry: ActionDirectoryEntry<ActionDirectoryFingerprint>,
+    /// The total size of the files in the artifact, which the fingerprint of a directory doesn't
+    /// tell us. Used to enforce the disk budget without looking at disk.
+    pub total_size: u64,
+}
 
 impl From<ActionDirectoryEntry<ActionSharedDirectory>> for ArtifactMetadata {
     fn from(entry: ActionDirectoryEntry<ActionSharedDirectory>) -> Self {
+        let total_size = entry.calc_output_count_and_bytes().bytes;
         let new_entry: ActionDirectoryEntry<ActionDirectoryFingerprint> = match entry {
             DirectoryEntry::Dir(dir) => DirectoryEntry::Dir(dir.fingerprint().dupe()),
             DirectoryEntry::Leaf(leaf) => DirectoryEntry::Leaf(leaf),
         };
-        Self(new_entry)
+        Self {
+            entry: new_entry,
+            total_size,
+        }
     }
 }
 
@@ -520,6 +542,14 @@ enum ArtifactMaterializationStage {
         /// Should not be deleted without invalidating DICE nodes, which currently
         /// means killing the daemon.
         active: bool,
+        /// How an active artifact was declared, so that it can be declared again (and
+        /// materialized when next needed) if it is evicted to stay within the disk budget. This is
+        /// `None` for artifacts that were already on disk when declared (e.g. outputs of local
+        /// actions), since we can't produce those again.
+        declaration: Option<(
+            ActionDirectoryEntry<ActionSharedDirectory>,
+            Arc<ArtifactMaterializationMethod>,
+        )>,
     },
 }
 
@@ -860,6 +890,7 @@ impl DeferredMaterializer {
                             metadata,
                             last_access_time,
                             active: false,
+                            declaration: None,
                         },
                         processing: Processing::Done(Version(0)),
                     }),
@@ -874,6 +905,7 @@ impl DeferredMaterializer {
                 buck_out_path,
                 re_client_manager,
                 io_executor: io_executor.dupe(),
+                copy_method: configs.copy_method,
             }),
             digest_config,
             sqlite_db,
@@ -886,6 +918,13 @@ impl DeferredMaterializer {
             subscriptions: MaterializerSubscriptions::new(),
         };
 
+        if let Some(disk_budget) = configs.disk_budget {
+            Handle::current().spawn(disk_budget::enforce_disk_budget_periodically(
+                command_sender.dupe(),
+                disk_budget,
+            ));
+        }
+
         let command_thread = std::thread::Builder::new()
             .name("buck2-dm".to_owned())
             .spawn({
@@ -1197,6 +1236,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                     metadata,
                     last_access_time: Utc::now(),
                     active: true,
+                    declaration: None,
                 },
                 processing: Processing::Done(self.version_tracker.next()),
             }),
@@ -1246,6 +1286,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                             metadata: metadata.dupe(),
                             last_access_time: *last_access_time,
                             active: true,
+                            declaration: Some((value.entry().dupe(), Arc::from(method))),
                         };
                         data.deps = deps;
 
@@ -1334,7 +1375,11 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
             ArtifactMaterializationStage::Materialized { metadata, .. } => {
                 let new_metadata: ArtifactMetadata = value.entry().dupe().into();
                 let is_match = *metadata == new_metadata;
-                tracing::trace!("materialized: found {}, is_match: {}", metadata.0, is_match);
+                tracing::trace!(
+                    "materialized: found {}, is_match: {}",
+                    metadata.entry,
+                    is_match
+                );
                 is_match
             }
             ArtifactMaterializationStage::Declared { entry, .. } => {
@@ -1620,10 +1665,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                             tracing::debug!("artifact is already materialized");
                             None
                         }
-                        ArtifactMaterializationStage::Declared {
-                            entry,
-                            method: _method,
-                        } => {
+                        ArtifactMaterializationStage::Declared { entry, method } => {
                             let metadata = ArtifactMetadata::from(entry.dupe());
 
                             // NOTE: We only insert this artifact if there isn't an in-progress cleanup
@@ -1642,6 +1684,7 @@ impl<T: IoHandler> DeferredMaterializerCommandProcessor<T> {
                                 metadata,
                                 last_access_time: timestamp,
                                 active: true,
+                                declaration: Some((entry.dupe(), method.dupe())),
                             })
                         }
                     };
@@ -89,13 +89,17 @@ fn test_remove_path() {
 mod state_machine {
     use std::path::Path;
 
+    use assert_matches::assert_matches;
+    use buck2_core::fs::project::ProjectRootTemp;
     use buck2_execute::directory::Symlink;
     use buck2_execute::directory::INTERNER;
+    use chrono::TimeZone;
     use parking_lot::Mutex;
     use tokio::time::sleep;
     use tokio::time::Duration as TokioDuration;
 
     use super::*;
+    use crate::materializers::deferred::disk_budget;
 
     #[derive(Debug, Eq, PartialEq)]
     enum Op {
@@ -498,4 +502,133 @@ mod state_machine {
 
         assert_eq!(paths, vec![foo_bar_baz.clone(), bar, foo_bar_baz]);
     }
+
+    #[tokio::test]
+    async fn test_evict_artifacts() -> anyhow::Result<()> {
+        let digest_config = DigestConfig::testing_default();
+        let value = ArtifactValue::file(digest_config.empty_file());
+        let metadata = ArtifactMetadata::from(value.entry().dupe());
+        let last_access_time = Utc.timestamp_opt(1000, 0).unwrap();
+
+        let fs = ProjectRootTemp::new()?;
+        let (sqlite_db, _) = MaterializerStateSqliteDb::initialize_impl(
+            fs.path()
+                .resolve(ProjectRelativePath::unchecked_new("materializer_state")),
+            HashMap::new(),
+            HashMap::new(),
+            digest_config,
+        )?;
+
+        let (mut dm, _) = make_processor(digest_config, Default::default());
+        dm.sqlite_db = Some(sqlite_db);
+
+        // Artifacts restored from a previous daemon, like `DeferredMaterializer::new` does.
+        let unchanged = make_path("unchanged");
+        let accessed = make_path("accessed");
+        let redeclared = make_path("redeclared");
+        let in_use = make_path("in_use");
+        for path in [&unchanged, &accessed, &redeclared, &in_use] {
+            dm.sqlite_db
+                .as_mut()
+                .unwrap()
+                .materializer_state_table()
+                .insert(path, &metadata, last_access_time)?;
+            let processing = if path == &in_use {
+                Processing::Active {
+                    future: ProcessingFuture::Cleaning(
+                        futures::future::ready(Ok(())).boxed().shared(),
+                    ),
+                    version: Version(0),
+                }
+            } else {
+                Processing::Done(Version(0))
+            };
+            dm.tree.insert(
+                path.iter().map(|f| f.to_owned()),
+                Box::new(ArtifactMaterializationData {
+                    deps: None,
+                    stage: ArtifactMaterializationStage::Materialized {
+                        metadata: metadata.dupe(),
+                        last_access_time,
+                        active: false,
+                        declaration: None,
+                    },
+                    processing,
+                }),
+            );
+        }
+
+        // Already on disk when declared by this daemon, so we couldn't produce it again.
+        let existing = make_path("existing");
+        dm.declare_existing(&existing, value.dupe());
+
+        let listed = disk_budget::list_materialized_artifacts(&dm.tree);
+        assert_eq!(
+            listed
+                .iter()
+                .map(|artifact| (artifact.path.clone(), artifact.evictable))
+                .collect::<HashMap<_, _>>(),
+            HashMap::from([
+                (unchanged.clone(), true),
+                (accessed.clone(), true),
+                (redeclared.clone(), true),
+                (in_use.clone(), false),
+                (existing.clone(), false),
+            ])
+        );
+
+        // Things happen between listing and evicting.
+        assert!(
+            dm.materialize_artifact(&accessed, EventDispatcher::null())
+                .is_none()
+        );
+        dm.declare(
+            &redeclared,
+            value.dupe(),
+            Box::new(ArtifactMaterializationMethod::Test),
+        );
+        assert_eq!(dm.io.take_log(), &[]);
+
+        let evicted = disk_budget::remove_unchanged_artifacts(&mut dm, listed)
+            .into_iter()
+            .map(|(artifact, eviction)| {
+                let redeclared = matches!(eviction, disk_budget::Eviction::Redeclared(..));
+                (artifact.path, redeclared)
+            })
+            .collect::<HashMap<_, _>>();
+        assert_eq!(
+            evicted,
+            HashMap::from([(unchanged.clone(), false), (redeclared.clone(), true)])
+        );
+
+        // Artifacts declared by this daemon are declared again, so they are materialized again
+        // when needed.
+        assert!(dm.tree.prefix_get(&mut unchanged.iter()).is_none());
+        assert_matches!(
+            dm.tree
+                .prefix_get(&mut redeclared.iter())
+                .map(|data| &data.stage),
+            Some(ArtifactMaterializationStage::Declared { .. })
+        );
+        assert_eq!(dm.io.take_log(), &[(Op::Clean, redeclared.clone())]);
+        for path in [&accessed, &in_use, &existing] {
+            assert_matches!(
+                dm.tree.prefix_get(&mut path.iter()).map(|data| &data.stage),
+                Some(ArtifactMaterializationStage::Materialized { .. })
+            );
+        }
+
+        let remaining = dm
+            .sqlite_db
+            .as_mut()
+            .unwrap()
+            .materializer_state_table()
+            .read_all(digest_config)?
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect::<HashSet<_>>();
+        assert_eq!(remaining, HashSet::from([accessed, in_use, existing]));
+
+        Ok(())
+    }
 }
@@ -47,6 +47,7 @@ use remote_execution::InlinedBlobWithDigest;
 use remote_execution::NamedDigest;
 
 use crate::materializers::immediate::ImmediateMaterializer;
+use crate::materializers::io::CopyMethod;
 
 #[derive(Allocative)]
 pub struct EdenMaterializer {
@@ -258,17 +259,21 @@ impl EdenMaterializer {
     pub fn new(
         fs: ProjectRoot,
         digest_config: DigestConfig,
+        buck_out_path: ProjectRelativePathBuf,
         re_client_manager: Arc<ReConnectionManager>,
         blocking_executor: Arc<dyn BlockingExecutor>,
         eden_buck_out: EdenBuckOut,
+        copy_method: CopyMethod,
     ) -> anyhow::Result<Self> {
         Ok(Self {
             re_client_manager: re_client_manager.dupe(),
             delegator: Arc::new(ImmediateMaterializer::new(
                 fs.dupe(),
                 digest_config,
+                buck_out_path,
                 re_client_manager,
                 blocking_executor,
+                copy_method,
             )),
             eden_buck_out,
             fs,
@@ -46,6 +46,7 @@ use remote_execution::NamedDigest;
 use remote_execution::NamedDigestWithPermissions;
 
 use crate::materializers::io::materialize_files;
+use crate::materializers::io::CopyMethod;
 use crate::materializers::io::MaterializeTreeStructure;
 
 /// Materializer that materializes everything immediately on declare.
@@ -53,22 +54,28 @@ use crate::materializers::io::MaterializeTreeStructure;
 pub struct ImmediateMaterializer {
     fs: ProjectRoot,
     digest_config: DigestConfig,
+    buck_out_path: ProjectRelativePathBuf,
     re_client_manager: Arc<ReConnectionManager>,
     io_executor: Arc<dyn BlockingExecutor>,
+    copy_method: CopyMethod,
 }
 
 impl ImmediateMaterializer {
     pub fn new(
         fs: ProjectRoot,
         digest_config: DigestConfig,
+        buck_out_path: ProjectRelativePathBuf,
         re_client_manager: Arc<ReConnectionManager>,
         io_executor: Arc<dyn BlockingExecutor>,
+        copy_method: CopyMethod,
     ) -> Self {
         Self {
             fs,
             digest_config,
+            buck_out_path,
             re_client_manager,
             io_executor,
+            copy_method,
         }
     }
 }
@@ -122,6 +129,10 @@ impl Materializer for ImmediateMaterializer {
                         copied_artifact.dest_entry.as_ref(),
                         &self.fs.root().join(&copied_artifact.src),
                         &self.fs.root().join(&copied_artifact.dest),
+                        self.copy_method,
+                        // Like in the deferred materializer, only outputs are safe to hardlink,
+                        // since sources might be edited in place.
+                        copied_artifact.src.starts_with(&self.buck_out_path),
                     )?;
                 }
                 Ok(())
@@ -189,7 +200,7 @@ impl Materializer for ImmediateMaterializer {
             &self.fs,
             self.digest_config,
             &path,
-            &info.url,
+            &info.urls,
             &info.checksum,
             info.metadata.is_executable,
         )
@@ -7,7 +7,9 @@
  * of this source tree.
  */
 
+use std::cmp::min;
 use std::collections::HashMap;
+use std::str::FromStr;
 
 use buck2_core::directory::DirectoryEntry;
 use buck2_core::fs::fs_util;
@@ -20,6 +22,107 @@ use buck2_execute::directory::ActionDirectoryEntry;
 use buck2_execute::directory::ActionDirectoryMember;
 use buck2_execute::directory::ActionSharedDirectory;
 use buck2_execute::execute::blocking::IoRequest;
+use derive_more::Display;
+use dupe::Dupe;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+enum CopyMethodError {
+    #[error(
+        "Invalid value for `buck2.copy_method`: `{0}`, expected one of `copy`, `reflink` or `reflink_or_hardlink`"
+    )]
+    InvalidCopyMethod(String),
+}
+
+/// How we copy files that are already on local disk, e.g. for `copy_file`.
+#[derive(Copy, Clone, Dupe, Debug, Display, PartialEq, Eq)]
+pub enum CopyMethod {
+    /// Always copy the contents of files.
+    #[display(fmt = "copy")]
+    Copy,
+    /// Clone files where the filesystem supports it, and copy them otherwise.
+    #[display(fmt = "reflink")]
+    Reflink,
+    /// Like `Reflink`, but fall back to hardlinks for files that won't be modified in place (see
+    /// [`CopyMethod::copy_file`]) before copying them.
+    #[display(fmt = "reflink_or_hardlink")]
+    ReflinkOrHardlink,
+}
+
+impl Default for CopyMethod {
+    fn default() -> Self {
+        Self::Copy
+    }
+}
+
+impl FromStr for CopyMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "copy" => Ok(Self::Copy),
+            "reflink" => Ok(Self::Reflink),
+            "reflink_or_hardlink" => Ok(Self::ReflinkOrHardlink),
+            _ => Err(CopyMethodError::InvalidCopyMethod(s.to_owned()).into()),
+        }
+    }
+}
+
+/// How a file was actually copied. These are ordered from the most expensive to the cheapest, so
+/// that the minimum tells us how a set of files was copied.
+#[derive(Copy, Clone, Dupe, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CopiedWith {
+    Copy,
+    Hardlink,
+    Reflink,
+}
+
+impl CopiedWith {
+    pub fn to_proto(self) -> buck2_data::MaterializationMethod {
+        match self {
+            CopiedWith::Copy => buck2_data::MaterializationMethod::LocalCopy,
+            CopiedWith::Hardlink => buck2_data::MaterializationMethod::LocalHardlink,
+            CopiedWith::Reflink => buck2_data::MaterializationMethod::LocalReflink,
+        }
+    }
+}
+
+impl CopyMethod {
+    /// Copy the file at `src` to `dest`, falling back to cheaper methods as needed.
+    ///
+    /// Hardlinks share their contents and metadata with the source, so modifying either one
+    /// modifies the other. We only use them if the caller says `src` is never modified in place
+    /// (e.g. it is a build output, which we delete before rebuilding), and `src` is read-only.
+    pub fn copy_file(
+        self,
+        src: &AbsNormPath,
+        dest: &AbsNormPath,
+        allow_hardlink: bool,
+    ) -> anyhow::Result<CopiedWith> {
+        if self == CopyMethod::Copy {
+            fs_util::copy(src, dest)?;
+            return Ok(CopiedWith::Copy);
+        }
+
+        match fs_util::reflink(src, dest) {
+            Ok(()) => return Ok(CopiedWith::Reflink),
+            Err(e) => tracing::trace!("Falling back from reflink: {:#}", e),
+        }
+
+        if self == CopyMethod::ReflinkOrHardlink
+            && allow_hardlink
+            && fs_util::symlink_metadata(src)?.permissions().readonly()
+        {
+            match fs_util::hard_link(src, dest) {
+                Ok(()) => return Ok(CopiedWith::Hardlink),
+                Err(e) => tracing::trace!("Falling back from hardlink: {:#}", e),
+            }
+        }
+
+        fs_util::copy(src, dest)?;
+        Ok(CopiedWith::Copy)
+    }
+}
 
 pub struct MaterializeTreeStructure {
     pub path: ProjectRelativePathBuf,
@@ -41,14 +144,17 @@ impl IoRequest for MaterializeTreeStructure {
 /// - `file_src`: takes the destination path of a file, and returns its
 ///   source path (where it should be copied from). If it returns [`None`],
 ///   the file is not materialized.
-fn materialize<F, D>(
+/// - `copy_file`: copies a file from its source path to its destination path.
+fn materialize<F, C, D>(
     entry: DirectoryEntry<&D, &ActionDirectoryMember>,
     dest: &AbsNormPath,
     materialize_dirs_and_syms: bool,
     mut file_src: F,
+    mut copy_file: C,
 ) -> anyhow::Result<()>
 where
     F: FnMut(&AbsNormPath) -> Option<AbsNormPathBuf>,
+    C: FnMut(&AbsNormPath, &AbsNormPath) -> anyhow::Result<()>,
     D: ActionDirectory,
 {
     let mut dest = dest.to_owned();
@@ -58,7 +164,18 @@ where
             fs_util::create_dir_all(parent)?;
         }
     }
-    materialize_recursively(entry, &mut dest, materialize_dirs_and_syms, &mut file_src)
+    materialize_recursively(
+        entry,
+        &mut dest,
+        materialize_dirs_and_syms,
+        &mut file_src,
+        &mut copy_file,
+    )
+}
+
+fn copy_file_contents(src: &AbsNormPath, dest: &AbsNormPath) -> anyhow::Result<()> {
+    fs_util::copy(src, dest)?;
+    Ok(())
 }
 
 /// Materializes the directories and symlinks of an entry at `dest`. Files
@@ -71,18 +188,27 @@ where
     P: AsRef<AbsNormPath>,
     D: ActionDirectory,
 {
-    materialize(entry, dest.as_ref(), true, |_: &AbsNormPath| None)
+    materialize(
+        entry,
+        dest.as_ref(),
+        true,
+        |_: &AbsNormPath| None,
+        copy_file_contents,
+    )
 }
 
 /// Materializes the files of an the entry rooted at `dest`.
 ///
 /// Files are copied from `src`. In other words, if a file would be
-/// materialized at `dest/p`, then it's copied from `src/p`.
+/// materialized at `dest/p`, then it's copied from `src/p`. Returns how the
+/// files were copied, or `None` if there was nothing to copy.
 pub(crate) fn materialize_files<P, D>(
     entry: DirectoryEntry<&D, &ActionDirectoryMember>,
     src: P,
     dest: P,
-) -> anyhow::Result<()>
+    copy_method: CopyMethod,
+    allow_hardlink: bool,
+) -> anyhow::Result<Option<CopiedWith>>
 where
     P: AsRef<AbsNormPath>,
     D: ActionDirectory,
@@ -100,7 +226,13 @@ where
             Some(src.join(subpath))
         }
     };
-    materialize(entry, dest, false, file_src)
+    let mut copied_with = None;
+    materialize(entry, dest, false, file_src, |src, dest| {
+        let file_copied_with = copy_method.copy_file(src, dest, allow_hardlink)?;
+        copied_with = Some(copied_with.map_or(file_copied_with, |c| min(c, file_copied_with)));
+        Ok(())
+    })?;
+    Ok(copied_with)
 }
 
 /// Materializes the files of an entry rooted at `dest`.
@@ -118,17 +250,19 @@ where
     D: ActionDirectory,
 {
     let file_src = |d: &AbsNormPath| srcs.remove(d);
-    materialize(entry, dest.as_ref(), false, file_src)
+    materialize(entry, dest.as_ref(), false, file_src, copy_file_contents)
 }
 
-fn materialize_recursively<F, D>(
+fn materialize_recursively<F, C, D>(
     entry: DirectoryEntry<&D, &ActionDirectoryMember>,
     dest: &mut AbsNormPathBuf,
     materialize_dirs_and_syms: bool,
     file_src: &mut F,
+    copy_file: &mut C,
 ) -> anyhow::Result<()>
 where
     F: FnMut(&AbsNormPath) -> Option<AbsNormPathBuf>,
+    C: FnMut(&AbsNormPath, &AbsNormPath) -> anyhow::Result<()>,
     D: ActionDirectory + ?Sized,
 {
     match entry {
@@ -138,7 +272,13 @@ where
             }
             for (name, entry) in d.entries() {
                 dest.push(name);
-                materialize_recursively(entry, dest, materialize_dirs_and_syms, file_src)?;
+                materialize_recursively(
+                    entry,
+                    dest,
+                    materialize_dirs_and_syms,
+                    file_src,
+                    copy_file,
+                )?;
                 dest.pop();
             }
             Ok(())
@@ -146,7 +286,7 @@ where
         DirectoryEntry::Leaf(ActionDirectoryMember::File(_)) => {
             if let Some(src) = file_src(dest) {
                 if fs_util::symlink_metadata(&dest).is_err() {
-                    fs_util::copy(src, dest)?;
+                    copy_file(&src, dest)?;
                 }
             }
             Ok(())
@@ -165,3 +305,52 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use buck2_core::fs::paths::forward_rel_path::ForwardRelativePath;
+
+    use super::*;
+
+    #[test]
+    fn test_copy_file() -> anyhow::Result<()> {
+        let tempdir = tempfile::tempdir()?;
+        let root = AbsNormPathBuf::new(tempdir.path().to_owned())?;
+        let src = root.join(ForwardRelativePath::unchecked_new("src"));
+        fs_util::write(&src, b"data")?;
+
+        let copy = |method: CopyMethod, dest: &str, allow_hardlink: bool| {
+            let dest = root.join(ForwardRelativePath::unchecked_new(dest));
+            let copied_with = method.copy_file(&src, &dest, allow_hardlink)?;
+            assert_eq!(fs_util::read_to_string(&dest)?, "data");
+            anyhow::Ok(copied_with)
+        };
+
+        assert_eq!(copy(CopyMethod::Copy, "copy", true)?, CopiedWith::Copy);
+        // Whether reflinks work depends on the filesystem the tests run on.
+        assert_ne!(
+            copy(CopyMethod::Reflink, "reflink", true)?,
+            CopiedWith::Hardlink
+        );
+        // Writable files are never hardlinked.
+        assert_ne!(
+            copy(CopyMethod::ReflinkOrHardlink, "writable", true)?,
+            CopiedWith::Hardlink
+        );
+
+        let mut permissions = fs_util::symlink_metadata(&src)?.permissions();
+        permissions.set_readonly(true);
+        fs_util::set_permissions(&src, permissions)?;
+
+        assert_ne!(
+            copy(CopyMethod::ReflinkOrHardlink, "readonly", true)?,
+            CopiedWith::Copy
+        );
+        assert_ne!(
+            copy(CopyMethod::ReflinkOrHardlink, "disallowed", false)?,
+            CopiedWith::Hardlink
+        );
+
+        Ok(())
+    }
+}
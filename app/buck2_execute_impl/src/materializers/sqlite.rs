@@ -45,7 +45,7 @@ use crate::materializers::deferred::ArtifactMetadata;
 /// materializer state sqlite db schema! If you forget to bump this version,
 /// then you can fix forward by bumping the `buck2.sqlite_materializer_state_version`
 /// buckconfig in the project root's .buckconfig.
-pub const DB_SCHEMA_VERSION: u64 = 4;
+pub const DB_SCHEMA_VERSION: u64 = 5;
 
 const STATE_TABLE_NAME: &str = "materializer_state";
 
@@ -78,6 +78,7 @@ pub(crate) struct ArtifactMetadataSqliteEntry {
     pub entry_hash_kind: Option<u8>,
     pub file_is_executable: Option<bool>,
     pub symlink_target: Option<String>,
+    pub total_size: u64,
 }
 
 impl ArtifactMetadataSqliteEntry {
@@ -88,6 +89,7 @@ impl ArtifactMetadataSqliteEntry {
         entry_hash_kind: Option<u8>,
         file_is_executable: Option<bool>,
         symlink_target: Option<String>,
+        total_size: u64,
     ) -> Self {
         Self {
             artifact_type,
@@ -96,6 +98,7 @@ impl ArtifactMetadataSqliteEntry {
             entry_hash_kind,
             file_is_executable,
             symlink_target,
+            total_size,
         }
     }
 }
@@ -117,7 +120,7 @@ impl From<&ArtifactMetadata> for ArtifactMetadataSqliteEntry {
             entry_hash_kind,
             file_is_executable,
             symlink_target,
-        ) = match &metadata.0 {
+        ) = match &metadata.entry {
             DirectoryEntry::Dir(digest) => {
                 let (entry_size, entry_hash, entry_hash_kind) = digest_parts(digest);
                 (
@@ -165,6 +168,7 @@ impl From<&ArtifactMetadata> for ArtifactMetadataSqliteEntry {
             entry_hash_kind,
             file_is_executable,
             symlink_target,
+            total_size: metadata.total_size,
         }
     }
 }
@@ -272,7 +276,10 @@ fn convert_artifact_metadata(
         }
     };
 
-    Ok(ArtifactMetadata(metadata))
+    Ok(ArtifactMetadata {
+        entry: metadata,
+        total_size: sqlite_entry.total_size,
+    })
 }
 
 pub(crate) struct MaterializerStateSqliteTable {
@@ -294,6 +301,7 @@ impl MaterializerStateSqliteTable {
                 entry_hash_kind         INTEGER NULL DEFAULT NULL,
                 file_is_executable      INTEGER NULL DEFAULT NULL,
                 symlink_target          TEXT NULL DEFAULT NULL,
+                total_size              INTEGER NOT NULL,
                 last_access_time        INTEGER NOT NULL
             )",
             STATE_TABLE_NAME,
@@ -315,7 +323,7 @@ impl MaterializerStateSqliteTable {
         let entry: ArtifactMetadataSqliteEntry = metadata.into();
         static SQL: Lazy<String> = Lazy::new(|| {
             format!(
-                "INSERT INTO {} (path, artifact_type, entry_size, entry_hash, entry_hash_kind, file_is_executable, symlink_target, last_access_time) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                "INSERT INTO {} (path, artifact_type, entry_size, entry_hash, entry_hash_kind, file_is_executable, symlink_target, total_size, last_access_time) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
                 STATE_TABLE_NAME
             )
         });
@@ -332,6 +340,7 @@ impl MaterializerStateSqliteTable {
                     entry.entry_hash_kind,
                     entry.file_is_executable,
                     entry.symlink_target,
+                    entry.total_size,
                     timestamp.timestamp(),
                 ],
             )
@@ -372,7 +381,7 @@ impl MaterializerStateSqliteTable {
     ) -> anyhow::Result<MaterializerState> {
         static SQL: Lazy<String> = Lazy::new(|| {
             format!(
-                "SELECT path, artifact_type, entry_size, entry_hash, entry_hash_kind, file_is_executable, symlink_target, last_access_time FROM {}",
+                "SELECT path, artifact_type, entry_size, entry_hash, entry_hash_kind, file_is_executable, symlink_target, total_size, last_access_time FROM {}",
                 STATE_TABLE_NAME,
             )
         });
@@ -392,8 +401,9 @@ impl MaterializerStateSqliteTable {
                             row.get(4)?,
                             row.get(5)?,
                             row.get(6)?,
+                            row.get(7)?,
                         ),
-                        row.get(7)?,
+                        row.get(8)?,
                     ))
                 },
             )?
@@ -641,7 +651,10 @@ mod tests {
 
         let digest =
             TrackedFileDigest::from_content(b"directory", digest_config.cas_digest_config());
-        let metadata = ArtifactMetadata(DirectoryEntry::Dir(digest));
+        let metadata = ArtifactMetadata {
+            entry: DirectoryEntry::Dir(digest),
+            total_size: 42,
+        };
         let entry = ArtifactMetadataSqliteEntry::from(&metadata);
         assert_eq!(
             metadata,
@@ -654,12 +667,13 @@ mod tests {
         let digest_config = DigestConfig::testing_default();
 
         let digest = TrackedFileDigest::from_content(b"file", digest_config.cas_digest_config());
-        let metadata = ArtifactMetadata(DirectoryEntry::Leaf(ActionDirectoryMember::File(
-            FileMetadata {
+        let metadata = ArtifactMetadata {
+            total_size: digest.size(),
+            entry: DirectoryEntry::Leaf(ActionDirectoryMember::File(FileMetadata {
                 digest,
                 is_executable: false,
-            },
-        )));
+            })),
+        };
         let entry = ArtifactMetadataSqliteEntry::from(&metadata);
         assert_eq!(
             metadata,
@@ -676,7 +690,10 @@ mod tests {
         // Verify that we have a Symlink here.
         assert_matches!(symlink, ActionDirectoryMember::Symlink(..));
 
-        let metadata = ArtifactMetadata(DirectoryEntry::Leaf(symlink));
+        let metadata = ArtifactMetadata {
+            entry: DirectoryEntry::Leaf(symlink),
+            total_size: 0,
+        };
         let entry = ArtifactMetadataSqliteEntry::from(&metadata);
         assert_eq!(
             metadata,
@@ -699,7 +716,10 @@ mod tests {
         // Verify that we have an ExternalSymlink here.
         assert_matches!(external_symlink, ActionDirectoryMember::ExternalSymlink(..));
 
-        let metadata = ArtifactMetadata(DirectoryEntry::Leaf(external_symlink));
+        let metadata = ArtifactMetadata {
+            entry: DirectoryEntry::Leaf(external_symlink),
+            total_size: 0,
+        };
         let entry = ArtifactMetadataSqliteEntry::from(&metadata);
         assert_eq!(
             metadata,
@@ -750,25 +770,40 @@ mod tests {
             (
                 ProjectRelativePath::unchecked_new("a").to_owned(),
                 (
-                    ArtifactMetadata(DirectoryEntry::Dir(dir_fingerprint)),
+                    ArtifactMetadata {
+                        entry: DirectoryEntry::Dir(dir_fingerprint),
+                        total_size: 42,
+                    },
                     now_seconds(),
                 ),
             ),
             (
                 ProjectRelativePath::unchecked_new("b/c").to_owned(),
-                (ArtifactMetadata(DirectoryEntry::Leaf(file)), now_seconds()),
+                (
+                    ArtifactMetadata {
+                        entry: DirectoryEntry::Leaf(file),
+                        total_size: 4,
+                    },
+                    now_seconds(),
+                ),
             ),
             (
                 ProjectRelativePath::unchecked_new("d").to_owned(),
                 (
-                    ArtifactMetadata(DirectoryEntry::Leaf(symlink)),
+                    ArtifactMetadata {
+                        entry: DirectoryEntry::Leaf(symlink),
+                        total_size: 0,
+                    },
                     now_seconds(),
                 ),
             ),
             (
                 ProjectRelativePath::unchecked_new("e").to_owned(),
                 (
-                    ArtifactMetadata(DirectoryEntry::Leaf(external_symlink)),
+                    ArtifactMetadata {
+                        entry: DirectoryEntry::Leaf(external_symlink),
+                        total_size: 0,
+                    },
                     now_seconds(),
                 ),
             ),
@@ -826,9 +861,13 @@ mod tests {
         let fs = ProjectRootTemp::new()?;
 
         let path = ProjectRelativePath::unchecked_new("foo").to_owned();
-        let artifact_metadata = ArtifactMetadata(DirectoryEntry::Dir(
-            TrackedFileDigest::from_content(b"directory", digest_config.cas_digest_config()),
-        ));
+        let artifact_metadata = ArtifactMetadata {
+            entry: DirectoryEntry::Dir(TrackedFileDigest::from_content(
+                b"directory",
+                digest_config.cas_digest_config(),
+            )),
+            total_size: 42,
+        };
         let timestamp = now_seconds();
         let metadatas = testing_metadatas();
 
@@ -13,6 +13,7 @@ use futures::stream::Stream;
 use futures::stream::StreamExt;
 
 use crate::run::CommandEvent;
+use crate::run::ExecutionStats;
 use crate::run::GatherOutputStatus;
 
 pub fn encode_event_stream<S>(
@@ -31,9 +32,18 @@ where
             CommandEvent::Stderr(bytes) => Data::Stderr(buck2_forkserver_proto::StreamEvent {
                 data: bytes.to_vec(),
             }),
-            CommandEvent::Exit(GatherOutputStatus::Finished(exit_code)) => {
-                Data::Exit(buck2_forkserver_proto::ExitEvent { exit_code })
-            }
+            CommandEvent::Exit(GatherOutputStatus::Finished {
+                exit_code,
+                execution_stats,
+            }) => Data::Exit(buck2_forkserver_proto::ExitEvent {
+                exit_code,
+                execution_stats: execution_stats.map(|stats| {
+                    buck2_forkserver_proto::ExecutionStats {
+                        memory_peak_bytes: stats.memory_peak_bytes,
+                        cpu_usage_us: stats.cpu_usage_us,
+                    }
+                }),
+            }),
             CommandEvent::Exit(GatherOutputStatus::TimedOut(duration)) => {
                 Data::Timeout(buck2_forkserver_proto::TimeoutEvent {
                     duration: duration.try_into().ok(),
@@ -71,9 +81,16 @@ where
             Data::Stderr(buck2_forkserver_proto::StreamEvent { data }) => {
                 CommandEvent::Stderr(data.into())
             }
-            Data::Exit(buck2_forkserver_proto::ExitEvent { exit_code }) => {
-                CommandEvent::Exit(GatherOutputStatus::Finished(exit_code))
-            }
+            Data::Exit(buck2_forkserver_proto::ExitEvent {
+                exit_code,
+                execution_stats,
+            }) => CommandEvent::Exit(GatherOutputStatus::Finished {
+                exit_code,
+                execution_stats: execution_stats.map(|stats| ExecutionStats {
+                    memory_peak_bytes: stats.memory_peak_bytes,
+                    cpu_usage_us: stats.cpu_usage_us,
+                }),
+            }),
             Data::Timeout(buck2_forkserver_proto::TimeoutEvent { duration }) => {
                 CommandEvent::Exit(GatherOutputStatus::TimedOut(
                     duration
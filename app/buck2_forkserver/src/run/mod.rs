@@ -9,6 +9,7 @@
 
 mod interruptible_async_read;
 
+use std::fs::File;
 use std::io;
 use std::pin::Pin;
 use std::process::Command;
@@ -34,13 +35,23 @@ use self::interruptible_async_read::InterruptibleAsyncRead;
 
 #[derive(Debug)]
 pub enum GatherOutputStatus {
-    /// Contains the exit code.
-    Finished(i32),
+    Finished {
+        exit_code: i32,
+        /// Only available when the command ran in its own cgroup.
+        execution_stats: Option<ExecutionStats>,
+    },
     TimedOut(Duration),
     Cancelled,
     SpawnFailed(String),
 }
 
+/// Resources used by a command and its children.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExecutionStats {
+    pub memory_peak_bytes: Option<u64>,
+    pub cpu_usage_us: Option<u64>,
+}
+
 #[derive(Debug)]
 pub enum CommandEvent {
     Stdout(Bytes),
@@ -193,7 +204,10 @@ where
                     exit_code = status.code();
                 }
 
-                let status = GatherOutputStatus::Finished(exit_code.unwrap_or(-1));
+                let status = GatherOutputStatus::Finished {
+                    exit_code: exit_code.unwrap_or(-1),
+                    execution_stats: None,
+                };
                 anyhow::Ok((status, false))
             };
 
@@ -259,7 +273,22 @@ pub async fn gather_output<T>(
 where
     T: Future<Output = anyhow::Result<GatherOutputStatus>> + Send,
 {
-    let cmd = prepare_command(cmd);
+    gather_output_with_stdin(cmd, None, cancellation).await
+}
+
+/// Like `gather_output`, but use `stdin` as the command's stdin instead of `/dev/null`.
+pub async fn gather_output_with_stdin<T>(
+    cmd: Command,
+    stdin: Option<File>,
+    cancellation: T,
+) -> anyhow::Result<(GatherOutputStatus, Vec<u8>, Vec<u8>)>
+where
+    T: Future<Output = anyhow::Result<GatherOutputStatus>> + Send,
+{
+    let mut cmd = prepare_command(cmd);
+    if let Some(stdin) = stdin {
+        cmd.stdin(stdin);
+    }
 
     let child = spawn_retry_txt_busy(cmd, || tokio::time::sleep(Duration::from_millis(50))).await;
     let stream = stream_command_events(child, cancellation)?;
@@ -385,13 +414,39 @@ mod tests {
         cmd.args(["-c", "echo hello"]);
 
         let (status, stdout, stderr) = gather_output(cmd, futures::future::pending()).await?;
-        assert!(matches!(status, GatherOutputStatus::Finished(s) if s == 0));
+        assert!(matches!(
+            status,
+            GatherOutputStatus::Finished { exit_code: 0, .. }
+        ));
         assert_eq!(str::from_utf8(&stdout)?.trim(), "hello");
         assert_eq!(stderr, b"");
 
         Ok(())
     }
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_gather_output_with_stdin() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let stdin = dir.path().join("stdin");
+        std::fs::write(&stdin, "hello")?;
+
+        let mut cmd = background_command("sh");
+        cmd.args(["-c", "cat; echo world >&2"]);
+
+        let (status, stdout, stderr) =
+            gather_output_with_stdin(cmd, Some(File::open(&stdin)?), futures::future::pending())
+                .await?;
+        assert!(matches!(
+            status,
+            GatherOutputStatus::Finished { exit_code: 0, .. }
+        ));
+        assert_eq!(stdout, b"hello");
+        assert_eq!(str::from_utf8(&stderr)?.trim(), "world");
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_gather_does_not_wait_for_children() -> anyhow::Result<()> {
         // If we wait for sleep, this will time out.
@@ -415,7 +470,10 @@ mod tests {
             timeout_into_cancellation(Some(Duration::from_secs(timeout))),
         )
         .await?;
-        assert!(matches!(status, GatherOutputStatus::Finished(s) if s == 0));
+        assert!(matches!(
+            status,
+            GatherOutputStatus::Finished { exit_code: 0, .. }
+        ));
         assert_eq!(str::from_utf8(&stdout)?.trim(), "hello");
         assert_eq!(stderr, b"");
 
@@ -553,7 +611,7 @@ mod tests {
 
         assert_matches!(
             status,
-            GatherOutputStatus::Finished(v) if v == 128 + Signal::SIGKILL as i32
+            GatherOutputStatus::Finished { exit_code, .. } if exit_code == 128 + Signal::SIGKILL as i32
         );
 
         Ok(())
@@ -0,0 +1,276 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Resource limits and accounting for local commands, using cgroups v2. Each command gets its own
+//! cgroup, created under a parent cgroup that was delegated to the user running Buck2. The
+//! command joins it in a `pre_exec` hook, so everything it spawns is accounted for (and limited)
+//! too. Once the command exits, we read its peak memory and CPU usage, then kill whatever it left
+//! behind and remove the cgroup.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use anyhow::Context as _;
+use buck2_forkserver_proto::Cgroup;
+use futures::stream::Stream;
+use futures::stream::StreamExt;
+
+use crate::run::CommandEvent;
+use crate::run::ExecutionStats;
+use crate::run::GatherOutputStatus;
+
+/// Period we use for `cpu.max`, in microseconds. This is the kernel default.
+const CPU_PERIOD_US: u64 = 100_000;
+
+/// Smallest quota the kernel accepts in `cpu.max`, in microseconds.
+const CPU_MIN_QUOTA_US: u64 = 1_000;
+
+static NEXT_CGROUP_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A cgroup created for a single command. Dropping it kills anything still running in it and
+/// removes it.
+pub struct ActionCgroup {
+    path: PathBuf,
+}
+
+impl ActionCgroup {
+    pub fn create(cgroup: &Cgroup) -> anyhow::Result<Self> {
+        let parent = Path::new(OsStr::from_bytes(&cgroup.parent));
+        if !parent.is_absolute() {
+            return Err(anyhow::anyhow!(
+                "Cgroup parent `{}` is not an absolute path",
+                parent.display()
+            ));
+        }
+
+        let mut controllers = vec!["memory"];
+        if cgroup.cpu_max_percent.is_some() {
+            controllers.push("cpu");
+        }
+
+        enable_controllers(parent, &controllers).with_context(|| {
+            format!(
+                "Error enabling the `{}` controllers in cgroup `{}`. It must be a cgroup v2 \
+                that is writable by the user running Buck2 and contains no processes",
+                controllers.join(" "),
+                parent.display()
+            )
+        })?;
+
+        let path = parent.join(format!(
+            "buck2-{}-{}",
+            std::process::id(),
+            NEXT_CGROUP_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir(&path)
+            .with_context(|| format!("Error creating cgroup `{}`", path.display()))?;
+
+        // From here on, dropping this removes the cgroup if we fail to configure it.
+        let res = Self { path };
+
+        if let Some(bytes) = cgroup.memory_max_bytes {
+            res.write("memory.max", &bytes.to_string())?;
+        }
+
+        if let Some(percent) = cgroup.cpu_max_percent {
+            res.write("cpu.max", &cpu_max(percent))?;
+        }
+
+        Ok(res)
+    }
+
+    /// Make the command join this cgroup before it execs.
+    pub fn apply(&self, cmd: &mut Command) -> anyhow::Result<()> {
+        let procs_path = self.path.join("cgroup.procs");
+        // This is opened with O_CLOEXEC, so the command will not inherit it.
+        let procs = fs::OpenOptions::new()
+            .write(true)
+            .open(&procs_path)
+            .with_context(|| format!("Error opening `{}`", procs_path.display()))?;
+
+        // SAFETY: This only makes a `write` syscall, which is async-signal-safe.
+        unsafe {
+            cmd.pre_exec(move || (&procs).write_all(b"0"));
+        }
+
+        Ok(())
+    }
+
+    pub fn stats(&self) -> ExecutionStats {
+        // `memory.peak` is only available since Linux 5.19.
+        let memory_peak_bytes = self
+            .read("memory.peak")
+            .ok()
+            .and_then(|s| s.trim().parse().ok());
+
+        let cpu_usage_us = self
+            .read("cpu.stat")
+            .ok()
+            .and_then(|s| parse_flat_keyed(&s, "usage_usec"));
+
+        ExecutionStats {
+            memory_peak_bytes,
+            cpu_usage_us,
+        }
+    }
+
+    /// Kill anything that is still running in the cgroup, then wait for it to exit and remove the
+    /// cgroup.
+    pub async fn destroy(self) {
+        self.kill();
+
+        for _ in 0..100 {
+            if !self.populated() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // Drop removes it.
+    }
+
+    fn kill(&self) {
+        // `cgroup.kill` is only available since Linux 5.14. Without it, processes that escaped the
+        // command's process group will keep the cgroup alive, and we'll fail to remove it.
+        if let Err(e) = self.write("cgroup.kill", "1") {
+            tracing::debug!("{:#}", e);
+        }
+    }
+
+    fn populated(&self) -> bool {
+        self.read("cgroup.events")
+            .ok()
+            .and_then(|s| parse_flat_keyed(&s, "populated"))
+            .map_or(false, |v| v != 0)
+    }
+
+    fn read(&self, file: &str) -> anyhow::Result<String> {
+        let path = self.path.join(file);
+        fs::read_to_string(&path).with_context(|| format!("Error reading `{}`", path.display()))
+    }
+
+    fn write(&self, file: &str, value: &str) -> anyhow::Result<()> {
+        let path = self.path.join(file);
+        fs::write(&path, value)
+            .with_context(|| format!("Error writing `{}` to `{}`", value, path.display()))
+    }
+}
+
+impl Drop for ActionCgroup {
+    fn drop(&mut self) {
+        self.kill();
+
+        if let Err(e) = fs::remove_dir(&self.path) {
+            tracing::warn!("Error removing cgroup `{}`: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Report the resource usage of the command in its exit event, and remove its cgroup once it is
+/// done.
+pub fn attach_execution_stats<S>(
+    stream: S,
+    cgroup: ActionCgroup,
+) -> impl Stream<Item = anyhow::Result<CommandEvent>>
+where
+    S: Stream<Item = anyhow::Result<CommandEvent>>,
+{
+    let mut cgroup = Some(cgroup);
+
+    stream.then(move |mut event| {
+        let cgroup = match &event {
+            Ok(CommandEvent::Exit(..)) | Err(..) => cgroup.take(),
+            _ => None,
+        };
+
+        async move {
+            if let Some(cgroup) = cgroup {
+                if let Ok(CommandEvent::Exit(GatherOutputStatus::Finished {
+                    execution_stats,
+                    ..
+                })) = &mut event
+                {
+                    *execution_stats = Some(cgroup.stats());
+                }
+                cgroup.destroy().await;
+            }
+            event
+        }
+    })
+}
+
+fn enable_controllers(parent: &Path, controllers: &[&str]) -> anyhow::Result<()> {
+    let subtree_control = parent.join("cgroup.subtree_control");
+
+    let enabled = fs::read_to_string(&subtree_control)
+        .with_context(|| format!("Error reading `{}`", subtree_control.display()))?;
+
+    let missing = controllers
+        .iter()
+        .filter(|c| !enabled.split_whitespace().any(|e| e == **c))
+        .map(|c| format!("+{}", c))
+        .collect::<Vec<_>>();
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    fs::write(&subtree_control, missing.join(" "))
+        .with_context(|| format!("Error writing `{}`", subtree_control.display()))
+}
+
+fn cpu_max(percent: u32) -> String {
+    let quota = (CPU_PERIOD_US * u64::from(percent) / 100).max(CPU_MIN_QUOTA_US);
+    format!("{} {}", quota, CPU_PERIOD_US)
+}
+
+/// Find a value in a file like `cpu.stat` or `cgroup.events`, which contain one `key value` pair
+/// per line.
+fn parse_flat_keyed(contents: &str, key: &str) -> Option<u64> {
+    contents.lines().find_map(|line| {
+        let (k, v) = line.split_once(' ')?;
+        if k == key {
+            v.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_max() {
+        assert_eq!(cpu_max(100), "100000 100000");
+        assert_eq!(cpu_max(250), "250000 100000");
+        assert_eq!(cpu_max(0), "1000 100000");
+    }
+
+    #[test]
+    fn test_parse_flat_keyed() {
+        let cpu_stat = "usage_usec 1234\nuser_usec 1000\nsystem_usec 234\n";
+        assert_eq!(parse_flat_keyed(cpu_stat, "usage_usec"), Some(1234));
+        assert_eq!(parse_flat_keyed(cpu_stat, "system_usec"), Some(234));
+        assert_eq!(parse_flat_keyed(cpu_stat, "nr_periods"), None);
+
+        let events = "populated 0\nfrozen 0\n";
+        assert_eq!(parse_flat_keyed(events, "populated"), Some(0));
+    }
+}
@@ -7,8 +7,12 @@
  * of this source tree.
  */
 
+#[cfg(target_os = "linux")]
+mod cgroup;
 mod command;
 mod launch;
+#[cfg(target_os = "linux")]
+mod sandbox;
 mod service;
 
 pub use command::run_forkserver;
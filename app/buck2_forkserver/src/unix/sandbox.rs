@@ -0,0 +1,385 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Sandboxing for local commands. On Linux, the command runs in its own user and mount
+//! namespaces, where the project root is replaced by a directory that only contains bind mounts
+//! of the paths the command declared. Anything else in the project is invisible to it, so reading
+//! an undeclared input fails right away instead of producing a build that only works locally.
+//!
+//! The sandbox is built in two steps: [`prepare`] runs in the forkserver and creates the mount
+//! points on disk, then [`PreparedSandbox::apply`] installs a `pre_exec` hook that enters the
+//! namespaces and does the mounts in the child, right before it execs.
+
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+use anyhow::Context as _;
+use buck2_forkserver_proto::Sandbox;
+use nix::fcntl::OFlag;
+use nix::mount::MsFlags;
+use nix::sched::CloneFlags;
+use nix::sys::stat::Mode;
+use nix::sys::statvfs::FsFlags;
+
+/// A path to expose in the sandbox, relative to the project root.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct SandboxPath {
+    path: PathBuf,
+    writable: bool,
+}
+
+/// Sort the paths, and drop those that are already visible through a parent directory with the
+/// same permissions, since they don't need their own mount. Inputs nested in a writable directory
+/// keep their own read-only mount, so that actions can't modify their inputs.
+fn normalize_paths(sandbox: &Sandbox) -> Vec<SandboxPath> {
+    let mut paths = sandbox
+        .inputs
+        .iter()
+        .map(|p| (p, false))
+        .chain(sandbox.writable.iter().map(|p| (p, true)))
+        .map(|(p, writable)| SandboxPath {
+            path: PathBuf::from(OsStr::from_bytes(p)),
+            writable,
+        })
+        .collect::<Vec<_>>();
+
+    // Sorting puts parents before their children. When a path is both an input and writable,
+    // the writable entry comes last and wins.
+    paths.sort();
+    paths.dedup_by(|b, a| {
+        if a.path == b.path {
+            a.writable |= b.writable;
+            true
+        } else {
+            false
+        }
+    });
+
+    let mut res: Vec<SandboxPath> = Vec::with_capacity(paths.len());
+    for path in paths {
+        let covered = res
+            .iter()
+            .rev()
+            .find(|p| path.path.starts_with(&p.path))
+            .map_or(false, |p| p.writable == path.writable);
+
+        if !covered {
+            res.push(path);
+        }
+    }
+
+    res
+}
+
+struct BindMount {
+    src: CString,
+    dst: CString,
+    /// Flags of the source filesystem that the kernel won't let us drop when remounting a bind
+    /// mount from within a user namespace. Only set if this is a read-only mount.
+    readonly_flags: Option<MsFlags>,
+}
+
+/// Everything the child needs to enter the sandbox. This is all computed ahead of time so that
+/// the child doesn't allocate between fork and exec.
+pub struct PreparedSandbox {
+    root: CString,
+    dir: CString,
+    cwd: Option<CString>,
+    mounts: Vec<BindMount>,
+    uid_map: Vec<u8>,
+    gid_map: Vec<u8>,
+}
+
+fn cstring(path: &Path) -> anyhow::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .with_context(|| format!("Path contains a NUL byte: `{}`", path.display()))
+}
+
+fn create_dir_all(path: &Path) -> anyhow::Result<()> {
+    fs::create_dir_all(path).with_context(|| format!("Error creating `{}`", path.display()))
+}
+
+fn create_parent(path: &Path) -> anyhow::Result<()> {
+    match path.parent() {
+        Some(parent) => create_dir_all(parent),
+        None => Ok(()),
+    }
+}
+
+fn locked_flags(path: &Path) -> anyhow::Result<MsFlags> {
+    let flags = nix::sys::statvfs::statvfs(path)
+        .with_context(|| format!("Error reading mount flags of `{}`", path.display()))?
+        .flags();
+
+    let mut res = MsFlags::empty();
+    for (fs_flag, ms_flag) in [
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ] {
+        if flags.contains(fs_flag) {
+            res |= ms_flag;
+        }
+    }
+    Ok(res)
+}
+
+/// Create the sandbox directory, with a mount point for every path in the sandbox. `cwd` is the
+/// absolute working directory of the command.
+pub fn prepare(sandbox: &Sandbox, cwd: Option<&Path>) -> anyhow::Result<PreparedSandbox> {
+    let root = Path::new(OsStr::from_bytes(&sandbox.root));
+    let dir = Path::new(OsStr::from_bytes(&sandbox.dir));
+
+    match fs::remove_dir_all(dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(anyhow::Error::from(e)
+                .context(format!("Error removing sandbox `{}`", dir.display())));
+        }
+    }
+    create_dir_all(dir)?;
+
+    let mut mounts = Vec::new();
+    let mut mounted: Vec<&Path> = Vec::new();
+
+    let paths = normalize_paths(sandbox);
+    for SandboxPath { path, writable } in &paths {
+        let src = root.join(path);
+        let dst = dir.join(path);
+
+        // If a parent is mounted already, the mount point is whatever is in the project at this
+        // path, so there is nothing to create in the sandbox directory.
+        let nested = mounted.iter().any(|m| path.starts_with(m));
+
+        if *writable {
+            create_dir_all(&src)?;
+            if !nested {
+                create_dir_all(&dst)?;
+            }
+        } else {
+            let metadata = match fs::symlink_metadata(&src) {
+                Ok(m) => m,
+                // Missing inputs are the command's problem, not ours.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(anyhow::Error::from(e)
+                        .context(format!("Error reading `{}`", src.display())));
+                }
+            };
+
+            if metadata.file_type().is_symlink() {
+                // Bind mounts follow symlinks, so recreate the link instead. Its target has to be
+                // in the sandbox too for the command to be able to use it.
+                if !nested {
+                    let target = fs::read_link(&src)
+                        .with_context(|| format!("Error reading `{}`", src.display()))?;
+                    create_parent(&dst)?;
+                    std::os::unix::fs::symlink(&target, &dst)
+                        .with_context(|| format!("Error creating `{}`", dst.display()))?;
+                }
+                continue;
+            }
+
+            if !nested {
+                if metadata.is_dir() {
+                    create_dir_all(&dst)?;
+                } else {
+                    create_parent(&dst)?;
+                    fs::File::create(&dst)
+                        .with_context(|| format!("Error creating `{}`", dst.display()))?;
+                }
+            }
+        }
+
+        mounts.push(BindMount {
+            src: cstring(&src)?,
+            dst: cstring(&dst)?,
+            readonly_flags: if *writable {
+                None
+            } else {
+                Some(locked_flags(&src)?)
+            },
+        });
+        mounted.push(path);
+    }
+
+    if let Some(rel) = cwd.and_then(|cwd| cwd.strip_prefix(root).ok()) {
+        // This does nothing visible if the directory ends up under a mount.
+        create_dir_all(&dir.join(rel))?;
+    }
+
+    let uid = nix::unistd::getuid();
+    let gid = nix::unistd::getgid();
+
+    Ok(PreparedSandbox {
+        root: cstring(root)?,
+        dir: cstring(dir)?,
+        cwd: cwd.map(cstring).transpose()?,
+        mounts,
+        uid_map: format!("{} {} 1\n", uid, uid).into_bytes(),
+        gid_map: format!("{} {} 1\n", gid, gid).into_bytes(),
+    })
+}
+
+fn c_str(bytes: &'static [u8]) -> &'static CStr {
+    // Those are NUL-terminated literals, so this can't fail.
+    CStr::from_bytes_with_nul(bytes).unwrap()
+}
+
+fn write_proc_file(path: &'static [u8], data: &[u8]) -> nix::Result<()> {
+    let fd = nix::fcntl::open(
+        c_str(path),
+        OFlag::O_WRONLY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+    let res = nix::unistd::write(fd, data);
+    let _ignored = nix::unistd::close(fd);
+    res.map(|_| ())
+}
+
+fn mount(src: Option<&CStr>, dst: &CStr, flags: MsFlags) -> nix::Result<()> {
+    nix::mount::mount(src, dst, None::<&CStr>, flags, None::<&CStr>)
+}
+
+impl PreparedSandbox {
+    /// Runs in the child, between fork and exec.
+    fn enter(&self) -> nix::Result<()> {
+        nix::sched::unshare(CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWNS)?;
+
+        // Keep our own uid and gid in the namespace, so that files the command creates are owned
+        // by the user running the build.
+        write_proc_file(b"/proc/self/setgroups\0", b"deny")?;
+        write_proc_file(b"/proc/self/uid_map\0", &self.uid_map)?;
+        write_proc_file(b"/proc/self/gid_map\0", &self.gid_map)?;
+
+        // Don't let our mounts propagate back to the host.
+        mount(None, c_str(b"/\0"), MsFlags::MS_REC | MsFlags::MS_PRIVATE)?;
+
+        for m in &self.mounts {
+            mount(Some(&m.src), &m.dst, MsFlags::MS_BIND | MsFlags::MS_REC)?;
+
+            if let Some(flags) = m.readonly_flags {
+                mount(
+                    None,
+                    &m.dst,
+                    MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY | flags,
+                )?;
+            }
+        }
+
+        mount(
+            Some(&self.dir),
+            &self.root,
+            MsFlags::MS_BIND | MsFlags::MS_REC,
+        )?;
+
+        // The working directory was entered before we replaced the project root, so it still
+        // points into the real project. Enter it again to go through the sandbox.
+        if let Some(cwd) = &self.cwd {
+            nix::unistd::chdir(cwd.as_c_str())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn apply(self, cmd: &mut Command) {
+        // SAFETY: `enter` only makes system calls, it does not allocate or take locks.
+        unsafe {
+            cmd.pre_exec(move || self.enter().map_err(io::Error::from));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sandbox(inputs: &[&str], writable: &[&str]) -> Sandbox {
+        Sandbox {
+            inputs: inputs.iter().map(|p| p.as_bytes().to_vec()).collect(),
+            writable: writable.iter().map(|p| p.as_bytes().to_vec()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn paths(paths: Vec<SandboxPath>) -> Vec<(String, bool)> {
+        paths
+            .into_iter()
+            .map(|p| (p.path.display().to_string(), p.writable))
+            .collect()
+    }
+
+    #[test]
+    fn test_normalize_paths() {
+        let res = normalize_paths(&sandbox(
+            &["b/c", "a", "a/b", "buck-out/gen/x", "buck-out/gen/y/z"],
+            &["buck-out/gen/y", "a/out"],
+        ));
+
+        assert_eq!(
+            paths(res),
+            vec![
+                ("a".to_owned(), false),
+                ("a/out".to_owned(), true),
+                ("b/c".to_owned(), false),
+                ("buck-out/gen/x".to_owned(), false),
+                ("buck-out/gen/y".to_owned(), true),
+                ("buck-out/gen/y/z".to_owned(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_normalize_paths_input_and_writable() {
+        let res = normalize_paths(&sandbox(&["out", "out/x"], &["out"]));
+        assert_eq!(
+            paths(res),
+            vec![("out".to_owned(), true), ("out/x".to_owned(), false)]
+        );
+    }
+
+    #[test]
+    fn test_prepare() -> anyhow::Result<()> {
+        let root = tempfile::tempdir()?;
+        let root = root.path();
+        std::fs::create_dir_all(root.join("src"))?;
+        std::fs::write(root.join("src/a"), "a")?;
+        std::fs::write(root.join("src/b"), "b")?;
+        std::os::unix::fs::symlink("a", root.join("src/link"))?;
+
+        let dir = root.join("buck-out/sandbox");
+        let mut sandbox = sandbox(&["src/a", "src/link", "src/missing"], &["buck-out/gen"]);
+        sandbox.root = root.as_os_str().as_bytes().to_vec();
+        sandbox.dir = dir.as_os_str().as_bytes().to_vec();
+
+        prepare(&sandbox, Some(&root.join("pkg")))?;
+
+        assert!(dir.join("src/a").is_file());
+        assert!(!dir.join("src/b").exists());
+        assert_eq!(std::fs::read_link(dir.join("src/link"))?, Path::new("a"));
+        assert!(!dir.join("src/missing").exists());
+        assert!(dir.join("buck-out/gen").is_dir());
+        assert!(root.join("buck-out/gen").is_dir());
+        assert!(dir.join("pkg").is_dir());
+
+        Ok(())
+    }
+}
@@ -8,15 +8,20 @@
  */
 
 use std::ffi::OsStr;
+use std::fs::File;
 use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::path::PathBuf;
 use std::pin::Pin;
 
 use anyhow::Context as _;
 use buck2_common::convert::ProstDurationExt;
 use buck2_core::logging::LogConfigurationReloadHandle;
 use buck2_forkserver_proto::forkserver_server::Forkserver;
+use buck2_forkserver_proto::Cgroup;
 use buck2_forkserver_proto::CommandRequest;
 use buck2_forkserver_proto::RequestEvent;
+use buck2_forkserver_proto::Sandbox;
 use buck2_forkserver_proto::SetLogFilterRequest;
 use buck2_forkserver_proto::SetLogFilterResponse;
 use buck2_grpc::to_tonic;
@@ -33,6 +38,7 @@ use crate::convert::encode_event_stream;
 use crate::run::prepare_command;
 use crate::run::stream_command_events;
 use crate::run::timeout_into_cancellation;
+use crate::run::CommandEvent;
 use crate::run::GatherOutputStatus;
 
 // Not quite BoxStream: it has to be Sync (...)
@@ -78,6 +84,9 @@ impl Forkserver for UnixForkserverService {
                 env,
                 cwd,
                 timeout,
+                sandbox,
+                cgroup,
+                stdin,
             } = msg;
 
             let exe = OsStr::from_bytes(&exe);
@@ -112,15 +121,37 @@ impl Forkserver for UnixForkserverService {
                 }
             }
 
+            // This must come before the sandbox: once the child is in its own user namespace, it
+            // may no longer be allowed to move itself to another cgroup.
+            let cgroup = match cgroup {
+                Some(cgroup) => Some(apply_cgroup(&mut cmd, cgroup)?),
+                None => None,
+            };
+
+            if let Some(sandbox) = sandbox {
+                apply_sandbox(&mut cmd, sandbox, cwd.map(PathBuf::from)).await?;
+            }
+
             let mut cmd = prepare_command(cmd);
+            if let Some(stdin) = stdin {
+                let stdin = OsStr::from_bytes(&stdin);
+                cmd.stdin(File::open(stdin).with_context(|| {
+                    format!("Error opening stdin `{}`", Path::new(stdin).display())
+                })?);
+            }
 
             let child = cmd.spawn();
 
+            // The child has joined the cgroup now (or failed to spawn), we no longer need to hold
+            // on to `cgroup.procs`.
+            drop(cmd);
+
             let timeout = timeout_into_cancellation(timeout);
 
             let cancellation = select(timeout.boxed(), cancel.boxed()).map(|r| r.factor_first().0);
 
             let stream = stream_command_events(child, cancellation)?;
+            let stream = with_execution_stats(stream, cgroup);
             let stream = encode_event_stream(stream);
             Ok(Box::pin(stream) as _)
         })
@@ -139,3 +170,169 @@ impl Forkserver for UnixForkserverService {
         Ok(Response::new(SetLogFilterResponse {}))
     }
 }
+
+#[cfg(target_os = "linux")]
+type ActionCgroup = crate::unix::cgroup::ActionCgroup;
+
+#[cfg(not(target_os = "linux"))]
+type ActionCgroup = std::convert::Infallible;
+
+#[cfg(target_os = "linux")]
+fn apply_cgroup(cmd: &mut std::process::Command, cgroup: Cgroup) -> anyhow::Result<ActionCgroup> {
+    let cgroup = ActionCgroup::create(&cgroup).context("Error creating cgroup")?;
+    cgroup.apply(cmd)?;
+    Ok(cgroup)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn apply_cgroup(_cmd: &mut std::process::Command, _cgroup: Cgroup) -> anyhow::Result<ActionCgroup> {
+    Err(anyhow::anyhow!(
+        "Resource limits for local actions are only supported on Linux"
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn with_execution_stats<S>(
+    stream: S,
+    cgroup: Option<ActionCgroup>,
+) -> impl Stream<Item = anyhow::Result<CommandEvent>>
+where
+    S: Stream<Item = anyhow::Result<CommandEvent>>,
+{
+    use futures::stream::StreamExt;
+
+    match cgroup {
+        Some(cgroup) => crate::unix::cgroup::attach_execution_stats(stream, cgroup).left_stream(),
+        None => stream.right_stream(),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn with_execution_stats<S>(
+    stream: S,
+    _cgroup: Option<ActionCgroup>,
+) -> impl Stream<Item = anyhow::Result<CommandEvent>>
+where
+    S: Stream<Item = anyhow::Result<CommandEvent>>,
+{
+    stream
+}
+
+#[cfg(target_os = "linux")]
+async fn apply_sandbox(
+    cmd: &mut std::process::Command,
+    sandbox: Sandbox,
+    cwd: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let sandbox = tokio::task::spawn_blocking(move || {
+        crate::unix::sandbox::prepare(&sandbox, cwd.as_deref())
+    })
+    .await
+    .context("Sandbox preparation was cancelled")?
+    .context("Error preparing sandbox")?;
+
+    sandbox.apply(cmd);
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn apply_sandbox(
+    _cmd: &mut std::process::Command,
+    _sandbox: Sandbox,
+    _cwd: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    Err(anyhow::anyhow!(
+        "Sandboxing local actions is only supported on Linux"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_forkserver_proto::forkserver_client::ForkserverClient;
+    use buck2_forkserver_proto::forkserver_server::ForkserverServer;
+    use buck2_grpc::DuplexChannel;
+    use futures::stream;
+    use futures::stream::StreamExt;
+    use tokio::net::UnixStream;
+
+    use super::*;
+    use crate::convert::decode_event_stream;
+    use crate::run::decode_command_event_stream;
+
+    /// Run a command through a forkserver running in this process.
+    async fn run(req: CommandRequest) -> anyhow::Result<(GatherOutputStatus, Vec<u8>, Vec<u8>)> {
+        let (client_io, server_io) = UnixStream::pair()?;
+
+        let server_io = {
+            let (read, write) = tokio::io::split(server_io);
+            DuplexChannel::new(read, write)
+        };
+        let router = tonic::transport::Server::builder().add_service(ForkserverServer::new(
+            UnixForkserverService {
+                log_reload_handle: <dyn LogConfigurationReloadHandle>::noop(),
+            },
+        ));
+        let _server = buck2_grpc::spawn_oneshot(server_io, router);
+
+        let channel = buck2_grpc::make_channel(client_io, "forkserver").await?;
+        let requests = stream::once(futures::future::ready(RequestEvent {
+            data: Some(req.into()),
+        }))
+        .chain(stream::pending());
+        let stream = ForkserverClient::new(channel)
+            .run(requests)
+            .await?
+            .into_inner();
+        decode_command_event_stream(decode_event_stream(stream)).await
+    }
+
+    #[tokio::test]
+    async fn test_stdin() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let stdin = dir.path().join("stdin");
+        std::fs::write(&stdin, "hello")?;
+
+        let (status, stdout, _stderr) = run(CommandRequest {
+            exe: b"cat".to_vec(),
+            stdin: Some(stdin.as_os_str().as_bytes().to_vec()),
+            ..Default::default()
+        })
+        .await?;
+        assert!(matches!(
+            status,
+            GatherOutputStatus::Finished { exit_code: 0, .. }
+        ));
+        assert_eq!(stdout, b"hello");
+
+        // Without a stdin, the command reads from /dev/null.
+        let (status, stdout, _stderr) = run(CommandRequest {
+            exe: b"cat".to_vec(),
+            ..Default::default()
+        })
+        .await?;
+        assert!(matches!(
+            status,
+            GatherOutputStatus::Finished { exit_code: 0, .. }
+        ));
+        assert_eq!(stdout, b"");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stdin_missing() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let stdin = dir.path().join("missing");
+
+        let err = run(CommandRequest {
+            exe: b"cat".to_vec(),
+            stdin: Some(stdin.as_os_str().as_bytes().to_vec()),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+        assert!(format!("{:#}", err).contains("Error opening stdin"));
+
+        Ok(())
+    }
+}
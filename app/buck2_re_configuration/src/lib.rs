@@ -7,7 +7,10 @@
  * of this source tree.
  */
 
+use std::str::FromStr;
+
 use allocative::Allocative;
+use anyhow::Context;
 use buck2_common::legacy_configs::LegacyBuckConfig;
 
 static BUCK2_RE_CLIENT_CFG_SECTION: &str = "buck2_re_client";
@@ -145,6 +148,62 @@ pub struct Buck2OssReConfiguration {
     /// This can contain environment variables using shell interpolation syntax (i.e. $VAR). They
     /// will be substituted before using the value.
     pub tls_client_cert: Option<String>,
+    /// Largest total size, in bytes, of a single `BatchReadBlobs` or `BatchUpdateBlobs` request.
+    /// Blobs too large to fit in a batch are transferred using the ByteStream API instead.
+    pub max_total_batch_size: Option<usize>,
+    /// The RE instance to operate against. Servers may host multiple instances (e.g. one per
+    /// tenant) and use this to select between them.
+    pub instance_name: Option<String>,
+    /// Extra gRPC metadata to send with every request, set as a comma-separated list of
+    /// `name:value` pairs.
+    ///
+    /// Values can contain environment variables using shell interpolation syntax (i.e. $VAR).
+    /// They will be substituted before using the value.
+    pub http_headers: Vec<HttpHeader>,
+    /// Path to a file containing a token to send as `authorization: Bearer <token>`.
+    ///
+    /// This can contain environment variables using shell interpolation syntax (i.e. $VAR). They
+    /// will be substituted before using the value.
+    pub auth_token_file: Option<String>,
+    /// A command (program and arguments, separated by whitespace) that prints a token to send as
+    /// `authorization: Bearer <token>`. It runs once, when connecting to RE. This cannot be set
+    /// alongside `auth_token_file`.
+    pub auth_token_helper: Option<String>,
+    /// How long, in seconds, to assume a blob that the CAS has will remain available. The REAPI
+    /// has no way to query TTLs, so this is what Buck2 uses to decide when to check again.
+    pub assumed_digest_ttl: Option<i64>,
+    /// How many times to retry a request that failed with a transient error (e.g. `UNAVAILABLE`).
+    /// Only requests that are safe to repeat are retried.
+    pub max_retries: Option<usize>,
+    /// Backoff, in milliseconds, before the first retry. This doubles with each retry, and some
+    /// random jitter is applied.
+    pub retry_backoff_ms: Option<u64>,
+    /// Upper bound, in milliseconds, for the backoff between two retries.
+    pub max_retry_backoff_ms: Option<u64>,
+    /// Deadline, in milliseconds, for a single attempt at a request. Attempts that exceed it are
+    /// retried. This does not apply to the stream that waits for an action to finish executing.
+    pub rpc_timeout_ms: Option<u64>,
+}
+
+#[derive(Clone, Debug, Allocative)]
+pub struct HttpHeader {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for HttpHeader {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (key, value) = s
+            .split_once(':')
+            .with_context(|| format!("Invalid header (expect name:value): `{}`", s))?;
+
+        Ok(Self {
+            key: key.trim().to_owned(),
+            value: value.trim().to_owned(),
+        })
+    }
 }
 
 impl Buck2OssReConfiguration {
@@ -156,6 +215,23 @@ impl Buck2OssReConfiguration {
                 .parse(BUCK2_RE_CLIENT_CFG_SECTION, "action_cache_address")?,
             tls_ca_certs: legacy_config.parse(BUCK2_RE_CLIENT_CFG_SECTION, "tls_ca_certs")?,
             tls_client_cert: legacy_config.parse(BUCK2_RE_CLIENT_CFG_SECTION, "tls_client_cert")?,
+            max_total_batch_size: legacy_config
+                .parse(BUCK2_RE_CLIENT_CFG_SECTION, "max_total_batch_size")?,
+            instance_name: legacy_config.parse(BUCK2_RE_CLIENT_CFG_SECTION, "instance_name")?,
+            http_headers: legacy_config
+                .parse_list(BUCK2_RE_CLIENT_CFG_SECTION, "http_headers")?
+                .unwrap_or_default(),
+            auth_token_file: legacy_config.parse(BUCK2_RE_CLIENT_CFG_SECTION, "auth_token_file")?,
+            auth_token_helper: legacy_config
+                .parse(BUCK2_RE_CLIENT_CFG_SECTION, "auth_token_helper")?,
+            assumed_digest_ttl: legacy_config
+                .parse(BUCK2_RE_CLIENT_CFG_SECTION, "assumed_digest_ttl")?,
+            max_retries: legacy_config.parse(BUCK2_RE_CLIENT_CFG_SECTION, "max_retries")?,
+            retry_backoff_ms: legacy_config
+                .parse(BUCK2_RE_CLIENT_CFG_SECTION, "retry_backoff_ms")?,
+            max_retry_backoff_ms: legacy_config
+                .parse(BUCK2_RE_CLIENT_CFG_SECTION, "max_retry_backoff_ms")?,
+            rpc_timeout_ms: legacy_config.parse(BUCK2_RE_CLIENT_CFG_SECTION, "rpc_timeout_ms")?,
         })
     }
 }
@@ -18,6 +18,8 @@ use anyhow::Context;
 use async_trait::async_trait;
 use buck2_build_api::actions::build_listener::BuildSignalSender;
 use buck2_build_api::actions::build_listener::SetBuildSignals;
+use buck2_build_api::actions::impls::run::determinism::DeterminismReport;
+use buck2_build_api::actions::impls::run::determinism::HasDeterminismReport;
 use buck2_build_api::actions::impls::run::knobs::HasRunActionKnobs;
 use buck2_build_api::actions::impls::run::knobs::RunActionKnobs;
 use buck2_build_api::build::HasCreateUnhashedSymlinkLock;
@@ -50,6 +52,7 @@ use buck2_core::async_once_cell::AsyncOnceCell;
 use buck2_core::cells::CellResolver;
 use buck2_core::facebook_only;
 use buck2_core::fs::paths::abs_norm_path::AbsNormPath;
+use buck2_core::fs::paths::abs_path::AbsPathBuf;
 use buck2_core::fs::project::ProjectRoot;
 use buck2_core::fs::project_rel_path::ProjectRelativePath;
 use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
@@ -65,13 +68,18 @@ use buck2_execute::execute::blocking::SetBlockingExecutor;
 use buck2_execute::execute::dice_data::set_fallback_executor_config;
 use buck2_execute::execute::dice_data::SetCommandExecutor;
 use buck2_execute::execute::dice_data::SetReClient;
+use buck2_execute::execute::request::LocalResourceLimits;
 use buck2_execute::knobs::ExecutorGlobalKnobs;
+use buck2_execute::materialize::http::HasUrlMirrors;
+use buck2_execute::materialize::http::UrlMirrors;
 use buck2_execute::materialize::materializer::Materializer;
 use buck2_execute::materialize::materializer::SetMaterializer;
 use buck2_execute::re::client::RemoteExecutionClient;
 use buck2_execute::re::manager::ReConnectionHandle;
 use buck2_execute::re::manager::ReConnectionManager;
 use buck2_execute::re::manager::ReConnectionObserver;
+use buck2_execute_impl::executors::worker::WorkerPool;
+use buck2_execute_impl::local_cache::LocalActionCache;
 use buck2_execute_impl::low_pass_filter::LowPassFilter;
 use buck2_forkserver::client::ForkserverClient;
 use buck2_interpreter::dice::starlark_profiler::StarlarkProfilerConfiguration;
@@ -110,6 +118,7 @@ use crate::dice_tracker::BuckDiceTracker;
 use crate::file_watcher::FileWatcher;
 use crate::heartbeat_guard::HeartbeatGuard;
 use crate::host_info;
+use crate::subscription::SubscriptionBroadcast;
 
 #[derive(Debug, thiserror::Error)]
 enum DaemonCommunicationError {
@@ -139,6 +148,10 @@ pub struct BaseServerCommandContext {
     pub materializer: Arc<dyn Materializer>,
     /// Forkserver connection, if any was started
     pub forkserver: Option<ForkserverClient>,
+    /// Persistent workers started by local actions.
+    pub workers: Arc<WorkerPool>,
+    /// The local action cache, if one is configured.
+    pub local_cache: Option<Arc<LocalActionCache>>,
     /// The event dispatcher for this command context.
     pub events: EventDispatcher,
     /// Removes this command from the set of active commands when dropped.
@@ -151,6 +164,8 @@ pub struct BaseServerCommandContext {
     pub daemon_start_time: Instant,
     /// Mutex for creating symlinks
     pub create_unhashed_outputs_lock: Arc<Mutex<()>>,
+    /// Notifies `buck2 subscribe` clients about what the daemon is doing.
+    pub(crate) subscription_broadcast: Arc<SubscriptionBroadcast>,
 }
 
 /// ServerCommandContext provides access to the global daemon state and information about the calling client for
@@ -337,6 +352,8 @@ impl ServerCommandContext {
         let build_signals = self.build_signals.dupe();
 
         let forkserver = self.base_context.forkserver.dupe();
+        let workers = self.base_context.workers.dupe();
+        let local_cache = self.base_context.local_cache.dupe();
 
         let upload_all_actions = self
             .build_options
@@ -345,6 +362,12 @@ impl ServerCommandContext {
 
         let create_unhashed_symlink_lock = self.base_context.create_unhashed_outputs_lock.dupe();
 
+        let determinism_report = self
+            .build_options
+            .as_ref()
+            .filter(|opts| opts.check_determinism)
+            .map(|_| Arc::new(DeterminismReport::default()));
+
         DiceCommandDataProvider {
             cell_configs_loader: self.cell_configs_loader.dupe(),
             events: self.events().dupe(),
@@ -357,9 +380,12 @@ impl ServerCommandContext {
             re_connection,
             build_signals,
             forkserver,
+            workers,
+            local_cache,
             upload_all_actions,
             no_remote_cache,
             create_unhashed_symlink_lock,
+            determinism_report,
         }
     }
 
@@ -444,10 +470,13 @@ struct DiceCommandDataProvider {
     re_connection: Arc<ReConnectionHandle>,
     build_signals: BuildSignalSender,
     forkserver: Option<ForkserverClient>,
+    workers: Arc<WorkerPool>,
+    local_cache: Option<Arc<LocalActionCache>>,
     upload_all_actions: bool,
     run_action_knobs: RunActionKnobs,
     no_remote_cache: bool,
     create_unhashed_symlink_lock: Arc<Mutex<()>>,
+    determinism_report: Option<Arc<DeterminismReport>>,
 }
 
 #[async_trait]
@@ -475,7 +504,34 @@ impl DiceDataProvider for DiceCommandDataProvider {
                 .instant_event(buck2_data::ConsolePreferences { max_lines });
         }
 
-        let executor_global_knobs = ExecutorGlobalKnobs {};
+        let local_action_cgroup_parent = root_config
+            .get("buck2", "local_action_cgroup_parent")
+            .map(|p| AbsPathBuf::try_from(p.to_owned()))
+            .transpose()
+            .context("Invalid `buck2.local_action_cgroup_parent`")?
+            .map(Arc::new);
+
+        let executor_global_knobs = ExecutorGlobalKnobs {
+            local_action_cgroup_parent,
+            local_action_limits: LocalResourceLimits {
+                memory_max_bytes: root_config.parse("buck2", "local_action_memory_max_bytes")?,
+                cpu_max_percent: root_config.parse("buck2", "local_action_cpu_max_percent")?,
+            },
+        };
+
+        if executor_global_knobs.local_action_cgroup_parent.is_none()
+            && !executor_global_knobs.local_action_limits.is_empty()
+        {
+            return Err(anyhow::anyhow!(
+                "Limits for local actions require `buck2.local_action_cgroup_parent` to be set"
+            ));
+        }
+
+        let url_mirrors = UrlMirrors::new(
+            root_config
+                .parse_list("download", "mirrors")?
+                .unwrap_or_default(),
+        );
 
         let host_sharing_broker =
             HostSharingBroker::new(HostSharingStrategy::SmallerTasksFirst, concurrency);
@@ -519,6 +575,8 @@ impl DiceDataProvider for DiceCommandDataProvider {
             executor_global_knobs,
             self.upload_all_actions,
             self.forkserver.dupe(),
+            self.workers.dupe(),
+            self.local_cache.dupe(),
             self.no_remote_cache,
             ctx.global_data()
                 .get_io_provider()
@@ -529,7 +587,11 @@ impl DiceDataProvider for DiceCommandDataProvider {
         data.set_materializer(self.materializer.dupe());
         data.set_build_signals(self.build_signals.dupe());
         data.set_run_action_knobs(self.run_action_knobs.dupe());
+        data.set_url_mirrors(Arc::new(url_mirrors));
         data.set_create_unhashed_symlink_lock(self.create_unhashed_symlink_lock.dupe());
+        if let Some(determinism_report) = &self.determinism_report {
+            data.set_determinism_report(determinism_report.dupe());
+        }
         data.spawner = Arc::new(BuckSpawner::default());
 
         let tags = vec![format!("lazy-cycle-detector:{}", has_cycle_detector)];
@@ -776,4 +838,8 @@ impl ServerCommandContextTrait for ServerCommandContext {
                 target_patterns: patterns,
             })
     }
+
+    fn has_subscribers(&self) -> bool {
+        self.base_context.subscription_broadcast.has_subscribers()
+    }
 }
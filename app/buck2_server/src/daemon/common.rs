@@ -37,7 +37,10 @@ use buck2_execute::re::manager::ReConnectionHandle;
 use buck2_execute_impl::executors::caching::CachingExecutor;
 use buck2_execute_impl::executors::hybrid::HybridExecutor;
 use buck2_execute_impl::executors::local::LocalExecutor;
+use buck2_execute_impl::executors::local_cache::LocalCachingExecutor;
 use buck2_execute_impl::executors::re::ReExecutor;
+use buck2_execute_impl::executors::worker::WorkerPool;
+use buck2_execute_impl::local_cache::LocalActionCache;
 use buck2_execute_impl::low_pass_filter::LowPassFilter;
 use buck2_forkserver::client::ForkserverClient;
 use dupe::Dupe;
@@ -71,6 +74,8 @@ pub struct CommandExecutorFactory {
     pub executor_global_knobs: ExecutorGlobalKnobs,
     pub upload_all_actions: bool,
     pub forkserver: Option<ForkserverClient>,
+    pub workers: Arc<WorkerPool>,
+    pub local_cache: Option<Arc<LocalActionCache>>,
     pub no_remote_cache: bool,
     project_root: ProjectRoot,
 }
@@ -86,6 +91,8 @@ impl CommandExecutorFactory {
         executor_global_knobs: ExecutorGlobalKnobs,
         upload_all_actions: bool,
         forkserver: Option<ForkserverClient>,
+        workers: Arc<WorkerPool>,
+        local_cache: Option<Arc<LocalActionCache>>,
         no_remote_cache: bool,
         project_root: ProjectRoot,
     ) -> Self {
@@ -99,6 +106,8 @@ impl CommandExecutorFactory {
             executor_global_knobs,
             upload_all_actions,
             forkserver,
+            workers,
+            local_cache,
             no_remote_cache,
             project_root,
         }
@@ -111,7 +120,7 @@ impl HasCommandExecutor for CommandExecutorFactory {
         artifact_fs: &ArtifactFs,
         executor_config: &CommandExecutorConfig,
     ) -> anyhow::Result<CommandExecutorResponse> {
-        let local_executor_new = |_options| {
+        let local_executor_new = |options: &LocalExecutorOptions| {
             LocalExecutor::new(
                 artifact_fs.clone(),
                 self.materializer.dupe(),
@@ -120,9 +129,36 @@ impl HasCommandExecutor for CommandExecutorFactory {
                 self.project_root.root().to_owned(),
                 self.forkserver.dupe(),
                 self.executor_global_knobs.dupe(),
+                options.dupe(),
+                self.workers.dupe(),
             )
         };
 
+        // NOTE: While we now have a legit flag for this, we keep the env var. This has been used
+        // in remediating prod incidents in the past, and this is the kind of thing that can easily
+        // become tribal knowledge. Keeping this does not hurt us.
+        static DISABLE_CACHING: EnvHelper<bool> = EnvHelper::new("BUCK2_TEST_DISABLE_CACHING");
+
+        let disable_caching = DISABLE_CACHING
+            .get_copied()?
+            .unwrap_or(self.no_remote_cache);
+
+        // The local cache sits in front of everything else, since it is the cheapest to query.
+        // Like the remote cache, it is bypassed when caching is disabled.
+        let with_local_cache =
+            |inner: Arc<dyn PreparedCommandExecutor>| -> Arc<dyn PreparedCommandExecutor> {
+                match &self.local_cache {
+                    Some(cache) if !disable_caching => Arc::new(LocalCachingExecutor {
+                        inner,
+                        cache: cache.dupe(),
+                        artifact_fs: artifact_fs.clone(),
+                        materializer: self.materializer.dupe(),
+                        blocking_executor: self.blocking_executor.dupe(),
+                    }),
+                    _ => inner,
+                }
+            };
+
         if !buck2_core::is_open_source() && !cfg!(fbcode_build) {
             static WARN: OnceCell<()> = OnceCell::new();
             WARN.get_or_init(|| {
@@ -137,7 +173,9 @@ impl HasCommandExecutor for CommandExecutorFactory {
             }
 
             return Ok(CommandExecutorResponse {
-                executor: Arc::new(local_executor_new(&LocalExecutorOptions {})),
+                executor: with_local_cache(Arc::new(local_executor_new(
+                    &LocalExecutorOptions::default(),
+                ))),
                 platform: Default::default(),
             });
         }
@@ -169,7 +207,7 @@ impl HasCommandExecutor for CommandExecutorFactory {
                     None
                 } else {
                     Some(CommandExecutorResponse {
-                        executor: Arc::new(local_executor_new(local)),
+                        executor: with_local_cache(Arc::new(local_executor_new(local))),
                         platform: Default::default(),
                     })
                 }
@@ -202,16 +240,6 @@ impl HasCommandExecutor for CommandExecutorFactory {
                     _ => None,
                 };
 
-                // NOTE: While we now have a legit flag for this, we keep the env var. This has been used
-                // in remediating prod incidents in the past, and this is the kind of thing that can easily
-                // become tribal knowledge. Keeping this does not hurt us.
-                static DISABLE_CACHING: EnvHelper<bool> =
-                    EnvHelper::new("BUCK2_TEST_DISABLE_CACHING");
-
-                let disable_caching = DISABLE_CACHING
-                    .get_copied()?
-                    .unwrap_or(self.no_remote_cache);
-
                 let executor = if disable_caching || !remote_cache_enabled {
                     inner_executor
                 } else {
@@ -239,7 +267,10 @@ impl HasCommandExecutor for CommandExecutorFactory {
                         .collect(),
                 };
 
-                executor.map(|executor| CommandExecutorResponse { executor, platform })
+                executor.map(|executor| CommandExecutorResponse {
+                    executor: with_local_cache(executor),
+                    platform,
+                })
             }
         };
 
@@ -294,11 +325,11 @@ impl ExecutionStrategyExt for ExecutionStrategy {
 /// This is used when execution platforms are not configured.
 pub fn get_default_executor_config(host_platform: HostPlatformOverride) -> CommandExecutorConfig {
     let executor = if buck2_core::is_open_source() {
-        Executor::Local(LocalExecutorOptions {})
+        Executor::Local(LocalExecutorOptions::default())
     } else {
         Executor::RemoteEnabled {
             executor: RemoteEnabledExecutor::Hybrid {
-                local: LocalExecutorOptions {},
+                local: LocalExecutorOptions::default(),
                 remote: RemoteExecutorOptions::default(),
                 level: HybridExecutionLevel::Limited,
             },
@@ -314,6 +345,7 @@ pub fn get_default_executor_config(host_platform: HostPlatformOverride) -> Comma
         options: CommandGenerationOptions {
             path_separator: PathSeparatorKind::system_default(),
             output_paths_behavior: Default::default(),
+            default_timeout: None,
         },
     }
 }
@@ -9,9 +9,12 @@
 
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use allocative::Allocative;
 use anyhow::Context;
+use buck2_build_api::actions::impls::run::dep_files_sqlite::DepFilesSqliteDb;
+use buck2_build_api::actions::impls::run::dep_files_sqlite::DEP_FILES_DB_SCHEMA_VERSION;
 use buck2_common::invocation_paths::InvocationPaths;
 use buck2_common::legacy_configs::LegacyBuckConfig;
 use buck2_core::fs::fs_util;
@@ -27,11 +30,16 @@ use buck2_execute_impl::materializers::sqlite::MaterializerState;
 use buck2_execute_impl::materializers::sqlite::MaterializerStateSqliteDb;
 use buck2_execute_impl::materializers::sqlite::DB_SCHEMA_VERSION;
 use chrono::Utc;
+use dice::DicePersistence;
+
+/// Bumped when the values persisted in the DICE snapshot change in an incompatible way.
+const DICE_STATE_SCHEMA_VERSION: u32 = 1;
 
 #[derive(Allocative)]
 pub struct DiskStateOptions {
     pub sqlite_materializer_state: bool,
-    // In future, this will include the config for dep files on disk
+    pub sqlite_dep_files_state: bool,
+    pub dice_state: bool,
 }
 
 impl DiskStateOptions {
@@ -47,8 +55,24 @@ impl DiskStateOptions {
             .parse::<RolloutPercentage>("buck2", "sqlite_materializer_state")?
             .unwrap_or_else(RolloutPercentage::never)
             .roll();
+        // Dep files can only be reused if we know their outputs are still there, which requires
+        // the materializer state.
+        let sqlite_dep_files_state = sqlite_materializer_state
+            && root_config
+                .parse::<RolloutPercentage>("buck2", "sqlite_dep_files_state")?
+                .unwrap_or_else(RolloutPercentage::never)
+                .roll();
+        // Only file digests are persisted in the DICE snapshot so far (see `PathMetadataKey`).
+        // Package listings, interpreter results and analysis results are recomputed after a
+        // restart.
+        let dice_state = root_config
+            .parse::<RolloutPercentage>("buck2", "dice_state")?
+            .unwrap_or_else(RolloutPercentage::never)
+            .roll();
         Ok(Self {
             sqlite_materializer_state,
+            sqlite_dep_files_state,
+            dice_state,
         })
     }
 }
@@ -117,6 +141,97 @@ pub(crate) async fn maybe_initialize_materializer_sqlite_db(
     Ok((Some(db), materializer_state))
 }
 
+pub(crate) async fn maybe_initialize_dep_files_sqlite_db(
+    options: &DiskStateOptions,
+    paths: &InvocationPaths,
+    io_executor: Arc<dyn BlockingExecutor>,
+    root_config: &LegacyBuckConfig,
+    fs: ProjectRoot,
+) -> anyhow::Result<Option<DepFilesSqliteDb>> {
+    if !options.sqlite_dep_files_state {
+        // Like for the materializer state, a db we don't keep up to date would go stale.
+        io_executor
+            .execute_io_inline(|| fs.remove_path_recursive(&paths.dep_files_state_path()))
+            .await?;
+        return Ok(None);
+    }
+
+    let mut versions = HashMap::from([(
+        "schema_version".to_owned(),
+        DEP_FILES_DB_SCHEMA_VERSION.to_string(),
+    )]);
+    if let Some(buckconfig_version) =
+        root_config.parse("buck2", "sqlite_dep_files_state_version")?
+    {
+        versions.insert("buckconfig_version".to_owned(), buckconfig_version);
+    }
+    if let Some(hostname) = buck2_events::metadata::collect().get("hostname") {
+        versions.insert("hostname".to_owned(), hostname.to_owned());
+    }
+
+    let db =
+        DepFilesSqliteDb::initialize(paths.dep_files_state_path(), versions, io_executor).await?;
+    Ok(Some(db))
+}
+
+pub(crate) async fn maybe_initialize_dice_persistence(
+    options: &DiskStateOptions,
+    paths: &InvocationPaths,
+    io_executor: Arc<dyn BlockingExecutor>,
+    root_config: &LegacyBuckConfig,
+    fs: ProjectRoot,
+    digest_config: DigestConfig,
+) -> anyhow::Result<Option<Arc<DicePersistence>>> {
+    if !options.dice_state {
+        // Values are validated when they are loaded, but there is no reason to keep them around.
+        io_executor
+            .execute_io_inline(|| fs.remove_path_recursive(&paths.dice_state_path()))
+            .await?;
+        return Ok(None);
+    }
+
+    // The snapshot is only reused if everything in the stamp matches.
+    let metadata = buck2_events::metadata::collect();
+    let mut stamp = vec![
+        format!("schema_version={}", DICE_STATE_SCHEMA_VERSION),
+        format!("digest_config={:?}", digest_config.cas_digest_config()),
+    ];
+    if let Some(buckconfig_version) = root_config.get("buck2", "dice_state_version") {
+        stamp.push(format!("buckconfig_version={}", buckconfig_version));
+    }
+    for key in ["buck2_revision", "hostname"] {
+        if let Some(value) = metadata.get(key) {
+            stamp.push(format!("{}={}", key, value));
+        }
+    }
+
+    let path = paths
+        .dice_state_path()
+        .join(FileName::unchecked_new("snapshot"));
+    Ok(Some(DicePersistence::open(
+        path.into_path_buf(),
+        stamp.join("\n"),
+    )))
+}
+
+/// Periodically writes out the DICE snapshot, so that it survives the daemon being killed. This
+/// runs for as long as the daemon is alive.
+pub(crate) async fn save_dice_persistence_periodically(
+    persistence: Arc<DicePersistence>,
+    frequency: Duration,
+) {
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + frequency, frequency);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+
+        if let Err(e) = persistence.save().await {
+            tracing::warn!("{:#}", e);
+        }
+    }
+}
+
 // Once we start storing disk state in the cache directory, we need to make sure
 // buck2 always deletes the cache directory if the cache is disabled.
 // Otherwise, buck-out state can diverge from the state of on-disk cache when
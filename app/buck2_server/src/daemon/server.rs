@@ -140,8 +140,16 @@ impl DaemonStateDiceConstructor for DaemonStateDiceConstructorImpl {
         io: Arc<dyn IoProvider>,
         digest_config: DigestConfig,
         root_config: &LegacyBuckConfig,
+        persistence: Option<Arc<DicePersistence>>,
     ) -> anyhow::Result<Arc<Dice>> {
-        configure_dice_for_buck(io, digest_config, Some(root_config), self.detect_cycles).await
+        configure_dice_for_buck(
+            io,
+            digest_config,
+            Some(root_config),
+            self.detect_cycles,
+            persistence,
+        )
+        .await
     }
 }
 
@@ -842,6 +850,7 @@ impl DaemonApi for BuckdServer {
         self.oneshot(req, DefaultCommandOptions, move |req| async move {
             let FlushDepFilesRequest {} = req;
             buck2_build_api::actions::impls::run::dep_files::flush_dep_files();
+            buck2_build_api::actions::impls::run::dep_files::flush_persisted_dep_files()?;
             Ok(GenericResponse {})
         })
         .await
@@ -1252,14 +1261,26 @@ impl DaemonApi for BuckdServer {
         &self,
         req: Request<tonic::Streaming<StreamingRequest>>,
     ) -> Result<Response<Self::SubscriptionStream>, Status> {
+        let subscription_broadcast = self
+            .0
+            .daemon_state
+            .data()
+            .map(|data| data.subscription_broadcast.dupe());
+
         self.run_bidirectional(
             req,
             DefaultCommandOptions,
             |ctx,
              partial_result_dispatcher,
              _client_ctx,
-             req: StreamingRequestHandler<SubscriptionRequestWrapper>| {
-                run_subscription_server_command(Box::new(ctx), partial_result_dispatcher, req)
+             req: StreamingRequestHandler<SubscriptionRequestWrapper>| async move {
+                run_subscription_server_command(
+                    Box::new(ctx),
+                    subscription_broadcast?,
+                    partial_result_dispatcher,
+                    req,
+                )
+                .await
             },
         )
         .await
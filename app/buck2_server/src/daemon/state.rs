@@ -27,6 +27,7 @@ use buck2_common::result::SharedResult;
 use buck2_common::result::ToSharedResultExt;
 use buck2_core::cells::name::CellName;
 use buck2_core::facebook_only;
+use buck2_core::fs::paths::abs_norm_path::AbsNormPathBuf;
 use buck2_core::fs::project::ProjectRoot;
 use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
 use buck2_core::rollout_percentage::RolloutPercentage;
@@ -42,10 +43,17 @@ use buck2_execute::execute::blocking::BuckBlockingExecutor;
 use buck2_execute::materialize::materializer::MaterializationMethod;
 use buck2_execute::materialize::materializer::Materializer;
 use buck2_execute::re::manager::ReConnectionManager;
+use buck2_execute_impl::executors::worker::WorkerPool;
+use buck2_execute_impl::executors::worker::DEFAULT_IDLE_TIMEOUT;
+use buck2_execute_impl::executors::worker::DEFAULT_MAX_INSTANCES;
+use buck2_execute_impl::local_cache;
+use buck2_execute_impl::local_cache::LocalActionCache;
 use buck2_execute_impl::materializers::deferred::DeferredMaterializer;
 use buck2_execute_impl::materializers::deferred::DeferredMaterializerConfigs;
+use buck2_execute_impl::materializers::deferred::DiskBudgetConfiguration;
 use buck2_execute_impl::materializers::deferred::TtlRefreshConfiguration;
 use buck2_execute_impl::materializers::immediate::ImmediateMaterializer;
+use buck2_execute_impl::materializers::io::CopyMethod;
 use buck2_execute_impl::materializers::sqlite::MaterializerState;
 use buck2_execute_impl::materializers::sqlite::MaterializerStateSqliteDb;
 use buck2_forkserver::client::ForkserverClient;
@@ -56,6 +64,7 @@ use buck2_server_ctx::concurrency::DiceCleanup;
 use buck2_server_ctx::concurrency::NestedInvocation;
 use buck2_server_ctx::concurrency::ParallelInvocation;
 use dice::Dice;
+use dice::DicePersistence;
 use dupe::Dupe;
 use fbinit::FacebookInit;
 use gazebo::variants::VariantName;
@@ -65,11 +74,16 @@ use crate::active_commands::ActiveCommandDropGuard;
 use crate::ctx::BaseServerCommandContext;
 use crate::daemon::check_working_dir;
 use crate::daemon::disk_state::delete_unknown_disk_state;
+use crate::daemon::disk_state::maybe_initialize_dep_files_sqlite_db;
+use crate::daemon::disk_state::maybe_initialize_dice_persistence;
 use crate::daemon::disk_state::maybe_initialize_materializer_sqlite_db;
+use crate::daemon::disk_state::save_dice_persistence_periodically;
 use crate::daemon::disk_state::DiskStateOptions;
 use crate::daemon::forkserver::maybe_launch_forkserver;
 use crate::daemon::panic::DaemonStatePanicDiceDump;
 use crate::file_watcher::FileWatcher;
+use crate::subscription::BroadcastingSink;
+use crate::subscription::SubscriptionBroadcast;
 
 /// For a buckd process there is a single DaemonState created at startup and never destroyed.
 #[derive(Allocative)]
@@ -118,6 +132,15 @@ pub struct DaemonStateData {
 
     pub(crate) forkserver: Option<ForkserverClient>,
 
+    /// Persistent workers outlive commands, so that they can be reused by subsequent builds.
+    #[allocative(skip)]
+    pub(crate) workers: Arc<WorkerPool>,
+
+    /// The local action cache, if one is configured. This is shared by all commands (and possibly
+    /// other daemons).
+    #[allocative(skip)]
+    pub(crate) local_cache: Option<Arc<LocalActionCache>>,
+
     #[allocative(skip)]
     pub scribe_sink: Option<Arc<dyn EventSink>>,
 
@@ -131,6 +154,10 @@ pub struct DaemonStateData {
 
     #[allocative(skip)]
     pub create_unhashed_outputs_lock: Arc<Mutex<()>>,
+
+    /// Notifies `buck2 subscribe` clients about events of all commands.
+    #[allocative(skip)]
+    pub(crate) subscription_broadcast: Arc<SubscriptionBroadcast>,
 }
 
 impl DaemonStateData {
@@ -157,6 +184,7 @@ pub trait DaemonStateDiceConstructor: Allocative + Send + Sync + 'static {
         io: Arc<dyn IoProvider>,
         digest_config: DigestConfig,
         root_config: &LegacyBuckConfig,
+        persistence: Option<Arc<DicePersistence>>,
     ) -> anyhow::Result<Arc<Dice>>;
 }
 
@@ -235,6 +263,32 @@ impl DaemonState {
         let valid_cache_dirs = paths.valid_cache_dirs();
         let fs_duped = fs.dupe();
 
+        let buffer_size = root_config
+            .parse("buck2", "event_log_buffer_size")?
+            .unwrap_or(10000);
+        let retry_backoff = Duration::from_millis(
+            root_config
+                .parse("buck2", "event_log_retry_backoff_duration_ms")?
+                .unwrap_or(500),
+        );
+        let retry_attempts = root_config
+            .parse("buck2", "event_log_retry_attempts")?
+            .unwrap_or(5);
+        let message_batch_size = root_config.parse("buck2", "event_log_message_batch_size")?;
+        let scribe_sink = Self::init_scribe_sink(
+            fb,
+            buffer_size,
+            retry_backoff,
+            retry_attempts,
+            message_batch_size,
+        )
+        .context("failed to init scribe sink")?;
+
+        // How to copy files that are already on disk, e.g. for `copy_file` or local cache hits.
+        let copy_method = root_config
+            .parse::<CopyMethod>("buck2", "copy_method")?
+            .unwrap_or_default();
+
         let deferred_materializer_configs = {
             let defer_write_actions = root_config
                 .parse::<RolloutPercentage>("buck2", "defer_write_actions")?
@@ -256,6 +310,17 @@ impl DaemonState {
                 .unwrap_or_else(RolloutPercentage::never)
                 .roll();
 
+            // When set, the materializer evicts the least recently used artifacts to keep
+            // buck-out below this size.
+            let disk_budget_max_bytes =
+                root_config.parse::<u64>("buck2", "materializer_disk_budget_bytes")?;
+
+            let disk_budget_frequency = parse_frequency_seconds(
+                root_config,
+                "materializer_disk_budget_frequency_seconds",
+                300,
+            )?;
+
             DeferredMaterializerConfigs {
                 materialize_final_artifacts: matches!(
                     materialization_method,
@@ -267,11 +332,22 @@ impl DaemonState {
                     min_ttl: chrono::Duration::seconds(ttl_refresh_min_ttl),
                     enabled: ttl_refresh_enabled,
                 },
+                disk_budget: disk_budget_max_bytes.map(|max_bytes| DiskBudgetConfiguration {
+                    max_bytes,
+                    frequency: disk_budget_frequency,
+                    // Eviction runs in the background rather than as part of a command, so it
+                    // reports its events under a trace of its own.
+                    event_dispatcher: match scribe_sink.dupe() {
+                        Some(scribe_sink) => EventDispatcher::new(TraceId::new(), scribe_sink),
+                        None => EventDispatcher::null(),
+                    },
+                }),
+                copy_method,
             }
         };
 
-        let (io, forkserver, _, (materializer_db, materializer_state)) =
-            futures::future::try_join4(
+        let (io, forkserver, _, (materializer_db, materializer_state), dep_files_db) =
+            futures::future::try_join5(
                 buck2_common::io::create_io_provider(
                     fb,
                     fs.dupe(),
@@ -291,12 +367,21 @@ impl DaemonState {
                     blocking_executor.dupe() as Arc<dyn BlockingExecutor>,
                     root_config,
                     &deferred_materializer_configs,
-                    fs,
+                    fs.dupe(),
                     digest_config,
                 ),
+                maybe_initialize_dep_files_sqlite_db(
+                    &disk_state_options,
+                    paths,
+                    blocking_executor.dupe() as Arc<dyn BlockingExecutor>,
+                    root_config,
+                    fs,
+                ),
             )
             .await?;
 
+        buck2_build_api::actions::impls::run::dep_files::set_dep_files_db(dep_files_db);
+
         let re_client_manager = Arc::new(ReConnectionManager::new(
             fb,
             false,
@@ -318,8 +403,26 @@ impl DaemonState {
             materializer_state,
         )?;
 
+        let dice_persistence = maybe_initialize_dice_persistence(
+            &disk_state_options,
+            paths,
+            blocking_executor.dupe() as Arc<dyn BlockingExecutor>,
+            root_config,
+            io.project_root().dupe(),
+            digest_config,
+        )
+        .await?;
+        if let Some(dice_persistence) = &dice_persistence {
+            let save_frequency =
+                parse_frequency_seconds(root_config, "dice_state_save_frequency_s", 60)?;
+            tokio::task::spawn(save_dice_persistence_periodically(
+                dice_persistence.dupe(),
+                save_frequency,
+            ));
+        }
+
         let dice = dice_constructor
-            .construct_dice(io.dupe(), digest_config, root_config)
+            .construct_dice(io.dupe(), digest_config, root_config, dice_persistence)
             .await?;
 
         // TODO(cjhopman): We want to use Expr::True here, but we need to workaround
@@ -353,26 +456,37 @@ impl DaemonState {
 
         let create_unhashed_outputs_lock = Arc::new(Mutex::new(()));
 
-        let buffer_size = root_config
-            .parse("buck2", "event_log_buffer_size")?
-            .unwrap_or(10000);
-        let retry_backoff = Duration::from_millis(
+        let worker_max_instances = root_config
+            .parse("buck2", "worker_max_instances")?
+            .unwrap_or(DEFAULT_MAX_INSTANCES);
+        // With no instances, worker actions would wait forever.
+        if worker_max_instances == 0 {
+            return Err(anyhow::anyhow!(
+                "Invalid `buck2.worker_max_instances`: must be at least 1"
+            ));
+        }
+
+        let workers = Arc::new(WorkerPool::new(
+            paths.worker_logs_dir(),
+            worker_max_instances,
             root_config
-                .parse("buck2", "event_log_retry_backoff_duration_ms")?
-                .unwrap_or(500),
-        );
-        let retry_attempts = root_config
-            .parse("buck2", "event_log_retry_attempts")?
-            .unwrap_or(5);
-        let message_batch_size = root_config.parse("buck2", "event_log_message_batch_size")?;
-        let scribe_sink = Self::init_scribe_sink(
-            fb,
-            buffer_size,
-            retry_backoff,
-            retry_attempts,
-            message_batch_size,
-        )
-        .context("failed to init scribe sink")?;
+                .parse("buck2", "worker_idle_timeout_s")?
+                .map_or(DEFAULT_IDLE_TIMEOUT, Duration::from_secs),
+        ));
+
+        let local_cache = root_config
+            .get("buck2", "local_cache_dir")
+            .map(|dir| {
+                let dir = AbsNormPathBuf::from(dir.to_owned())
+                    .context("Invalid `buck2.local_cache_dir`")?;
+                let max_bytes = root_config
+                    .parse("buck2", "local_cache_max_bytes")?
+                    .unwrap_or(local_cache::DEFAULT_MAX_BYTES);
+                LocalActionCache::open(dir.clone(), max_bytes, copy_method)
+                    .with_context(|| format!("Error opening local cache at `{}`", dir))
+            })
+            .transpose()?
+            .map(Arc::new);
 
         // Kick off an initial sync eagerly. This gets Watchamn to start watching the path we care
         // about (potentially kicking off an initial crawl).
@@ -392,11 +506,14 @@ impl DaemonState {
             blocking_executor,
             materializer,
             forkserver,
+            workers,
+            local_cache,
             scribe_sink,
             hash_all_commands,
             disk_state_options,
             start_time: std::time::Instant::now(),
             create_unhashed_outputs_lock,
+            subscription_broadcast: Arc::new(SubscriptionBroadcast::new()),
         }))
     }
 
@@ -416,8 +533,10 @@ impl DaemonState {
             MaterializationMethod::Immediate => Ok(Arc::new(ImmediateMaterializer::new(
                 fs,
                 digest_config,
+                buck_out_path,
                 re_client_manager,
                 blocking_executor,
+                deferred_materializer_configs.copy_method,
             ))),
             MaterializationMethod::Deferred | MaterializationMethod::DeferredSkipFinalArtifacts => {
                 Ok(Arc::new(DeferredMaterializer::new(
@@ -444,6 +563,7 @@ impl DaemonState {
                             EdenMaterializer::new(
                                 fs,
                                 digest_config,
+                                buck_out_path.clone(),
                                 re_client_manager.dupe(),
                                 blocking_executor,
                                 EdenBuckOut::new(
@@ -453,6 +573,7 @@ impl DaemonState {
                                     re_client_manager,
                                 )
                                 .context("Failed to create EdenFS-based buck-out")?,
+                                deferred_materializer_configs.copy_method,
                             )
                             .context("Failed to create Eden materializer")?,
                         ))
@@ -503,6 +624,7 @@ impl DaemonState {
         facebook_only();
         let (events, sink) = buck2_events::create_source_sink_pair();
         let data = self.data()?;
+        let sink = BroadcastingSink::new(sink, data.subscription_broadcast.dupe());
         let dispatcher = if let Some(scribe_sink) = data.scribe_sink.dupe() {
             EventDispatcher::new(trace_id, TeeSink::new(scribe_sink, sink))
         } else {
@@ -536,6 +658,11 @@ impl DaemonState {
                 "sqlite-materializer-state:{}",
                 data.disk_state_options.sqlite_materializer_state
             ),
+            format!(
+                "sqlite-dep-files-state:{}",
+                data.disk_state_options.sqlite_dep_files_state
+            ),
+            format!("dice-state:{}", data.disk_state_options.dice_state),
         ];
 
         dispatcher.instant_event(buck2_data::TagEvent { tags });
@@ -554,10 +681,13 @@ impl DaemonState {
             file_watcher: data.file_watcher.dupe(),
             events: dispatcher,
             forkserver: data.forkserver.dupe(),
+            workers: data.workers.dupe(),
+            local_cache: data.local_cache.dupe(),
             hash_all_commands: data.hash_all_commands,
             _drop_guard: drop_guard,
             daemon_start_time: data.start_time,
             create_unhashed_outputs_lock: data.create_unhashed_outputs_lock.dupe(),
+            subscription_broadcast: data.subscription_broadcast.dupe(),
         })
     }
 
@@ -565,3 +695,20 @@ impl DaemonState {
         Ok(self.data.dupe()?)
     }
 }
+
+/// Parse `buck2.<property>`, a frequency in seconds for something that runs periodically. These
+/// are used as `tokio::time::interval` periods, which must not be zero.
+fn parse_frequency_seconds(
+    root_config: &LegacyBuckConfig,
+    property: &str,
+    default: u64,
+) -> anyhow::Result<Duration> {
+    let seconds = root_config.parse("buck2", property)?.unwrap_or(default);
+    if seconds == 0 {
+        return Err(anyhow::anyhow!(
+            "Invalid `buck2.{}`: must be greater than 0",
+            property
+        ));
+    }
+    Ok(Duration::from_secs(seconds))
+}
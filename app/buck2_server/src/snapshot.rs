@@ -119,6 +119,7 @@ impl SnapshotCollector {
 
             snapshot.re_download_bytes = stats.downloaded;
             snapshot.re_upload_bytes = stats.uploaded;
+            snapshot.re_retries = stats.retries;
             snapshot.re_uploads_started = stats.uploads.started;
             snapshot.re_uploads_finished_successfully = stats.uploads.finished_successfully;
             snapshot.re_uploads_finished_with_error = stats.uploads.finished_with_error;
@@ -161,6 +162,8 @@ impl SnapshotCollector {
         snapshot.dice_key_count = metrics.key_count as u64;
         snapshot.dice_currently_running_key_count = metrics.currently_running_key_count as u64;
         snapshot.dice_active_transaction_count = metrics.active_transaction_count;
+        snapshot.dice_evicted_key_count = metrics.evicted_key_count;
+        snapshot.dice_recomputed_key_count = metrics.recomputed_key_count;
     }
 
     fn add_materializer_metrics(&self, snapshot: &mut buck2_data::Snapshot) {
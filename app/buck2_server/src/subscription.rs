@@ -7,19 +7,224 @@
  * of this source tree.
  */
 
+//! Implementation of `buck2 subscribe`, which lets clients (typically IDEs) be notified about
+//! what the daemon is doing, and request that paths be materialized eagerly.
+
+use std::sync::Arc;
+
+use anyhow::Context as _;
+use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
+use buck2_data::buck_event;
+use buck2_data::command_end;
+use buck2_data::command_start;
+use buck2_data::instant_event;
+use buck2_data::span_end_event;
+use buck2_data::span_start_event;
 use buck2_events::dispatch::span_async;
+use buck2_events::BuckEvent;
+use buck2_events::ControlEvent;
+use buck2_events::EventSink;
+use buck2_events::EventSinkStats;
+use buck2_execute::materialize::materializer::DeferredMaterializerSubscription;
 use buck2_server_ctx::command_end::command_end;
 use buck2_server_ctx::ctx::ServerCommandContextTrait;
 use buck2_server_ctx::partial_result_dispatcher::PartialResultDispatcher;
+use buck2_subscription_proto::subscription_request::Request;
+use buck2_subscription_proto::subscription_response::Response;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
 
 use crate::streaming_request_handler::StreamingRequestHandler;
 
+/// How many notifications can be buffered for a subscriber before it starts missing some.
+const BROADCAST_CAPACITY: usize = 1000;
+
+/// Fans out notifications about what the daemon is doing (commands starting and finishing,
+/// targets being built, file changes) to all active subscriptions.
+pub(crate) struct SubscriptionBroadcast {
+    sender: broadcast::Sender<Arc<Response>>,
+}
+
+impl SubscriptionBroadcast {
+    pub(crate) fn new() -> Self {
+        let (sender, _) = broadcast::channel(BROADCAST_CAPACITY);
+        Self { sender }
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Arc<Response>> {
+        self.sender.subscribe()
+    }
+
+    pub(crate) fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    fn notify(&self, event: &BuckEvent) {
+        // Don't bother looking at events if nobody is listening.
+        if !self.has_subscribers() {
+            return;
+        }
+
+        if let Some(notification) = notification_for_event(event) {
+            // This only fails if all receivers went away since we checked.
+            let _ignored = self.sender.send(Arc::new(notification));
+        }
+    }
+}
+
+/// An `EventSink` that forwards events to another sink, and notifies subscriptions about them.
+pub(crate) struct BroadcastingSink<S> {
+    sink: S,
+    broadcast: Arc<SubscriptionBroadcast>,
+}
+
+impl<S> BroadcastingSink<S> {
+    pub(crate) fn new(sink: S, broadcast: Arc<SubscriptionBroadcast>) -> Self {
+        Self { sink, broadcast }
+    }
+}
+
+impl<S: EventSink> EventSink for BroadcastingSink<S> {
+    fn send(&self, event: BuckEvent) {
+        self.broadcast.notify(&event);
+        self.sink.send(event);
+    }
+
+    fn send_control(&self, control_event: ControlEvent) {
+        self.sink.send_control(control_event);
+    }
+
+    fn stats(&self) -> Option<EventSinkStats> {
+        self.sink.stats()
+    }
+}
+
+fn notification_for_event(event: &BuckEvent) -> Option<Response> {
+    let trace_id = event.event().trace_id.clone();
+
+    match event.data() {
+        buck_event::Data::SpanStart(start) => match start.data.as_ref()? {
+            span_start_event::Data::Command(command) => {
+                Some(Response::from(buck2_subscription_proto::CommandStarted {
+                    trace_id,
+                    command: command_start_name(command.data.as_ref()?).to_owned(),
+                }))
+            }
+            _ => None,
+        },
+        buck_event::Data::SpanEnd(end) => match end.data.as_ref()? {
+            span_end_event::Data::Command(command) => {
+                Some(Response::from(buck2_subscription_proto::CommandFinished {
+                    trace_id,
+                    command: command_end_name(command.data.as_ref()?).to_owned(),
+                    success: command.is_success,
+                }))
+            }
+            span_end_event::Data::FileWatcher(file_watcher) => {
+                let stats = file_watcher.stats.as_ref()?;
+                let paths = stats
+                    .events
+                    .iter()
+                    .map(|e| e.path.clone())
+                    .collect::<Vec<_>>();
+                let incomplete = stats.fresh_instance || stats.incomplete_events_reason.is_some();
+                if paths.is_empty() && !incomplete {
+                    return None;
+                }
+                Some(Response::from(buck2_subscription_proto::FilesChanged {
+                    trace_id,
+                    paths,
+                    incomplete,
+                }))
+            }
+            _ => None,
+        },
+        buck_event::Data::Instant(instant) => match instant.data.as_ref()? {
+            instant_event::Data::TargetBuilt(target_built) => {
+                Some(Response::from(buck2_subscription_proto::TargetBuilt {
+                    trace_id,
+                    target: target_built.target.clone(),
+                    success: target_built.success,
+                    outputs: target_built.outputs.clone(),
+                }))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn command_start_name(data: &command_start::Data) -> &'static str {
+    match data {
+        command_start::Data::Build(..) => "build",
+        command_start::Data::Targets(..) => "targets",
+        command_start::Data::Query(..) => "query",
+        command_start::Data::Cquery(..) => "cquery",
+        command_start::Data::Test(..) => "test",
+        command_start::Data::Audit(..) => "audit",
+        command_start::Data::Docs(..) => "docs",
+        command_start::Data::Clean(..) => "clean",
+        command_start::Data::Aquery(..) => "aquery",
+        command_start::Data::Install(..) => "install",
+        command_start::Data::Materialize(..) => "materialize",
+        command_start::Data::Profile(..) => "profile",
+        command_start::Data::Bxl(..) => "bxl",
+        command_start::Data::Lsp(..) => "lsp",
+        command_start::Data::FileStatus(..) => "file-status",
+        command_start::Data::Starlark(..) => "starlark",
+        command_start::Data::Subscribe(..) => "subscribe",
+    }
+}
+
+fn command_end_name(data: &command_end::Data) -> &'static str {
+    match data {
+        command_end::Data::Build(..) => "build",
+        command_end::Data::Targets(..) => "targets",
+        command_end::Data::Query(..) => "query",
+        command_end::Data::Cquery(..) => "cquery",
+        command_end::Data::Test(..) => "test",
+        command_end::Data::Audit(..) => "audit",
+        command_end::Data::Docs(..) => "docs",
+        command_end::Data::Clean(..) => "clean",
+        command_end::Data::Aquery(..) => "aquery",
+        command_end::Data::Install(..) => "install",
+        command_end::Data::Materialize(..) => "materialize",
+        command_end::Data::Profile(..) => "profile",
+        command_end::Data::Bxl(..) => "bxl",
+        command_end::Data::Lsp(..) => "lsp",
+        command_end::Data::FileStatus(..) => "file-status",
+        command_end::Data::Starlark(..) => "starlark",
+        command_end::Data::Subscribe(..) => "subscribe",
+    }
+}
+
+/// The notifications a client asked for. Materializations are not listed here since the client
+/// asks for specific paths instead.
+#[derive(Default)]
+struct Subscribed {
+    commands: bool,
+    built_targets: bool,
+    file_changes: bool,
+}
+
+impl Subscribed {
+    fn wants(&self, notification: &Response) -> bool {
+        match notification {
+            Response::Materialized(..) => true,
+            Response::CommandStarted(..) | Response::CommandFinished(..) => self.commands,
+            Response::TargetBuilt(..) => self.built_targets,
+            Response::FilesChanged(..) => self.file_changes,
+        }
+    }
+}
+
 pub(crate) async fn run_subscription_server_command(
     ctx: Box<dyn ServerCommandContextTrait>,
-    _partial_result_dispatcher: PartialResultDispatcher<
+    broadcast: Arc<SubscriptionBroadcast>,
+    partial_result_dispatcher: PartialResultDispatcher<
         buck2_cli_proto::SubscriptionResponseWrapper,
     >,
-    _req: StreamingRequestHandler<buck2_cli_proto::SubscriptionRequestWrapper>,
+    req: StreamingRequestHandler<buck2_cli_proto::SubscriptionRequestWrapper>,
 ) -> anyhow::Result<buck2_cli_proto::SubscriptionCommandResponse> {
     let metadata = ctx.request_metadata().await?;
     let start_event = buck2_data::CommandStart {
@@ -27,10 +232,181 @@ pub(crate) async fn run_subscription_server_command(
         data: Some(buck2_data::SubscriptionCommandStart {}.into()),
     };
     span_async(start_event, async move {
-        let result = Ok(buck2_cli_proto::SubscriptionCommandResponse {});
-        // TODO: Fill in the implementation!
+        let result = run_subscription_server_command_inner(
+            ctx.as_ref(),
+            &broadcast,
+            partial_result_dispatcher,
+            req,
+        )
+        .await;
         let end_event = command_end(metadata, &result, buck2_data::SubscriptionCommandEnd {});
         (result, end_event)
     })
     .await
 }
+
+async fn run_subscription_server_command_inner(
+    ctx: &dyn ServerCommandContextTrait,
+    broadcast: &SubscriptionBroadcast,
+    mut partial_result_dispatcher: PartialResultDispatcher<
+        buck2_cli_proto::SubscriptionResponseWrapper,
+    >,
+    mut req: StreamingRequestHandler<buck2_cli_proto::SubscriptionRequestWrapper>,
+) -> anyhow::Result<buck2_cli_proto::SubscriptionCommandResponse> {
+    let materializer = ctx.materializer();
+    let mut materializer_subscription = match materializer.as_deferred_materializer_extension() {
+        Some(extension) => Some(extension.create_subscription().await?),
+        None => None,
+    };
+
+    let mut notifications = broadcast.subscribe();
+    let mut subscribed = Subscribed::default();
+
+    loop {
+        let response = tokio::select! {
+            message = req.message() => {
+                let request = match message {
+                    Ok(message) => message.request.and_then(|r| r.request),
+                    // The client went away.
+                    Err(..) => break,
+                };
+
+                match request.context("Empty subscription request")? {
+                    Request::Disconnect(..) => break,
+                    Request::SubscribeToPaths(subscribe) => {
+                        let paths = subscribe
+                            .paths
+                            .into_iter()
+                            .map(ProjectRelativePathBuf::try_from)
+                            .collect::<anyhow::Result<Vec<_>>>()?;
+                        materializer_subscription
+                            .as_mut()
+                            .context("Subscribing to paths requires the deferred materializer")?
+                            .subscribe_to_paths(paths);
+                    }
+                    Request::SubscribeToCommands(..) => subscribed.commands = true,
+                    Request::SubscribeToBuiltTargets(..) => subscribed.built_targets = true,
+                    Request::SubscribeToFileChanges(..) => subscribed.file_changes = true,
+                }
+
+                continue;
+            }
+            path = next_materialization(&mut materializer_subscription) => {
+                match path {
+                    Some(path) => Response::from(buck2_subscription_proto::Materialized {
+                        path: path.to_string(),
+                    }),
+                    None => {
+                        // The materializer is shutting down.
+                        materializer_subscription = None;
+                        continue;
+                    }
+                }
+            }
+            notification = notifications.recv() => {
+                match notification {
+                    Ok(notification) => {
+                        if !subscribed.wants(&notification) {
+                            continue;
+                        }
+                        (*notification).clone()
+                    }
+                    Err(RecvError::Lagged(count)) => {
+                        tracing::warn!("Subscription fell behind, dropped {} notifications", count);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        };
+
+        partial_result_dispatcher.emit(buck2_cli_proto::SubscriptionResponseWrapper {
+            response: Some(buck2_subscription_proto::SubscriptionResponse {
+                response: Some(response),
+            }),
+        });
+    }
+
+    Ok(buck2_cli_proto::SubscriptionCommandResponse {})
+}
+
+async fn next_materialization(
+    subscription: &mut Option<Box<dyn DeferredMaterializerSubscription>>,
+) -> Option<ProjectRelativePathBuf> {
+    match subscription {
+        Some(subscription) => subscription.next_materialization().await,
+        None => futures::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use buck2_events::trace::TraceId;
+
+    use super::*;
+
+    fn event(data: buck_event::Data) -> BuckEvent {
+        BuckEvent::new(SystemTime::now(), TraceId::new(), None, None, data)
+    }
+
+    fn file_watcher_end(stats: buck2_data::FileWatcherStats) -> BuckEvent {
+        event(buck_event::Data::SpanEnd(buck2_data::SpanEndEvent {
+            data: Some(buck2_data::FileWatcherEnd { stats: Some(stats) }.into()),
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn test_notification_for_command() {
+        let start = event(buck_event::Data::SpanStart(buck2_data::SpanStartEvent {
+            data: Some(
+                buck2_data::CommandStart {
+                    metadata: Default::default(),
+                    data: Some(buck2_data::BuildCommandStart {}.into()),
+                }
+                .into(),
+            ),
+        }));
+
+        match notification_for_event(&start) {
+            Some(Response::CommandStarted(started)) => {
+                assert_eq!(started.command, "build");
+                assert_eq!(started.trace_id, start.event().trace_id);
+            }
+            n => panic!("Unexpected notification: {:?}", n),
+        }
+    }
+
+    #[test]
+    fn test_notification_for_file_changes() {
+        // Syncing with the file watcher without any changes is not worth notifying about.
+        assert_eq!(
+            notification_for_event(&file_watcher_end(Default::default())),
+            None
+        );
+
+        match notification_for_event(&file_watcher_end(buck2_data::FileWatcherStats {
+            events: vec![buck2_data::FileWatcherEvent {
+                path: "foo/bar.txt".to_owned(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        })) {
+            Some(Response::FilesChanged(changed)) => {
+                assert_eq!(changed.paths, vec!["foo/bar.txt".to_owned()]);
+                assert!(!changed.incomplete);
+            }
+            n => panic!("Unexpected notification: {:?}", n),
+        }
+
+        match notification_for_event(&file_watcher_end(buck2_data::FileWatcherStats {
+            fresh_instance: true,
+            ..Default::default()
+        })) {
+            Some(Response::FilesChanged(changed)) => assert!(changed.incomplete),
+            n => panic!("Unexpected notification: {:?}", n),
+        }
+    }
+}
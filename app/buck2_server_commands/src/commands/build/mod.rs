@@ -14,6 +14,7 @@ use std::sync::Arc;
 
 use anyhow::Context as _;
 use async_trait::async_trait;
+use buck2_build_api::actions::impls::run::determinism::HasDeterminismReport;
 use buck2_build_api::build;
 use buck2_build_api::build::BuildTargetResult;
 use buck2_build_api::build::ConvertMaterializationContext;
@@ -40,6 +41,7 @@ use buck2_core::pattern::ProvidersPattern;
 use buck2_core::provider::label::ConfiguredProvidersLabel;
 use buck2_core::provider::label::ProvidersLabel;
 use buck2_core::target::label::TargetLabel;
+use buck2_events::dispatch::console_message;
 use buck2_events::dispatch::span_async;
 use buck2_execute::materialize::materializer::HasMaterializer;
 use buck2_interpreter_for_build::interpreter::calculation::InterpreterCalculation;
@@ -64,6 +66,7 @@ use crate::commands::build::results::build_report::BuildReportCollector;
 use crate::commands::build::results::providers::ProvidersPrinter;
 use crate::commands::build::results::result_report::ResultReporter;
 use crate::commands::build::results::result_report::ResultReporterOptions;
+use crate::commands::build::results::target_built::TargetBuiltReporter;
 use crate::commands::build::results::BuildOwner;
 use crate::commands::build::results::BuildResultCollector;
 use crate::commands::build::unhashed_outputs::create_unhashed_outputs;
@@ -206,8 +209,18 @@ async fn build(
         None
     };
 
+    // Only subscriptions consume these events, so don't send them to everyone else.
+    let mut target_built_reporter = if server_ctx.has_subscribers() {
+        Some(TargetBuiltReporter::new(&artifact_fs))
+    } else {
+        None
+    };
+
     let mut result_collectors = vec![
         Some(&mut result_collector as &mut dyn BuildResultCollector),
+        target_built_reporter
+            .as_mut()
+            .map(|v| v as &mut dyn BuildResultCollector),
         build_report_collector
             .as_mut()
             .map(|v| v as &mut dyn BuildResultCollector),
@@ -278,6 +291,26 @@ async fn build(
         };
     }
 
+    if let Some(determinism_report) = ctx.per_transaction_data().get_determinism_report() {
+        // Actions whose results DICE already has are not executed again, so we can't check them.
+        console_message(format!(
+            "Checked the determinism of {} command(s) that ran during this build. \
+            Commands whose results were already computed by this daemon were not checked, \
+            run `buck2 kill` before building to check all of them.",
+            determinism_report.checked()
+        ));
+
+        if !build_opts.determinism_report_filename.is_empty() {
+            let file = fs_util::create_file(
+                fs.resolve(cwd)
+                    .as_path()
+                    .join(&build_opts.determinism_report_filename),
+            )
+            .context("Error writing determinism report")?;
+            determinism_report.write_json(BufWriter::new(file))?;
+        }
+    }
+
     // TODO(nmj): The BuildResult / BuildResponse will eventually return all of the
     //            data back to the CLI client, and all build report generation will happen there.
     //            For now, we're going to be a little hacky to remove some stdout printing that
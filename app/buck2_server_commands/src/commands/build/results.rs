@@ -448,3 +448,60 @@ pub mod providers {
         }
     }
 }
+
+pub mod target_built {
+    use buck2_build_api::build::BuildProviderType;
+    use buck2_events::dispatch::instant_event;
+    use buck2_execute::artifact::fs::ArtifactFs;
+
+    use crate::commands::build::results::BuildOwner;
+    use crate::commands::build::results::BuildResultCollector;
+    use crate::commands::build::BuildTargetResult;
+
+    /// Emits a `TargetBuilt` event for every target, so that subscribers (e.g. IDEs using
+    /// `buck2 subscribe`) know when targets they care about are built. Only used when there are
+    /// subscribers.
+    pub(crate) struct TargetBuiltReporter<'a> {
+        artifact_fs: &'a ArtifactFs,
+    }
+
+    impl<'a> TargetBuiltReporter<'a> {
+        pub(crate) fn new(artifact_fs: &'a ArtifactFs) -> Self {
+            Self { artifact_fs }
+        }
+    }
+
+    impl<'a> BuildResultCollector for TargetBuiltReporter<'a> {
+        fn collect_result(&mut self, label: &BuildOwner, result: &BuildTargetResult) {
+            let target = match label {
+                BuildOwner::Target(t) => t.to_string(),
+                BuildOwner::_Bxl(_) => return,
+            };
+
+            let mut success = true;
+            let mut outputs = Vec::new();
+            for output in &result.outputs {
+                match output {
+                    Ok(output) => {
+                        if !matches!(output.provider_type, BuildProviderType::Default) {
+                            continue;
+                        }
+                        for (artifact, _value) in output.values.iter() {
+                            match self.artifact_fs.resolve(artifact.get_path()) {
+                                Ok(path) => outputs.push(path.to_string()),
+                                Err(_) => success = false,
+                            }
+                        }
+                    }
+                    Err(_) => success = false,
+                }
+            }
+
+            instant_event(buck2_data::TargetBuilt {
+                target,
+                success,
+                outputs,
+            });
+        }
+    }
+}
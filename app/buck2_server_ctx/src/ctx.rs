@@ -58,6 +58,10 @@ pub trait ServerCommandContextTrait: Send + Sync + 'static {
     ) -> anyhow::Result<HashMap<String, String>>;
 
     fn log_target_pattern(&self, providers_patterns: &[ParsedPattern<ProvidersPattern>]);
+
+    /// Whether any `buck2 subscribe` client is listening, so that events only they consume can be
+    /// skipped otherwise.
+    fn has_subscribers(&self) -> bool;
 }
 
 pub struct PrivateStruct(());
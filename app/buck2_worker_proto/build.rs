@@ -0,0 +1,31 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::io;
+
+fn main() -> io::Result<()> {
+    let proto_files = &["worker_protocol.proto"];
+
+    // The JSON flavour of the protocol uses the proto3 JSON mapping, so field names are camelCase
+    // and default values may be omitted.
+    buck2_protoc_dev::configure()
+        .setup_protoc()
+        .type_attribute(
+            "buck.worker.WorkRequest",
+            "#[derive(::serde::Serialize, ::serde::Deserialize)] #[serde(rename_all = \"camelCase\", default)]",
+        )
+        .type_attribute(
+            "buck.worker.WorkResponse",
+            "#[derive(::serde::Serialize, ::serde::Deserialize)] #[serde(rename_all = \"camelCase\", default)]",
+        )
+        // We never send inputs (we don't digest them for workers), and `bytes` would need a
+        // base64 mapping in JSON.
+        .field_attribute("buck.worker.WorkRequest.inputs", "#[serde(skip)]")
+        .compile(proto_files, &["."])
+}
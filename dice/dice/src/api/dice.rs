@@ -198,6 +198,7 @@ use crate::api::cycles::DetectCycles;
 use crate::api::transaction::DiceTransactionUpdater;
 use crate::api::user_data::UserComputationData;
 use crate::legacy::metrics::Metrics;
+use crate::persistence::DicePersistence;
 use crate::DiceDataBuilderImpl;
 use crate::DiceImplementation;
 
@@ -270,6 +271,18 @@ impl DiceDataBuilder {
         self.0.set(val);
     }
 
+    /// Persist the values of [`PersistentKey`](crate::PersistentKey)s in this snapshot, and reuse
+    /// the ones it already contains.
+    pub fn set_persistence(&mut self, persistence: Arc<DicePersistence>) {
+        self.0.set_persistence(persistence);
+    }
+
+    /// Evict the least recently used computed values when the memory they use exceeds
+    /// `max_bytes`. Evicted values are recomputed if they are requested again.
+    pub fn set_memory_budget(&mut self, max_bytes: usize) {
+        self.0.set_memory_budget(max_bytes);
+    }
+
     pub fn build(self, detect_cycles: DetectCycles) -> Arc<Dice> {
         self.0.build(detect_cycles)
     }
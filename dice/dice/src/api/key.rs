@@ -16,6 +16,7 @@ use async_trait::async_trait;
 use dupe::Dupe;
 
 use crate::api::computations::DiceComputations;
+use crate::api::persistence::KeyPersistence;
 use crate::introspection::graph::short_type_name;
 use crate::legacy::incremental::StorageType;
 
@@ -60,4 +61,10 @@ pub trait Key: Allocative + Debug + Display + Clone + Eq + Hash + Send + Sync +
     fn storage_type() -> StorageType {
         StorageType::LastN(1)
     }
+
+    /// Keys that return `Some` here have their values persisted across restarts when DICE is
+    /// built with persistence enabled. See [`crate::PersistentKey`].
+    fn persistence() -> Option<KeyPersistence<Self>> {
+        None
+    }
 }
@@ -18,6 +18,7 @@ pub mod events;
 pub mod injected;
 pub mod key;
 pub mod opaque;
+pub mod persistence;
 pub mod projection;
 pub mod transaction;
 pub mod user_data;
@@ -0,0 +1,157 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Persisting values across restarts of the process holding the DICE graph.
+
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+use crate::api::computations::DiceComputations;
+use crate::api::key::Key;
+use crate::persistence::DicePersistence;
+
+/// Extension of [`Key`] for keys whose values can be persisted on disk, and reused after a
+/// restart instead of being recomputed. Keys opt in by implementing this trait and returning
+/// [`KeyPersistence::new`] from [`Key::persistence`].
+///
+/// A persisted value is only reused if the key's [`inputs_fingerprint`](Self::inputs_fingerprint)
+/// is unchanged. Keys are only fingerprinted if a value was persisted for them, or when a value
+/// computed for them is about to be persisted. The fingerprint is computed in the context of the key, so the dependencies it
+/// records are what invalidates the value afterwards.
+#[async_trait]
+pub trait PersistentKey: Key {
+    /// Serialize this key. This must identify the key among all keys of the same type.
+    fn serialize_key(&self) -> anyhow::Result<Vec<u8>>;
+
+    /// Serialize a value computed for this key, or return `None` if it should not be persisted
+    /// (e.g. because it is an error).
+    fn serialize_value(&self, value: &Self::Value) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Deserialize a value returned by `serialize_value`.
+    fn deserialize_value(
+        &self,
+        ctx: &DiceComputations,
+        value: &[u8],
+    ) -> anyhow::Result<Self::Value>;
+
+    /// A fingerprint of the inputs the value of this key is computed from, e.g. the digests or
+    /// the metadata of the files it reads. This should be much cheaper than computing the value.
+    ///
+    /// When fingerprinting a value that was just computed, `computed_since` is the time its
+    /// computation started. This must fail if any input may have changed since then, since the
+    /// value could have been computed from older inputs than the ones that were fingerprinted.
+    async fn inputs_fingerprint(
+        &self,
+        ctx: &DiceComputations,
+        computed_since: Option<SystemTime>,
+    ) -> anyhow::Result<Vec<u8>>;
+}
+
+/// How to compute a key when persistence is enabled, see [`Key::persistence`].
+pub struct KeyPersistence<K: Key> {
+    compute:
+        for<'a> fn(&'a K, &'a DiceComputations, &'a DicePersistence) -> BoxFuture<'a, K::Value>,
+}
+
+impl<K: PersistentKey> KeyPersistence<K> {
+    pub fn new() -> Self {
+        Self {
+            compute: compute_persistent_boxed::<K>,
+        }
+    }
+}
+
+impl<K: PersistentKey> Default for KeyPersistence<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Key> KeyPersistence<K> {
+    pub(crate) async fn compute(
+        &self,
+        key: &K,
+        ctx: &DiceComputations,
+        persistence: &DicePersistence,
+    ) -> K::Value {
+        (self.compute)(key, ctx, persistence).await
+    }
+}
+
+fn compute_persistent_boxed<'a, K: PersistentKey>(
+    key: &'a K,
+    ctx: &'a DiceComputations,
+    persistence: &'a DicePersistence,
+) -> BoxFuture<'a, K::Value> {
+    compute_persistent(key, ctx, persistence).boxed()
+}
+
+async fn compute_persistent<K: PersistentKey>(
+    key: &K,
+    ctx: &DiceComputations,
+    persistence: &DicePersistence,
+) -> K::Value {
+    let serialized_key = match key.serialize_key() {
+        Ok(serialized_key) => serialized_key,
+        Err(e) => {
+            debug!("Not persisting `{}`: {:#}", key, e);
+            return key.compute(ctx).await;
+        }
+    };
+
+    if let Some((fingerprint, value)) = persistence.get(K::key_type_name(), &serialized_key).await {
+        match key.inputs_fingerprint(ctx, None).await {
+            Ok(current) if current == fingerprint => match key.deserialize_value(ctx, &value) {
+                Ok(value) => {
+                    persistence.record_lookup(true);
+                    return value;
+                }
+                Err(e) => debug!("Invalid persisted value for `{}`: {:#}", key, e),
+            },
+            Ok(_) => {}
+            Err(e) => debug!("Not reusing persisted value for `{}`: {:#}", key, e),
+        }
+    }
+    persistence.record_lookup(false);
+
+    let computed_since = SystemTime::now();
+    let value = key.compute(ctx).await;
+
+    if !K::validity(&value) {
+        return value;
+    }
+
+    let serialized_value = match key.serialize_value(&value) {
+        Ok(Some(serialized_value)) => serialized_value,
+        Ok(None) => return value,
+        Err(e) => {
+            debug!("Not persisting `{}`: {:#}", key, e);
+            return value;
+        }
+    };
+
+    match key.inputs_fingerprint(ctx, Some(computed_since)).await {
+        Ok(fingerprint) => {
+            persistence
+                .insert(
+                    K::key_type_name(),
+                    serialized_key,
+                    fingerprint,
+                    serialized_value,
+                )
+                .await
+        }
+        Err(e) => debug!("Not persisting `{}`: {:#}", key, e),
+    }
+
+    value
+}
@@ -0,0 +1,253 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Keeps the values stored in DICE within a memory budget, by evicting the least recently used
+//! ones. Evicted values are recomputed if they are requested again.
+//!
+//! Only computed values that no other node in the graph depends on can be evicted: invalidation
+//! is propagated through reverse dependencies, so evicting a node that is depended upon would
+//! prevent its dependents from being invalidated. Evicting a node releases its reverse
+//! dependencies on its own dependencies, which can then be evicted in turn.
+//!
+//! The memory used by a node is estimated with `allocative` when it's created, and only accounts
+//! for data the node owns uniquely, since that is what evicting it frees.
+
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use dupe::Dupe;
+use parking_lot::Mutex;
+use parking_lot::RwLock;
+
+use crate::legacy::incremental::ErasedEngine;
+use crate::legacy::map::DiceMap;
+
+/// Tracks when the nodes of a DICE instance were accessed, and how much memory they use. Nodes
+/// register themselves here when they are created and unregister when they are dropped, so we
+/// know whether the budget is met without scanning the graph.
+#[derive(Debug, Default)]
+pub(crate) struct EvictionTracker {
+    /// Incremented on every eviction pass. Nodes record the epoch at which they were last
+    /// accessed, which orders them for eviction.
+    access_epoch: AtomicU64,
+    /// Estimated memory used by the nodes that are alive.
+    used_bytes: AtomicUsize,
+}
+
+impl EvictionTracker {
+    pub(crate) fn access_epoch(&self) -> u64 {
+        self.access_epoch.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn node_created(&self, size: usize) {
+        self.used_bytes.fetch_add(size, Ordering::Relaxed);
+    }
+
+    pub(crate) fn node_dropped(&self, size: usize) {
+        self.used_bytes.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    fn used_bytes(&self) -> usize {
+        self.used_bytes.load(Ordering::Relaxed)
+    }
+}
+
+/// A key that can be evicted from an engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct EvictionCandidate {
+    /// The epoch at which the key was last accessed.
+    pub(crate) last_accessed: u64,
+    /// Estimated memory freed by evicting the key.
+    pub(crate) size: usize,
+}
+
+/// The maximum memory the values stored in DICE should use.
+#[derive(Debug)]
+pub(crate) struct MemoryBudget {
+    max_bytes: usize,
+    /// Whether to evict values whenever DICE becomes idle. Tests turn this off to control when
+    /// eviction happens.
+    evict_when_idle: bool,
+    tracker: Arc<EvictionTracker>,
+    /// Held while an eviction pass is running.
+    running: Mutex<()>,
+}
+
+impl MemoryBudget {
+    pub(crate) fn new(max_bytes: usize, evict_when_idle: bool) -> Arc<Self> {
+        Arc::new(Self {
+            max_bytes,
+            evict_when_idle,
+            tracker: Arc::new(EvictionTracker::default()),
+            running: Mutex::new(()),
+        })
+    }
+
+    pub(crate) fn tracker(&self) -> &Arc<EvictionTracker> {
+        &self.tracker
+    }
+
+    /// Evict values in the background, unless an eviction pass is already running. This is
+    /// called whenever DICE becomes idle.
+    pub(crate) fn enforce_in_background(self: &Arc<Self>, map: Arc<RwLock<DiceMap>>) {
+        if !self.evict_when_idle {
+            return;
+        }
+
+        let this = self.dupe();
+        let run = move || {
+            if let Some(_guard) = this.running.try_lock() {
+                this.evict(&engines(&map));
+            }
+        };
+
+        // This is called from the version tracker's callback, which holds its lock: evicting
+        // there would block every transaction until it's done.
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn_blocking(run);
+            }
+            Err(_) => {
+                thread::spawn(run);
+            }
+        }
+    }
+
+    /// Evict values until the budget is met, or nothing else can be evicted. If an eviction pass
+    /// is already running, this waits for it to finish first.
+    #[cfg(test)]
+    pub(crate) fn enforce(&self, map: &RwLock<DiceMap>) {
+        let _guard = self.running.lock();
+        self.evict(&engines(map));
+    }
+
+    fn evict(&self, engines: &[Arc<dyn ErasedEngine + Send + Sync + 'static>]) {
+        if self.tracker.used_bytes() <= self.max_bytes {
+            return;
+        }
+
+        let start = Instant::now();
+        // Anything accessed from now on is more recent than what we are about to evict.
+        self.tracker.access_epoch.fetch_add(1, Ordering::Relaxed);
+
+        let mut evicted_key_count = 0;
+        loop {
+            let to_free = self.tracker.used_bytes().saturating_sub(self.max_bytes);
+            if to_free == 0 {
+                break;
+            }
+
+            let mut candidates = Vec::new();
+            for engine in engines {
+                engine.collect_eviction_candidates(&mut candidates);
+            }
+
+            let evict_accessed_until = match select_for_eviction(candidates, to_free) {
+                Some(epoch) => epoch,
+                None => break,
+            };
+
+            let evicted = engines
+                .iter()
+                .map(|engine| engine.evict(evict_accessed_until))
+                .sum::<usize>();
+            if evicted == 0 {
+                break;
+            }
+            evicted_key_count += evicted;
+        }
+
+        debug!(
+            "DICE memory budget: {} bytes used out of {}, evicted {} keys in {:?}",
+            self.tracker.used_bytes(),
+            self.max_bytes,
+            evicted_key_count,
+            start.elapsed()
+        );
+    }
+}
+
+/// The engines to evict values from. We don't hold the lock on the map while evicting, since
+/// computations need it to add engines.
+fn engines(map: &RwLock<DiceMap>) -> Vec<Arc<dyn ErasedEngine + Send + Sync + 'static>> {
+    map.read().engines().to_vec()
+}
+
+/// Select which candidates to evict to free at least `to_free` bytes, least recently used first.
+/// Returns the epoch up to which accessed candidates should be evicted, or `None` if there is
+/// nothing to evict.
+fn select_for_eviction(mut candidates: Vec<EvictionCandidate>, to_free: usize) -> Option<u64> {
+    if to_free == 0 {
+        return None;
+    }
+
+    candidates.sort_by_key(|c| c.last_accessed);
+
+    let mut freed = 0;
+    let mut last_accessed = None;
+    for candidate in candidates {
+        if freed >= to_free {
+            break;
+        }
+        freed += candidate.size;
+        last_accessed = Some(candidate.last_accessed);
+    }
+    last_accessed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(last_accessed: u64, size: usize) -> EvictionCandidate {
+        EvictionCandidate {
+            last_accessed,
+            size,
+        }
+    }
+
+    #[test]
+    fn test_select_for_eviction() {
+        let candidates = vec![candidate(3, 10), candidate(1, 20), candidate(2, 30)];
+
+        assert_eq!(select_for_eviction(candidates.clone(), 0), None);
+        assert_eq!(select_for_eviction(Vec::new(), 100), None);
+
+        // Least recently used first.
+        assert_eq!(select_for_eviction(candidates.clone(), 20), Some(1));
+        assert_eq!(select_for_eviction(candidates.clone(), 21), Some(2));
+
+        // Everything is evicted if that's not enough.
+        assert_eq!(select_for_eviction(candidates, 100), Some(3));
+    }
+
+    #[test]
+    fn test_eviction_pass_only_when_over_budget() {
+        let budget = MemoryBudget::new(100, false);
+        let other_budget = MemoryBudget::new(0, false);
+
+        budget.tracker().node_created(100);
+        budget.evict(&[]);
+        assert_eq!(budget.tracker().access_epoch(), 0);
+
+        budget.tracker().node_created(1);
+        budget.evict(&[]);
+        assert_eq!(budget.tracker().access_epoch(), 1);
+        // Access epochs are tracked separately for each instance.
+        assert_eq!(other_budget.tracker().access_epoch(), 0);
+
+        budget.tracker().node_dropped(1);
+        budget.evict(&[]);
+        assert_eq!(budget.tracker().access_epoch(), 1);
+    }
+}
@@ -204,4 +204,13 @@ impl VersionedRevDependencies {
     pub(crate) fn rdeps(&self) -> RwLockReadGuard<VersionedRevDependenciesData> {
         self.data.read()
     }
+
+    /// Whether any of the nodes depending on this one are still alive.
+    pub(crate) fn has_live_rdeps(&self) -> bool {
+        self.data
+            .read()
+            .rdeps
+            .keys()
+            .any(|rdep| rdep.0.strong_count() > 0)
+    }
 }
@@ -23,20 +23,27 @@ pub(crate) mod storage_properties;
 
 use std::collections::Bound;
 use std::fmt::Debug;
+use std::hash::Hash;
+use std::hash::Hasher;
 use std::ops::Bound::Included;
 use std::ops::Bound::Unbounded;
 use std::ops::Deref;
 use std::ops::DerefMut;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::sync::Weak;
 
 use allocative::Allocative;
 use dashmap::mapref::one::RefMut;
 use dashmap::DashMap;
+use dashmap::DashSet;
 use dupe::Clone_;
 use dupe::Copy_;
 use dupe::Dupe;
 use dupe::Dupe_;
+use fnv::FnvHasher;
 use gazebo::variants::UnpackVariants;
 use gazebo::variants::VariantName;
 use parking_lot::MappedRwLockReadGuard;
@@ -46,6 +53,8 @@ use parking_lot::RwLockWriteGuard;
 use sorted_vector_map::SortedVectorMap;
 
 use crate::introspection::graph::AnyKey;
+use crate::legacy::eviction::EvictionCandidate;
+use crate::legacy::eviction::EvictionTracker;
 use crate::legacy::incremental::dep_trackers::BothDeps;
 use crate::legacy::incremental::graph::dependencies::ComputedDependency;
 use crate::legacy::incremental::graph::dependencies::VersionedDependencies;
@@ -179,6 +188,20 @@ pub(crate) struct OccupiedGraphNode<K: StorageProperties> {
     key: K::Key,
     res: K::Value,
     metadata: RwLock<NodeMetadata>,
+    /// The eviction epoch at which this node was last accessed.
+    last_accessed: AtomicU64,
+    /// Estimated memory used by this node. Only computed if it's tracked for eviction.
+    size: usize,
+    #[allocative(skip)]
+    eviction_tracker: Option<Arc<EvictionTracker>>,
+}
+
+impl<K: StorageProperties> Drop for OccupiedGraphNode<K> {
+    fn drop(&mut self) {
+        if let Some(eviction_tracker) = &self.eviction_tracker {
+            eviction_tracker.node_dropped(self.size);
+        }
+    }
 }
 
 /// Represents a node currently in the DICE graph, along with its typed value.
@@ -377,16 +400,22 @@ impl<'a> DerefMut for WritableMetadata<'a> {
 }
 
 impl<K: StorageProperties> OccupiedGraphNode<K> {
-    pub(crate) fn new(key: K::Key, res: K::Value, hist: CellHistory) -> Self {
-        Self {
+    pub(crate) fn new(
+        key: K::Key,
+        res: K::Value,
+        hist: CellHistory,
+        eviction_tracker: Option<Arc<EvictionTracker>>,
+    ) -> Self {
+        Self::new_with_metadata(
             key,
             res,
-            metadata: RwLock::new(NodeMetadata {
+            NodeMetadata {
                 hist,
                 deps: VersionedDependencies::new(),
                 rdeps: VersionedRevDependencies::new(),
-            }),
-        }
+            },
+            eviction_tracker,
+        )
     }
 
     #[cfg(test)]
@@ -397,10 +426,31 @@ impl<K: StorageProperties> OccupiedGraphNode<K> {
         deps: VersionedDependencies,
         rdeps: VersionedRevDependencies,
     ) -> Self {
+        Self::new_with_metadata(key, res, NodeMetadata { deps, rdeps, hist }, None)
+    }
+
+    fn new_with_metadata(
+        key: K::Key,
+        res: K::Value,
+        metadata: NodeMetadata,
+        eviction_tracker: Option<Arc<EvictionTracker>>,
+    ) -> Self {
+        let (last_accessed, size) = match &eviction_tracker {
+            Some(eviction_tracker) => {
+                let size = allocative::size_of_unique(&key) + allocative::size_of_unique(&res);
+                eviction_tracker.node_created(size);
+                (eviction_tracker.access_epoch(), size)
+            }
+            None => (0, 0),
+        };
+
         Self {
             key,
             res,
-            metadata: RwLock::new(NodeMetadata { deps, rdeps, hist }),
+            metadata: RwLock::new(metadata),
+            last_accessed: AtomicU64::new(last_accessed),
+            size,
+            eviction_tracker,
         }
     }
 
@@ -412,6 +462,16 @@ impl<K: StorageProperties> OccupiedGraphNode<K> {
         self.metadata.try_read()
     }
 
+    fn mark_accessed(&self) {
+        if let Some(eviction_tracker) = &self.eviction_tracker {
+            let epoch = eviction_tracker.access_epoch();
+            // Avoid writing to nodes that are accessed concurrently when nothing changes.
+            if self.last_accessed.load(Ordering::Relaxed) < epoch {
+                self.last_accessed.store(epoch, Ordering::Relaxed);
+            }
+        }
+    }
+
     pub(crate) fn mark_unchanged(
         &self,
         v: VersionNumber,
@@ -567,6 +627,13 @@ pub(crate) struct VersionedGraph<K: StorageProperties> {
     pub(crate) last_n:
         DashMap<K::Key, SortedVectorMap<VersionNumber, VersionedGraphNodeInternal<K>>>,
     pub(crate) storage_properties: K,
+    /// Whether values of this graph are injected rather than computed, in which case they can't
+    /// be evicted.
+    injected: AtomicBool,
+    /// Hashes of the keys that were evicted and were not computed again since.
+    evicted: DashSet<u64>,
+    evicted_key_count: AtomicU64,
+    recomputed_key_count: AtomicU64,
 }
 
 #[derive(Clone_)]
@@ -610,6 +677,10 @@ where
         Self {
             last_n: Default::default(),
             storage_properties,
+            injected: AtomicBool::new(false),
+            evicted: DashSet::new(),
+            evicted_key_count: AtomicU64::new(0),
+            recomputed_key_count: AtomicU64::new(0),
         }
     }
 
@@ -627,6 +698,7 @@ where
         where
             K: StorageProperties,
         {
+            entry.mark_accessed();
             match entry.read_meta().hist.get_history(&key.v) {
                 HistoryState::Verified => {
                     VersionedGraphResult::Match(GraphNode::occupied((*entry).dupe()))
@@ -762,6 +834,8 @@ where
         key: VersionedGraphKey<K::Key>,
         res: K::Value,
     ) -> (GraphNode<K>, Option<GraphNode<K>>) {
+        self.injected.store(true, Ordering::Relaxed);
+
         let entry_updater = EntryUpdater {
             storage_properties: &self.storage_properties,
             kind: EntryUpdaterKind::ValidOnly { res },
@@ -780,6 +854,13 @@ where
         entry_updater: EntryUpdater<K>,
     ) -> (GraphNode<K>, Option<GraphNode<K>>) {
         let StorageType::LastN(num_to_keep) = self.storage_properties.storage_type();
+
+        if self.evicted_key_count.load(Ordering::Relaxed) > 0
+            && self.evicted.remove(&key_hash(&key.k)).is_some()
+        {
+            self.recomputed_key_count.fetch_add(1, Ordering::Relaxed);
+        }
+
         // persistent keys, if any changes, are committed at the moment when the version
         // is increased. therefore, it must be the case that the current update for the
         // persistent key is the largest/newest version. it's also the case that they are
@@ -1086,6 +1167,107 @@ where
     pub(crate) fn len(&self) -> usize {
         self.last_n.len()
     }
+
+    /// Adds the keys that can be evicted to `candidates`.
+    pub(crate) fn collect_eviction_candidates(&self, candidates: &mut Vec<EvictionCandidate>) {
+        if self.injected.load(Ordering::Relaxed) {
+            return;
+        }
+
+        for entry in self.last_n.iter() {
+            if let Some(last_accessed) = Self::evictable(entry.value()) {
+                candidates.push(EvictionCandidate {
+                    last_accessed,
+                    size: Self::occupied_nodes(entry.value())
+                        .map(|node| node.size)
+                        .sum(),
+                });
+            }
+        }
+    }
+
+    /// Evicts the keys that can be evicted and were last accessed at or before
+    /// `accessed_until`. Returns the number of keys evicted.
+    pub(crate) fn evict(&self, accessed_until: u64) -> usize {
+        if self.injected.load(Ordering::Relaxed) {
+            return 0;
+        }
+
+        let mut evicted = Vec::new();
+        self.last_n.retain(|key, versioned| {
+            match Self::evictable(versioned) {
+                Some(last_accessed) if last_accessed <= accessed_until => {
+                    self.evicted.insert(key_hash(key));
+                    // Drop the nodes once the map is unlocked.
+                    evicted.push(std::mem::take(versioned));
+                    false
+                }
+                _ => true,
+            }
+        });
+
+        self.evicted_key_count
+            .fetch_add(evicted.len() as u64, Ordering::Relaxed);
+        evicted.len()
+    }
+
+    /// The number of keys that were evicted, and the number of these that were computed again.
+    pub(crate) fn eviction_metrics(&self) -> (u64, u64) {
+        (
+            self.evicted_key_count.load(Ordering::Relaxed),
+            self.recomputed_key_count.load(Ordering::Relaxed),
+        )
+    }
+
+    fn occupied_nodes(
+        versioned: &SortedVectorMap<VersionNumber, VersionedGraphNodeInternal<K>>,
+    ) -> impl Iterator<Item = &Arc<OccupiedGraphNode<K>>> {
+        versioned.values().filter_map(|node| match node {
+            VersionedGraphNodeInternal::Occupied(node) => Some(node),
+            _ => None,
+        })
+    }
+
+    /// A key can be evicted if nothing outside of this graph holds its nodes, and no other node
+    /// depends on them. Returns the epoch at which it was last accessed in that case.
+    fn evictable(
+        versioned: &SortedVectorMap<VersionNumber, VersionedGraphNodeInternal<K>>,
+    ) -> Option<u64> {
+        let mut last_accessed = None;
+        for node in versioned.values() {
+            match node {
+                VersionedGraphNodeInternal::Occupied(node) => {
+                    // The same node can be stored at several versions.
+                    let stored_count = Self::occupied_nodes(versioned)
+                        .filter(|n| Arc::ptr_eq(n, node))
+                        .count();
+                    if Arc::strong_count(node) != stored_count {
+                        return None;
+                    }
+                    let meta = node.try_read_meta()?;
+                    if meta.rdeps.has_live_rdeps() {
+                        return None;
+                    }
+                    last_accessed =
+                        last_accessed.max(Some(node.last_accessed.load(Ordering::Relaxed)));
+                }
+                VersionedGraphNodeInternal::Vacant(node) => {
+                    if Arc::strong_count(node) != 1 {
+                        return None;
+                    }
+                }
+                VersionedGraphNodeInternal::Transient(_) => return None,
+            }
+        }
+        // Keys that don't store any value aren't worth evicting.
+        last_accessed
+    }
+}
+
+fn key_hash<T: Hash>(key: &T) -> u64 {
+    let mut hasher = FnvHasher::default();
+    key.hash(&mut hasher);
+    hasher.finish()
 }
 
 struct EntryUpdater<'a, K: StorageProperties> {
@@ -1210,6 +1392,10 @@ impl<'a, K: StorageProperties> EntryUpdater<'a, K> {
         since: VersionNumber,
         hist: CellHistory,
     ) -> (VersionNumber, VersionedGraphNodeInternal<K>) {
+        let eviction_tracker = self
+            .storage_properties
+            .eviction_tracker()
+            .map(|eviction_tracker| eviction_tracker.dupe());
         match self.kind {
             EntryUpdaterKind::ValidOnly { res, .. } => (
                 since,
@@ -1219,6 +1405,7 @@ impl<'a, K: StorageProperties> EntryUpdater<'a, K> {
                     BothDeps::default(),
                     since,
                     hist,
+                    eviction_tracker,
                 )),
             ),
             EntryUpdaterKind::Reuse { e, both_deps, .. } => (
@@ -1229,6 +1416,7 @@ impl<'a, K: StorageProperties> EntryUpdater<'a, K> {
                     both_deps,
                     since,
                     hist,
+                    eviction_tracker,
                 )),
             ),
             EntryUpdaterKind::Computed {
@@ -1253,6 +1441,7 @@ impl<'a, K: StorageProperties> EntryUpdater<'a, K> {
                             both_deps,
                             since,
                             hist,
+                            eviction_tracker,
                         )),
                     )
                 } else {
@@ -1291,8 +1480,9 @@ impl<'a, K: StorageProperties> EntryUpdater<'a, K> {
         since: VersionNumber,
         // the full history
         hist: CellHistory,
+        eviction_tracker: Option<Arc<EvictionTracker>>,
     ) -> Arc<OccupiedGraphNode<K>> {
-        let new = Arc::new(OccupiedGraphNode::new(key, res, hist));
+        let new = Arc::new(OccupiedGraphNode::new(key, res, hist, eviction_tracker));
 
         // register the existing node's deps with reverse edges first before creating the history
         // of this node and putting it on the cache.
@@ -9,10 +9,12 @@
 
 use std::any::Any;
 use std::fmt::Debug;
+use std::sync::Arc;
 
 use allocative::Allocative;
 use dupe::Dupe;
 
+use crate::legacy::eviction::EvictionTracker;
 use crate::legacy::incremental::Computable;
 use crate::legacy::incremental::StorageType;
 
@@ -37,6 +39,10 @@ pub(crate) trait StorageProperties:
     fn equality(&self, x: &Self::Value, y: &Self::Value) -> bool;
     /// Is computed value valid (or transient)?
     fn validity(&self, x: &Self::Value) -> bool;
+    /// Tracks the stored values for eviction, if they must fit in a memory budget.
+    fn eviction_tracker(&self) -> Option<&Arc<EvictionTracker>> {
+        None
+    }
 }
 
 #[cfg(test)]
@@ -61,6 +61,7 @@ use crate::legacy::dice_futures::dice_future::DiceFuture;
 use crate::legacy::dice_futures::dice_task::DiceTask;
 use crate::legacy::dice_futures::future_handle::WeakDiceFutureHandle;
 use crate::legacy::dice_futures::sync_handle::SyncDiceTaskHandle;
+use crate::legacy::eviction::EvictionCandidate;
 use crate::legacy::incremental::dep_trackers::BothDeps;
 use crate::legacy::incremental::evaluator::Evaluator;
 pub(crate) use crate::legacy::incremental::graph::dependencies::ComputedDependency;
@@ -141,6 +142,16 @@ pub(crate) trait ErasedEngine: Allocative {
     fn introspect(&self) -> &dyn EngineForIntrospection;
 
     fn gc_version(&self, v: VersionNumber);
+
+    /// Adds the keys that can be evicted to `candidates`.
+    fn collect_eviction_candidates(&self, candidates: &mut Vec<EvictionCandidate>);
+
+    /// Evicts the keys that can be evicted and were last accessed at or before `accessed_until`.
+    /// Returns the number of keys evicted.
+    fn evict(&self, accessed_until: u64) -> usize;
+
+    /// The number of keys that were evicted, and the number of these that were computed again.
+    fn eviction_metrics(&self) -> (u64, u64);
 }
 
 impl<K> ErasedEngine for IncrementalEngine<K>
@@ -156,6 +167,18 @@ where
         running_map.remove(&v);
         running_map.shrink_to_fit();
     }
+
+    fn collect_eviction_candidates(&self, candidates: &mut Vec<EvictionCandidate>) {
+        self.versioned_cache.collect_eviction_candidates(candidates)
+    }
+
+    fn evict(&self, accessed_until: u64) -> usize {
+        self.versioned_cache.evict(accessed_until)
+    }
+
+    fn eviction_metrics(&self) -> (u64, u64) {
+        self.versioned_cache.eviction_metrics()
+    }
 }
 
 pub trait Computable:
@@ -1657,6 +1680,7 @@ mod tests {
             1337,
             1,
             CellHistory::verified(VersionNumber::new(1)),
+            None,
         ));
         entry.writable().deps.add_deps(
             VersionNumber::new(1),
@@ -1732,6 +1756,7 @@ mod tests {
             1338,
             1,
             CellHistory::verified(VersionNumber::new(1)),
+            None,
         ));
         entry.writable().deps.add_deps(
             VersionNumber::new(1),
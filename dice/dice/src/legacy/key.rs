@@ -14,6 +14,7 @@ use std::sync::Weak;
 use allocative::Allocative;
 
 use crate::api::key::Key;
+use crate::legacy::eviction::EvictionTracker;
 use crate::legacy::incremental::graph::storage_properties::StorageProperties;
 use crate::legacy::incremental::StorageType;
 use crate::legacy::DiceLegacy;
@@ -23,6 +24,8 @@ use crate::legacy::DiceLegacy;
 pub(crate) struct StoragePropertiesForKey<K: Key> {
     _k: std::marker::PhantomData<K>,
     pub(crate) dice: Weak<DiceLegacy>,
+    #[allocative(skip)]
+    eviction_tracker: Option<Arc<EvictionTracker>>,
 }
 
 impl<K: Key> StoragePropertiesForKey<K> {
@@ -30,6 +33,7 @@ impl<K: Key> StoragePropertiesForKey<K> {
         StoragePropertiesForKey {
             _k: std::marker::PhantomData,
             dice: Arc::downgrade(dice),
+            eviction_tracker: dice.eviction_tracker(),
         }
     }
 }
@@ -53,6 +57,10 @@ where
         K::validity(x)
     }
 
+    fn eviction_tracker(&self) -> Option<&Arc<EvictionTracker>> {
+        self.eviction_tracker.as_ref()
+    }
+
     fn key_type_name() -> &'static str {
         K::key_type_name()
     }
@@ -79,6 +79,16 @@ impl DiceMap {
             .map(|e| e.introspect().currently_running_key_count())
             .sum()
     }
+
+    /// The number of keys that were evicted, and the number of these that were computed again.
+    pub(crate) fn eviction_metrics(&self) -> (u64, u64) {
+        self.erased
+            .iter()
+            .map(|e| e.eviction_metrics())
+            .fold((0, 0), |(evicted, recomputed), (e, r)| {
+                (evicted + e, recomputed + r)
+            })
+    }
 }
 
 #[cfg(test)]
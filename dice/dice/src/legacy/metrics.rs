@@ -15,17 +15,24 @@ pub struct Metrics {
     pub key_count: usize,
     pub currently_running_key_count: usize,
     pub active_transaction_count: u32,
+    /// Number of keys evicted to stay within the memory budget.
+    pub evicted_key_count: u64,
+    /// Number of evicted keys that had to be computed again.
+    pub recomputed_key_count: u64,
 }
 
 impl Metrics {
     pub(crate) fn collect(dice: &DiceLegacy) -> Metrics {
         let dice_map = dice.map.read();
+        let (evicted_key_count, recomputed_key_count) = dice_map.eviction_metrics();
         Metrics {
             key_count: dice_map.key_count(),
             currently_running_key_count: dice_map.currently_running_key_count(),
             active_transaction_count: dice
                 .active_transaction_count
                 .load(std::sync::atomic::Ordering::SeqCst),
+            evicted_key_count,
+            recomputed_key_count,
         }
     }
 }
@@ -40,7 +47,12 @@ mod tests {
 
     #[test]
     fn test_active_transaction_count() {
-        let dice = Arc::new(DiceLegacy::new(DiceData::new(), DetectCycles::Enabled));
+        let dice = Arc::new(DiceLegacy::new(
+            DiceData::new(),
+            DetectCycles::Enabled,
+            None,
+            None,
+        ));
         assert_eq!(0, dice.metrics().active_transaction_count);
         let ctx = dice.updater().commit();
         assert_eq!(1, dice.metrics().active_transaction_count);
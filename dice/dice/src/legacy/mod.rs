@@ -48,11 +48,15 @@ use crate::introspection::serialize_dense_graph;
 use crate::introspection::serialize_graph;
 use crate::legacy::ctx::ComputationData;
 use crate::legacy::ctx::DiceComputationsImplLegacy;
+use crate::legacy::eviction::EvictionTracker;
+use crate::legacy::eviction::MemoryBudget;
+use crate::persistence::DicePersistence;
 use crate::transaction_update::DiceTransactionUpdaterImpl;
 
 pub(crate) mod ctx;
 pub(crate) mod cycles;
 pub(crate) mod dice_futures;
+pub(crate) mod eviction;
 pub(crate) mod key;
 pub(crate) mod map;
 pub(crate) mod metrics;
@@ -76,29 +80,66 @@ pub(crate) struct DiceLegacy {
     pub(crate) active_transaction_count: AtomicU32,
     #[allocative(skip)]
     active_versions_observer: watch::Receiver<usize>,
+    #[allocative(skip)]
+    pub(crate) persistence: Option<Arc<DicePersistence>>,
+    #[allocative(skip)]
+    memory_budget: Option<Arc<MemoryBudget>>,
 }
 
 impl Debug for DiceLegacy {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Dice")
             .field("detect_cycles", &self.detect_cycles)
+            .field("memory_budget", &self.memory_budget)
             .finish_non_exhaustive()
     }
 }
 
-pub(crate) struct DiceLegacyDataBuilder(DiceData);
+pub(crate) struct DiceLegacyDataBuilder {
+    data: DiceData,
+    persistence: Option<Arc<DicePersistence>>,
+    memory_budget: Option<usize>,
+    evict_when_idle: bool,
+}
 
 impl DiceLegacyDataBuilder {
     pub(crate) fn new() -> Self {
-        Self(DiceData::new())
+        Self {
+            data: DiceData::new(),
+            persistence: None,
+            memory_budget: None,
+            evict_when_idle: true,
+        }
     }
 
     pub fn set<K: Send + Sync + 'static>(&mut self, val: K) {
-        self.0.set(val);
+        self.data.set(val);
+    }
+
+    pub fn set_persistence(&mut self, persistence: Arc<DicePersistence>) {
+        self.persistence = Some(persistence);
+    }
+
+    pub fn set_memory_budget(&mut self, max_bytes: usize) {
+        self.memory_budget = Some(max_bytes);
+    }
+
+    /// Only evict values when `enforce_memory_budget` is called, rather than whenever DICE
+    /// becomes idle.
+    #[cfg(test)]
+    pub(crate) fn disable_eviction_when_idle(&mut self) {
+        self.evict_when_idle = false;
     }
 
     pub fn build(self, detect_cycles: DetectCycles) -> Arc<DiceLegacy> {
-        DiceLegacy::new(self.0, detect_cycles)
+        let evict_when_idle = self.evict_when_idle;
+        DiceLegacy::new(
+            self.data,
+            detect_cycles,
+            self.persistence,
+            self.memory_budget
+                .map(|max_bytes| MemoryBudget::new(max_bytes, evict_when_idle)),
+        )
     }
 }
 
@@ -108,10 +149,16 @@ impl DiceLegacy {
         DiceLegacyDataBuilder::new()
     }
 
-    pub(crate) fn new(data: DiceData, detect_cycles: DetectCycles) -> Arc<Self> {
+    pub(crate) fn new(
+        data: DiceData,
+        detect_cycles: DetectCycles,
+        persistence: Option<Arc<DicePersistence>>,
+        memory_budget: Option<Arc<MemoryBudget>>,
+    ) -> Arc<Self> {
         let map = Arc::new(RwLock::new(DiceMap::new()));
         let weak_map = Arc::downgrade(&map);
         let (active_versions_sender, active_versions_observer) = watch::channel(0);
+        let tracker_memory_budget = memory_budget.dupe();
 
         Arc::new(DiceLegacy {
             data,
@@ -128,12 +175,24 @@ impl DiceLegacy {
                     }
                 }
 
+                // Evict values once nothing is running anymore, so we don't evict values that
+                // are about to be requested again.
+                if update.active_version_count() == 0 {
+                    if let (Some(memory_budget), Some(map)) =
+                        (&tracker_memory_budget, weak_map.upgrade())
+                    {
+                        memory_budget.enforce_in_background(map);
+                    }
+                }
+
                 // If the corresponding Dice has been dropped, then so be it, ignore the error.
                 active_versions_sender.send_replace(update.active_version_count());
             })),
             detect_cycles,
             active_transaction_count: AtomicU32::new(0),
             active_versions_observer,
+            persistence,
+            memory_budget,
         })
     }
 
@@ -237,6 +296,21 @@ impl DiceLegacy {
         Metrics::collect(self)
     }
 
+    /// Tracks values for eviction, if they must fit in a memory budget.
+    pub(crate) fn eviction_tracker(&self) -> Option<Arc<EvictionTracker>> {
+        self.memory_budget
+            .as_ref()
+            .map(|memory_budget| memory_budget.tracker().dupe())
+    }
+
+    /// Evict values until they fit in the memory budget, if one is set.
+    #[cfg(test)]
+    pub(crate) fn enforce_memory_budget(&self) {
+        if let Some(memory_budget) = &self.memory_budget {
+            memory_budget.enforce(&self.map);
+        }
+    }
+
     /// Wait until all active versions have exited.
     pub fn wait_for_idle(&self) -> impl Future<Output = ()> + 'static {
         let obs = self.active_versions_observer.clone();
@@ -283,17 +357,22 @@ impl<K: Key> Evaluator for StoragePropertiesForKey<K> {
         transaction_ctx: Arc<TransactionCtx>,
         extra: ComputationData,
     ) -> ValueWithDeps<K::Value> {
-        let ctx = DiceComputationsImplLegacy::new_for_key_evaluation(
-            self.dice
-                .upgrade()
-                .expect("Dice holds DiceMap so it should still be alive here"),
-            transaction_ctx,
-            extra,
-        );
-
-        let value = k
-            .compute(&DiceComputations(DiceComputationsImpl::Legacy(ctx.dupe())))
-            .await;
+        let dice = self
+            .dice
+            .upgrade()
+            .expect("Dice holds DiceMap so it should still be alive here");
+        let persistence = dice.persistence.dupe();
+        let ctx = DiceComputationsImplLegacy::new_for_key_evaluation(dice, transaction_ctx, extra);
+        let computations = DiceComputations(DiceComputationsImpl::Legacy(ctx.dupe()));
+
+        let value = match (persistence, K::persistence()) {
+            (Some(persistence), Some(key_persistence)) => {
+                key_persistence
+                    .compute(k, &computations, &persistence)
+                    .await
+            }
+            _ => k.compute(&computations).await,
+        };
 
         let both_deps = ctx.finalize();
 
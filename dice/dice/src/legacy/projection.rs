@@ -22,6 +22,7 @@ use crate::api::projection::ProjectionKey;
 use crate::introspection::graph::short_type_name;
 use crate::legacy::ctx::ComputationData;
 use crate::legacy::dice_futures::sync_handle::SyncDiceTaskHandle;
+use crate::legacy::eviction::EvictionTracker;
 use crate::legacy::incremental::graph::storage_properties::StorageProperties;
 use crate::legacy::incremental::IncrementalComputeProperties;
 use crate::legacy::incremental::IncrementalEngine;
@@ -45,6 +46,8 @@ pub(crate) struct ProjectionKeyAsKey<P: ProjectionKey> {
 pub(crate) struct ProjectionKeyProperties<P: ProjectionKey> {
     _marker: std::marker::PhantomData<P>,
     pub(crate) dice: Weak<DiceLegacy>,
+    #[allocative(skip)]
+    eviction_tracker: Option<Arc<EvictionTracker>>,
 }
 
 impl<P: ProjectionKey> ProjectionKeyProperties<P> {
@@ -52,6 +55,7 @@ impl<P: ProjectionKey> ProjectionKeyProperties<P> {
         ProjectionKeyProperties {
             _marker: std::marker::PhantomData,
             dice: Arc::downgrade(dice),
+            eviction_tracker: dice.eviction_tracker(),
         }
     }
 }
@@ -72,6 +76,10 @@ impl<P: ProjectionKey> StorageProperties for ProjectionKeyProperties<P> {
         P::validity(x)
     }
 
+    fn eviction_tracker(&self) -> Option<&Arc<EvictionTracker>> {
+        self.eviction_tracker.as_ref()
+    }
+
     /// Provides a short informative name for this projection type.
     fn key_type_name() -> &'static str {
         short_type_name(std::any::type_name::<Self>())
@@ -146,3 +146,200 @@ async fn compute_and_update_uses_proper_version_numbers() -> anyhow::Result<()>
 
     Ok(())
 }
+
+#[tokio::test]
+async fn persisted_values_are_reused_after_restart() -> anyhow::Result<()> {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    use crate::api::computations::DiceComputations;
+    use crate::api::key::Key;
+    use crate::api::persistence::KeyPersistence;
+    use crate::api::persistence::PersistentKey;
+    use crate::persistence::DicePersistence;
+
+    static COMPUTE_COUNT: AtomicUsize = AtomicUsize::new(0);
+    static FINGERPRINT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Clone, Dupe, Debug, Display, Eq, Hash, PartialEq, Allocative)]
+    #[display(fmt = "{:?}", self)]
+    struct Doubled(i32);
+
+    #[async_trait]
+    impl Key for Doubled {
+        type Value = i32;
+
+        async fn compute(&self, ctx: &DiceComputations) -> Self::Value {
+            COMPUTE_COUNT.fetch_add(1, Ordering::SeqCst);
+            ctx.compute(&Foo(self.0)).await.unwrap() * 2
+        }
+
+        fn equality(x: &Self::Value, y: &Self::Value) -> bool {
+            x == y
+        }
+
+        fn persistence() -> Option<KeyPersistence<Self>> {
+            Some(KeyPersistence::new())
+        }
+    }
+
+    #[async_trait]
+    impl PersistentKey for Doubled {
+        fn serialize_key(&self) -> anyhow::Result<Vec<u8>> {
+            Ok(self.0.to_le_bytes().to_vec())
+        }
+
+        fn serialize_value(&self, value: &Self::Value) -> anyhow::Result<Option<Vec<u8>>> {
+            // Don't persist zeros, to check that they are not fingerprinted either.
+            Ok((*value != 0).then(|| value.to_le_bytes().to_vec()))
+        }
+
+        fn deserialize_value(
+            &self,
+            _ctx: &DiceComputations,
+            value: &[u8],
+        ) -> anyhow::Result<Self::Value> {
+            Ok(i32::from_le_bytes(value.try_into()?))
+        }
+
+        async fn inputs_fingerprint(
+            &self,
+            ctx: &DiceComputations,
+            _computed_since: Option<SystemTime>,
+        ) -> anyhow::Result<Vec<u8>> {
+            FINGERPRINT_COUNT.fetch_add(1, Ordering::SeqCst);
+            Ok(ctx.compute(&Foo(self.0)).await?.to_le_bytes().to_vec())
+        }
+    }
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("snapshot");
+
+    let compute = |persistence: Arc<DicePersistence>, input: i32| async move {
+        let mut builder = DiceLegacy::builder();
+        builder.set_persistence(persistence);
+        let dice = builder.build(DetectCycles::Enabled);
+
+        let mut ctx = dice.updater();
+        ctx.changed_to(vec![(Foo(0), input)])?;
+        let ctx = ctx.commit().await;
+        anyhow::Ok(ctx.compute(&Doubled(0)).await?)
+    };
+
+    let persistence = DicePersistence::open(path.clone(), "stamp".to_owned());
+    assert_eq!(compute(persistence.dupe(), 1).await?, 2);
+    assert_eq!(COMPUTE_COUNT.load(Ordering::SeqCst), 1);
+    persistence.save().await?;
+
+    // After a restart, the persisted value is reused.
+    let persistence = DicePersistence::open(path, "stamp".to_owned());
+    assert_eq!(compute(persistence.dupe(), 1).await?, 2);
+    assert_eq!(COMPUTE_COUNT.load(Ordering::SeqCst), 1);
+
+    // Unless its inputs changed.
+    assert_eq!(compute(persistence.dupe(), 3).await?, 6);
+    assert_eq!(COMPUTE_COUNT.load(Ordering::SeqCst), 2);
+
+    // The persisted value is fingerprinted to check it, but the new value is not persisted, so
+    // it isn't fingerprinted again.
+    let fingerprint_count = FINGERPRINT_COUNT.load(Ordering::SeqCst);
+    assert_eq!(compute(persistence.dupe(), 0).await?, 0);
+    assert_eq!(COMPUTE_COUNT.load(Ordering::SeqCst), 3);
+    assert_eq!(
+        FINGERPRINT_COUNT.load(Ordering::SeqCst),
+        fingerprint_count + 1
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn values_are_evicted_and_recomputed_within_memory_budget() -> anyhow::Result<()> {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    use crate::api::computations::DiceComputations;
+    use crate::api::key::Key;
+
+    static COMPUTE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Clone, Dupe, Debug, Display, Eq, Hash, PartialEq, Allocative)]
+    #[display(fmt = "{:?}", self)]
+    struct Doubled(i32);
+
+    #[async_trait]
+    impl Key for Doubled {
+        type Value = i32;
+
+        async fn compute(&self, ctx: &DiceComputations) -> Self::Value {
+            COMPUTE_COUNT.fetch_add(1, Ordering::SeqCst);
+            ctx.compute(&Foo(self.0)).await.unwrap() * 2
+        }
+
+        fn equality(x: &Self::Value, y: &Self::Value) -> bool {
+            x == y
+        }
+    }
+
+    #[derive(Clone, Dupe, Debug, Display, Eq, Hash, PartialEq, Allocative)]
+    #[display(fmt = "{:?}", self)]
+    struct PlusOne(i32);
+
+    #[async_trait]
+    impl Key for PlusOne {
+        type Value = i32;
+
+        async fn compute(&self, ctx: &DiceComputations) -> Self::Value {
+            COMPUTE_COUNT.fetch_add(1, Ordering::SeqCst);
+            ctx.compute(&Doubled(self.0)).await.unwrap() + 1
+        }
+
+        fn equality(x: &Self::Value, y: &Self::Value) -> bool {
+            x == y
+        }
+    }
+
+    let mut builder = DiceLegacy::builder();
+    // Evict everything that can be evicted, but only when we ask for it.
+    builder.set_memory_budget(0);
+    builder.disable_eviction_when_idle();
+    let dice = builder.build(DetectCycles::Enabled);
+
+    {
+        let mut ctx = dice.updater();
+        ctx.changed_to(vec![(Foo(0), 1)])?;
+        let ctx = ctx.commit().await;
+        assert_eq!(ctx.compute(&PlusOne(0)).await?, 3);
+        assert_eq!(COMPUTE_COUNT.load(Ordering::SeqCst), 2);
+    }
+
+    // `Doubled` can only be evicted once `PlusOne`, which depends on it, was evicted. Injected
+    // values are never evicted.
+    dice.enforce_memory_budget();
+    let metrics = dice.metrics();
+    assert_eq!(metrics.key_count, 1);
+    assert_eq!(metrics.evicted_key_count, 2);
+    assert_eq!(metrics.recomputed_key_count, 0);
+
+    {
+        let ctx = dice.updater().commit().await;
+        assert_eq!(ctx.compute(&PlusOne(0)).await?, 3);
+        assert_eq!(COMPUTE_COUNT.load(Ordering::SeqCst), 4);
+
+        let metrics = dice.metrics();
+        assert_eq!(metrics.evicted_key_count, 2);
+        assert_eq!(metrics.recomputed_key_count, 2);
+    }
+
+    // Recomputed values are still invalidated when their dependencies change.
+    {
+        let mut ctx = dice.updater();
+        ctx.changed_to(vec![(Foo(0), 2)])?;
+        let ctx = ctx.commit().await;
+        assert_eq!(ctx.compute(&PlusOne(0)).await?, 5);
+    }
+
+    Ok(())
+}
@@ -209,6 +209,7 @@ mod impls;
 pub mod introspection;
 mod legacy;
 mod opaque;
+mod persistence;
 mod transaction_update;
 mod versions;
 
@@ -244,6 +245,8 @@ pub use crate::api::events::DiceEventListener;
 pub use crate::api::injected::InjectedKey;
 pub use crate::api::key::Key;
 pub use crate::api::opaque::OpaqueValue;
+pub use crate::api::persistence::KeyPersistence;
+pub use crate::api::persistence::PersistentKey;
 pub use crate::api::projection::DiceProjectionComputations;
 pub use crate::api::projection::ProjectionKey;
 pub use crate::api::transaction::DiceEquality;
@@ -256,6 +259,8 @@ use crate::impls::dice::DiceModern;
 use crate::impls::dice::DiceModernDataBuilder;
 use crate::legacy::DiceLegacy;
 use crate::legacy::DiceLegacyDataBuilder;
+pub use crate::persistence::DicePersistence;
+pub use crate::persistence::DicePersistenceStats;
 use crate::transaction_update::DiceTransactionUpdaterImpl;
 
 #[derive(Allocative, Debug)]
@@ -362,6 +367,25 @@ impl DiceDataBuilderImpl {
         }
     }
 
+    pub fn set_persistence(&mut self, persistence: Arc<DicePersistence>) {
+        match self {
+            DiceDataBuilderImpl::Legacy(d) => d.set_persistence(persistence),
+            DiceDataBuilderImpl::Modern(_d) => {
+                // Persistence is an optimization, so we can do without it.
+                warn!("Persistence is not supported by this DICE implementation, ignoring it");
+            }
+        }
+    }
+
+    pub fn set_memory_budget(&mut self, max_bytes: usize) {
+        match self {
+            DiceDataBuilderImpl::Legacy(d) => d.set_memory_budget(max_bytes),
+            DiceDataBuilderImpl::Modern(_d) => {
+                warn!("Memory budgets are not supported by this DICE implementation, ignoring it");
+            }
+        }
+    }
+
     pub fn build(self, detect_cycles: DetectCycles) -> Arc<Dice> {
         Dice::new(match self {
             DiceDataBuilderImpl::Legacy(d) => DiceImplementation::Legacy(d.build(detect_cycles)),
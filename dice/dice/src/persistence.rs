@@ -0,0 +1,286 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! On-disk snapshot of the values of [`PersistentKey`](crate::PersistentKey)s.
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use anyhow::Context;
+use dupe::Dupe;
+use futures::future::BoxFuture;
+use futures::future::Shared;
+use futures::FutureExt;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::HashMap;
+
+/// Bumped when the layout of the snapshot changes.
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Entry {
+    fingerprint: Vec<u8>,
+    value: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    format_version: u32,
+    stamp: String,
+    entries: Vec<(Vec<u8>, Entry)>,
+}
+
+/// Statistics about a [`DicePersistence`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DicePersistenceStats {
+    pub entry_count: usize,
+    /// Number of values that were reused instead of being recomputed.
+    pub hits: u64,
+    /// Number of values that had to be computed because they were not persisted, or their
+    /// inputs changed.
+    pub misses: u64,
+}
+
+/// An on-disk snapshot of the values of persistent keys, used to avoid recomputing them after
+/// the process restarts.
+///
+/// The snapshot is loaded in the background when it is opened, and lookups wait for it to be
+/// loaded. It is only written out when [`DicePersistence::save`] is called.
+///
+/// A snapshot is only used if it was written with the same `stamp`. This should identify
+/// everything the persisted values depend on that the keys don't fingerprint, such as the
+/// version of the binary that serialized them.
+pub struct DicePersistence {
+    path: PathBuf,
+    stamp: String,
+    /// Resolves once the snapshot was loaded into `entries`.
+    loaded: Shared<BoxFuture<'static, ()>>,
+    entries: Arc<Mutex<HashMap<Vec<u8>, Entry>>>,
+    /// Whether there are entries that were not saved yet.
+    dirty: AtomicBool,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl DicePersistence {
+    /// Open the snapshot at `path`, and start loading it in the background. A missing, invalid
+    /// or outdated snapshot is treated as empty. This must be called within a Tokio runtime.
+    pub fn open(path: PathBuf, stamp: String) -> Arc<Self> {
+        let entries = Arc::new(Mutex::new(HashMap::default()));
+
+        let loaded = {
+            let path = path.clone();
+            let stamp = stamp.clone();
+            let entries = entries.dupe();
+            tokio::task::spawn_blocking(move || match load(&path, &stamp) {
+                Ok(loaded) => {
+                    let mut entries = entries.lock();
+                    for (key, entry) in loaded {
+                        // Don't overwrite anything that was inserted while we were loading.
+                        entries.entry(key).or_insert(entry);
+                    }
+                }
+                Err(e) => {
+                    warn!(
+                        "Error loading DICE snapshot from `{}`, ignoring it: {:#}",
+                        path.display(),
+                        e
+                    );
+                }
+            })
+            .map(|_| ())
+            .boxed()
+            .shared()
+        };
+
+        Arc::new(Self {
+            path,
+            stamp,
+            loaded,
+            entries,
+            dirty: AtomicBool::new(false),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    /// Get the fingerprint and the value persisted for a key, if any.
+    pub(crate) async fn get(&self, key_type: &str, key: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+        self.loaded.clone().await;
+
+        self.entries
+            .lock()
+            .get(&entry_key(key_type, key))
+            .map(|entry| (entry.fingerprint.clone(), entry.value.clone()))
+    }
+
+    /// Record whether a persisted value was reused instead of being recomputed.
+    pub(crate) fn record_lookup(&self, hit: bool) {
+        if hit {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) async fn insert(
+        &self,
+        key_type: &str,
+        key: Vec<u8>,
+        fingerprint: Vec<u8>,
+        value: Vec<u8>,
+    ) {
+        self.loaded.clone().await;
+
+        self.entries
+            .lock()
+            .insert(entry_key(key_type, &key), Entry { fingerprint, value });
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Write the snapshot to disk, if anything changed since it was last written.
+    pub async fn save(&self) -> anyhow::Result<()> {
+        self.loaded.clone().await;
+
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let snapshot = Snapshot {
+            format_version: FORMAT_VERSION,
+            stamp: self.stamp.clone(),
+            entries: self
+                .entries
+                .lock()
+                .iter()
+                .map(|(key, entry)| (key.clone(), entry.clone()))
+                .collect(),
+        };
+
+        let path = self.path.clone();
+        let res = tokio::task::spawn_blocking(move || save(&path, &snapshot))
+            .await
+            .context("Saving DICE snapshot panicked")
+            .and_then(|res| res);
+
+        if res.is_err() {
+            // Try again next time.
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+
+        res.with_context(|| format!("Error saving DICE snapshot to `{}`", self.path.display()))
+    }
+
+    pub fn stats(&self) -> DicePersistenceStats {
+        DicePersistenceStats {
+            entry_count: self.entries.lock().len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn entry_key(key_type: &str, key: &[u8]) -> Vec<u8> {
+    let mut entry_key = Vec::with_capacity(key_type.len() + 1 + key.len());
+    entry_key.extend_from_slice(key_type.as_bytes());
+    // Key type names don't contain NUL bytes.
+    entry_key.push(0);
+    entry_key.extend_from_slice(key);
+    entry_key
+}
+
+fn load(path: &Path, stamp: &str) -> anyhow::Result<Vec<(Vec<u8>, Entry)>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let snapshot: Snapshot = bincode::deserialize_from(BufReader::new(file))?;
+    if snapshot.format_version != FORMAT_VERSION || snapshot.stamp != stamp {
+        debug!(
+            "Ignoring outdated DICE snapshot at `{}` (stamp `{}`)",
+            path.display(),
+            snapshot.stamp
+        );
+        return Ok(Vec::new());
+    }
+
+    Ok(snapshot.entries)
+}
+
+fn save(path: &Path, snapshot: &Snapshot) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // Write to a temporary file first, so that a crash doesn't leave a truncated snapshot behind.
+    let temp_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    bincode::serialize_into(&mut writer, snapshot)?;
+    writer.flush()?;
+    drop(writer);
+
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_save_and_load() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("snapshot");
+
+        let persistence = DicePersistence::open(path.clone(), "v1".to_owned());
+        assert_eq!(persistence.get("Key", b"a").await, None);
+        persistence
+            .insert("Key", b"a".to_vec(), b"1".to_vec(), b"value".to_vec())
+            .await;
+        persistence.save().await?;
+
+        let persistence = DicePersistence::open(path.clone(), "v1".to_owned());
+        assert_eq!(
+            persistence.get("Key", b"a").await,
+            Some((b"1".to_vec(), b"value".to_vec()))
+        );
+        assert_eq!(persistence.get("OtherKey", b"a").await, None);
+        persistence.record_lookup(true);
+        persistence.record_lookup(false);
+        assert_eq!(
+            persistence.stats(),
+            DicePersistenceStats {
+                entry_count: 1,
+                hits: 1,
+                misses: 1,
+            }
+        );
+
+        // Snapshots with another stamp are ignored.
+        let persistence = DicePersistence::open(path, "v2".to_owned());
+        assert_eq!(persistence.get("Key", b"a").await, None);
+
+        Ok(())
+    }
+}
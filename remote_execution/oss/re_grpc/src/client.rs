@@ -8,53 +8,102 @@
  */
 
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::env::VarError;
+use std::io::Write;
+use std::process::Stdio;
+use std::sync::atomic::AtomicI64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::sync::Mutex;
 
 use anyhow::Context;
-use buck2_core::fs::fs_util;
 use buck2_re_configuration::Buck2OssReConfiguration;
+use dupe::Dupe;
 use futures::future::Future;
 use futures::stream::BoxStream;
 use futures::stream::StreamExt;
 use futures::stream::TryStreamExt;
+use futures::SinkExt;
 use gazebo::prelude::*;
 use once_cell::sync::Lazy;
 use prost::Message;
 use re_grpc_proto::build::bazel::remote::execution::v2::action_cache_client::ActionCacheClient;
 use re_grpc_proto::build::bazel::remote::execution::v2::batch_update_blobs_request::Request;
+use re_grpc_proto::build::bazel::remote::execution::v2::capabilities_client::CapabilitiesClient;
 use re_grpc_proto::build::bazel::remote::execution::v2::compressor;
 use re_grpc_proto::build::bazel::remote::execution::v2::content_addressable_storage_client::ContentAddressableStorageClient;
+use re_grpc_proto::build::bazel::remote::execution::v2::digest_function;
 use re_grpc_proto::build::bazel::remote::execution::v2::execution_client::ExecutionClient;
 use re_grpc_proto::build::bazel::remote::execution::v2::execution_stage;
 use re_grpc_proto::build::bazel::remote::execution::v2::ActionResult;
 use re_grpc_proto::build::bazel::remote::execution::v2::BatchReadBlobsRequest;
 use re_grpc_proto::build::bazel::remote::execution::v2::BatchReadBlobsResponse;
 use re_grpc_proto::build::bazel::remote::execution::v2::BatchUpdateBlobsRequest;
+use re_grpc_proto::build::bazel::remote::execution::v2::BatchUpdateBlobsResponse;
 use re_grpc_proto::build::bazel::remote::execution::v2::Digest;
 use re_grpc_proto::build::bazel::remote::execution::v2::ExecuteOperationMetadata;
 use re_grpc_proto::build::bazel::remote::execution::v2::ExecuteRequest as GExecuteRequest;
 use re_grpc_proto::build::bazel::remote::execution::v2::ExecuteResponse as GExecuteResponse;
+use re_grpc_proto::build::bazel::remote::execution::v2::FindMissingBlobsRequest as GFindMissingBlobsRequest;
+use re_grpc_proto::build::bazel::remote::execution::v2::FindMissingBlobsResponse as GFindMissingBlobsResponse;
 use re_grpc_proto::build::bazel::remote::execution::v2::GetActionResultRequest;
+use re_grpc_proto::build::bazel::remote::execution::v2::GetCapabilitiesRequest;
+use re_grpc_proto::build::bazel::remote::execution::v2::GetTreeRequest as GGetTreeRequest;
+use re_grpc_proto::build::bazel::remote::execution::v2::GetTreeResponse as GGetTreeResponse;
 use re_grpc_proto::build::bazel::remote::execution::v2::ResultsCachePolicy;
+use re_grpc_proto::build::bazel::remote::execution::v2::ServerCapabilities;
+use re_grpc_proto::build::bazel::remote::execution::v2::WaitExecutionRequest;
+use re_grpc_proto::google::bytestream::byte_stream_client::ByteStreamClient;
+use re_grpc_proto::google::bytestream::ReadRequest;
+use re_grpc_proto::google::bytestream::ReadResponse;
+use re_grpc_proto::google::bytestream::WriteRequest;
+use re_grpc_proto::google::bytestream::WriteResponse;
 use re_grpc_proto::google::longrunning::operation::Result as OpResult;
+use re_grpc_proto::google::longrunning::Operation;
 use re_grpc_proto::google::rpc::Code;
 use re_grpc_proto::google::rpc::Status;
 use regex::Regex;
+use sha1::Digest as _;
 use tokio::fs::OpenOptions;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
 use tokio::io::AsyncWriteExt;
+use tonic::metadata::AsciiMetadataKey;
+use tonic::metadata::AsciiMetadataValue;
 use tonic::metadata::MetadataValue;
 use tonic::transport::channel::ClientTlsConfig;
 use tonic::transport::Certificate;
 use tonic::transport::Channel;
 use tonic::transport::Identity;
+use tonic::Streaming;
+use uuid::Uuid;
 
 use crate::error::*;
 use crate::metadata::*;
 use crate::request::*;
 use crate::response::*;
+use crate::retry::is_retryable;
+use crate::retry::with_retries;
+use crate::retry::RetryPolicy;
 
-const INSTANCE_NAME: &str = "";
+/// Used for `assumed_digest_ttl` when it is not configured. This needs to be comfortably longer
+/// than the TTL Buck2 asks for when checking action inputs, or they'd get uploaded every time.
+const DEFAULT_ASSUMED_DIGEST_TTL: i64 = 60 * 60;
+
+/// Used for `max_total_batch_size` when it is not configured. This is the default maximum message
+/// size for gRPC servers, minus some headroom.
+const DEFAULT_MAX_TOTAL_BATCH_SIZE: usize = 4 * 1000 * 1000;
+
+/// Per-blob allowance for the digest and framing when packing blobs into a batch request.
+const BATCH_BLOB_OVERHEAD: usize = 256;
+
+/// Size of the chunks we send in ByteStream writes.
+const BYTESTREAM_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// How many batch or ByteStream requests a single upload or download keeps in flight.
+const MAX_CONCURRENT_TRANSFERS: usize = 8;
 
 fn tdigest_to(tdigest: TDigest) -> Digest {
     Digest {
@@ -134,6 +183,90 @@ async fn create_tls_config(opts: &Buck2OssReConfiguration) -> anyhow::Result<Cli
     Ok(config)
 }
 
+/// Metadata we attach to every gRPC request we send.
+type Headers = Vec<(AsciiMetadataKey, AsciiMetadataValue)>;
+
+async fn create_headers(opts: &Buck2OssReConfiguration) -> anyhow::Result<Headers> {
+    let mut headers = Vec::with_capacity(opts.http_headers.len() + 1);
+
+    for header in &opts.http_headers {
+        let key = AsciiMetadataKey::from_bytes(header.key.as_bytes())
+            .with_context(|| format!("Invalid header name `{}`", header.key))?;
+        let value = substitute_env_vars(&header.value)
+            .with_context(|| format!("Invalid value for header `{}`", header.key))?;
+        let value = value
+            .parse::<AsciiMetadataValue>()
+            .with_context(|| format!("Invalid value for header `{}`", header.key))?;
+        headers.push((key, value));
+    }
+
+    let token = match (&opts.auth_token_file, &opts.auth_token_helper) {
+        (Some(_), Some(_)) => {
+            return Err(anyhow::anyhow!(
+                "Only one of `auth_token_file` and `auth_token_helper` can be set"
+            ));
+        }
+        (Some(auth_token_file), None) => {
+            let auth_token_file =
+                substitute_env_vars(auth_token_file).context("Invalid `auth_token_file`")?;
+            Some(
+                tokio::fs::read_to_string(&auth_token_file)
+                    .await
+                    .with_context(|| format!("Error reading `{}`", auth_token_file))?,
+            )
+        }
+        (None, Some(auth_token_helper)) => Some(
+            run_auth_token_helper(auth_token_helper)
+                .await
+                .context("Invalid `auth_token_helper`")?,
+        ),
+        (None, None) => None,
+    };
+
+    if let Some(token) = token {
+        let mut value = format!("Bearer {}", token.trim())
+            .parse::<AsciiMetadataValue>()
+            .context("Invalid auth token")?;
+        value.set_sensitive(true);
+        headers.push((AsciiMetadataKey::from_static("authorization"), value));
+    }
+
+    Ok(headers)
+}
+
+/// Run the auth token helper (a program followed by its arguments, separated by whitespace)
+/// and return what it printed.
+async fn run_auth_token_helper(auth_token_helper: &str) -> anyhow::Result<String> {
+    let auth_token_helper = substitute_env_vars(auth_token_helper)?;
+    let mut argv = auth_token_helper.split_whitespace();
+    let program = argv.next().context("Command is empty")?;
+
+    let output = tokio::process::Command::new(program)
+        .args(argv)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .with_context(|| format!("Error running `{}`", auth_token_helper))?;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "`{}` failed with {}: {}",
+            auth_token_helper,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim(),
+        ));
+    }
+
+    String::from_utf8(output.stdout).context("Token is not UTF-8")
+}
+
+fn with_headers<T>(mut msg: tonic::Request<T>, headers: &Headers) -> tonic::Request<T> {
+    for (key, value) in headers {
+        msg.metadata_mut().append(key.clone(), value.clone());
+    }
+    msg
+}
+
 pub struct REClientBuilder;
 
 impl REClientBuilder {
@@ -142,6 +275,10 @@ impl REClientBuilder {
             .await
             .context("Invalid TLS config")?;
 
+        let headers = create_headers(opts)
+            .await
+            .context("Invalid RE request headers")?;
+
         let tls_config = &tls_config;
 
         let create_channel = |address: Option<String>| async move {
@@ -164,10 +301,28 @@ impl REClientBuilder {
         )
         .await;
 
+        let cas = cas.context("Error creating CAS client")?;
+
+        // Not all servers implement this, so if it fails we just carry on with our defaults.
+        let capabilities = match CapabilitiesClient::new(cas.clone())
+            .get_capabilities(with_headers(
+                tonic::Request::new(GetCapabilitiesRequest {
+                    instance_name: opts.instance_name.clone().unwrap_or_default(),
+                }),
+                &headers,
+            ))
+            .await
+        {
+            Ok(capabilities) => Some(capabilities.into_inner()),
+            Err(e) => {
+                tracing::warn!("Error querying RE server capabilities: {}", e);
+                None
+            }
+        };
+
         let grpc_clients = GRPCClients {
-            cas_client: ContentAddressableStorageClient::new(
-                cas.context("Error creating CAS client")?,
-            ),
+            cas_client: ContentAddressableStorageClient::new(cas.clone()),
+            bytestream_client: ByteStreamClient::new(cas),
             execution_client: ExecutionClient::new(
                 execution.context("Error creating Execution client")?,
             ),
@@ -176,12 +331,16 @@ impl REClientBuilder {
             ),
         };
 
-        Ok(REClient::new(grpc_clients))
+        let runtime_opts = RERuntimeOpts::new(opts, capabilities.as_ref());
+        tracing::debug!("RE client options: {:?}", runtime_opts);
+
+        Ok(REClient::new(runtime_opts, grpc_clients, headers))
     }
 }
 
 pub struct GRPCClients {
     cas_client: ContentAddressableStorageClient<Channel>,
+    bytestream_client: ByteStreamClient<Channel>,
     execution_client: ExecutionClient<Channel>,
     action_cache_client: ActionCacheClient<Channel>,
 }
@@ -191,11 +350,106 @@ pub struct REState {
     // TODO(aloiscochard): Update those values
     network_uploaded: i64,   // in bytes
     network_downloaded: i64, // in bytes
+    upload_skipped: i64,     // in bytes, blobs the CAS already had
+}
+
+/// Bytes sent and bytes deduplicated by a single call to `upload`.
+#[derive(Default, Debug, PartialEq, Eq)]
+struct UploadStats {
+    uploaded: i64,
+    skipped: i64,
+}
+
+/// Client settings that are fixed once we are connected, based on our configuration and on the
+/// capabilities the server reports.
+#[derive(Clone, Debug)]
+pub struct RERuntimeOpts {
+    /// The RE instance that all requests operate against.
+    instance_name: String,
+    /// Largest total size of a batch CAS request. Blobs that don't fit are sent via ByteStream.
+    max_total_batch_size: usize,
+    /// Whether to zstd-compress blobs in batch requests.
+    batch_zstd: bool,
+    /// Whether to zstd-compress blobs in ByteStream requests.
+    bytestream_zstd: bool,
+    /// The digest function the server uses, if it told us.
+    digest_function: Option<digest_function::Value>,
+    /// TTL, in seconds, we report for blobs that the CAS has.
+    assumed_digest_ttl: i64,
+    /// How we retry requests that fail with transient errors.
+    retry_policy: RetryPolicy,
+}
+
+impl Default for RERuntimeOpts {
+    fn default() -> Self {
+        Self {
+            instance_name: String::new(),
+            max_total_batch_size: DEFAULT_MAX_TOTAL_BATCH_SIZE,
+            batch_zstd: false,
+            bytestream_zstd: false,
+            digest_function: None,
+            assumed_digest_ttl: DEFAULT_ASSUMED_DIGEST_TTL,
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+}
+
+impl RERuntimeOpts {
+    fn new(opts: &Buck2OssReConfiguration, capabilities: Option<&ServerCapabilities>) -> Self {
+        let cache = capabilities.and_then(|c| c.cache_capabilities.as_ref());
+
+        // A limit of zero means the server does not impose one.
+        let server_max_batch_size = cache
+            .and_then(|c| usize::try_from(c.max_batch_total_size_bytes).ok())
+            .filter(|size| *size > 0);
+
+        let max_total_batch_size = match (opts.max_total_batch_size, server_max_batch_size) {
+            (Some(configured), Some(server)) => std::cmp::min(configured, server),
+            (configured, server) => configured
+                .or(server)
+                .unwrap_or(DEFAULT_MAX_TOTAL_BATCH_SIZE),
+        };
+
+        let supports_zstd =
+            |compressors: &[i32]| compressors.contains(&(compressor::Value::Zstd as i32));
+
+        // Prefer what the execution service says, since that's the one that has to hash action
+        // inputs, but fall back to the CAS if it only supports one.
+        let digest_function = capabilities
+            .and_then(|c| c.execution_capabilities.as_ref())
+            .map(|c| c.digest_function)
+            .filter(|f| *f != digest_function::Value::Unknown as i32)
+            .or_else(|| match cache.map(|c| c.digest_functions.as_slice()) {
+                Some([f]) => Some(*f),
+                _ => None,
+            })
+            .and_then(digest_function::Value::from_i32)
+            .filter(|f| *f != digest_function::Value::Unknown);
+
+        Self {
+            instance_name: opts.instance_name.clone().unwrap_or_default(),
+            max_total_batch_size,
+            batch_zstd: cache.map_or(false, |c| {
+                supports_zstd(&c.supported_batch_update_compressors)
+            }),
+            bytestream_zstd: cache.map_or(false, |c| supports_zstd(&c.supported_compressors)),
+            digest_function,
+            assumed_digest_ttl: opts
+                .assumed_digest_ttl
+                .unwrap_or(DEFAULT_ASSUMED_DIGEST_TTL),
+            retry_policy: RetryPolicy::new(opts),
+        }
+    }
 }
 
 pub struct REClient {
+    runtime_opts: RERuntimeOpts,
     grpc_clients: GRPCClients,
+    headers: Headers,
     state: Mutex<REState>,
+    /// Requests we sent again after a transient error. This is shared with execution streams,
+    /// which outlive the borrow of the client.
+    retries: Arc<AtomicI64>,
 }
 
 impl Drop for REClient {
@@ -206,29 +460,48 @@ impl Drop for REClient {
 }
 
 impl REClient {
-    pub fn new(grpc_clients: GRPCClients) -> Self {
+    pub fn new(runtime_opts: RERuntimeOpts, grpc_clients: GRPCClients, headers: Headers) -> Self {
         REClient {
+            runtime_opts,
             grpc_clients,
+            headers,
             state: Mutex::new(REState::default()),
+            retries: Arc::new(AtomicI64::new(0)),
         }
     }
 
+    /// Wrap a message into a request carrying our metadata and configured headers.
+    fn request<T>(&self, t: T, metadata: RemoteExecutionMetadata) -> tonic::Request<T> {
+        with_headers(with_internal_metadata(t, metadata), &self.headers)
+    }
+
+    /// Send a request, retrying it according to our retry policy. Only use this for requests that
+    /// are safe to repeat.
+    async fn with_retries<T, F, Fut>(&self, what: &str, f: F) -> Result<T, tonic::Status>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, tonic::Status>>,
+    {
+        with_retries(&self.runtime_opts.retry_policy, &self.retries, what, f).await
+    }
+
     pub async fn get_action_result(
         &self,
         metadata: RemoteExecutionMetadata,
         request: ActionResultRequest,
     ) -> anyhow::Result<ActionResultResponse> {
-        let mut client = self.grpc_clients.action_cache_client.clone();
+        let re_request = GetActionResultRequest {
+            instance_name: self.runtime_opts.instance_name.clone(),
+            action_digest: Some(tdigest_to(request.digest)),
+            ..Default::default()
+        };
 
-        let res = client
-            .get_action_result(with_internal_metadata(
-                GetActionResultRequest {
-                    instance_name: INSTANCE_NAME.into(),
-                    action_digest: Some(tdigest_to(request.digest)),
-                    ..Default::default()
-                },
-                metadata,
-            ))
+        let res = self
+            .with_retries("GetActionResult", || {
+                let mut client = self.grpc_clients.action_cache_client.clone();
+                let request = self.request(re_request.clone(), metadata.clone());
+                async move { client.get_action_result(request).await }
+            })
             .await?;
 
         Ok(ActionResultResponse {
@@ -253,25 +526,45 @@ impl REClient {
         // TODO(aloiscochard): Map those properly in the request
         // use crate::proto::build::bazel::remote::execution::v2::ExecutionPolicy;
 
-        let mut client = self.grpc_clients.execution_client.clone();
+        check_digest_function(
+            self.runtime_opts.digest_function,
+            &execute_request.action_digest,
+        )?;
 
         let action_digest = tdigest_to(execute_request.action_digest.clone());
 
         let request = GExecuteRequest {
-            instance_name: INSTANCE_NAME.into(),
+            instance_name: self.runtime_opts.instance_name.clone(),
             skip_cache_lookup: false,
             execution_policy: None,
             results_cache_policy: Some(ResultsCachePolicy { priority: 0 }),
             action_digest: Some(action_digest.clone()),
         };
 
-        let stream = client
-            .execute(with_internal_metadata(request, metadata))
+        // Execute is idempotent: the server deduplicates requests for the same action.
+        let stream = self
+            .with_retries("Execute", || {
+                let mut client = self.grpc_clients.execution_client.clone();
+                let request = self.request(request.clone(), metadata.clone());
+                async move { client.execute(request).await }
+            })
             .await?
             .into_inner();
 
-        let stream = futures::stream::try_unfold(stream, move |mut stream| async {
-            let msg = match stream.try_next().await.context("RE channel error")? {
+        let state = ExecuteStreamState {
+            client: self.grpc_clients.execution_client.clone(),
+            headers: self.headers.clone(),
+            metadata,
+            retry_policy: self.runtime_opts.retry_policy.clone(),
+            retries: self.retries.dupe(),
+            stream,
+            operation_name: None,
+            resumes: 0,
+            done: false,
+        };
+
+        let stream = futures::stream::try_unfold(state, move |mut state| async {
+            let msg = match state.next().await? {
                 Some(msg) => msg,
                 None => return Ok(None),
             };
@@ -292,11 +585,36 @@ impl REClient {
                         let execute_response_grpc: GExecuteResponse =
                             GExecuteResponse::decode(&any.value[..])?;
 
-                        check_status(execute_response_grpc.status.unwrap_or_default())?;
+                        let status = execute_response_grpc.status.unwrap_or_default();
+
+                        // Actions that timed out may still have a (partial) result, so we report
+                        // the timeout in the response instead of failing, which lets the caller
+                        // show what the action printed.
+                        let timed_out = TCode(status.code) == TCode::DEADLINE_EXCEEDED;
+                        let error = if timed_out {
+                            REError {
+                                code: TCode::DEADLINE_EXCEEDED,
+                                message: status.message,
+                                ..Default::default()
+                            }
+                        } else {
+                            check_status(status)?;
+                            REError {
+                                code: TCode::OK,
+                                ..Default::default()
+                            }
+                        };
 
-                        let action_result = execute_response_grpc
-                            .result
-                            .with_context(|| "The action result is not defined.")?;
+                        let action_result = match execute_response_grpc.result {
+                            Some(action_result) => action_result,
+                            None if timed_out => ActionResult {
+                                execution_metadata: Some(Default::default()),
+                                ..Default::default()
+                            },
+                            None => {
+                                return Err(anyhow::anyhow!("The action result is not defined."));
+                            }
+                        };
 
                         let action_result = convert_action_result(action_result)?;
 
@@ -304,10 +622,7 @@ impl REClient {
                             action_result,
                             action_result_digest: TDigest::default(),
                             action_result_ttl: 0,
-                            error: REError {
-                                code: TCode::OK,
-                                ..Default::default()
-                            },
+                            error,
                             cached_result: execute_response_grpc.cached_result,
                             action_digest: Default::default(), // Filled in below.
                         };
@@ -339,7 +654,7 @@ impl REClient {
                 }
             };
 
-            anyhow::Ok(Some((status, stream)))
+            anyhow::Ok(Some((status, state)))
         });
 
         // We fill in the action digest a little later here. We do it this way so we don't have to
@@ -367,81 +682,95 @@ impl REClient {
         metadata: RemoteExecutionMetadata,
         request: UploadRequest,
     ) -> anyhow::Result<UploadResponse> {
-        let mut client = self.grpc_clients.cas_client.clone();
-
-        let files_with_digest: Vec<Request> = request
-            .files_with_digest
-            .unwrap_or_default()
-            .into_try_map(|x| {
-                anyhow::Ok(Request {
-                    digest: Some(tdigest_to(x.digest)),
-                    // FIXME: This could do a lot of blocking reads
-                    data: fs_util::read(&x.name)?,
-                    compressor: compressor::Value::Identity as i32,
-                })
-            })?;
-
-        let re_request = BatchUpdateBlobsRequest {
-            instance_name: INSTANCE_NAME.into(),
-            requests: [
-                request
-                    .inlined_blobs_with_digest
-                    .unwrap_or_default()
-                    .into_map(|x| Request {
-                        digest: Some(tdigest_to(x.digest)),
-                        data: x.blob,
-                        compressor: compressor::Value::Identity as i32,
-                    }),
-                files_with_digest,
-            ]
-            .concat(),
-        };
+        let metadata = &metadata;
+
+        let stats = upload_impl(
+            request,
+            &self.runtime_opts,
+            |re_request| async move {
+                Ok(self
+                    .with_retries("FindMissingBlobs", || {
+                        let mut client = self.grpc_clients.cas_client.clone();
+                        let request = self.request(re_request.clone(), metadata.clone());
+                        async move { client.find_missing_blobs(request).await }
+                    })
+                    .await?
+                    .into_inner())
+            },
+            |re_request| async move {
+                Ok(self
+                    .with_retries("BatchUpdateBlobs", || {
+                        let mut client = self.grpc_clients.cas_client.clone();
+                        let request = self.request(re_request.clone(), metadata.clone());
+                        async move { client.batch_update_blobs(request).await }
+                    })
+                    .await?
+                    .into_inner())
+            },
+            // We don't retry writes: we'd need to send the data again, but we've consumed it.
+            |re_request| async move {
+                let mut client = self.grpc_clients.bytestream_client.clone();
+                Ok(client
+                    .write(self.request(re_request, metadata.clone()))
+                    .await?
+                    .into_inner())
+            },
+        )
+        .await?;
 
-        let blob_hashes = re_request
-            .requests
-            .iter()
-            .map(|x| x.digest.as_ref().unwrap().hash.clone())
-            .collect::<Vec<String>>();
-        let response = client
-            .batch_update_blobs(with_internal_metadata(re_request, metadata))
-            .await?;
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.network_uploaded += stats.uploaded;
+        state.upload_skipped += stats.skipped;
 
-        let failures: Vec<String> = response
-            .get_ref()
-            .responses
-            .iter()
-            .filter_map(|r| {
-                r.status.as_ref().and_then(|s| {
-                    if s.code == (Code::Ok as i32) {
-                        None
-                    } else {
-                        Some(format!(
-                            "Unable to upload blob '{}', rpc status code: {}, message: \"{}\"",
-                            r.digest.as_ref().map_or("N/A", |d| &d.hash),
-                            s.code,
-                            s.message
-                        ))
-                    }
-                })
+        // TODO(aloiscochard): Add something interesting in UploadResponse?
+        Ok(UploadResponse {})
+    }
+
+    pub async fn find_missing_blobs(
+        &self,
+        metadata: RemoteExecutionMetadata,
+        request: FindMissingBlobsRequest,
+    ) -> anyhow::Result<FindMissingBlobsResponse> {
+        let re_request = GFindMissingBlobsRequest {
+            instance_name: self.runtime_opts.instance_name.clone(),
+            blob_digests: request.digests.into_map(tdigest_to),
+        };
+
+        let res = self
+            .with_retries("FindMissingBlobs", || {
+                let mut client = self.grpc_clients.cas_client.clone();
+                let request = self.request(re_request.clone(), metadata.clone());
+                async move { client.find_missing_blobs(request).await }
             })
-            .collect();
+            .await?;
 
-        if failures.is_empty() {
-            tracing::debug!("uploaded: {:?}", blob_hashes);
-            // TODO(aloiscochard): Add something interesting in UploadResponse?
-            Ok(UploadResponse {})
-        } else {
-            Err(anyhow::anyhow!("Batch upload failed: {:?}", failures))
-        }
+        Ok(FindMissingBlobsResponse {
+            missing_digests: res.into_inner().missing_blob_digests.into_map(tdigest_from),
+        })
     }
 
     pub async fn upload_blob(
         &self,
-        _blob: Vec<u8>,
-        _metadata: RemoteExecutionMetadata,
+        blob: Vec<u8>,
+        metadata: RemoteExecutionMetadata,
     ) -> anyhow::Result<TDigest> {
-        // TODO(aloiscochard)
-        Err(anyhow::anyhow!("Not implemented (RE upload_blob)"))
+        let digest = compute_digest(self.runtime_opts.digest_function, &blob)?;
+
+        self.upload(
+            metadata,
+            UploadRequest {
+                inlined_blobs_with_digest: Some(vec![InlinedBlobWithDigest {
+                    blob,
+                    digest: digest.clone(),
+                    ..Default::default()
+                }]),
+                upload_only_missing: true,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        Ok(digest)
     }
 
     pub async fn download(
@@ -449,28 +778,90 @@ impl REClient {
         metadata: RemoteExecutionMetadata,
         request: DownloadRequest,
     ) -> anyhow::Result<DownloadResponse> {
-        download_impl(request, |re_request| async {
-            let mut client = self.grpc_clients.cas_client.clone();
-            Ok(client
-                .batch_read_blobs(with_internal_metadata(re_request, metadata))
-                .await?
-                .into_inner())
-        })
+        let metadata = &metadata;
+
+        download_impl(
+            request,
+            &self.runtime_opts,
+            |re_request| async move {
+                Ok(self
+                    .with_retries("BatchReadBlobs", || {
+                        let mut client = self.grpc_clients.cas_client.clone();
+                        let request = self.request(re_request.clone(), metadata.clone());
+                        async move { client.batch_read_blobs(request).await }
+                    })
+                    .await?
+                    .into_inner())
+            },
+            |re_request| async move {
+                Ok(self
+                    .with_retries("ByteStream.Read", || {
+                        let mut client = self.grpc_clients.bytestream_client.clone();
+                        let request = self.request(re_request.clone(), metadata.clone());
+                        async move { client.read(request).await }
+                    })
+                    .await?
+                    .into_inner()
+                    .map_err(anyhow::Error::from)
+                    .boxed())
+            },
+        )
         .await
     }
 
+    /// The REAPI has no way to query TTLs, so we ask the CAS which digests it is missing and
+    /// report those as expired. Everything else gets the configured `assumed_digest_ttl`.
     pub async fn get_digests_ttl(
         &self,
-        _metadata: RemoteExecutionMetadata,
+        metadata: RemoteExecutionMetadata,
         request: GetDigestsTtlRequest,
     ) -> anyhow::Result<GetDigestsTtlResponse> {
-        // TODO(aloiscochard): For now we just say all digests have expired,
-        // we should fetch and return real TTL instead
-        Ok(GetDigestsTtlResponse {
-            digests_with_ttl: request
-                .digests
-                .into_map(|digest| DigestWithTtl { digest, ttl: 0 }),
-        })
+        let missing = self
+            .find_missing_blobs(
+                metadata,
+                FindMissingBlobsRequest {
+                    digests: request.digests.clone(),
+                    ..Default::default()
+                },
+            )
+            .await?
+            .missing_digests;
+
+        Ok(digests_ttl(
+            request.digests,
+            missing,
+            self.runtime_opts.assumed_digest_ttl,
+        ))
+    }
+
+    /// Fetch a directory and all its descendants, following the server's pagination.
+    pub async fn get_tree(
+        &self,
+        metadata: RemoteExecutionMetadata,
+        request: GetTreeRequest,
+    ) -> anyhow::Result<GetTreeResponse> {
+        let metadata = &metadata;
+        get_tree_impl(
+            &self.runtime_opts.instance_name,
+            request.digest,
+            |re_request| async move {
+                Ok(self
+                    .with_retries("GetTree", || {
+                        let mut client = self.grpc_clients.cas_client.clone();
+                        let request = self.request(re_request.clone(), metadata.clone());
+                        async move {
+                            client
+                                .get_tree(request)
+                                .await?
+                                .into_inner()
+                                .try_collect::<Vec<_>>()
+                                .await
+                        }
+                    })
+                    .await?)
+            },
+        )
+        .await
     }
 
     pub fn get_execution_client(&self) -> &Self {
@@ -499,6 +890,8 @@ impl REClient {
         Ok(NetworkStatisticsResponse {
             downloaded: state.network_downloaded,
             uploaded: state.network_uploaded,
+            upload_skipped: state.upload_skipped,
+            retries: self.retries.load(Ordering::Relaxed),
             _dot_dot_default: (),
         })
     }
@@ -508,6 +901,90 @@ impl REClient {
     }
 }
 
+/// The stream of updates for an action we asked the server to execute. If the stream breaks before
+/// the action is done, we reattach to the operation using `WaitExecution`.
+struct ExecuteStreamState {
+    client: ExecutionClient<Channel>,
+    headers: Headers,
+    metadata: RemoteExecutionMetadata,
+    retry_policy: RetryPolicy,
+    retries: Arc<AtomicI64>,
+    stream: Streaming<Operation>,
+    /// The name of the operation, once the server told us.
+    operation_name: Option<String>,
+    /// How many times in a row we reattached without receiving anything.
+    resumes: usize,
+    done: bool,
+}
+
+impl ExecuteStreamState {
+    async fn next(&mut self) -> anyhow::Result<Option<Operation>> {
+        if self.done {
+            return Ok(None);
+        }
+
+        loop {
+            let status = match self.stream.try_next().await {
+                Ok(Some(msg)) => {
+                    if !msg.name.is_empty() {
+                        self.operation_name = Some(msg.name.clone());
+                    }
+                    self.resumes = 0;
+                    self.done = msg.done;
+                    return Ok(Some(msg));
+                }
+                Ok(None) => None,
+                Err(status) => Some(status),
+            };
+
+            let can_resume = self.resumes < self.retry_policy.max_retries
+                && status.as_ref().map_or(true, is_retryable);
+
+            let name = match (&self.operation_name, can_resume) {
+                (Some(name), true) => name.clone(),
+                _ => {
+                    return match status {
+                        Some(status) => {
+                            Err(anyhow::Error::from(status).context("RE channel error"))
+                        }
+                        None => Ok(None),
+                    };
+                }
+            };
+
+            let backoff = self.retry_policy.backoff(self.resumes);
+            tracing::debug!(
+                "Execution stream for `{}` broke, reattaching in {:?}: {:?}",
+                name,
+                backoff,
+                status
+            );
+            self.resumes += 1;
+            self.retries.fetch_add(1, Ordering::Relaxed);
+            tokio::time::sleep(backoff).await;
+
+            let client = &self.client;
+            let headers = &self.headers;
+            let metadata = &self.metadata;
+
+            self.stream = with_retries(&self.retry_policy, &self.retries, "WaitExecution", || {
+                let mut client = client.clone();
+                let request = with_headers(
+                    with_internal_metadata(
+                        WaitExecutionRequest { name: name.clone() },
+                        metadata.clone(),
+                    ),
+                    headers,
+                );
+                async move { client.wait_execution(request).await }
+            })
+            .await
+            .context("Error reattaching to RE execution")?
+            .into_inner();
+        }
+    }
+}
+
 fn convert_action_result(action_result: ActionResult) -> anyhow::Result<TActionResult2> {
     let execution_metadata = action_result
         .execution_metadata
@@ -593,132 +1070,782 @@ fn convert_action_result(action_result: ActionResult) -> anyhow::Result<TActionR
     Ok(action_result)
 }
 
-async fn download_impl<F, Fut>(request: DownloadRequest, f: F) -> anyhow::Result<DownloadResponse>
-where
-    F: FnOnce(BatchReadBlobsRequest) -> Fut,
-    Fut: Future<Output = anyhow::Result<BatchReadBlobsResponse>>,
-{
-    let inlined_digests = request.inlined_digests.unwrap_or_default();
-    let file_digests = request.file_digests.unwrap_or_default();
+/// Whether a blob of this size can be transferred using the batch APIs. Anything else has to go
+/// through ByteStream.
+fn fits_in_batch(digest: &TDigest, max_total_batch_size: usize) -> bool {
+    usize::try_from(digest.size_in_bytes).map_or(false, |size| {
+        size.saturating_add(BATCH_BLOB_OVERHEAD) <= max_total_batch_size
+    })
+}
 
-    let re_request = BatchReadBlobsRequest {
-        instance_name: INSTANCE_NAME.into(),
-        digests: file_digests
-            .iter()
-            .map(|req| &req.named_digest.digest)
-            .chain(inlined_digests.iter())
-            .map(|d| tdigest_to(d.clone()))
-            .collect(),
-        acceptable_compressors: vec![compressor::Value::Identity as i32],
-    };
+/// Group items into batches whose total size stays under `max_total_batch_size`. Items are
+/// expected to individually pass `fits_in_batch`.
+fn split_into_batches<T>(
+    items: Vec<T>,
+    digest: impl Fn(&T) -> &TDigest,
+    max_total_batch_size: usize,
+) -> Vec<Vec<T>> {
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    let mut batch_size = 0;
+
+    for item in items {
+        let size = digest(&item).size_in_bytes as usize + BATCH_BLOB_OVERHEAD;
+        if !batch.is_empty() && batch_size + size > max_total_batch_size {
+            batches.push(std::mem::take(&mut batch));
+            batch_size = 0;
+        }
+        batch_size += size;
+        batch.push(item);
+    }
 
-    let response = f(re_request).await?;
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
 
-    let response = response
-        .responses
-        .into_iter()
-        .map(|r| {
-            check_status(r.status.unwrap_or_default())?;
-            let digest = tdigest_from(r.digest.context("Response digest not found.")?);
-            anyhow::Ok((digest, r.data))
-        })
-        .collect::<Result<HashMap<_, _>, _>>()?;
+    batches
+}
+
+fn bytestream_read_resource_name(instance_name: &str, digest: &TDigest, zstd: bool) -> String {
+    with_instance_name(
+        instance_name,
+        format!(
+            "{}/{}/{}",
+            bytestream_blobs_path(zstd),
+            digest.hash,
+            digest.size_in_bytes
+        ),
+    )
+}
 
-    let inlined_blobs = inlined_digests.into_try_map(|digest| {
-        let data = response
-            .get(&digest)
-            .with_context(|| format!("Did not receive digest data for `{}`", digest))?
-            .clone();
+fn bytestream_write_resource_name(instance_name: &str, digest: &TDigest, zstd: bool) -> String {
+    with_instance_name(
+        instance_name,
+        format!(
+            "uploads/{}/{}/{}/{}",
+            Uuid::new_v4(),
+            bytestream_blobs_path(zstd),
+            digest.hash,
+            digest.size_in_bytes
+        ),
+    )
+}
 
-        anyhow::Ok(InlinedDigestWithStatus {
-            digest,
-            status: tstatus_ok(),
-            blob: data,
+fn bytestream_blobs_path(zstd: bool) -> &'static str {
+    if zstd {
+        "compressed-blobs/zstd"
+    } else {
+        "blobs"
+    }
+}
+
+fn with_instance_name(instance_name: &str, resource_name: String) -> String {
+    if instance_name.is_empty() {
+        resource_name
+    } else {
+        format!("{}/{}", instance_name, resource_name)
+    }
+}
+
+/// Servers may use a different digest function from ours, in which case they'd just tell us
+/// that none of our inputs exist. We can't tell every digest function apart from the hash alone,
+/// but we can catch the common case of a SHA1 / SHA256 mismatch and explain what is wrong.
+fn check_digest_function(
+    digest_function: Option<digest_function::Value>,
+    action_digest: &TDigest,
+) -> anyhow::Result<()> {
+    let expected_len = match digest_function {
+        Some(digest_function::Value::Sha1) => 40,
+        Some(digest_function::Value::Sha256) => 64,
+        _ => return Ok(()),
+    };
+
+    if action_digest.hash.len() != expected_len {
+        return Err(anyhow::anyhow!(
+            "Action digest `{}` cannot have been produced by the digest function used by the RE server ({:?}), check `buck2.digest_algorithms`",
+            action_digest,
+            digest_function.unwrap(),
+        ));
+    }
+
+    Ok(())
+}
+
+async fn get_tree_impl<F, Fut>(
+    instance_name: &str,
+    digest: TDigest,
+    f: F,
+) -> anyhow::Result<GetTreeResponse>
+where
+    F: Fn(GGetTreeRequest) -> Fut,
+    Fut: Future<Output = anyhow::Result<Vec<GGetTreeResponse>>>,
+{
+    let root_digest = tdigest_to(digest.clone());
+    let mut directories = Vec::new();
+    let mut page_token = String::new();
+
+    loop {
+        let pages = f(GGetTreeRequest {
+            instance_name: instance_name.to_owned(),
+            root_digest: Some(root_digest.clone()),
+            page_size: 0,
+            page_token: page_token.clone(),
         })
-    })?;
+        .await
+        .with_context(|| format!("GetTree failed for `{}`", digest))?;
 
-    let writes = file_digests.iter().map(|req| async {
-        let data = response
-            .get(&req.named_digest.digest)
-            .with_context(|| {
-                format!(
-                    "Did not receive digest data for `{}`",
-                    req.named_digest.digest
-                )
-            })?
-            .clone();
+        // A stream may contain several pages, only the last token tells us whether to continue.
+        let mut next_page_token = String::new();
+        for page in pages {
+            directories.extend(page.directories);
+            next_page_token = page.next_page_token;
+        }
 
-        let mut opts = OpenOptions::new();
-        opts.read(true).write(true).create_new(true);
-        #[cfg(unix)]
-        {
-            if req.is_executable {
-                opts.mode(0o755);
-            } else {
-                opts.mode(0o644);
-            }
+        if next_page_token.is_empty() {
+            break;
         }
 
-        async {
-            let mut file = opts
-                .open(&req.named_digest.name)
-                .await
-                .context("Error opening")?;
-            file.write_all(&data).await.context("Error writing")?;
-            file.flush().await.context("Error flushing")?;
-            anyhow::Ok(())
+        if next_page_token == page_token {
+            return Err(anyhow::anyhow!(
+                "GetTree for `{}` returned the same page token twice: `{}`",
+                digest,
+                page_token
+            ));
         }
-        .await
-        .with_context(|| {
-            format!(
-                "Error writing digest `{}` to `{}`",
-                req.named_digest.digest, req.named_digest.name,
-            )
-        })
-    });
 
-    futures::future::try_join_all(writes).await?;
+        page_token = next_page_token;
+    }
 
-    Ok(DownloadResponse {
-        inlined_blobs: Some(inlined_blobs),
-        directories: None,
-    })
+    if directories.is_empty() {
+        return Err(anyhow::anyhow!(
+            "GetTree returned no directories for `{}`",
+            digest
+        ));
+    }
+
+    Ok(GetTreeResponse { directories })
 }
 
-fn with_internal_metadata<T>(t: T, metadata: RemoteExecutionMetadata) -> tonic::Request<T> {
-    // This is pretty ugly, but the protobuf spec that defines this is internal, so considering
-    // field numbers need to be stable anyway (= low risk), and this is not used in prod (= low
-    // impact if this goes wrong), we just inline it here. This is a small hack that lets us use
-    // our internal RE using this GRPC client for testing.
-    //
-    // This is defined in `fbcode/remote_execution/grpc/metadata.proto`.
-    #[derive(prost::Message)]
-    struct Metadata {
-        #[prost(message, optional, tag = "15")]
-        platform: Option<crate::grpc::Platform>,
-        #[prost(string, optional, tag = "18")]
-        use_case_id: Option<String>,
+fn digests_ttl(
+    digests: Vec<TDigest>,
+    missing: Vec<TDigest>,
+    assumed_ttl: i64,
+) -> GetDigestsTtlResponse {
+    let missing = missing.into_iter().collect::<HashSet<_>>();
+
+    GetDigestsTtlResponse {
+        digests_with_ttl: digests.into_map(|digest| {
+            let ttl = if missing.contains(&digest) {
+                0
+            } else {
+                assumed_ttl
+            };
+            DigestWithTtl { digest, ttl }
+        }),
     }
+}
 
-    let mut msg = tonic::Request::new(t);
+/// Hash a blob the way the server expects. Servers that don't advertise a digest function are
+/// assumed to use SHA256, as the REAPI specifies.
+fn compute_digest(
+    digest_function: Option<digest_function::Value>,
+    blob: &[u8],
+) -> anyhow::Result<TDigest> {
+    let hash = match digest_function.unwrap_or(digest_function::Value::Sha256) {
+        digest_function::Value::Sha1 => format!("{:x}", sha1::Sha1::digest(blob)),
+        digest_function::Value::Sha256 => format!("{:x}", sha2::Sha256::digest(blob)),
+        digest_function::Value::Sha384 => format!("{:x}", sha2::Sha384::digest(blob)),
+        digest_function::Value::Sha512 => format!("{:x}", sha2::Sha512::digest(blob)),
+        other => {
+            return Err(anyhow::anyhow!(
+                "Digest function used by the RE server ({:?}) is not supported",
+                other
+            ));
+        }
+    };
 
-    // We encode minimal metadata here. This is a bit of a hack to be compatible with internal RE.
+    Ok(TDigest {
+        hash,
+        size_in_bytes: blob.len() as i64,
+        ..Default::default()
+    })
+}
 
-    let mut encoded = Vec::new();
-    Metadata {
-        platform: metadata.platform,
-        use_case_id: Some(metadata.use_case_id),
+fn batch_compressor(zstd: bool) -> compressor::Value {
+    if zstd {
+        compressor::Value::Zstd
+    } else {
+        compressor::Value::Identity
     }
-    .encode(&mut encoded)
-    .expect("Encoding into a Vec cannot not fail");
-    msg.metadata_mut()
-        .insert_bin("re-metadata-bin", MetadataValue::from_bytes(&encoded));
-    msg
 }
 
-/// Replace occurences of $FOO in a string with the value of the env var $FOO.
-fn substitute_env_vars(s: &str) -> anyhow::Result<String> {
-    substitute_env_vars_impl(s, |v| std::env::var(v))
+fn decompress_batch_blob(compressor: i32, data: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    match compressor::Value::from_i32(compressor) {
+        Some(compressor::Value::Identity) => Ok(data),
+        Some(compressor::Value::Zstd) => {
+            zstd::stream::decode_all(data.as_slice()).context("Error decompressing blob")
+        }
+        _ => Err(anyhow::anyhow!("Unsupported compressor: {}", compressor)),
+    }
+}
+
+/// Applies or undoes zstd compression on blob data that we transfer in chunks.
+enum StreamCodec {
+    Identity,
+    Compress(zstd::stream::write::Encoder<'static, Vec<u8>>),
+    Decompress(zstd::stream::write::Decoder<'static, Vec<u8>>),
+}
+
+impl StreamCodec {
+    fn compress(zstd: bool) -> anyhow::Result<Self> {
+        if !zstd {
+            return Ok(Self::Identity);
+        }
+        Ok(Self::Compress(zstd::stream::write::Encoder::new(
+            Vec::new(),
+            zstd::DEFAULT_COMPRESSION_LEVEL,
+        )?))
+    }
+
+    fn decompress(zstd: bool) -> anyhow::Result<Self> {
+        if !zstd {
+            return Ok(Self::Identity);
+        }
+        Ok(Self::Decompress(zstd::stream::write::Decoder::new(
+            Vec::new(),
+        )?))
+    }
+
+    /// Feed a chunk of input, returning whatever output is ready (which may be nothing).
+    fn push(&mut self, chunk: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Self::Identity => Ok(chunk),
+            Self::Compress(encoder) => {
+                encoder.write_all(&chunk).context("Error compressing")?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            Self::Decompress(decoder) => {
+                decoder.write_all(&chunk).context("Error decompressing")?;
+                decoder.flush().context("Error decompressing")?;
+                Ok(std::mem::take(decoder.get_mut()))
+            }
+        }
+    }
+
+    /// Return any remaining output. Nothing should be pushed after this.
+    fn finish(&mut self) -> anyhow::Result<Vec<u8>> {
+        match std::mem::replace(self, Self::Identity) {
+            Self::Identity => Ok(Vec::new()),
+            Self::Compress(encoder) => encoder.finish().context("Error compressing"),
+            Self::Decompress(mut decoder) => {
+                decoder.flush().context("Error decompressing")?;
+                Ok(decoder.into_inner())
+            }
+        }
+    }
+}
+
+/// Read a blob using ByteStream, writing chunks to `writer` as they arrive.
+async fn bytestream_download<Fs, FutS, W>(
+    digest: &TDigest,
+    opts: &RERuntimeOpts,
+    bytestream_read: &Fs,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    Fs: Fn(ReadRequest) -> FutS,
+    FutS: Future<Output = anyhow::Result<BoxStream<'static, anyhow::Result<ReadResponse>>>>,
+    W: AsyncWrite + Unpin,
+{
+    let mut codec = StreamCodec::decompress(opts.bytestream_zstd)?;
+
+    let mut stream = bytestream_read(ReadRequest {
+        resource_name: bytestream_read_resource_name(
+            &opts.instance_name,
+            digest,
+            opts.bytestream_zstd,
+        ),
+        read_offset: 0,
+        read_limit: 0,
+    })
+    .await?;
+
+    let mut received = 0;
+    while let Some(response) = stream.try_next().await.context("ByteStream read error")? {
+        let data = codec.push(response.data)?;
+        received += data.len() as i64;
+        writer.write_all(&data).await.context("Error writing")?;
+    }
+
+    let data = codec.finish()?;
+    received += data.len() as i64;
+    writer.write_all(&data).await.context("Error writing")?;
+
+    if received != digest.size_in_bytes {
+        return Err(anyhow::anyhow!(
+            "ByteStream read for `{}` returned {} bytes",
+            digest,
+            received
+        ));
+    }
+
+    Ok(())
+}
+
+async fn download_impl<Fr, FutR, Fs, FutS>(
+    request: DownloadRequest,
+    opts: &RERuntimeOpts,
+    batch_read: Fr,
+    bytestream_read: Fs,
+) -> anyhow::Result<DownloadResponse>
+where
+    Fr: Fn(BatchReadBlobsRequest) -> FutR,
+    FutR: Future<Output = anyhow::Result<BatchReadBlobsResponse>>,
+    Fs: Fn(ReadRequest) -> FutS,
+    FutS: Future<Output = anyhow::Result<BoxStream<'static, anyhow::Result<ReadResponse>>>>,
+{
+    let inlined_digests = request.inlined_digests.unwrap_or_default();
+    let file_digests = request.file_digests.unwrap_or_default();
+
+    // The same blob can be requested more than once (e.g. identical files), but we only need to
+    // fetch it once.
+    let mut seen = HashSet::new();
+    let batched_digests = file_digests
+        .iter()
+        .map(|req| &req.named_digest.digest)
+        .chain(inlined_digests.iter())
+        .filter(|d| fits_in_batch(d, opts.max_total_batch_size) && seen.insert(*d))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let batch_read = &batch_read;
+    let bytestream_read = &bytestream_read;
+
+    let batches = split_into_batches(batched_digests, |d| d, opts.max_total_batch_size);
+    let responses = futures::stream::iter(batches.into_iter().map(|digests| async move {
+        let response = batch_read(BatchReadBlobsRequest {
+            instance_name: opts.instance_name.clone(),
+            digests: digests.into_map(tdigest_to),
+            acceptable_compressors: vec![batch_compressor(opts.batch_zstd) as i32],
+        })
+        .await?;
+
+        response.responses.into_try_map(|r| {
+            check_status(r.status.unwrap_or_default())?;
+            let digest = tdigest_from(r.digest.context("Response digest not found.")?);
+            let data = decompress_batch_blob(r.compressor, r.data)
+                .with_context(|| format!("Invalid data for digest `{}`", digest))?;
+            anyhow::Ok((digest, data))
+        })
+    }))
+    .buffer_unordered(MAX_CONCURRENT_TRANSFERS)
+    .try_collect::<Vec<_>>()
+    .await?;
+
+    let response = responses.into_iter().flatten().collect::<HashMap<_, _>>();
+    let response = &response;
+
+    let inlined_blobs =
+        futures::future::try_join_all(inlined_digests.into_iter().map(|digest| async move {
+            let blob = match response.get(&digest) {
+                Some(data) => data.clone(),
+                None if !fits_in_batch(&digest, opts.max_total_batch_size) => {
+                    let mut blob = Vec::new();
+                    bytestream_download(&digest, opts, bytestream_read, &mut blob).await?;
+                    blob
+                }
+                None => {
+                    return Err(anyhow::anyhow!(
+                        "Did not receive digest data for `{}`",
+                        digest
+                    ));
+                }
+            };
+
+            anyhow::Ok(InlinedDigestWithStatus {
+                digest,
+                status: tstatus_ok(),
+                blob,
+            })
+        }))
+        .await?;
+
+    let writes = file_digests.iter().map(|req| async {
+        let digest = &req.named_digest.digest;
+
+        // `None` here means the blob is too large to have been batched, and we stream it
+        // straight into the file instead.
+        let data = match response.get(digest) {
+            Some(data) => Some(data),
+            None if !fits_in_batch(digest, opts.max_total_batch_size) => None,
+            None => {
+                return Err(anyhow::anyhow!(
+                    "Did not receive digest data for `{}`",
+                    digest
+                ));
+            }
+        };
+
+        let mut open_options = OpenOptions::new();
+        open_options.read(true).write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            if req.is_executable {
+                open_options.mode(0o755);
+            } else {
+                open_options.mode(0o644);
+            }
+        }
+
+        async {
+            let mut file = open_options
+                .open(&req.named_digest.name)
+                .await
+                .context("Error opening")?;
+            match data {
+                Some(data) => file.write_all(data).await.context("Error writing")?,
+                None => bytestream_download(digest, opts, bytestream_read, &mut file).await?,
+            }
+            file.flush().await.context("Error flushing")?;
+            anyhow::Ok(())
+        }
+        .await
+        .with_context(|| {
+            format!(
+                "Error writing digest `{}` to `{}`",
+                req.named_digest.digest, req.named_digest.name,
+            )
+        })
+    });
+
+    futures::future::try_join_all(writes).await?;
+
+    Ok(DownloadResponse {
+        inlined_blobs: Some(inlined_blobs),
+        directories: None,
+    })
+}
+
+/// Where the contents of a blob we are uploading come from.
+enum UploadSource {
+    Inlined(Vec<u8>),
+    File(String),
+}
+
+impl UploadSource {
+    async fn read(self) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Self::Inlined(data) => Ok(data),
+            Self::File(path) => tokio::fs::read(&path)
+                .await
+                .with_context(|| format!("Error reading `{}`", path)),
+        }
+    }
+
+    async fn open(self) -> anyhow::Result<Box<dyn AsyncRead + Send + Unpin>> {
+        match self {
+            Self::Inlined(data) => Ok(Box::new(std::io::Cursor::new(data))),
+            Self::File(path) => Ok(Box::new(
+                tokio::fs::File::open(&path)
+                    .await
+                    .with_context(|| format!("Error opening `{}`", path))?,
+            )),
+        }
+    }
+}
+
+async fn upload_batch<Fu, FutU>(
+    batch: Vec<(TDigest, UploadSource)>,
+    opts: &RERuntimeOpts,
+    batch_update: &Fu,
+) -> anyhow::Result<()>
+where
+    Fu: Fn(BatchUpdateBlobsRequest) -> FutU,
+    FutU: Future<Output = anyhow::Result<BatchUpdateBlobsResponse>>,
+{
+    let mut requests = Vec::with_capacity(batch.len());
+    for (digest, source) in batch {
+        let mut data = source.read().await?;
+        if opts.batch_zstd {
+            data = zstd::bulk::compress(&data, zstd::DEFAULT_COMPRESSION_LEVEL)
+                .with_context(|| format!("Error compressing `{}`", digest))?;
+        }
+        requests.push(Request {
+            data,
+            digest: Some(tdigest_to(digest)),
+            compressor: batch_compressor(opts.batch_zstd) as i32,
+        });
+    }
+
+    let re_request = BatchUpdateBlobsRequest {
+        instance_name: opts.instance_name.clone(),
+        requests,
+    };
+
+    let blob_hashes = re_request
+        .requests
+        .iter()
+        .map(|x| x.digest.as_ref().unwrap().hash.clone())
+        .collect::<Vec<String>>();
+    let response = batch_update(re_request).await?;
+
+    let failures: Vec<String> = response
+        .responses
+        .iter()
+        .filter_map(|r| {
+            r.status.as_ref().and_then(|s| {
+                if s.code == (Code::Ok as i32) {
+                    None
+                } else {
+                    Some(format!(
+                        "Unable to upload blob '{}', rpc status code: {}, message: \"{}\"",
+                        r.digest.as_ref().map_or("N/A", |d| &d.hash),
+                        s.code,
+                        s.message
+                    ))
+                }
+            })
+        })
+        .collect();
+
+    if failures.is_empty() {
+        tracing::debug!("uploaded: {:?}", blob_hashes);
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("Batch upload failed: {:?}", failures))
+    }
+}
+
+/// Write a blob using ByteStream. The data is read from `source` one chunk at a time as the
+/// server accepts it, so large files are never held in memory in full.
+async fn bytestream_upload<Fw, FutW>(
+    digest: TDigest,
+    source: UploadSource,
+    opts: &RERuntimeOpts,
+    bytestream_write: &Fw,
+) -> anyhow::Result<()>
+where
+    Fw: Fn(BoxStream<'static, WriteRequest>) -> FutW,
+    FutW: Future<Output = anyhow::Result<WriteResponse>>,
+{
+    let (mut tx, rx) = futures::channel::mpsc::channel(1);
+    let size = digest.size_in_bytes;
+    let zstd = opts.bytestream_zstd;
+    let resource_name = bytestream_write_resource_name(&opts.instance_name, &digest, zstd);
+    let mut codec = StreamCodec::compress(zstd)?;
+
+    let produce = async move {
+        let mut reader = source.open().await?;
+        let mut resource_name = Some(resource_name);
+        let mut read = 0;
+        let mut write_offset = 0;
+
+        loop {
+            let mut chunk = Vec::with_capacity(BYTESTREAM_CHUNK_SIZE);
+            (&mut reader)
+                .take(BYTESTREAM_CHUNK_SIZE as u64)
+                .read_to_end(&mut chunk)
+                .await
+                .context("Error reading blob")?;
+
+            if chunk.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Blob ended after {} bytes, expected {}",
+                    read,
+                    size
+                ));
+            }
+
+            read += chunk.len() as i64;
+            let finish_write = read >= size;
+
+            let mut data = codec.push(chunk)?;
+            if finish_write {
+                data.extend(codec.finish()?);
+            } else if data.is_empty() {
+                // The compressor is still buffering.
+                continue;
+            }
+
+            let request = WriteRequest {
+                // Only the first request needs to carry the resource name.
+                resource_name: resource_name.take().unwrap_or_default(),
+                write_offset,
+                finish_write,
+                data,
+            };
+            write_offset += request.data.len() as i64;
+
+            // If the receiver went away, the write failed, and the error will come from there.
+            if tx.send(request).await.is_err() || finish_write {
+                break;
+            }
+        }
+
+        anyhow::Ok(())
+    };
+
+    let write = async {
+        let response = bytestream_write(rx.boxed()).await?;
+        // For compressed uploads, the committed size isn't the size of the blob, and servers
+        // don't agree on what it should be instead, so there is nothing to check.
+        if !zstd && response.committed_size != size {
+            return Err(anyhow::anyhow!(
+                "ByteStream write committed {} bytes, expected {}",
+                response.committed_size,
+                size
+            ));
+        }
+        anyhow::Ok(())
+    };
+
+    futures::future::try_join(produce, write)
+        .await
+        .with_context(|| format!("Error uploading `{}`", digest))?;
+
+    Ok(())
+}
+
+/// Upload the blobs in `request` that the CAS does not already have. We always ask the CAS
+/// which blobs are missing first (regardless of `upload_only_missing`), since sending a blob
+/// the server already has is far more expensive than the `FindMissingBlobs` round trip.
+async fn upload_impl<Fm, FutM, Fu, FutU, Fw, FutW>(
+    request: UploadRequest,
+    opts: &RERuntimeOpts,
+    find_missing: Fm,
+    batch_update: Fu,
+    bytestream_write: Fw,
+) -> anyhow::Result<UploadStats>
+where
+    Fm: FnOnce(GFindMissingBlobsRequest) -> FutM,
+    FutM: Future<Output = anyhow::Result<GFindMissingBlobsResponse>>,
+    Fu: Fn(BatchUpdateBlobsRequest) -> FutU,
+    FutU: Future<Output = anyhow::Result<BatchUpdateBlobsResponse>>,
+    Fw: Fn(BoxStream<'static, WriteRequest>) -> FutW,
+    FutW: Future<Output = anyhow::Result<WriteResponse>>,
+{
+    let inlined_blobs = request.inlined_blobs_with_digest.unwrap_or_default();
+    let files_with_digest = request.files_with_digest.unwrap_or_default();
+
+    let mut stats = UploadStats::default();
+
+    if inlined_blobs.is_empty() && files_with_digest.is_empty() {
+        return Ok(stats);
+    }
+
+    let missing = find_missing(GFindMissingBlobsRequest {
+        instance_name: opts.instance_name.clone(),
+        blob_digests: inlined_blobs
+            .iter()
+            .map(|x| &x.digest)
+            .chain(files_with_digest.iter().map(|x| &x.digest))
+            .map(|d| tdigest_to(d.clone()))
+            .collect(),
+    })
+    .await
+    .context("Error finding missing blobs")?;
+
+    // We remove digests from this set as we schedule them for upload, so that a blob that shows
+    // up more than once in the request only gets sent once.
+    let mut missing = missing
+        .missing_blob_digests
+        .into_iter()
+        .map(tdigest_from)
+        .collect::<HashSet<_>>();
+
+    let blobs = inlined_blobs
+        .into_iter()
+        .map(|x| (x.digest, UploadSource::Inlined(x.blob)))
+        .chain(
+            files_with_digest
+                .into_iter()
+                .map(|x| (x.digest, UploadSource::File(x.name))),
+        );
+
+    let mut batched = Vec::new();
+    let mut streamed = Vec::new();
+
+    for (digest, source) in blobs {
+        if !missing.remove(&digest) {
+            stats.skipped += digest.size_in_bytes;
+            continue;
+        }
+
+        stats.uploaded += digest.size_in_bytes;
+        if fits_in_batch(&digest, opts.max_total_batch_size) {
+            batched.push((digest, source));
+        } else {
+            streamed.push((digest, source));
+        }
+    }
+
+    if batched.is_empty() && streamed.is_empty() {
+        tracing::debug!(
+            "upload skipped, all {} bytes already present",
+            stats.skipped
+        );
+        return Ok(stats);
+    }
+
+    let batch_update = &batch_update;
+    let bytestream_write = &bytestream_write;
+
+    let batches = split_into_batches(batched, |(digest, _)| digest, opts.max_total_batch_size);
+    let batch_uploads = futures::stream::iter(
+        batches
+            .into_iter()
+            .map(|batch| upload_batch(batch, opts, batch_update)),
+    )
+    .buffer_unordered(MAX_CONCURRENT_TRANSFERS)
+    .try_collect::<Vec<_>>();
+
+    let stream_uploads = futures::stream::iter(
+        streamed
+            .into_iter()
+            .map(|(digest, source)| bytestream_upload(digest, source, opts, bytestream_write)),
+    )
+    .buffer_unordered(MAX_CONCURRENT_TRANSFERS)
+    .try_collect::<Vec<_>>();
+
+    futures::future::try_join(batch_uploads, stream_uploads).await?;
+
+    Ok(stats)
+}
+
+fn with_internal_metadata<T>(t: T, metadata: RemoteExecutionMetadata) -> tonic::Request<T> {
+    // This is pretty ugly, but the protobuf spec that defines this is internal, so considering
+    // field numbers need to be stable anyway (= low risk), and this is not used in prod (= low
+    // impact if this goes wrong), we just inline it here. This is a small hack that lets us use
+    // our internal RE using this GRPC client for testing.
+    //
+    // This is defined in `fbcode/remote_execution/grpc/metadata.proto`.
+    #[derive(prost::Message)]
+    struct Metadata {
+        #[prost(message, optional, tag = "15")]
+        platform: Option<crate::grpc::Platform>,
+        #[prost(string, optional, tag = "18")]
+        use_case_id: Option<String>,
+    }
+
+    let mut msg = tonic::Request::new(t);
+
+    // We encode minimal metadata here. This is a bit of a hack to be compatible with internal RE.
+
+    let mut encoded = Vec::new();
+    Metadata {
+        platform: metadata.platform,
+        use_case_id: Some(metadata.use_case_id),
+    }
+    .encode(&mut encoded)
+    .expect("Encoding into a Vec cannot not fail");
+    msg.metadata_mut()
+        .insert_bin("re-metadata-bin", MetadataValue::from_bytes(&encoded));
+    msg
+}
+
+/// Replace occurences of $FOO in a string with the value of the env var $FOO.
+fn substitute_env_vars(s: &str) -> anyhow::Result<String> {
+    substitute_env_vars_impl(s, |v| std::env::var(v))
 }
 
 fn substitute_env_vars_impl(
@@ -750,6 +1877,9 @@ mod tests {
     use re_grpc_proto::build::bazel::remote::execution::v2::batch_read_blobs_response;
 
     use super::*;
+    use crate::Directory;
+    use crate::FileNode;
+    use crate::InlinedBlobWithDigest;
     use crate::NamedDigest;
     use crate::NamedDigestWithPermissions;
 
@@ -763,13 +1893,13 @@ mod tests {
         let path2 = work.path().join("path2");
         let path2 = path2.to_str().context("tempdir is not utf8")?;
 
-        let digest1 = TDigest {
+        let digest1 = &TDigest {
             hash: "aa".to_owned(),
             size_in_bytes: 3,
             ..Default::default()
         };
 
-        let digest2 = TDigest {
+        let digest2 = &TDigest {
             hash: "bb".to_owned(),
             size_in_bytes: 3,
             ..Default::default()
@@ -799,7 +1929,7 @@ mod tests {
             ..Default::default()
         };
 
-        let res = BatchReadBlobsResponse {
+        let res = &BatchReadBlobsResponse {
             responses: vec![
                 // Reply out of order
                 batch_read_blobs_response::Response {
@@ -815,12 +1945,17 @@ mod tests {
             ],
         };
 
-        download_impl(req, |req| async move {
-            assert_eq!(req.digests.len(), 2);
-            assert_eq!(req.digests[0], tdigest_to(digest1.clone()));
-            assert_eq!(req.digests[1], tdigest_to(digest2.clone()));
-            Ok(res)
-        })
+        download_impl(
+            req,
+            &RERuntimeOpts::default(),
+            |req| async move {
+                assert_eq!(req.digests.len(), 2);
+                assert_eq!(req.digests[0], tdigest_to(digest1.clone()));
+                assert_eq!(req.digests[1], tdigest_to(digest2.clone()));
+                Ok(res.clone())
+            },
+            |_req| async move { Err(anyhow::anyhow!("Unexpected ByteStream read")) },
+        )
         .await?;
 
         assert_eq!(tokio::fs::read(&path1).await?, vec![1, 2, 3]);
@@ -861,7 +1996,7 @@ mod tests {
             ..Default::default()
         };
 
-        let res = BatchReadBlobsResponse {
+        let res = &BatchReadBlobsResponse {
             responses: vec![
                 // Reply out of order
                 batch_read_blobs_response::Response {
@@ -877,12 +2012,17 @@ mod tests {
             ],
         };
 
-        let res = download_impl(req, |req| async move {
-            assert_eq!(req.digests.len(), 2);
-            assert_eq!(req.digests[0], tdigest_to(digest1.clone()));
-            assert_eq!(req.digests[1], tdigest_to(digest2.clone()));
-            Ok(res)
-        })
+        let res = download_impl(
+            req,
+            &RERuntimeOpts::default(),
+            |req| async move {
+                assert_eq!(req.digests.len(), 2);
+                assert_eq!(req.digests[0], tdigest_to(digest1.clone()));
+                assert_eq!(req.digests[1], tdigest_to(digest2.clone()));
+                Ok(res.clone())
+            },
+            |_req| async move { Err(anyhow::anyhow!("Unexpected ByteStream read")) },
+        )
         .await?;
 
         let inlined_blobs = res.inlined_blobs.unwrap();
@@ -898,6 +2038,625 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_download_bytestream() -> anyhow::Result<()> {
+        let work = tempfile::tempdir()?;
+
+        let path1 = work.path().join("path1");
+        let path1 = path1.to_str().context("tempdir is not utf8")?;
+
+        let small = &TDigest {
+            hash: "aa".to_owned(),
+            size_in_bytes: 3,
+            ..Default::default()
+        };
+
+        let large = &TDigest {
+            hash: "bb".to_owned(),
+            size_in_bytes: 2000,
+            ..Default::default()
+        };
+
+        let req = DownloadRequest {
+            inlined_digests: Some(vec![small.clone(), large.clone()]),
+            file_digests: Some(vec![NamedDigestWithPermissions {
+                named_digest: NamedDigest {
+                    name: path1.to_owned(),
+                    digest: large.clone(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+
+        let res = download_impl(
+            req,
+            &RERuntimeOpts {
+                max_total_batch_size: 1024,
+                ..Default::default()
+            },
+            |req| async move {
+                assert_eq!(req.digests, vec![tdigest_to(small.clone())]);
+                Ok(BatchReadBlobsResponse {
+                    responses: vec![batch_read_blobs_response::Response {
+                        digest: Some(tdigest_to(small.clone())),
+                        data: vec![1, 2, 3],
+                        ..Default::default()
+                    }],
+                })
+            },
+            |req| async move {
+                assert_eq!(req.resource_name, "blobs/bb/2000");
+                Ok(futures::stream::iter(vec![
+                    anyhow::Ok(ReadResponse {
+                        data: vec![7; 1500],
+                    }),
+                    anyhow::Ok(ReadResponse { data: vec![7; 500] }),
+                ])
+                .boxed())
+            },
+        )
+        .await?;
+
+        assert_eq!(tokio::fs::read(&path1).await?, vec![7; 2000]);
+
+        let inlined_blobs = res.inlined_blobs.unwrap();
+        assert_eq!(inlined_blobs.len(), 2);
+        assert_eq!(inlined_blobs[0].blob, vec![1, 2, 3]);
+        assert_eq!(inlined_blobs[1].blob, vec![7; 2000]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload_only_missing() -> anyhow::Result<()> {
+        let work = tempfile::tempdir()?;
+
+        let path1 = work.path().join("path1");
+        tokio::fs::write(&path1, [7, 8, 9]).await?;
+        let path1 = path1.to_str().context("tempdir is not utf8")?;
+
+        let digest1 = &TDigest {
+            hash: "aa".to_owned(),
+            size_in_bytes: 3,
+            ..Default::default()
+        };
+
+        let digest2 = &TDigest {
+            hash: "bb".to_owned(),
+            size_in_bytes: 4,
+            ..Default::default()
+        };
+
+        let digest3 = &TDigest {
+            hash: "cc".to_owned(),
+            size_in_bytes: 3,
+            ..Default::default()
+        };
+
+        let req = UploadRequest {
+            files_with_digest: Some(vec![NamedDigest {
+                name: path1.to_owned(),
+                digest: digest3.clone(),
+                ..Default::default()
+            }]),
+            inlined_blobs_with_digest: Some(vec![
+                InlinedBlobWithDigest {
+                    blob: vec![1, 2, 3],
+                    digest: digest1.clone(),
+                    ..Default::default()
+                },
+                InlinedBlobWithDigest {
+                    blob: vec![4, 5, 6, 7],
+                    digest: digest2.clone(),
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        };
+
+        let stats = upload_impl(
+            req,
+            &RERuntimeOpts::default(),
+            |req| async move {
+                assert_eq!(req.blob_digests.len(), 3);
+                Ok(GFindMissingBlobsResponse {
+                    missing_blob_digests: vec![
+                        tdigest_to(digest1.clone()),
+                        tdigest_to(digest3.clone()),
+                    ],
+                })
+            },
+            |req| async move {
+                assert_eq!(req.requests.len(), 2);
+                assert_eq!(req.requests[0].digest, Some(tdigest_to(digest1.clone())));
+                assert_eq!(req.requests[0].data, vec![1, 2, 3]);
+                assert_eq!(req.requests[1].digest, Some(tdigest_to(digest3.clone())));
+                assert_eq!(req.requests[1].data, vec![7, 8, 9]);
+                Ok(BatchUpdateBlobsResponse { responses: vec![] })
+            },
+            |_req| async move { Err(anyhow::anyhow!("Unexpected ByteStream write")) },
+        )
+        .await?;
+
+        assert_eq!(
+            stats,
+            UploadStats {
+                uploaded: 6,
+                skipped: 4
+            }
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload_nothing_missing() -> anyhow::Result<()> {
+        let digest1 = TDigest {
+            hash: "aa".to_owned(),
+            size_in_bytes: 3,
+            ..Default::default()
+        };
+
+        let req = UploadRequest {
+            inlined_blobs_with_digest: Some(vec![InlinedBlobWithDigest {
+                blob: vec![1, 2, 3],
+                digest: digest1,
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+
+        let stats = upload_impl(
+            req,
+            &RERuntimeOpts::default(),
+            |_req| async move {
+                Ok(GFindMissingBlobsResponse {
+                    missing_blob_digests: vec![],
+                })
+            },
+            |_req| async move { Err(anyhow::anyhow!("Nothing should be uploaded")) },
+            |_req| async move { Err(anyhow::anyhow!("Nothing should be uploaded")) },
+        )
+        .await?;
+
+        assert_eq!(
+            stats,
+            UploadStats {
+                uploaded: 0,
+                skipped: 3
+            }
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload_bytestream() -> anyhow::Result<()> {
+        let work = tempfile::tempdir()?;
+
+        let path1 = work.path().join("path1");
+        tokio::fs::write(&path1, vec![42; BYTESTREAM_CHUNK_SIZE + 10]).await?;
+        let path1 = path1.to_str().context("tempdir is not utf8")?;
+
+        let digest1 = &TDigest {
+            hash: "aa".to_owned(),
+            size_in_bytes: (BYTESTREAM_CHUNK_SIZE + 10) as i64,
+            ..Default::default()
+        };
+
+        let req = UploadRequest {
+            files_with_digest: Some(vec![NamedDigest {
+                name: path1.to_owned(),
+                digest: digest1.clone(),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+
+        let stats = upload_impl(
+            req,
+            &RERuntimeOpts {
+                max_total_batch_size: 1024,
+                ..Default::default()
+            },
+            |req| async move {
+                Ok(GFindMissingBlobsResponse {
+                    missing_blob_digests: req.blob_digests,
+                })
+            },
+            |_req| async move { Err(anyhow::anyhow!("Unexpected batch upload")) },
+            |stream| async move {
+                let requests = stream.collect::<Vec<_>>().await;
+                assert_eq!(requests.len(), 2);
+
+                assert!(requests[0].resource_name.starts_with("uploads/"));
+                assert!(
+                    requests[0]
+                        .resource_name
+                        .ends_with(&format!("/blobs/aa/{}", digest1.size_in_bytes))
+                );
+                assert_eq!(requests[0].write_offset, 0);
+                assert_eq!(requests[0].data.len(), BYTESTREAM_CHUNK_SIZE);
+                assert!(!requests[0].finish_write);
+
+                assert_eq!(requests[1].resource_name, "");
+                assert_eq!(requests[1].write_offset, BYTESTREAM_CHUNK_SIZE as i64);
+                assert_eq!(requests[1].data, vec![42; 10]);
+                assert!(requests[1].finish_write);
+
+                Ok(WriteResponse {
+                    committed_size: digest1.size_in_bytes,
+                })
+            },
+        )
+        .await?;
+
+        assert_eq!(
+            stats,
+            UploadStats {
+                uploaded: digest1.size_in_bytes,
+                skipped: 0
+            }
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_bytestream_zstd() -> anyhow::Result<()> {
+        let work = tempfile::tempdir()?;
+
+        let path1 = work.path().join("path1");
+        let path1 = path1.to_str().context("tempdir is not utf8")?;
+
+        let data = (0..5000).map(|i| (i % 7) as u8).collect::<Vec<_>>();
+        let compressed = &zstd::bulk::compress(&data, 0)?;
+
+        let digest1 = TDigest {
+            hash: "aa".to_owned(),
+            size_in_bytes: data.len() as i64,
+            ..Default::default()
+        };
+
+        let req = DownloadRequest {
+            file_digests: Some(vec![NamedDigestWithPermissions {
+                named_digest: NamedDigest {
+                    name: path1.to_owned(),
+                    digest: digest1,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+
+        download_impl(
+            req,
+            &RERuntimeOpts {
+                max_total_batch_size: 1024,
+                bytestream_zstd: true,
+                ..Default::default()
+            },
+            |_req| async move { Err(anyhow::anyhow!("Unexpected batch read")) },
+            |req| async move {
+                assert_eq!(req.resource_name, "compressed-blobs/zstd/aa/5000");
+                let (first, second) = compressed.split_at(compressed.len() / 2);
+                Ok(futures::stream::iter(vec![
+                    anyhow::Ok(ReadResponse {
+                        data: first.to_vec(),
+                    }),
+                    anyhow::Ok(ReadResponse {
+                        data: second.to_vec(),
+                    }),
+                ])
+                .boxed())
+            },
+        )
+        .await?;
+
+        assert_eq!(tokio::fs::read(&path1).await?, data);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_upload_zstd() -> anyhow::Result<()> {
+        let small = vec![1; 100];
+        let large = vec![2; BYTESTREAM_CHUNK_SIZE * 2];
+
+        let small_digest = TDigest {
+            hash: "aa".to_owned(),
+            size_in_bytes: small.len() as i64,
+            ..Default::default()
+        };
+
+        let large_digest = TDigest {
+            hash: "bb".to_owned(),
+            size_in_bytes: large.len() as i64,
+            ..Default::default()
+        };
+
+        let req = UploadRequest {
+            inlined_blobs_with_digest: Some(vec![
+                InlinedBlobWithDigest {
+                    blob: small.clone(),
+                    digest: small_digest,
+                    ..Default::default()
+                },
+                InlinedBlobWithDigest {
+                    blob: large.clone(),
+                    digest: large_digest,
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        };
+
+        let small = &small;
+        let large = &large;
+
+        upload_impl(
+            req,
+            &RERuntimeOpts {
+                max_total_batch_size: 1024,
+                batch_zstd: true,
+                bytestream_zstd: true,
+                ..Default::default()
+            },
+            |req| async move {
+                Ok(GFindMissingBlobsResponse {
+                    missing_blob_digests: req.blob_digests,
+                })
+            },
+            |req| async move {
+                assert_eq!(req.requests.len(), 1);
+                assert_eq!(req.requests[0].compressor, compressor::Value::Zstd as i32);
+                assert_eq!(
+                    zstd::stream::decode_all(req.requests[0].data.as_slice()).unwrap(),
+                    *small
+                );
+                Ok(BatchUpdateBlobsResponse { responses: vec![] })
+            },
+            |stream| async move {
+                let requests = stream.collect::<Vec<_>>().await;
+                assert!(requests[0].resource_name.starts_with("uploads/"));
+                assert!(
+                    requests[0]
+                        .resource_name
+                        .ends_with(&format!("/compressed-blobs/zstd/bb/{}", large.len()))
+                );
+                assert!(requests.last().unwrap().finish_write);
+
+                let compressed = requests
+                    .iter()
+                    .flat_map(|r| r.data.iter().copied())
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    zstd::stream::decode_all(compressed.as_slice()).unwrap(),
+                    *large
+                );
+
+                Ok(WriteResponse { committed_size: -1 })
+            },
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_runtime_opts_from_capabilities() {
+        use re_grpc_proto::build::bazel::remote::execution::v2::CacheCapabilities;
+        use re_grpc_proto::build::bazel::remote::execution::v2::ExecutionCapabilities;
+
+        let config = Buck2OssReConfiguration::default();
+
+        let opts = RERuntimeOpts::new(&config, None);
+        assert_eq!(opts.max_total_batch_size, DEFAULT_MAX_TOTAL_BATCH_SIZE);
+        assert!(!opts.batch_zstd);
+        assert!(!opts.bytestream_zstd);
+        assert_eq!(opts.digest_function, None);
+
+        let capabilities = ServerCapabilities {
+            cache_capabilities: Some(CacheCapabilities {
+                digest_functions: vec![digest_function::Value::Sha256 as i32],
+                max_batch_total_size_bytes: 1000,
+                supported_compressors: vec![compressor::Value::Zstd as i32],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let opts = RERuntimeOpts::new(&config, Some(&capabilities));
+        assert_eq!(opts.max_total_batch_size, 1000);
+        assert!(!opts.batch_zstd);
+        assert!(opts.bytestream_zstd);
+        assert_eq!(opts.digest_function, Some(digest_function::Value::Sha256));
+
+        let capabilities = ServerCapabilities {
+            cache_capabilities: Some(CacheCapabilities {
+                digest_functions: vec![
+                    digest_function::Value::Sha256 as i32,
+                    digest_function::Value::Sha1 as i32,
+                ],
+                supported_batch_update_compressors: vec![compressor::Value::Zstd as i32],
+                ..Default::default()
+            }),
+            execution_capabilities: Some(ExecutionCapabilities {
+                digest_function: digest_function::Value::Sha1 as i32,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let config = Buck2OssReConfiguration {
+            max_total_batch_size: Some(2000),
+            ..Default::default()
+        };
+
+        let opts = RERuntimeOpts::new(&config, Some(&capabilities));
+        assert_eq!(opts.max_total_batch_size, 2000);
+        assert!(opts.batch_zstd);
+        assert!(!opts.bytestream_zstd);
+        assert_eq!(opts.digest_function, Some(digest_function::Value::Sha1));
+    }
+
+    #[test]
+    fn test_check_digest_function() {
+        let digest = |hash: &str| TDigest {
+            hash: hash.to_owned(),
+            size_in_bytes: 1,
+            ..Default::default()
+        };
+
+        let sha1 = digest(&"a".repeat(40));
+        let sha256 = digest(&"a".repeat(64));
+
+        assert!(check_digest_function(None, &sha1).is_ok());
+        assert!(check_digest_function(Some(digest_function::Value::Sha1), &sha1).is_ok());
+        assert!(check_digest_function(Some(digest_function::Value::Sha256), &sha256).is_ok());
+        assert!(check_digest_function(Some(digest_function::Value::Sha256), &sha1).is_err());
+    }
+
+    #[test]
+    fn test_split_into_batches() {
+        let digest = |size| TDigest {
+            hash: "aa".to_owned(),
+            size_in_bytes: size,
+            ..Default::default()
+        };
+
+        let max_total_batch_size = BATCH_BLOB_OVERHEAD * 2 + 20;
+
+        assert!(fits_in_batch(&digest(20), max_total_batch_size));
+        assert!(!fits_in_batch(
+            &digest(max_total_batch_size as i64),
+            max_total_batch_size
+        ));
+
+        let batches = split_into_batches(
+            vec![digest(10), digest(10), digest(5), digest(20)],
+            |d| d,
+            max_total_batch_size,
+        );
+
+        let sizes = batches
+            .iter()
+            .map(|b| b.iter().map(|d| d.size_in_bytes).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        assert_eq!(sizes, vec![vec![10, 10], vec![5], vec![20]]);
+    }
+
+    #[test]
+    fn test_digests_ttl() {
+        let present = TDigest {
+            hash: "aa".to_owned(),
+            size_in_bytes: 1,
+            ..Default::default()
+        };
+        let missing = TDigest {
+            hash: "bb".to_owned(),
+            size_in_bytes: 2,
+            ..Default::default()
+        };
+
+        let res = digests_ttl(
+            vec![present.clone(), missing.clone()],
+            vec![missing.clone()],
+            3600,
+        );
+        let ttls = res.digests_with_ttl.into_map(|d| (d.digest, d.ttl));
+        assert_eq!(ttls, vec![(present, 3600), (missing, 0)]);
+    }
+
+    #[tokio::test]
+    async fn test_get_tree_pagination() -> anyhow::Result<()> {
+        let root = TDigest {
+            hash: "aa".to_owned(),
+            size_in_bytes: 1,
+            ..Default::default()
+        };
+
+        let dir = |name: &str| Directory {
+            files: vec![FileNode {
+                name: name.to_owned(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let res = get_tree_impl("instance", root, |req| async move {
+            assert_eq!(req.instance_name, "instance");
+            assert_eq!(req.root_digest.map(|d| d.hash), Some("aa".to_owned()));
+
+            let pages = match req.page_token.as_str() {
+                "" => vec![
+                    GGetTreeResponse {
+                        directories: vec![dir("root")],
+                        next_page_token: "1".to_owned(),
+                    },
+                    GGetTreeResponse {
+                        directories: vec![dir("a")],
+                        next_page_token: "2".to_owned(),
+                    },
+                ],
+                "2" => vec![GGetTreeResponse {
+                    directories: vec![dir("b")],
+                    next_page_token: String::new(),
+                }],
+                _ => vec![],
+            };
+            Ok(pages)
+        })
+        .await?;
+
+        let names = res
+            .directories
+            .into_map(|d| d.files.into_iter().next().map(|f| f.name));
+        assert_eq!(
+            names,
+            vec![
+                Some("root".to_owned()),
+                Some("a".to_owned()),
+                Some("b".to_owned())
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_tree_repeated_token() {
+        let res = get_tree_impl("", TDigest::default(), |_req| async move {
+            Ok(vec![GGetTreeResponse {
+                directories: vec![Directory::default()],
+                next_page_token: "1".to_owned(),
+            }])
+        })
+        .await;
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_compute_digest() -> anyhow::Result<()> {
+        let digest = compute_digest(None, b"foo")?;
+        assert_eq!(
+            digest.hash,
+            "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae"
+        );
+        assert_eq!(digest.size_in_bytes, 3);
+
+        let digest = compute_digest(Some(digest_function::Value::Sha1), b"foo")?;
+        assert_eq!(digest.hash, "0beec7b5ea3f0fdbc95d0dd47f3c5bc275da8a33");
+
+        assert!(compute_digest(Some(digest_function::Value::Md5), b"foo").is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_substitute_env_vars() {
         let getter = |s: &str| match s {
@@ -923,4 +2682,35 @@ mod tests {
         assert_eq!(substitute_env_vars_impl("FOO", getter).unwrap(), "FOO");
         assert!(substitute_env_vars_impl("$FOO$BAZ", getter).is_err());
     }
+
+    #[tokio::test]
+    async fn test_create_headers() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let token_file = dir.path().join("token");
+        tokio::fs::write(&token_file, "secret\n").await?;
+
+        let opts = Buck2OssReConfiguration {
+            http_headers: vec!["x-foo: bar".parse()?],
+            auth_token_file: Some(token_file.to_str().unwrap().to_owned()),
+            ..Default::default()
+        };
+        let headers = create_headers(&opts).await?;
+        let headers = headers
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.to_str().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            headers,
+            vec![("x-foo", "bar"), ("authorization", "Bearer secret")]
+        );
+
+        let opts = Buck2OssReConfiguration {
+            auth_token_file: Some(token_file.to_str().unwrap().to_owned()),
+            auth_token_helper: Some("echo secret".to_owned()),
+            ..Default::default()
+        };
+        assert!(create_headers(&opts).await.is_err());
+
+        Ok(())
+    }
 }
@@ -42,6 +42,7 @@ pub struct TCode(pub i32);
 impl TCode {
     pub const OK: Self = TCode(0i32);
     pub const INVALID_ARGUMENT: Self = TCode(3i32);
+    pub const DEADLINE_EXCEEDED: Self = TCode(4i32);
     pub const NOT_FOUND: Self = TCode(5i32);
 }
 
@@ -51,6 +52,8 @@ impl Display for TCode {
             write!(f, "OK")
         } else if self == &TCode::INVALID_ARGUMENT {
             write!(f, "INVALID_ARGUMENT")
+        } else if self == &TCode::DEADLINE_EXCEEDED {
+            write!(f, "DEADLINE_EXCEEDED")
         } else {
             write!(f, "UNKNOWN")
         }
@@ -19,6 +19,9 @@ mod grpc;
 mod metadata;
 mod request;
 mod response;
+mod retry;
+#[cfg(all(test, unix))]
+mod server_tests;
 pub use client::*;
 pub use digest::*;
 pub use error::*;
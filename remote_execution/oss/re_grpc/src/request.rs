@@ -73,6 +73,12 @@ pub struct GetDigestsTtlRequest {
     pub _dot_dot: (),
 }
 
+#[derive(Default)]
+pub struct GetTreeRequest {
+    pub digest: TDigest,
+    pub _dot_dot: (),
+}
+
 #[derive(Clone, Default)]
 pub struct ExecuteRequest {
     pub action_digest: TDigest,
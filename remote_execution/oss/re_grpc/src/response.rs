@@ -136,6 +136,12 @@ pub struct GetDigestsTtlResponse {
     pub digests_with_ttl: Vec<DigestWithTtl>,
 }
 
+#[derive(Clone, Default)]
+pub struct GetTreeResponse {
+    /// The root directory comes first, followed by all its descendants.
+    pub directories: Vec<crate::grpc::Directory>,
+}
+
 #[derive(Clone, Default)]
 pub struct ExecuteResponse {
     pub action_result: TActionResult2,
@@ -207,6 +213,10 @@ pub struct TFile {
 pub struct NetworkStatisticsResponse {
     pub uploaded: i64,
     pub downloaded: i64,
+    /// Bytes that did not need uploading because the CAS already had them.
+    pub upload_skipped: i64,
+    /// Requests that were sent again after failing with a transient error.
+    pub retries: i64,
     // Compatibility with the Thrift structs
     pub _dot_dot_default: (),
 }
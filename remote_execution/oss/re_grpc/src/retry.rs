@@ -0,0 +1,220 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::sync::atomic::AtomicI64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use buck2_re_configuration::Buck2OssReConfiguration;
+use futures::future::Future;
+use rand::Rng;
+use tonic::Code;
+use tonic::Status;
+
+const DEFAULT_MAX_RETRIES: usize = 3;
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// How we retry requests that fail with a transient error. This only applies to requests that
+/// are safe to send more than once.
+#[derive(Clone, Debug)]
+pub(crate) struct RetryPolicy {
+    /// How many times to retry after the first attempt failed.
+    pub(crate) max_retries: usize,
+    /// Backoff before the first retry. This doubles with every subsequent retry.
+    pub(crate) initial_backoff: Duration,
+    /// Upper bound for the backoff between two attempts.
+    pub(crate) max_backoff: Duration,
+    /// Deadline for a single attempt, if any.
+    pub(crate) rpc_timeout: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: DEFAULT_MAX_RETRIES,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            rpc_timeout: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub(crate) fn new(opts: &Buck2OssReConfiguration) -> Self {
+        Self {
+            max_retries: opts.max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+            initial_backoff: opts
+                .retry_backoff_ms
+                .map_or(DEFAULT_INITIAL_BACKOFF, Duration::from_millis),
+            max_backoff: opts
+                .max_retry_backoff_ms
+                .map_or(DEFAULT_MAX_BACKOFF, Duration::from_millis),
+            rpc_timeout: opts.rpc_timeout_ms.map(Duration::from_millis),
+        }
+    }
+
+    /// How long to wait before retry number `attempt` (starting at zero). We apply jitter so that
+    /// clients that failed at the same time don't all come back at the same time.
+    pub(crate) fn backoff(&self, attempt: usize) -> Duration {
+        let exponent = u32::try_from(attempt).unwrap_or(u32::MAX).min(16);
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+        backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+/// Whether this error is likely to go away if we send the same request again.
+pub(crate) fn is_retryable(status: &Status) -> bool {
+    matches!(
+        status.code(),
+        Code::Unavailable | Code::ResourceExhausted | Code::Aborted | Code::DeadlineExceeded
+    )
+}
+
+/// Run `f` until it succeeds, fails with an error that isn't retryable, or we run out of retries.
+/// Every retry is counted in `retries`.
+pub(crate) async fn with_retries<T, F, Fut>(
+    policy: &RetryPolicy,
+    retries: &AtomicI64,
+    what: &str,
+    mut f: F,
+) -> Result<T, Status>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Status>>,
+{
+    let mut attempt = 0;
+
+    loop {
+        let res = match policy.rpc_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, f()).await {
+                Ok(res) => res,
+                Err(_) => Err(Status::deadline_exceeded(format!(
+                    "{} timed out after {:?}",
+                    what, timeout
+                ))),
+            },
+            None => f().await,
+        };
+
+        match res {
+            Err(status) if attempt < policy.max_retries && is_retryable(&status) => {
+                let backoff = policy.backoff(attempt);
+                tracing::debug!("Retrying {} in {:?}: {}", what, backoff, status);
+                retries.fetch_add(1, Ordering::Relaxed);
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+            rpc_timeout: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_transient_errors() {
+        let attempts = &AtomicUsize::new(0);
+        let retries = AtomicI64::new(0);
+
+        let res = with_retries(&policy(), &retries, "test", || async move {
+            if attempts.fetch_add(1, Ordering::Relaxed) == 0 {
+                Err(Status::unavailable("try again"))
+            } else {
+                Ok(42)
+            }
+        })
+        .await;
+
+        assert_eq!(res.unwrap(), 42);
+        assert_eq!(retries.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up() {
+        let attempts = &AtomicUsize::new(0);
+        let retries = AtomicI64::new(0);
+
+        let res: Result<(), _> = with_retries(&policy(), &retries, "test", || async move {
+            attempts.fetch_add(1, Ordering::Relaxed);
+            Err(Status::unavailable("try again"))
+        })
+        .await;
+
+        assert_eq!(res.unwrap_err().code(), Code::Unavailable);
+        assert_eq!(attempts.load(Ordering::Relaxed), 3);
+        assert_eq!(retries.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_permanent_errors() {
+        let attempts = &AtomicUsize::new(0);
+        let retries = AtomicI64::new(0);
+
+        let res: Result<(), _> = with_retries(&policy(), &retries, "test", || async move {
+            attempts.fetch_add(1, Ordering::Relaxed);
+            Err(Status::invalid_argument("no"))
+        })
+        .await;
+
+        assert_eq!(res.unwrap_err().code(), Code::InvalidArgument);
+        assert_eq!(attempts.load(Ordering::Relaxed), 1);
+        assert_eq!(retries.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_rpc_timeout() {
+        let retries = AtomicI64::new(0);
+        let policy = RetryPolicy {
+            max_retries: 0,
+            rpc_timeout: Some(Duration::from_millis(1)),
+            ..policy()
+        };
+
+        let res: Result<(), _> = with_retries(&policy, &retries, "test", || async move {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(())
+        })
+        .await;
+
+        assert_eq!(res.unwrap_err().code(), Code::DeadlineExceeded);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(1000),
+            ..policy()
+        };
+
+        let backoff = policy.backoff(0);
+        assert!(backoff >= Duration::from_millis(50) && backoff <= Duration::from_millis(100));
+        let backoff = policy.backoff(2);
+        assert!(backoff >= Duration::from_millis(200) && backoff <= Duration::from_millis(400));
+        let backoff = policy.backoff(100);
+        assert!(backoff >= Duration::from_millis(500) && backoff <= Duration::from_millis(1000));
+    }
+}
@@ -0,0 +1,357 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Tests running our client against the reference server in `re_grpc_server`.
+
+use std::path::Path;
+
+use anyhow::Context;
+use buck2_re_configuration::Buck2OssReConfiguration;
+use futures::stream::TryStreamExt;
+use prost::Message;
+use re_grpc_server::DigestFunction;
+use re_grpc_server::ServerConfig;
+use sha2::Digest as _;
+
+use crate::Action;
+use crate::ActionResultRequest;
+use crate::Command;
+use crate::Digest;
+use crate::Directory;
+use crate::DirectoryNode;
+use crate::DownloadRequest;
+use crate::ExecuteRequest;
+use crate::ExecuteResponse;
+use crate::FileNode;
+use crate::FindMissingBlobsRequest;
+use crate::GetTreeRequest;
+use crate::NamedDigest;
+use crate::NamedDigestWithPermissions;
+use crate::REClient;
+use crate::REClientBuilder;
+use crate::RemoteExecutionMetadata;
+use crate::TCode;
+use crate::TDigest;
+
+/// Blobs larger than this go through ByteStream.
+const MAX_TOTAL_BATCH_SIZE: usize = 1024;
+
+/// Start a server storing its data in `root`, and connect to it.
+async fn connect(root: &Path) -> anyhow::Result<REClient> {
+    let address = re_grpc_server::spawn(ServerConfig {
+        root: root.to_owned(),
+        digest_function: DigestFunction::Sha256,
+        interrupt_execute_streams: true,
+    })
+    .await?;
+    let address = format!("http://{}", address);
+
+    REClientBuilder::build_and_connect(&Buck2OssReConfiguration {
+        cas_address: Some(address.clone()),
+        engine_address: Some(address.clone()),
+        action_cache_address: Some(address),
+        max_total_batch_size: Some(MAX_TOTAL_BATCH_SIZE),
+        retry_backoff_ms: Some(1),
+        ..Default::default()
+    })
+    .await
+}
+
+fn digest(data: &[u8]) -> TDigest {
+    TDigest {
+        hash: format!("{:x}", sha2::Sha256::digest(data)),
+        size_in_bytes: data.len() as i64,
+        ..Default::default()
+    }
+}
+
+fn to_proto(digest: &TDigest) -> Option<Digest> {
+    Some(Digest {
+        hash: digest.hash.clone(),
+        size_bytes: digest.size_in_bytes,
+    })
+}
+
+async fn upload_action(
+    client: &REClient,
+    command: &Command,
+    input_root: &Directory,
+    timeout: Option<prost_types::Duration>,
+) -> anyhow::Result<TDigest> {
+    let command_digest = client
+        .upload_blob(command.encode_to_vec(), RemoteExecutionMetadata::default())
+        .await?;
+    let input_root_digest = client
+        .upload_blob(
+            input_root.encode_to_vec(),
+            RemoteExecutionMetadata::default(),
+        )
+        .await?;
+    let action = Action {
+        command_digest: to_proto(&command_digest),
+        input_root_digest: to_proto(&input_root_digest),
+        timeout,
+        ..Default::default()
+    };
+    client
+        .upload_blob(action.encode_to_vec(), RemoteExecutionMetadata::default())
+        .await
+}
+
+/// Execute an action, and return the final response.
+async fn execute(client: &REClient, action_digest: &TDigest) -> anyhow::Result<ExecuteResponse> {
+    let updates = client
+        .execute_with_progress(
+            RemoteExecutionMetadata::default(),
+            ExecuteRequest {
+                action_digest: action_digest.clone(),
+                ..Default::default()
+            },
+        )
+        .await?
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    updates
+        .into_iter()
+        .last()
+        .and_then(|update| update.execute_response)
+        .context("Execution did not complete")
+}
+
+#[tokio::test]
+async fn test_cas() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let client = connect(dir.path()).await?;
+
+    let small = b"small".to_vec();
+    let large = vec![42; 4 * MAX_TOTAL_BATCH_SIZE];
+    let unknown = digest(b"unknown");
+
+    let small_digest = client
+        .upload_blob(small.clone(), RemoteExecutionMetadata::default())
+        .await?;
+    let large_digest = client
+        .upload_blob(large.clone(), RemoteExecutionMetadata::default())
+        .await?;
+    assert_eq!(small_digest, digest(&small));
+    assert_eq!(large_digest, digest(&large));
+
+    let missing = client
+        .find_missing_blobs(
+            RemoteExecutionMetadata::default(),
+            FindMissingBlobsRequest {
+                digests: vec![small_digest.clone(), large_digest.clone(), unknown.clone()],
+                ..Default::default()
+            },
+        )
+        .await?
+        .missing_digests;
+    assert_eq!(missing, vec![unknown]);
+
+    // The large blob is read through ByteStream, both inlined and into a file.
+    let large_path = dir.path().join("large");
+    let blobs = client
+        .download(
+            RemoteExecutionMetadata::default(),
+            DownloadRequest {
+                inlined_digests: Some(vec![small_digest.clone(), large_digest.clone()]),
+                file_digests: Some(vec![NamedDigestWithPermissions {
+                    named_digest: NamedDigest {
+                        name: large_path
+                            .to_str()
+                            .context("tempdir is not utf8")?
+                            .to_owned(),
+                        digest: large_digest.clone(),
+                        ..Default::default()
+                    },
+                    is_executable: false,
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            },
+        )
+        .await?
+        .inlined_blobs
+        .context("No inlined blobs")?;
+    assert_eq!(blobs.len(), 2);
+    assert_eq!(blobs[0].blob, small);
+    assert_eq!(blobs[1].blob, large);
+    assert_eq!(std::fs::read(&large_path)?, large);
+
+    let child = Directory {
+        files: vec![FileNode {
+            name: "small".to_owned(),
+            digest: to_proto(&small_digest),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let child_digest = client
+        .upload_blob(child.encode_to_vec(), RemoteExecutionMetadata::default())
+        .await?;
+    let root = Directory {
+        directories: vec![DirectoryNode {
+            name: "child".to_owned(),
+            digest: to_proto(&child_digest),
+        }],
+        files: vec![FileNode {
+            name: "large".to_owned(),
+            digest: to_proto(&large_digest),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let root_digest = client
+        .upload_blob(root.encode_to_vec(), RemoteExecutionMetadata::default())
+        .await?;
+
+    let tree = client
+        .get_tree(
+            RemoteExecutionMetadata::default(),
+            GetTreeRequest {
+                digest: root_digest,
+                ..Default::default()
+            },
+        )
+        .await?;
+    assert_eq!(tree.directories, vec![root, child]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_execute() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let client = connect(dir.path()).await?;
+
+    let input = b"hello\n".to_vec();
+    let input_digest = client
+        .upload_blob(input.clone(), RemoteExecutionMetadata::default())
+        .await?;
+
+    // The action sleeps so that the server ends the `Execute` stream before it completes, and we
+    // have to reattach with `WaitExecution`.
+    let command = Command {
+        arguments: vec![
+            "/bin/sh".to_owned(),
+            "-c".to_owned(),
+            "sleep 1 && cp input out".to_owned(),
+        ],
+        output_paths: vec!["out".to_owned()],
+        ..Default::default()
+    };
+    let input_root = Directory {
+        files: vec![FileNode {
+            name: "input".to_owned(),
+            digest: to_proto(&input_digest),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    let action_digest = upload_action(&client, &command, &input_root, None).await?;
+
+    let response = execute(&client, &action_digest).await?;
+    assert_eq!(response.error.code, TCode::OK);
+    assert!(!response.cached_result);
+    assert_eq!(response.action_digest, action_digest);
+    assert_eq!(response.action_result.exit_code, 0);
+    assert_eq!(response.action_result.output_files.len(), 1);
+    assert_eq!(response.action_result.output_files[0].name, "out");
+    assert_eq!(
+        response.action_result.output_files[0].digest.digest,
+        input_digest
+    );
+    assert_eq!(client.get_network_stats()?.retries, 1);
+
+    let response = execute(&client, &action_digest).await?;
+    assert!(response.cached_result);
+
+    let action_result = client
+        .get_action_result(
+            RemoteExecutionMetadata::default(),
+            ActionResultRequest {
+                digest: action_digest,
+                ..Default::default()
+            },
+        )
+        .await?
+        .action_result;
+    assert_eq!(action_result.exit_code, 0);
+    assert_eq!(action_result.output_files[0].digest.digest, input_digest);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_execute_timeout() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let client = connect(dir.path()).await?;
+
+    let command = Command {
+        arguments: vec![
+            "/bin/sh".to_owned(),
+            "-c".to_owned(),
+            "echo started && sleep 10".to_owned(),
+        ],
+        ..Default::default()
+    };
+    let action_digest = upload_action(
+        &client,
+        &command,
+        &Directory::default(),
+        Some(prost_types::Duration {
+            seconds: 1,
+            nanos: 0,
+        }),
+    )
+    .await?;
+
+    // Timeouts are reported in the response rather than as an error, along with what the action
+    // printed before it was killed.
+    let response = execute(&client, &action_digest).await?;
+    assert_eq!(response.error.code, TCode::DEADLINE_EXCEEDED);
+    assert!(!response.cached_result);
+    assert_eq!(
+        response.action_result.stdout_digest,
+        Some(digest(b"started\n"))
+    );
+
+    // Actions that timed out are not cached.
+    let response = execute(&client, &action_digest).await?;
+    assert_eq!(response.error.code, TCode::DEADLINE_EXCEEDED);
+    assert!(!response.cached_result);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_execute_outside_of_input_root() -> anyhow::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let client = connect(dir.path()).await?;
+
+    for (working_directory, output_path) in [("..", "out"), ("", "../out"), ("", "/tmp/out")] {
+        let command = Command {
+            arguments: vec!["/bin/true".to_owned()],
+            working_directory: working_directory.to_owned(),
+            output_paths: vec![output_path.to_owned()],
+            ..Default::default()
+        };
+        let action_digest = upload_action(&client, &command, &Directory::default(), None).await?;
+
+        let err = execute(&client, &action_digest).await.unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("Invalid path in command"),
+            "{:#}",
+            err
+        );
+    }
+
+    Ok(())
+}
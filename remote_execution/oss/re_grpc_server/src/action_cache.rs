@@ -0,0 +1,110 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use prost::Message;
+use re_grpc_proto::build::bazel::remote::execution::v2::action_cache_server::ActionCache;
+use re_grpc_proto::build::bazel::remote::execution::v2::ActionResult;
+use re_grpc_proto::build::bazel::remote::execution::v2::Digest;
+use re_grpc_proto::build::bazel::remote::execution::v2::GetActionResultRequest;
+use re_grpc_proto::build::bazel::remote::execution::v2::UpdateActionResultRequest;
+use tonic::Request;
+use tonic::Response;
+use tonic::Status;
+use uuid::Uuid;
+
+use crate::digest::required_digest;
+use crate::digest::DigestFunction;
+
+/// Action results, stored as one file per action, named after the action's hash.
+pub struct LocalActionCache {
+    root: PathBuf,
+    digest_function: DigestFunction,
+}
+
+impl LocalActionCache {
+    pub fn new(root: PathBuf, digest_function: DigestFunction) -> Self {
+        Self {
+            root,
+            digest_function,
+        }
+    }
+
+    pub async fn get(&self, action_digest: &Digest) -> Result<Option<ActionResult>, Status> {
+        self.digest_function.validate(action_digest)?;
+
+        match tokio::fs::read(self.root.join(&action_digest.hash)).await {
+            Ok(data) => Ok(Some(
+                ActionResult::decode(data.as_slice())
+                    .map_err(|e| Status::internal(e.to_string()))?,
+            )),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    pub async fn put(&self, action_digest: &Digest, result: &ActionResult) -> Result<(), Status> {
+        self.digest_function.validate(action_digest)?;
+
+        let temp = self.root.join(format!(".tmp-{}", Uuid::new_v4()));
+        let res = async {
+            tokio::fs::write(&temp, result.encode_to_vec()).await?;
+            tokio::fs::rename(&temp, self.root.join(&action_digest.hash)).await
+        }
+        .await;
+
+        if let Err(e) = res {
+            let _ignored = tokio::fs::remove_file(&temp).await;
+            return Err(Status::internal(e.to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+pub struct ActionCacheService {
+    pub action_cache: Arc<LocalActionCache>,
+}
+
+#[tonic::async_trait]
+impl ActionCache for ActionCacheService {
+    async fn get_action_result(
+        &self,
+        request: Request<GetActionResultRequest>,
+    ) -> Result<Response<ActionResult>, Status> {
+        let request = request.into_inner();
+        let action_digest = required_digest(request.action_digest.as_ref(), "action_digest")?;
+
+        match self.action_cache.get(&action_digest).await? {
+            Some(result) => Ok(Response::new(result)),
+            None => Err(Status::not_found(format!(
+                "No action result for {}/{}",
+                action_digest.hash, action_digest.size_bytes
+            ))),
+        }
+    }
+
+    async fn update_action_result(
+        &self,
+        request: Request<UpdateActionResultRequest>,
+    ) -> Result<Response<ActionResult>, Status> {
+        let request = request.into_inner();
+        let action_digest = required_digest(request.action_digest.as_ref(), "action_digest")?;
+        let result = request
+            .action_result
+            .ok_or_else(|| Status::invalid_argument("Missing action_result"))?;
+
+        self.action_cache.put(&action_digest, &result).await?;
+
+        Ok(Response::new(result))
+    }
+}
@@ -0,0 +1,509 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use futures::stream::BoxStream;
+use futures::stream::StreamExt;
+use futures::stream::TryStreamExt;
+use prost::Message;
+use re_grpc_proto::build::bazel::remote::execution::v2::batch_read_blobs_response;
+use re_grpc_proto::build::bazel::remote::execution::v2::batch_update_blobs_response;
+use re_grpc_proto::build::bazel::remote::execution::v2::compressor;
+use re_grpc_proto::build::bazel::remote::execution::v2::content_addressable_storage_server::ContentAddressableStorage;
+use re_grpc_proto::build::bazel::remote::execution::v2::BatchReadBlobsRequest;
+use re_grpc_proto::build::bazel::remote::execution::v2::BatchReadBlobsResponse;
+use re_grpc_proto::build::bazel::remote::execution::v2::BatchUpdateBlobsRequest;
+use re_grpc_proto::build::bazel::remote::execution::v2::BatchUpdateBlobsResponse;
+use re_grpc_proto::build::bazel::remote::execution::v2::Digest;
+use re_grpc_proto::build::bazel::remote::execution::v2::Directory;
+use re_grpc_proto::build::bazel::remote::execution::v2::FindMissingBlobsRequest;
+use re_grpc_proto::build::bazel::remote::execution::v2::FindMissingBlobsResponse;
+use re_grpc_proto::build::bazel::remote::execution::v2::GetTreeRequest;
+use re_grpc_proto::build::bazel::remote::execution::v2::GetTreeResponse;
+use re_grpc_proto::google::bytestream::byte_stream_server::ByteStream;
+use re_grpc_proto::google::bytestream::QueryWriteStatusRequest;
+use re_grpc_proto::google::bytestream::QueryWriteStatusResponse;
+use re_grpc_proto::google::bytestream::ReadRequest;
+use re_grpc_proto::google::bytestream::ReadResponse;
+use re_grpc_proto::google::bytestream::WriteRequest;
+use re_grpc_proto::google::bytestream::WriteResponse;
+use tonic::Request;
+use tonic::Response;
+use tonic::Status;
+use tonic::Streaming;
+use uuid::Uuid;
+
+use crate::digest::required_digest;
+use crate::digest::DigestFunction;
+
+/// Size of the chunks we send back from ByteStream reads.
+const BYTESTREAM_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// How many directories we return in one GetTree page if the client doesn't say.
+const DEFAULT_TREE_PAGE_SIZE: usize = 1000;
+
+/// Blobs, stored as one file per blob, named after their hash.
+pub struct LocalCas {
+    root: PathBuf,
+    digest_function: DigestFunction,
+}
+
+impl LocalCas {
+    pub fn new(root: PathBuf, digest_function: DigestFunction) -> Self {
+        Self {
+            root,
+            digest_function,
+        }
+    }
+
+    pub fn digest_function(&self) -> DigestFunction {
+        self.digest_function
+    }
+
+    fn path(&self, digest: &Digest) -> Result<PathBuf, Status> {
+        self.digest_function.validate(digest)?;
+        Ok(self.root.join(&digest.hash))
+    }
+
+    pub async fn contains(&self, digest: &Digest) -> Result<bool, Status> {
+        match tokio::fs::metadata(self.path(digest)?).await {
+            Ok(meta) => Ok(meta.len() == digest.size_bytes as u64),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    pub async fn read(&self, digest: &Digest) -> Result<Vec<u8>, Status> {
+        match tokio::fs::read(self.path(digest)?).await {
+            Ok(data) => Ok(data),
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(Status::not_found(format!(
+                "Blob not found: {}/{}",
+                digest.hash, digest.size_bytes
+            ))),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    pub async fn read_message<T: Message + Default>(&self, digest: &Digest) -> Result<T, Status> {
+        let data = self.read(digest).await?;
+        T::decode(data.as_slice()).map_err(|e| {
+            Status::invalid_argument(format!(
+                "Blob {}/{} is not a valid message: {}",
+                digest.hash, digest.size_bytes, e
+            ))
+        })
+    }
+
+    /// Store a blob the client sent, after checking that it matches its digest.
+    pub async fn write(&self, digest: &Digest, data: &[u8]) -> Result<(), Status> {
+        let path = self.path(digest)?;
+
+        if self.digest_function.digest(data) != *digest {
+            return Err(Status::invalid_argument(format!(
+                "Data does not match digest {}/{}",
+                digest.hash, digest.size_bytes
+            )));
+        }
+
+        // Write to a temporary file first, so that concurrent readers never see a partial blob.
+        let temp = self.root.join(format!(".tmp-{}", Uuid::new_v4()));
+        let res = async {
+            tokio::fs::write(&temp, data).await?;
+            tokio::fs::rename(&temp, &path).await
+        }
+        .await;
+
+        if let Err(e) = res {
+            let _ignored = tokio::fs::remove_file(&temp).await;
+            return Err(Status::internal(e.to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Store a blob we produced ourselves.
+    pub async fn put(&self, data: &[u8]) -> Result<Digest, Status> {
+        let digest = self.digest_function.digest(data);
+        if !self.contains(&digest).await? {
+            self.write(&digest, data).await?;
+        }
+        Ok(digest)
+    }
+
+    pub async fn put_message<T: Message>(&self, message: &T) -> Result<Digest, Status> {
+        self.put(&message.encode_to_vec()).await
+    }
+}
+
+fn rpc_status(status: Result<(), Status>) -> re_grpc_proto::google::rpc::Status {
+    match status {
+        Ok(()) => Default::default(),
+        Err(status) => re_grpc_proto::google::rpc::Status {
+            code: status.code() as i32,
+            message: status.message().to_owned(),
+            details: Vec::new(),
+        },
+    }
+}
+
+pub struct CasService {
+    pub cas: Arc<LocalCas>,
+}
+
+#[tonic::async_trait]
+impl ContentAddressableStorage for CasService {
+    async fn find_missing_blobs(
+        &self,
+        request: Request<FindMissingBlobsRequest>,
+    ) -> Result<Response<FindMissingBlobsResponse>, Status> {
+        let mut missing_blob_digests = Vec::new();
+
+        for digest in request.into_inner().blob_digests {
+            if !self.cas.contains(&digest).await? {
+                missing_blob_digests.push(digest);
+            }
+        }
+
+        Ok(Response::new(FindMissingBlobsResponse {
+            missing_blob_digests,
+        }))
+    }
+
+    async fn batch_update_blobs(
+        &self,
+        request: Request<BatchUpdateBlobsRequest>,
+    ) -> Result<Response<BatchUpdateBlobsResponse>, Status> {
+        let mut responses = Vec::new();
+
+        for req in request.into_inner().requests {
+            let status = async {
+                let digest = required_digest(req.digest.as_ref(), "digest")?;
+                if req.compressor != compressor::Value::Identity as i32 {
+                    return Err(Status::invalid_argument("Compression is not supported"));
+                }
+                self.cas.write(&digest, &req.data).await
+            }
+            .await;
+
+            responses.push(batch_update_blobs_response::Response {
+                digest: req.digest,
+                status: Some(rpc_status(status)),
+            });
+        }
+
+        Ok(Response::new(BatchUpdateBlobsResponse { responses }))
+    }
+
+    async fn batch_read_blobs(
+        &self,
+        request: Request<BatchReadBlobsRequest>,
+    ) -> Result<Response<BatchReadBlobsResponse>, Status> {
+        let mut responses = Vec::new();
+
+        for digest in request.into_inner().digests {
+            let (data, status) = match self.cas.read(&digest).await {
+                Ok(data) => (data, Ok(())),
+                Err(e) => (Vec::new(), Err(e)),
+            };
+
+            responses.push(batch_read_blobs_response::Response {
+                digest: Some(digest),
+                data,
+                compressor: compressor::Value::Identity as i32,
+                status: Some(rpc_status(status)),
+            });
+        }
+
+        Ok(Response::new(BatchReadBlobsResponse { responses }))
+    }
+
+    type GetTreeStream = BoxStream<'static, Result<GetTreeResponse, Status>>;
+
+    /// Return the directories under the root, breadth-first. Page tokens are offsets into that
+    /// list.
+    async fn get_tree(
+        &self,
+        request: Request<GetTreeRequest>,
+    ) -> Result<Response<Self::GetTreeStream>, Status> {
+        let request = request.into_inner();
+        let root = required_digest(request.root_digest.as_ref(), "root_digest")?;
+
+        let offset = if request.page_token.is_empty() {
+            0
+        } else {
+            request
+                .page_token
+                .parse::<usize>()
+                .map_err(|_| Status::invalid_argument("Invalid page_token"))?
+        };
+
+        let page_size = match usize::try_from(request.page_size) {
+            Ok(0) | Err(_) => DEFAULT_TREE_PAGE_SIZE,
+            Ok(n) => n,
+        };
+
+        let directories = collect_tree(&self.cas, root).await?;
+        let pages = directories
+            .into_iter()
+            .enumerate()
+            .skip(offset)
+            .collect::<Vec<_>>()
+            .chunks(page_size)
+            .map(|chunk| {
+                let next = chunk.last().map_or(0, |(i, _)| i + 1);
+                (chunk.iter().map(|(_, d)| d.clone()).collect(), next)
+            })
+            .collect::<Vec<(Vec<Directory>, usize)>>();
+
+        let page_count = pages.len();
+        let stream = futures::stream::iter(pages.into_iter().enumerate().map(
+            move |(i, (directories, next))| {
+                Ok(GetTreeResponse {
+                    directories,
+                    next_page_token: if i + 1 == page_count {
+                        String::new()
+                    } else {
+                        next.to_string()
+                    },
+                })
+            },
+        ));
+
+        Ok(Response::new(stream.boxed()))
+    }
+}
+
+/// All the directories under `root` (including itself), breadth-first, without duplicates.
+async fn collect_tree(cas: &LocalCas, root: Digest) -> Result<Vec<Directory>, Status> {
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+    let mut directories = Vec::new();
+
+    seen.insert((root.hash.clone(), root.size_bytes));
+    queue.push_back(root);
+
+    while let Some(digest) = queue.pop_front() {
+        let directory: Directory = cas.read_message(&digest).await?;
+        for child in &directory.directories {
+            let child = required_digest(child.digest.as_ref(), "directory digest")?;
+            if seen.insert((child.hash.clone(), child.size_bytes)) {
+                queue.push_back(child);
+            }
+        }
+        directories.push(directory);
+    }
+
+    Ok(directories)
+}
+
+/// Extract the digest from a ByteStream resource name. Those look like
+/// `{instance_name}/blobs/{hash}/{size}` for reads and
+/// `{instance_name}/uploads/{uuid}/blobs/{hash}/{size}` for writes, where the instance name is
+/// optional and might itself contain slashes.
+fn parse_resource_name(resource_name: &str) -> Result<Digest, Status> {
+    let invalid = || Status::invalid_argument(format!("Invalid resource: `{}`", resource_name));
+
+    let parts = resource_name.split('/').collect::<Vec<_>>();
+
+    if parts.iter().any(|p| *p == "compressed-blobs") {
+        return Err(Status::unimplemented("Compressed blobs are not supported"));
+    }
+
+    let pos = parts
+        .iter()
+        .rposition(|p| *p == "blobs")
+        .ok_or_else(invalid)?;
+    match parts.get(pos + 1..pos + 3) {
+        Some([hash, size]) => Ok(Digest {
+            hash: (*hash).to_owned(),
+            size_bytes: size.parse().map_err(|_| invalid())?,
+        }),
+        _ => Err(invalid()),
+    }
+}
+
+pub struct ByteStreamService {
+    pub cas: Arc<LocalCas>,
+}
+
+#[tonic::async_trait]
+impl ByteStream for ByteStreamService {
+    type ReadStream = BoxStream<'static, Result<ReadResponse, Status>>;
+
+    async fn read(
+        &self,
+        request: Request<ReadRequest>,
+    ) -> Result<Response<Self::ReadStream>, Status> {
+        let request = request.into_inner();
+        let digest = parse_resource_name(&request.resource_name)?;
+        let data = self.cas.read(&digest).await?;
+
+        let start = usize::try_from(request.read_offset)
+            .ok()
+            .filter(|o| *o <= data.len())
+            .ok_or_else(|| Status::out_of_range("Invalid read_offset"))?;
+        let end = match usize::try_from(request.read_limit) {
+            Ok(0) => data.len(),
+            Ok(limit) => std::cmp::min(data.len(), start.saturating_add(limit)),
+            Err(_) => return Err(Status::invalid_argument("Invalid read_limit")),
+        };
+
+        let chunks = data[start..end]
+            .chunks(BYTESTREAM_CHUNK_SIZE)
+            .map(|chunk| {
+                Ok(ReadResponse {
+                    data: chunk.to_vec(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        Ok(Response::new(futures::stream::iter(chunks).boxed()))
+    }
+
+    async fn write(
+        &self,
+        request: Request<Streaming<WriteRequest>>,
+    ) -> Result<Response<WriteResponse>, Status> {
+        let mut stream = request.into_inner();
+        let mut resource_name = None;
+        let mut data = Vec::new();
+
+        while let Some(req) = stream.try_next().await? {
+            if resource_name.is_none() {
+                resource_name = Some(req.resource_name.clone());
+            }
+
+            if req.write_offset != data.len() as i64 {
+                return Err(Status::invalid_argument(format!(
+                    "Invalid write_offset: expected {}, got {}",
+                    data.len(),
+                    req.write_offset
+                )));
+            }
+
+            data.extend_from_slice(&req.data);
+
+            if req.finish_write {
+                let resource_name = resource_name.unwrap_or_default();
+                let digest = parse_resource_name(&resource_name)?;
+                self.cas.write(&digest, &data).await?;
+                return Ok(Response::new(WriteResponse {
+                    committed_size: data.len() as i64,
+                }));
+            }
+        }
+
+        Err(Status::invalid_argument(
+            "Write stream ended without finish_write",
+        ))
+    }
+
+    /// We don't support resuming writes, so all we can report is whether the blob exists.
+    async fn query_write_status(
+        &self,
+        request: Request<QueryWriteStatusRequest>,
+    ) -> Result<Response<QueryWriteStatusResponse>, Status> {
+        let digest = parse_resource_name(&request.into_inner().resource_name)?;
+
+        if self.cas.contains(&digest).await? {
+            Ok(Response::new(QueryWriteStatusResponse {
+                committed_size: digest.size_bytes,
+                complete: true,
+            }))
+        } else {
+            Err(Status::not_found("No such write"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use re_grpc_proto::build::bazel::remote::execution::v2::DirectoryNode;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_resource_name() {
+        let digest = parse_resource_name("blobs/abc/12").unwrap();
+        assert_eq!(digest.hash, "abc");
+        assert_eq!(digest.size_bytes, 12);
+
+        let digest = parse_resource_name("some/instance/uploads/uuid/blobs/abc/12").unwrap();
+        assert_eq!(digest.hash, "abc");
+        assert_eq!(digest.size_bytes, 12);
+
+        assert!(parse_resource_name("blobs/abc").is_err());
+        assert!(parse_resource_name("blobs/abc/foo").is_err());
+        assert!(parse_resource_name("compressed-blobs/zstd/abc/12").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_write_and_read() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cas = LocalCas::new(dir.path().to_owned(), DigestFunction::Sha256);
+
+        let digest = cas.put(b"foo").await?;
+        assert!(cas.contains(&digest).await?);
+        assert_eq!(cas.read(&digest).await?, b"foo");
+
+        let other = DigestFunction::Sha256.digest(b"bar");
+        assert!(!cas.contains(&other).await?);
+        assert!(cas.write(&other, b"baz").await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_tree_pages() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cas = Arc::new(LocalCas::new(dir.path().to_owned(), DigestFunction::Sha256));
+
+        let leaf = cas.put_message(&Directory::default()).await?;
+        let root = cas
+            .put_message(&Directory {
+                directories: vec![
+                    DirectoryNode {
+                        name: "a".to_owned(),
+                        digest: Some(leaf.clone()),
+                    },
+                    DirectoryNode {
+                        name: "b".to_owned(),
+                        digest: Some(leaf),
+                    },
+                ],
+                ..Default::default()
+            })
+            .await?;
+
+        let service = CasService { cas };
+        let pages = service
+            .get_tree(Request::new(GetTreeRequest {
+                root_digest: Some(root),
+                page_size: 1,
+                ..Default::default()
+            }))
+            .await?
+            .into_inner()
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        // The leaf is shared, so it's only returned once.
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].directories.len(), 1);
+        assert_eq!(pages[0].next_page_token, "1");
+        assert_eq!(pages[1].next_page_token, "");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,114 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::str::FromStr;
+
+use re_grpc_proto::build::bazel::remote::execution::v2::digest_function;
+use re_grpc_proto::build::bazel::remote::execution::v2::Digest;
+use sha1::Digest as _;
+use tonic::Status;
+
+/// The hash function the server uses to address blobs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DigestFunction {
+    Sha1,
+    Sha256,
+}
+
+impl DigestFunction {
+    pub(crate) fn to_proto(self) -> digest_function::Value {
+        match self {
+            Self::Sha1 => digest_function::Value::Sha1,
+            Self::Sha256 => digest_function::Value::Sha256,
+        }
+    }
+
+    fn hash_len(self) -> usize {
+        match self {
+            Self::Sha1 => 40,
+            Self::Sha256 => 64,
+        }
+    }
+
+    pub(crate) fn digest(self, data: &[u8]) -> Digest {
+        let hash = match self {
+            Self::Sha1 => format!("{:x}", sha1::Sha1::digest(data)),
+            Self::Sha256 => format!("{:x}", sha2::Sha256::digest(data)),
+        };
+
+        Digest {
+            hash,
+            size_bytes: data.len() as i64,
+        }
+    }
+
+    /// Check that a digest sent by a client is well-formed. Since we use the hash to name files
+    /// on disk, this also ensures it can't be used to escape the storage directory.
+    pub(crate) fn validate(self, digest: &Digest) -> Result<(), Status> {
+        if digest.hash.len() != self.hash_len()
+            || !digest
+                .hash
+                .bytes()
+                .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        {
+            return Err(Status::invalid_argument(format!(
+                "Invalid {:?} digest: `{}`",
+                self, digest.hash
+            )));
+        }
+
+        if digest.size_bytes < 0 {
+            return Err(Status::invalid_argument(format!(
+                "Invalid size for digest `{}`: {}",
+                digest.hash, digest.size_bytes
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for DigestFunction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "sha1" => Ok(Self::Sha1),
+            "sha256" => Ok(Self::Sha256),
+            _ => Err(anyhow::anyhow!(
+                "Invalid digest function `{}`, expected `sha1` or `sha256`",
+                s
+            )),
+        }
+    }
+}
+
+pub(crate) fn required_digest(digest: Option<&Digest>, what: &str) -> Result<Digest, Status> {
+    digest
+        .cloned()
+        .ok_or_else(|| Status::invalid_argument(format!("Missing {}", what)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let digest = DigestFunction::Sha256.digest(b"foo");
+        assert!(DigestFunction::Sha256.validate(&digest).is_ok());
+        assert!(DigestFunction::Sha1.validate(&digest).is_err());
+
+        let escape = Digest {
+            hash: "../".repeat(21) + "a",
+            size_bytes: 3,
+        };
+        assert!(DigestFunction::Sha256.validate(&escape).is_err());
+    }
+}
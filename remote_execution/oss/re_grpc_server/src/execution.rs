@@ -0,0 +1,232 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use futures::stream::BoxStream;
+use futures::stream::StreamExt;
+use prost::Message;
+use re_grpc_proto::build::bazel::remote::execution::v2::execution_server::Execution;
+use re_grpc_proto::build::bazel::remote::execution::v2::execution_stage;
+use re_grpc_proto::build::bazel::remote::execution::v2::Action;
+use re_grpc_proto::build::bazel::remote::execution::v2::Digest;
+use re_grpc_proto::build::bazel::remote::execution::v2::ExecuteOperationMetadata;
+use re_grpc_proto::build::bazel::remote::execution::v2::ExecuteRequest;
+use re_grpc_proto::build::bazel::remote::execution::v2::ExecuteResponse;
+use re_grpc_proto::build::bazel::remote::execution::v2::WaitExecutionRequest;
+use re_grpc_proto::google::longrunning::operation;
+use re_grpc_proto::google::longrunning::Operation;
+use re_grpc_proto::google::rpc::Code;
+use tokio::sync::watch;
+use tonic::Request;
+use tonic::Response;
+use tonic::Status;
+use uuid::Uuid;
+
+use crate::action_cache::LocalActionCache;
+use crate::cas::LocalCas;
+use crate::digest::required_digest;
+use crate::executor::LocalExecutor;
+
+type OperationStream = BoxStream<'static, Result<Operation, Status>>;
+
+pub struct ExecutionService {
+    pub cas: Arc<LocalCas>,
+    pub action_cache: Arc<LocalActionCache>,
+    pub executor: Arc<LocalExecutor>,
+    /// Operations we started, so that clients can reattach with `WaitExecution`. These are kept
+    /// for as long as the server runs.
+    pub operations: Mutex<HashMap<String, watch::Receiver<Operation>>>,
+    /// End `Execute` streams after their first update. See `ServerConfig`.
+    pub interrupt_streams: bool,
+}
+
+impl ExecutionService {
+    /// Run the action, and produce the response to send back. Errors running the action are
+    /// reported in the response, not as an RPC failure.
+    async fn run(
+        cas: &LocalCas,
+        action_cache: &LocalActionCache,
+        executor: &LocalExecutor,
+        action_digest: &Digest,
+    ) -> ExecuteResponse {
+        let res = async {
+            let action: Action = cas.read_message(action_digest).await.map_err(|e| {
+                if e.code() == tonic::Code::NotFound {
+                    Status::failed_precondition(e.message())
+                } else {
+                    e
+                }
+            })?;
+
+            let outcome = executor.execute(&action).await?;
+
+            if !outcome.timed_out && outcome.result.exit_code == 0 && !action.do_not_cache {
+                action_cache.put(action_digest, &outcome.result).await?;
+            }
+
+            Ok::<_, Status>(outcome)
+        }
+        .await;
+
+        match res {
+            Ok(outcome) => ExecuteResponse {
+                result: Some(outcome.result),
+                status: outcome
+                    .timed_out
+                    .then(|| re_grpc_proto::google::rpc::Status {
+                        code: Code::DeadlineExceeded as i32,
+                        message: "Action timed out".to_owned(),
+                        details: Vec::new(),
+                    }),
+                ..Default::default()
+            },
+            Err(status) => ExecuteResponse {
+                status: Some(re_grpc_proto::google::rpc::Status {
+                    code: status.code() as i32,
+                    message: status.message().to_owned(),
+                    details: Vec::new(),
+                }),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+fn operation(
+    name: &str,
+    action_digest: &Digest,
+    stage: execution_stage::Value,
+    response: Option<ExecuteResponse>,
+) -> Operation {
+    let metadata = ExecuteOperationMetadata {
+        stage: stage as i32,
+        action_digest: Some(action_digest.clone()),
+        ..Default::default()
+    };
+
+    Operation {
+        name: name.to_owned(),
+        metadata: Some(prost_types::Any {
+            type_url:
+                "type.googleapis.com/build.bazel.remote.execution.v2.ExecuteOperationMetadata"
+                    .to_owned(),
+            value: metadata.encode_to_vec(),
+        }),
+        done: response.is_some(),
+        result: response.map(|response| {
+            operation::Result::Response(prost_types::Any {
+                type_url: "type.googleapis.com/build.bazel.remote.execution.v2.ExecuteResponse"
+                    .to_owned(),
+                value: response.encode_to_vec(),
+            })
+        }),
+    }
+}
+
+/// Follow an operation until it is done.
+fn operation_stream(rx: watch::Receiver<Operation>) -> OperationStream {
+    futures::stream::unfold(Some((rx, true)), |state| async move {
+        let (mut rx, first) = state?;
+
+        if !first && rx.changed().await.is_err() {
+            return Some((Err(Status::internal("Execution was abandoned")), None));
+        }
+
+        let op = rx.borrow_and_update().clone();
+        let next = if op.done { None } else { Some((rx, false)) };
+        Some((Ok(op), next))
+    })
+    .boxed()
+}
+
+#[tonic::async_trait]
+impl Execution for ExecutionService {
+    type ExecuteStream = OperationStream;
+
+    async fn execute(
+        &self,
+        request: Request<ExecuteRequest>,
+    ) -> Result<Response<Self::ExecuteStream>, Status> {
+        let request = request.into_inner();
+        let action_digest = required_digest(request.action_digest.as_ref(), "action_digest")?;
+        let name = Uuid::new_v4().to_string();
+
+        if !request.skip_cache_lookup {
+            if let Some(result) = self.action_cache.get(&action_digest).await? {
+                let op = operation(
+                    &name,
+                    &action_digest,
+                    execution_stage::Value::Completed,
+                    Some(ExecuteResponse {
+                        result: Some(result),
+                        cached_result: true,
+                        ..Default::default()
+                    }),
+                );
+                return Ok(Response::new(futures::stream::iter([Ok(op)]).boxed()));
+            }
+        }
+
+        let (tx, rx) = watch::channel(operation(
+            &name,
+            &action_digest,
+            execution_stage::Value::Executing,
+            None,
+        ));
+
+        self.operations
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(name.clone(), rx.clone());
+
+        let cas = self.cas.clone();
+        let action_cache = self.action_cache.clone();
+        let executor = self.executor.clone();
+
+        tokio::spawn(async move {
+            let response = Self::run(&cas, &action_cache, &executor, &action_digest).await;
+            // Nobody might be listening anymore, that's fine.
+            let _ignored = tx.send(operation(
+                &name,
+                &action_digest,
+                execution_stage::Value::Completed,
+                Some(response),
+            ));
+        });
+
+        let stream = operation_stream(rx);
+        if self.interrupt_streams {
+            Ok(Response::new(stream.take(1).boxed()))
+        } else {
+            Ok(Response::new(stream))
+        }
+    }
+
+    type WaitExecutionStream = OperationStream;
+
+    async fn wait_execution(
+        &self,
+        request: Request<WaitExecutionRequest>,
+    ) -> Result<Response<Self::WaitExecutionStream>, Status> {
+        let name = request.into_inner().name;
+
+        let rx = self
+            .operations
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&name)
+            .cloned()
+            .ok_or_else(|| Status::not_found(format!("No such operation: `{}`", name)))?;
+
+        Ok(Response::new(operation_stream(rx)))
+    }
+}
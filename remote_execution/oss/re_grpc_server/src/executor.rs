@@ -0,0 +1,504 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use futures::future::BoxFuture;
+use futures::future::FutureExt;
+use re_grpc_proto::build::bazel::remote::execution::v2::Action;
+use re_grpc_proto::build::bazel::remote::execution::v2::ActionResult;
+use re_grpc_proto::build::bazel::remote::execution::v2::Command;
+use re_grpc_proto::build::bazel::remote::execution::v2::Digest;
+use re_grpc_proto::build::bazel::remote::execution::v2::Directory;
+use re_grpc_proto::build::bazel::remote::execution::v2::DirectoryNode;
+use re_grpc_proto::build::bazel::remote::execution::v2::ExecutedActionMetadata;
+use re_grpc_proto::build::bazel::remote::execution::v2::FileNode;
+use re_grpc_proto::build::bazel::remote::execution::v2::OutputDirectory;
+use re_grpc_proto::build::bazel::remote::execution::v2::OutputFile;
+use re_grpc_proto::build::bazel::remote::execution::v2::OutputSymlink;
+use re_grpc_proto::build::bazel::remote::execution::v2::SymlinkNode;
+use re_grpc_proto::build::bazel::remote::execution::v2::Tree;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::task::JoinHandle;
+use tonic::Status;
+use uuid::Uuid;
+
+use crate::cas::LocalCas;
+use crate::digest::required_digest;
+
+/// Runs actions as local processes, each in its own scratch directory.
+pub struct LocalExecutor {
+    cas: Arc<LocalCas>,
+    work_dir: PathBuf,
+}
+
+/// The outcome of running an action. A timed out action still has a result, since its outputs
+/// may help debugging.
+pub struct ExecutionOutcome {
+    pub result: ActionResult,
+    pub timed_out: bool,
+}
+
+impl LocalExecutor {
+    pub fn new(cas: Arc<LocalCas>, work_dir: PathBuf) -> Self {
+        Self { cas, work_dir }
+    }
+
+    pub async fn execute(&self, action: &Action) -> Result<ExecutionOutcome, Status> {
+        let command_digest = required_digest(action.command_digest.as_ref(), "command_digest")?;
+        let input_root_digest =
+            required_digest(action.input_root_digest.as_ref(), "input_root_digest")?;
+
+        let command: Command = self
+            .cas
+            .read_message(&command_digest)
+            .await
+            .map_err(missing_input)?;
+
+        let exec_root = self.work_dir.join(Uuid::new_v4().to_string());
+        let res = self
+            .execute_in(action, &command, &input_root_digest, &exec_root)
+            .await;
+
+        if let Err(e) = tokio::fs::remove_dir_all(&exec_root).await {
+            tracing::warn!("Error cleaning up `{}`: {}", exec_root.display(), e);
+        }
+
+        res
+    }
+
+    async fn execute_in(
+        &self,
+        action: &Action,
+        command: &Command,
+        input_root_digest: &Digest,
+        exec_root: &Path,
+    ) -> Result<ExecutionOutcome, Status> {
+        let (program, args) = command
+            .arguments
+            .split_first()
+            .ok_or_else(|| Status::invalid_argument("Command has no arguments"))?;
+
+        let mut metadata = ExecutedActionMetadata {
+            worker: "local".to_owned(),
+            input_fetch_start_timestamp: Some(SystemTime::now().into()),
+            ..Default::default()
+        };
+
+        materialize_directory(&self.cas, input_root_digest.clone(), exec_root.to_owned()).await?;
+
+        let working_directory = join_relative(exec_root, &command.working_directory)?;
+
+        // Outputs are relative to the working directory, and their parents must exist.
+        let output_paths = output_paths(command);
+        for path in &output_paths {
+            if let Some(parent) = join_relative(&working_directory, path)?.parent() {
+                tokio::fs::create_dir_all(parent).await.map_err(internal)?;
+            }
+        }
+
+        metadata.input_fetch_completed_timestamp = Some(SystemTime::now().into());
+
+        // Relative programs are relative to the working directory, not to ours.
+        let program = if program.contains('/') {
+            working_directory.join(program)
+        } else {
+            PathBuf::from(program)
+        };
+
+        let mut child = tokio::process::Command::new(program)
+            .args(args)
+            .current_dir(&working_directory)
+            .env_clear()
+            .envs(
+                command
+                    .environment_variables
+                    .iter()
+                    .map(|e| (&e.name, &e.value)),
+            )
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| Status::invalid_argument(format!("Error spawning command: {}", e)))?;
+
+        metadata.execution_start_timestamp = Some(SystemTime::now().into());
+
+        let stdout = CapturedOutput::spawn(child.stdout.take().expect("stdout is piped"));
+        let stderr = CapturedOutput::spawn(child.stderr.take().expect("stderr is piped"));
+
+        let timeout = action
+            .timeout
+            .as_ref()
+            .and_then(|t| Duration::try_from(t.clone()).ok())
+            .filter(|t| !t.is_zero());
+
+        let status = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, child.wait()).await {
+                Ok(status) => Some(status.map_err(internal)?),
+                Err(_) => None,
+            },
+            None => Some(child.wait().await.map_err(internal)?),
+        };
+        let timed_out = status.is_none();
+
+        let mut result = ActionResult::default();
+
+        let (stdout, stderr) = match status {
+            Some(status) => {
+                result.exit_code = status.code().unwrap_or(-1);
+                (stdout.finish().await?, stderr.finish().await?)
+            }
+            None => {
+                if let Err(e) = child.kill().await {
+                    tracing::warn!("Error killing timed out action: {}", e);
+                }
+                (stdout.abort(), stderr.abort())
+            }
+        };
+
+        metadata.execution_completed_timestamp = Some(SystemTime::now().into());
+        metadata.output_upload_start_timestamp = Some(SystemTime::now().into());
+
+        result.stdout_digest = Some(self.cas.put(&stdout).await?);
+        result.stderr_digest = Some(self.cas.put(&stderr).await?);
+
+        for path in output_paths {
+            self.collect_output(&working_directory, path, &mut result)
+                .await?;
+        }
+
+        metadata.output_upload_completed_timestamp = Some(SystemTime::now().into());
+        result.execution_metadata = Some(metadata);
+
+        Ok(ExecutionOutcome { result, timed_out })
+    }
+
+    async fn collect_output(
+        &self,
+        working_directory: &Path,
+        path: String,
+        result: &mut ActionResult,
+    ) -> Result<(), Status> {
+        let abs_path = join_relative(working_directory, &path)?;
+
+        let meta = match tokio::fs::symlink_metadata(&abs_path).await {
+            Ok(meta) => meta,
+            // Missing outputs are not an error for the server, the client decides what to do.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(internal(e)),
+        };
+
+        if meta.file_type().is_symlink() {
+            let target = tokio::fs::read_link(&abs_path).await.map_err(internal)?;
+            result.output_symlinks.push(OutputSymlink {
+                path,
+                target: target.to_string_lossy().into_owned(),
+                ..Default::default()
+            });
+        } else if meta.is_dir() {
+            let (root, children) = upload_directory(&self.cas, abs_path).await?;
+            let tree_digest = self
+                .cas
+                .put_message(&Tree {
+                    root: Some(root),
+                    children,
+                })
+                .await?;
+            result.output_directories.push(OutputDirectory {
+                path,
+                tree_digest: Some(tree_digest),
+                is_topologically_sorted: false,
+            });
+        } else {
+            let (digest, is_executable) = upload_file(&self.cas, &abs_path).await?;
+            result.output_files.push(OutputFile {
+                path,
+                digest: Some(digest),
+                is_executable,
+                ..Default::default()
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the output of a process as it is produced, so that we keep what it printed even if we
+/// have to kill it.
+struct CapturedOutput {
+    data: Arc<Mutex<Vec<u8>>>,
+    reader: JoinHandle<std::io::Result<()>>,
+}
+
+impl CapturedOutput {
+    fn spawn(mut stream: impl AsyncRead + Unpin + Send + 'static) -> Self {
+        let data = Arc::new(Mutex::new(Vec::new()));
+        let reader = tokio::spawn({
+            let data = data.clone();
+            async move {
+                let mut buf = [0; 8192];
+                loop {
+                    let n = stream.read(&mut buf).await?;
+                    if n == 0 {
+                        return Ok(());
+                    }
+                    data.lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .extend_from_slice(&buf[..n]);
+                }
+            }
+        });
+        Self { data, reader }
+    }
+
+    /// Wait until the process closes the stream, and return everything it wrote.
+    async fn finish(self) -> Result<Vec<u8>, Status> {
+        self.reader
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(internal)?;
+        Ok(self.take())
+    }
+
+    /// Stop reading, and return what the process wrote so far.
+    fn abort(self) -> Vec<u8> {
+        self.reader.abort();
+        self.take()
+    }
+
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.data.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// The paths the command wants back. Newer clients use `output_paths`, older ones split files
+/// and directories.
+fn output_paths(command: &Command) -> Vec<String> {
+    if !command.output_paths.is_empty() {
+        return command.output_paths.clone();
+    }
+
+    command
+        .output_files
+        .iter()
+        .chain(command.output_directories.iter())
+        .cloned()
+        .collect()
+}
+
+fn internal(e: std::io::Error) -> Status {
+    Status::internal(e.to_string())
+}
+
+/// The REAPI wants missing inputs reported as `FAILED_PRECONDITION`.
+fn missing_input(status: Status) -> Status {
+    if status.code() == tonic::Code::NotFound {
+        Status::failed_precondition(status.message())
+    } else {
+        status
+    }
+}
+
+fn materialize_directory(
+    cas: &LocalCas,
+    digest: Digest,
+    path: PathBuf,
+) -> BoxFuture<'_, Result<(), Status>> {
+    async move {
+        let directory: Directory = cas.read_message(&digest).await.map_err(missing_input)?;
+
+        tokio::fs::create_dir_all(&path).await.map_err(internal)?;
+
+        for file in &directory.files {
+            let file_path = join_name(&path, &file.name)?;
+            let digest = required_digest(file.digest.as_ref(), "file digest")?;
+            let data = cas.read(&digest).await.map_err(missing_input)?;
+            tokio::fs::write(&file_path, data).await.map_err(internal)?;
+            if file.is_executable {
+                set_executable(&file_path).await?;
+            }
+        }
+
+        for symlink in &directory.symlinks {
+            create_symlink(&symlink.target, &join_name(&path, &symlink.name)?)?;
+        }
+
+        for child in &directory.directories {
+            let digest = required_digest(child.digest.as_ref(), "directory digest")?;
+            materialize_directory(cas, digest, join_name(&path, &child.name)?).await?;
+        }
+
+        Ok(())
+    }
+    .boxed()
+}
+
+/// Upload a directory and everything under it, returning its `Directory` along with those of
+/// all its descendants.
+fn upload_directory(
+    cas: &LocalCas,
+    path: PathBuf,
+) -> BoxFuture<'_, Result<(Directory, Vec<Directory>), Status>> {
+    async move {
+        let mut directory = Directory::default();
+        let mut children = Vec::new();
+
+        let mut entries = tokio::fs::read_dir(&path).await.map_err(internal)?;
+        while let Some(entry) = entries.next_entry().await.map_err(internal)? {
+            let name = entry
+                .file_name()
+                .into_string()
+                .map_err(|_| Status::internal("Output file names must be UTF-8"))?;
+            let file_type = entry.file_type().await.map_err(internal)?;
+
+            if file_type.is_symlink() {
+                let target = tokio::fs::read_link(entry.path()).await.map_err(internal)?;
+                directory.symlinks.push(SymlinkNode {
+                    name,
+                    target: target.to_string_lossy().into_owned(),
+                    ..Default::default()
+                });
+            } else if file_type.is_dir() {
+                let (child, grandchildren) = upload_directory(cas, entry.path()).await?;
+                let digest = cas.put_message(&child).await?;
+                directory.directories.push(DirectoryNode {
+                    name,
+                    digest: Some(digest),
+                });
+                children.push(child);
+                children.extend(grandchildren);
+            } else {
+                let (digest, is_executable) = upload_file(cas, &entry.path()).await?;
+                directory.files.push(FileNode {
+                    name,
+                    digest: Some(digest),
+                    is_executable,
+                    ..Default::default()
+                });
+            }
+        }
+
+        // The REAPI requires entries to be sorted for directories to have a canonical digest.
+        directory.files.sort_by(|a, b| a.name.cmp(&b.name));
+        directory.directories.sort_by(|a, b| a.name.cmp(&b.name));
+        directory.symlinks.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok((directory, children))
+    }
+    .boxed()
+}
+
+async fn upload_file(cas: &LocalCas, path: &Path) -> Result<(Digest, bool), Status> {
+    let data = tokio::fs::read(path).await.map_err(internal)?;
+    let digest = cas.put(&data).await?;
+    Ok((digest, is_executable(path).await?))
+}
+
+/// Names in a `Directory` must be a single path component.
+fn join_name(path: &Path, name: &str) -> Result<PathBuf, Status> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(Status::invalid_argument(format!(
+            "Invalid name in input directory: `{}`",
+            name
+        )));
+    }
+    Ok(path.join(name))
+}
+
+/// Paths in a `Command` are relative, and must not escape the directory they are relative to.
+fn join_relative(path: &Path, relative: &str) -> Result<PathBuf, Status> {
+    let invalid = || {
+        Status::invalid_argument(format!(
+            "Invalid path in command, expected a relative path without `..`: `{}`",
+            relative
+        ))
+    };
+
+    if relative.starts_with('/') {
+        return Err(invalid());
+    }
+
+    let mut res = path.to_owned();
+    for component in relative.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return Err(invalid()),
+            _ => res.push(component),
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(unix)]
+async fn set_executable(path: &Path) -> Result<(), Status> {
+    use std::os::unix::fs::PermissionsExt;
+
+    tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+        .await
+        .map_err(internal)
+}
+
+#[cfg(not(unix))]
+async fn set_executable(_path: &Path) -> Result<(), Status> {
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn is_executable(path: &Path) -> Result<bool, Status> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let meta = tokio::fs::metadata(path).await.map_err(internal)?;
+    Ok(meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+async fn is_executable(_path: &Path) -> Result<bool, Status> {
+    Ok(false)
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &str, path: &Path) -> Result<(), Status> {
+    std::os::unix::fs::symlink(target, path).map_err(internal)
+}
+
+#[cfg(not(unix))]
+fn create_symlink(_target: &str, _path: &Path) -> Result<(), Status> {
+    Err(Status::unimplemented(
+        "Symlinks in inputs are only supported on UNIX",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_relative() {
+        let root = Path::new("/root");
+        assert_eq!(join_relative(root, "").unwrap(), root);
+        assert_eq!(
+            join_relative(root, "a/./b/").unwrap(),
+            Path::new("/root/a/b")
+        );
+
+        for escape in ["/etc/passwd", "..", "a/../..", "a/../b"] {
+            let err = join_relative(root, escape).unwrap_err();
+            assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        }
+    }
+}
@@ -0,0 +1,285 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! A small Remote Execution server, implementing the CAS, ActionCache, Capabilities and Execution
+//! services on top of a local directory, and running actions as local processes.
+//!
+//! This is meant for testing Buck2's RE integration without a real RE cluster: it can be run as a
+//! standalone binary, or embedded in tests with [`spawn`].
+
+mod action_cache;
+mod cas;
+mod digest;
+mod execution;
+mod executor;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use anyhow::Context;
+use re_grpc_proto::build::bazel::remote::execution::v2::action_cache_server::ActionCacheServer;
+use re_grpc_proto::build::bazel::remote::execution::v2::capabilities_server::Capabilities;
+use re_grpc_proto::build::bazel::remote::execution::v2::capabilities_server::CapabilitiesServer;
+use re_grpc_proto::build::bazel::remote::execution::v2::content_addressable_storage_server::ContentAddressableStorageServer;
+use re_grpc_proto::build::bazel::remote::execution::v2::execution_server::ExecutionServer;
+use re_grpc_proto::build::bazel::remote::execution::v2::ActionCacheUpdateCapabilities;
+use re_grpc_proto::build::bazel::remote::execution::v2::CacheCapabilities;
+use re_grpc_proto::build::bazel::remote::execution::v2::ExecutionCapabilities;
+use re_grpc_proto::build::bazel::remote::execution::v2::GetCapabilitiesRequest;
+use re_grpc_proto::build::bazel::remote::execution::v2::ServerCapabilities;
+use re_grpc_proto::build::bazel::semver::SemVer;
+use re_grpc_proto::google::bytestream::byte_stream_server::ByteStreamServer;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+use tonic::Request;
+use tonic::Response;
+use tonic::Status;
+
+use crate::action_cache::ActionCacheService;
+use crate::action_cache::LocalActionCache;
+use crate::cas::ByteStreamService;
+use crate::cas::CasService;
+use crate::cas::LocalCas;
+pub use crate::digest::DigestFunction;
+use crate::execution::ExecutionService;
+use crate::executor::LocalExecutor;
+
+/// Largest batch request we accept. Clients send bigger blobs via ByteStream.
+const MAX_BATCH_TOTAL_SIZE_BYTES: i64 = 4 * 1000 * 1000;
+
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// Where to store blobs, action results and scratch directories for actions.
+    pub root: PathBuf,
+    pub digest_function: DigestFunction,
+    /// End `Execute` streams after their first update, before the action completes, so that
+    /// clients have to reattach with `WaitExecution`. This is for testing clients.
+    pub interrupt_execute_streams: bool,
+}
+
+struct CapabilitiesService {
+    digest_function: DigestFunction,
+}
+
+#[tonic::async_trait]
+impl Capabilities for CapabilitiesService {
+    async fn get_capabilities(
+        &self,
+        _request: Request<GetCapabilitiesRequest>,
+    ) -> Result<Response<ServerCapabilities>, Status> {
+        let digest_function = self.digest_function.to_proto() as i32;
+
+        Ok(Response::new(ServerCapabilities {
+            cache_capabilities: Some(CacheCapabilities {
+                digest_functions: vec![digest_function],
+                action_cache_update_capabilities: Some(ActionCacheUpdateCapabilities {
+                    update_enabled: true,
+                }),
+                max_batch_total_size_bytes: MAX_BATCH_TOTAL_SIZE_BYTES,
+                ..Default::default()
+            }),
+            execution_capabilities: Some(ExecutionCapabilities {
+                digest_function,
+                exec_enabled: true,
+                ..Default::default()
+            }),
+            low_api_version: Some(SemVer {
+                major: 2,
+                ..Default::default()
+            }),
+            high_api_version: Some(SemVer {
+                major: 2,
+                minor: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }))
+    }
+}
+
+/// Serve all the services on connections accepted by `listener`, until an error occurs.
+pub async fn serve(config: ServerConfig, listener: TcpListener) -> anyhow::Result<()> {
+    let cas_dir = config.root.join("cas");
+    let ac_dir = config.root.join("ac");
+    let work_dir = config.root.join("work");
+
+    for dir in [&cas_dir, &ac_dir, &work_dir] {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Error creating `{}`", dir.display()))?;
+    }
+
+    let cas = Arc::new(LocalCas::new(cas_dir, config.digest_function));
+    let action_cache = Arc::new(LocalActionCache::new(ac_dir, config.digest_function));
+    let executor = Arc::new(LocalExecutor::new(cas.clone(), work_dir));
+
+    Server::builder()
+        .add_service(CapabilitiesServer::new(CapabilitiesService {
+            digest_function: config.digest_function,
+        }))
+        .add_service(ContentAddressableStorageServer::new(CasService {
+            cas: cas.clone(),
+        }))
+        .add_service(ByteStreamServer::new(ByteStreamService {
+            cas: cas.clone(),
+        }))
+        .add_service(ActionCacheServer::new(ActionCacheService {
+            action_cache: action_cache.clone(),
+        }))
+        .add_service(ExecutionServer::new(ExecutionService {
+            cas,
+            action_cache,
+            executor,
+            operations: Mutex::new(HashMap::new()),
+            interrupt_streams: config.interrupt_execute_streams,
+        }))
+        .serve_with_incoming(TcpListenerStream::new(listener))
+        .await
+        .context("Error serving")?;
+
+    Ok(())
+}
+
+/// Start a server on a free port on localhost, in the background. Returns the address to
+/// connect to. The server runs until the Tokio runtime shuts down.
+pub async fn spawn(config: ServerConfig) -> anyhow::Result<SocketAddr> {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .context("Error binding")?;
+    let address = listener.local_addr()?;
+
+    tokio::spawn(async move {
+        if let Err(e) = serve(config, listener).await {
+            tracing::error!("RE server failed: {:#}", e);
+        }
+    });
+
+    Ok(address)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use futures::stream::TryStreamExt;
+    use prost::Message;
+    use re_grpc_proto::build::bazel::remote::execution::v2::batch_update_blobs_request;
+    use re_grpc_proto::build::bazel::remote::execution::v2::content_addressable_storage_client::ContentAddressableStorageClient;
+    use re_grpc_proto::build::bazel::remote::execution::v2::execution_client::ExecutionClient;
+    use re_grpc_proto::build::bazel::remote::execution::v2::Action;
+    use re_grpc_proto::build::bazel::remote::execution::v2::BatchReadBlobsRequest;
+    use re_grpc_proto::build::bazel::remote::execution::v2::BatchUpdateBlobsRequest;
+    use re_grpc_proto::build::bazel::remote::execution::v2::Command;
+    use re_grpc_proto::build::bazel::remote::execution::v2::Digest;
+    use re_grpc_proto::build::bazel::remote::execution::v2::Directory;
+    use re_grpc_proto::build::bazel::remote::execution::v2::ExecuteRequest;
+    use re_grpc_proto::build::bazel::remote::execution::v2::ExecuteResponse;
+    use re_grpc_proto::google::longrunning::operation;
+
+    use super::*;
+
+    async fn execute(
+        client: &mut ExecutionClient<tonic::transport::Channel>,
+        action_digest: &Digest,
+    ) -> anyhow::Result<ExecuteResponse> {
+        let ops = client
+            .execute(ExecuteRequest {
+                action_digest: Some(action_digest.clone()),
+                ..Default::default()
+            })
+            .await?
+            .into_inner()
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        match ops.last().and_then(|op| op.result.as_ref()) {
+            Some(operation::Result::Response(any)) => {
+                Ok(ExecuteResponse::decode(any.value.as_slice())?)
+            }
+            _ => Err(anyhow::anyhow!("Execution did not complete")),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let digest_function = DigestFunction::Sha256;
+        let address = spawn(ServerConfig {
+            root: dir.path().to_owned(),
+            digest_function,
+            interrupt_execute_streams: false,
+        })
+        .await?;
+        let address = format!("http://{}", address);
+
+        let command = Command {
+            arguments: vec![
+                "/bin/sh".to_owned(),
+                "-c".to_owned(),
+                "mkdir -p out && echo hello > out/file".to_owned(),
+            ],
+            output_paths: vec!["out/file".to_owned()],
+            ..Default::default()
+        }
+        .encode_to_vec();
+        let input_root = Directory::default().encode_to_vec();
+        let command_digest = digest_function.digest(&command);
+        let input_root_digest = digest_function.digest(&input_root);
+        let action = Action {
+            command_digest: Some(command_digest.clone()),
+            input_root_digest: Some(input_root_digest.clone()),
+            ..Default::default()
+        }
+        .encode_to_vec();
+        let action_digest = digest_function.digest(&action);
+
+        let mut cas = ContentAddressableStorageClient::connect(address.clone()).await?;
+        cas.batch_update_blobs(BatchUpdateBlobsRequest {
+            requests: [
+                (command_digest, command),
+                (input_root_digest, input_root),
+                (action_digest.clone(), action),
+            ]
+            .into_iter()
+            .map(|(digest, data)| batch_update_blobs_request::Request {
+                digest: Some(digest),
+                data,
+                ..Default::default()
+            })
+            .collect(),
+            ..Default::default()
+        })
+        .await?;
+
+        let mut execution = ExecutionClient::connect(address).await?;
+
+        let response = execute(&mut execution, &action_digest).await?;
+        assert!(!response.cached_result);
+        let result = response.result.context("No result")?;
+        assert_eq!(result.exit_code, 0);
+        assert_eq!(result.output_files.len(), 1);
+        assert_eq!(result.output_files[0].path, "out/file");
+
+        let blobs = cas
+            .batch_read_blobs(BatchReadBlobsRequest {
+                digests: vec![result.output_files[0].digest.clone().context("No digest")?],
+                ..Default::default()
+            })
+            .await?
+            .into_inner();
+        assert_eq!(blobs.responses[0].data, b"hello\n");
+
+        let response = execute(&mut execution, &action_digest).await?;
+        assert!(response.cached_result);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use clap::Parser;
+use re_grpc_server::DigestFunction;
+use re_grpc_server::ServerConfig;
+use tokio::net::TcpListener;
+
+#[derive(Parser)]
+struct Opt {
+    #[clap(
+        long,
+        help = "Directory to store blobs, action results and action scratch space in"
+    )]
+    root: PathBuf,
+
+    #[clap(long, default_value = "127.0.0.1:8980", help = "Address to listen on")]
+    address: SocketAddr,
+
+    #[clap(
+        long,
+        default_value = "sha256",
+        help = "Digest function to use (sha1 or sha256), this must match `buck2.digest_algorithms`"
+    )]
+    digest_function: DigestFunction,
+
+    #[clap(
+        long,
+        help = "End Execute streams before actions complete, to test clients reattaching with WaitExecution"
+    )]
+    interrupt_execute_streams: bool,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let Opt {
+        root,
+        address,
+        digest_function,
+        interrupt_execute_streams,
+    } = Opt::parse();
+
+    let listener = TcpListener::bind(address).await?;
+    eprintln!("Listening on {}", listener.local_addr()?);
+
+    re_grpc_server::serve(
+        ServerConfig {
+            root,
+            digest_function,
+            interrupt_execute_streams,
+        },
+        listener,
+    )
+    .await
+}
@@ -186,6 +186,7 @@ const manualSidebar = [
       isInternal() ? 'developers/heap_profiling' : [],
       'developers/parity_script',
       'developers/what-ran',
+      'developers/check-determinism',
       {
         type: 'category',
         label: 'Starlark Language',